@@ -1,9 +1,18 @@
 use std::{cell::UnsafeCell, marker::PhantomData};
 use std::ptr::NonNull;
-use std::sync::atomic;
 use std::mem::ManuallyDrop;
+use std::pin::Pin;
 
-use atomic::{AtomicUsize, Ordering};
+use sync::{fence, spin_loop, AtomicUsize, Ordering};
+
+/// The atomics that `Arc` is built on, which get swapped out for [loom](https://docs.rs/loom)'s
+/// when model checking (i.e: `RUSTFLAGS="--cfg loom" cargo test --release atomic_refcount`).
+mod sync {
+    #[cfg(not(loom))]
+    pub(super) use std::{hint::spin_loop, sync::atomic::{fence, AtomicUsize, Ordering}};
+    #[cfg(loom)]
+    pub(super) use loom::{hint::spin_loop, sync::atomic::{fence, AtomicUsize, Ordering}};
+}
 
 pub struct Arc<T: ?Sized> {
     ptr: NonNull<ArcInner<T>>,
@@ -41,6 +50,14 @@ impl<T> Arc<T> {
             phantom: PhantomData
         }
     }
+    
+    /// Constructs a new `Pin<Arc<T>>`. If `T` does not implement [`Unpin`], then
+    /// `data` will be pinned in memory and unable to be moved.
+    pub fn pin(data: T) -> Pin<Self> {
+        // SAFETY: the data is never moved out of its allocation (unless we have
+        //         the `Arc` by value, which the `Pin` never gives out for `!Unpin` types)
+        unsafe { Pin::new_unchecked(Self::new(data)) }
+    }
 }
 
 impl<T: ?Sized> Arc<T> {
@@ -49,24 +66,50 @@ impl<T: ?Sized> Arc<T> {
         unsafe { self.ptr.as_ref() }
     }
     
+    /// Returns a mutable reference to the data, if there are no other `Arc`s or `WeakArc`s to it.
+    /// 
+    /// NOTE: this can only return `None` spuriously if another `Arc` to the same
+    /// data is in the middle of `get_mut`, in which case it isn't unique anyways.
     pub fn get_mut(arc: &mut Self) -> Option<&mut T> {
+        // "lock" the weak count, so no `downgrade` can happen while we check the strong count.
+        // Acquire syncs with the Release in `WeakArc::drop`, so we see all uses of the dropped weaks.
         if arc.inner().weak_count.compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed).is_err() {
             return None
         }
         
         let is_unique = arc.inner().strong_count.load(Ordering::Relaxed) == 1;
         
-        arc.inner().weak_count.store(1, Ordering::Relaxed);
+        // Release syncs with the Acquire in `downgrade`, so that any `WeakArc` made after
+        // this point can't observe the mutations we make through the returned reference.
+        arc.inner().weak_count.store(1, Ordering::Release);
         if !is_unique {
             return None
         }
         
-        atomic::fence(Ordering::Acquire);
+        // Acquire syncs with the Release in `Arc::drop`, so we see all uses of the dropped `Arc`s.
+        fence(Ordering::Acquire);
         unsafe { Some(&mut *arc.inner().data.get()) }
     }
     
-    pub fn downgrade(_arc: Self) -> WeakArc<T> {
-        todo!()
+    /// Creates a new [`WeakArc`] pointing to the same data.
+    pub fn downgrade(arc: &Self) -> WeakArc<T> {
+        let mut n = arc.inner().weak_count.load(Ordering::Relaxed);
+        loop {
+            // the weak count is locked by `get_mut`, so wait for it to be unlocked
+            if n == usize::MAX {
+                spin_loop();
+                n = arc.inner().weak_count.load(Ordering::Relaxed);
+                continue
+            }
+            assert!(n < isize::MAX as usize);
+            // Acquire syncs with the Release store in `get_mut`
+            if let Err(e) = arc.inner().weak_count
+                .compare_exchange_weak(n, n+1, Ordering::Acquire, Ordering::Relaxed) {
+                n = e;
+                continue
+            }
+            return WeakArc { ptr: arc.ptr }
+        }
     }
 }
 
@@ -97,7 +140,7 @@ impl<T: ?Sized> Drop for Arc<T> {
     fn drop(&mut self) {
         // Ordering::Release guarantees that any previous increments are visible
         if self.inner().strong_count.fetch_sub(1, Ordering::Release) == 1 {
-            atomic::fence(Ordering::Acquire);
+            fence(Ordering::Acquire);
             
             // SAFETY: since the refcnt is now 0, nothing else is referencing the data.
             unsafe {
@@ -147,7 +190,7 @@ impl<T: ?Sized> Clone for WeakArc<T> {
 impl<T: ?Sized> Drop for WeakArc<T> {
     fn drop(&mut self) {
         if self.inner().weak_count.fetch_sub(1, Ordering::Release) == 1 {
-            atomic::fence(Ordering::Acquire);
+            fence(Ordering::Acquire);
             
             drop(
                 unsafe { Box::from_raw(self.ptr.as_ptr()) }
@@ -157,7 +200,7 @@ impl<T: ?Sized> Drop for WeakArc<T> {
 }
 
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
     use super::*;
     
@@ -188,4 +231,218 @@ mod tests {
         
         assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 1);
     }
+    
+    #[test]
+    fn test_weak() {
+        let mut x = Arc::new(5);
+        let weak = Arc::downgrade(&x);
+        assert!(Arc::get_mut(&mut x).is_none());
+        
+        let y = weak.upgrade().unwrap();
+        assert_eq!(*y, 5);
+        drop(y);
+        drop(weak);
+        
+        *Arc::get_mut(&mut x).unwrap() += 1;
+        let weak = Arc::downgrade(&x);
+        drop(x);
+        assert!(weak.upgrade().is_none());
+    }
+    
+    #[test]
+    fn test_pin() {
+        use std::marker::PhantomPinned;
+        
+        struct NotUnpin {
+            value: i32,
+            _pinned: PhantomPinned,
+        }
+        
+        let x: Pin<Arc<NotUnpin>> = Arc::pin(NotUnpin { value: 5, _pinned: PhantomPinned });
+        let y = x.clone();
+        let address = &*x as *const NotUnpin;
+        
+        let t = std::thread::spawn(move || {
+            let pinned: Pin<&NotUnpin> = y.as_ref();
+            assert_eq!(pinned.value, 5);
+            (pinned.get_ref() as *const NotUnpin).addr()
+        });
+        
+        assert_eq!(x.value, 5);
+        assert_eq!(t.join().unwrap(), address.addr());
+    }
+    
+    /// Hammers `get_mut` on one thread, while other threads `upgrade`, `downgrade`, and drop handles to the same data
+    #[test]
+    fn test_get_mut_weak_stress() {
+        use std::sync::mpsc;
+        
+        const T: usize = 4;
+        const R: usize = 100_000;
+        
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Data([usize; 8]);
+        impl Drop for Data {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        let mut arc = Arc::new(Data([0; 8]));
+        
+        let (senders, handles): (Vec<_>, Vec<_>) = (0..T).map(|_| {
+            let (tx, rx) = mpsc::sync_channel::<WeakArc<Data>>(4);
+            let handle = std::thread::spawn(move || {
+                for weak in rx {
+                    // the sender can't drop its `Arc` while we're using its weak reference
+                    let strong = weak.upgrade().expect("the main thread always holds an `Arc`");
+                    let data = strong.0;
+                    assert!(data.iter().all(|&x| x == data[0]), "`get_mut` handed out a mutable reference while shared: {data:?}");
+                    
+                    // make some more weak references, and throw them away at different times
+                    let weak_2 = Arc::downgrade(&strong);
+                    drop(strong);
+                    drop(weak);
+                    let strong_2 = weak_2.upgrade().unwrap();
+                    drop(weak_2.clone());
+                    drop(weak_2);
+                    assert!(strong_2.0.iter().all(|&x| x == strong_2.0[0]));
+                }
+            });
+            (tx, handle)
+        }).unzip();
+        
+        let mut num_mutations = 0;
+        for i in 0..R {
+            if let Some(data) = Arc::get_mut(&mut arc) {
+                let next = data.0[0] + 1;
+                for x in &mut data.0 { *x = next }
+                num_mutations += 1;
+            }
+            // if the channel is full, just drop it instead
+            let _ = senders[i % T].try_send(Arc::downgrade(&arc));
+        }
+        
+        drop(senders);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        
+        // now that everybody else is gone, `get_mut` should always succeed
+        let data = Arc::get_mut(&mut arc).expect("no other references should exist");
+        assert_eq!(data.0, [num_mutations; 8]);
+        
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
+        drop(arc);
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 1);
+    }
+}
+
+/// Model checked versions of the races between `clone`, `drop`, `upgrade`, and `downgrade`, which
+/// make sure the data is dropped exactly once, and never while something can still use it.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::cell::UnsafeCell as LoomCell;
+    use loom::thread;
+    use std::sync::Arc as StdArc;
+    use std::sync::atomic::AtomicUsize as StdAtomicUsize;
+    
+    /// A value that loom tracks accesses to, so any read that isn't ordered before the drop is a
+    /// data race. It also counts how many times it was dropped, in a counter outside of the model.
+    struct Tracked {
+        value: LoomCell<usize>,
+        drops: StdArc<StdAtomicUsize>,
+    }
+    
+    impl Tracked {
+        fn get(&self) -> usize {
+            self.value.with(|value| unsafe { *value })
+        }
+        
+        fn set(&mut self, new: usize) {
+            self.value.with_mut(|value| unsafe { *value = new })
+        }
+    }
+    
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.set(0);
+            let old = self.drops.fetch_add(1, Ordering::Relaxed);
+            assert_eq!(old, 0, "value was dropped twice");
+        }
+    }
+    
+    fn tracked(value: usize) -> (Arc<Tracked>, StdArc<StdAtomicUsize>) {
+        let drops = StdArc::new(StdAtomicUsize::new(0));
+        (Arc::new(Tracked { value: LoomCell::new(value), drops: drops.clone() }), drops)
+    }
+    
+    #[test]
+    fn loom_clone_drop() {
+        loom::model(|| {
+            let (x, drops) = tracked(5);
+            let y = x.clone();
+            
+            let t = thread::spawn(move || {
+                let z = y.clone();
+                assert_eq!(y.get(), 5);
+                drop(y);
+                assert_eq!(z.get(), 5);
+            });
+            
+            assert_eq!(x.get(), 5);
+            drop(x);
+            t.join().unwrap();
+            
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+    
+    #[test]
+    fn loom_upgrade_drop() {
+        loom::model(|| {
+            let (x, drops) = tracked(5);
+            let weak = Arc::downgrade(&x);
+            
+            let t = thread::spawn(move || {
+                // either the last `Arc` is already gone, or this keeps the value alive
+                if let Some(strong) = weak.upgrade() {
+                    assert_eq!(strong.get(), 5);
+                }
+            });
+            
+            drop(x);
+            t.join().unwrap();
+            
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+    
+    #[test]
+    fn loom_downgrade_get_mut() {
+        loom::model(|| {
+            let (mut x, drops) = tracked(5);
+            let y = x.clone();
+            
+            let t = thread::spawn(move || {
+                let weak = Arc::downgrade(&y);
+                drop(y);
+                let clone = weak.clone();
+                drop(weak);
+                clone.upgrade().map(|strong| strong.get())
+            });
+            
+            // if this succeeds, nothing else can be looking at the value
+            if let Some(data) = Arc::get_mut(&mut x) {
+                data.set(6);
+            }
+            let seen = t.join().unwrap();
+            assert!(matches!(seen, None | Some(5 | 6)));
+            
+            assert!(Arc::get_mut(&mut x).is_some());
+            drop(x);
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
 }
@@ -29,6 +29,43 @@ impl<'data, T> AtomicCell<'data, T> {
         unsafe { Some(NonNull::new(ptr)?.as_mut()) }
     }
     
+    /// Whether the cell is currently empty (e.g: after a call to [`take`](Self::take)).
+    /// 
+    /// NOTE: another thread can always fill or empty the cell right after this returns.
+    pub fn is_empty(&self) -> bool {
+        self.0.load(Ordering::Acquire).is_null()
+    }
+    
+    /// Replaces the reference in the cell with the result of `f`, and returns the reference that
+    /// got installed.
+    /// 
+    /// This loads the current reference, calls `f` with it, and then tries to swap in whatever
+    /// `f` returned. If some other thread changed the cell in the meantime, the reference `f`
+    /// returned is just dropped (it never got into the cell), and `f` gets called again with
+    /// the new current one. This is the [`AtomicPtr::fetch_update`] of this cell.
+    /// 
+    /// # Safety
+    /// * `f` gets a reference to the value that's still in the cell, so other threads might be
+    ///   looking at it at the same time (e.g: in their own `update`s, or with [`get`](Self::get)).
+    ///   `f` must not write through it, or hold on to it after it returns.
+    /// * The returned reference is still in the cell too, so it can't be used once something
+    ///   else could get to it (e.g: with [`take`](Self::take), or another `update`).
+    pub unsafe fn update<F>(&self, mut f: F) -> Option<&'data mut T>
+    where
+        F : FnMut(Option<&mut T>) -> Option<&'data mut T>
+    {
+        let mut current = self.0.load(Ordering::Acquire);
+        loop {
+            // SAFETY: the caller promises `f` doesn't use this in any way that conflicts with anyone else
+            let new = f(unsafe { current.as_mut() }).map_or(std::ptr::null_mut(), |new| new as *mut T);
+            match self.0.compare_exchange_weak(current, new, Ordering::AcqRel, Ordering::Acquire) {
+                // SAFETY: the caller promises not to use it once anything else could
+                Ok(_) => return unsafe { new.as_mut() },
+                Err(actual) => current = actual,
+            }
+        }
+    }
+    
     pub fn get_mut<'a>(&'a mut self) -> &'a mut Option<&'data mut T> {
         // NOTE: returning a &mut *mut T is unsound since you can set it to a dangling
         // pointer, but then calling any other method would dereference it
@@ -41,3 +78,83 @@ impl<'data, T> AtomicCell<'data, T> {
         unsafe { self.0.into_inner().as_mut() }
     }
 }
+
+/// Only prints the address in the cell, since another thread could take the value out at any time.
+impl<T> std::fmt::Debug for AtomicCell<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AtomicCell").field(&self.0.load(Ordering::Acquire)).finish()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Has two threads race to increment the value in the cell, by installing references to their own slots
+    #[test]
+    fn test_update_concurrent() {
+        const N: usize = 10000;
+        
+        let mut initial = 0;
+        let mut slots = [vec![0usize; N], vec![0usize; N]];
+        let cell = AtomicCell::from_mut(&mut initial);
+        
+        std::thread::scope(|s| {
+            for slots in &mut slots {
+                let cell = &cell;
+                s.spawn(move || {
+                    let base = slots.as_mut_ptr();
+                    for i in 0..N {
+                        // SAFETY: `f` only reads the current value, and nothing ever takes values out of the cell
+                        let new = unsafe { cell.update(|current| {
+                            // SAFETY: slot `i` only ever gets installed into the cell once, so
+                            // it's only written to here (possibly a few times, if this retries)
+                            let slot = unsafe { &mut *base.add(i) };
+                            *slot = *current.unwrap() + 1;
+                            Some(slot)
+                        }) };
+                        assert!(std::ptr::eq(new.unwrap(), unsafe { base.add(i) }));
+                    }
+                });
+            }
+        });
+        
+        assert_eq!(cell.get(), 2 * N);
+        
+        // every update should have seen the result of the one before it
+        let mut values: Vec<usize> = slots.iter().flatten().copied().collect();
+        values.sort();
+        assert!(values.into_iter().eq(1..=2 * N));
+    }
+    
+    /// A panicking update shouldn't change the cell
+    #[test]
+    fn test_update_panic() {
+        let mut x = 5;
+        let cell = AtomicCell::from_mut(&mut x);
+        
+        // SAFETY: `f` doesn't do anything with the value
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe { cell.update(|_| panic!("oops")) }));
+        assert!(result.is_err());
+        assert_eq!(cell.get(), 5);
+        assert_eq!(cell.take().copied(), Some(5));
+    }
+    
+    #[test]
+    fn test_is_empty() {
+        let mut x = 5;
+        let mut y = 6;
+        let cell = AtomicCell::from_mut(&mut x);
+        assert!(!cell.is_empty());
+        
+        assert_eq!(cell.take().copied(), Some(5));
+        assert!(cell.is_empty());
+        assert!(cell.take().is_none());
+        assert_eq!(format!("{cell:?}"), format!("AtomicCell({:?})", std::ptr::null_mut::<i32>()));
+        
+        assert!(cell.replace(&mut y).is_none());
+        assert!(!cell.is_empty());
+    }
+}
@@ -1,7 +1,10 @@
 use core::cell::SyncUnsafeCell;
 use core::sync::atomic::{AtomicIsize, Ordering};
-use core::marker::PhantomData;
-use core::ops::{Deref, DerefMut, DerefPure};
+#[cfg(feature = "std")]
+use core::sync::atomic::AtomicUsize;
+use core::marker::{PhantomData, Unsize};
+use core::ops::{CoerceUnsized, Deref, DerefMut, DerefPure};
+use core::ptr::NonNull;
 
 /// A thread-safe [`RefCell`].
 /// 
@@ -11,13 +14,39 @@ use core::ops::{Deref, DerefMut, DerefPure};
 /// uses atomic operations to ensure it happens safely across threads. However,
 /// when failing to acquire a reference to the data, it behaves differently.
 /// Unlike a [`RefCell`], it does not panic by default, and unlike an [`RwLock`],
-/// it does not block.
+/// it does not block. (except through [`read`] and [`write`], which block just
+/// like [`RwLock`]'s methods of the same names, for easier migration)
+/// 
+/// # Borrow policy
+/// 
+/// By default, the cell has no preference between readers and writers. Since
+/// [`try_borrow_mut`] only succeeds when there are no borrows at all, a steady
+/// stream of overlapping readers can keep a writer out forever. When a writer
+/// needs to make progress, it can use [`try_borrow_mut_blocking_new_readers`]
+/// instead, which marks the cell as "write pending" so that new calls to
+/// [`try_borrow`] fail, and then waits for the existing readers to finish.
 /// 
 /// [`RefCell`]: core::cell::RefCell
 /// [`RwLock`]: std::sync::RwLock
+/// [`try_borrow`]: AtomicRefCell::try_borrow
+/// [`try_borrow_mut`]: AtomicRefCell::try_borrow_mut
+/// [`try_borrow_mut_blocking_new_readers`]: AtomicRefCell::try_borrow_mut_blocking_new_readers
+/// [`read`]: AtomicRefCell::read
+/// [`write`]: AtomicRefCell::write
 #[derive(Debug)]
 pub struct AtomicRefCell<T: ?Sized> {
+    /// The borrow counter, which is one of:
+    ///  - `0`, if the cell isn't borrowed at all
+    ///  - `n`, if there are `n` shared borrows (up to [`MAX_SHARED_BORROWS`])
+    ///  - `WRITE_PENDING | n`, if a writer is waiting for `n` shared borrows to be dropped
+    ///  - `-1`, if the cell is exclusively borrowed
+    /// 
+    /// See [`BorrowState`] for the decoded version.
     borrows: AtomicIsize,
+    /// Tells this cell's entries in [`RECURSIVE_BORROWS`] apart from stale ones, or `0` if it
+    /// hasn't been given one yet (see [`generation`](AtomicRefCell::generation)).
+    #[cfg(feature = "std")]
+    generation: AtomicUsize,
     value: SyncUnsafeCell<T>
 }
 
@@ -25,15 +54,103 @@ pub struct AtomicRefCell<T: ?Sized> {
 //         And since an &AtomicRefCell<T> can be used to send `&T`s across threads, T must be Sync.
 unsafe impl<T: ?Sized + Send + Sync> Sync for AtomicRefCell<T> {}
 
+/// Set in the borrow counter while a writer is waiting for the readers to drain.
+/// 
+/// The rest of the bits still count the shared borrows, so readers can release
+/// their borrows as normal while it is set.
+const WRITE_PENDING: isize = 1 << (isize::BITS - 2);
+
+/// The most shared borrows the counter can hold without running into [`WRITE_PENDING`].
+const MAX_SHARED_BORROWS: isize = WRITE_PENDING - 1;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// How deep each cell is being [`borrow_recursive`](AtomicRefCell::borrow_recursive)d on this
+    /// thread, as `(address of its borrow counter, generation, depth)`.
+    /// 
+    /// An entry only counts if the generation still matches the cell's. If a `ReentrantBorrow`
+    /// gets leaked, its entry never gets removed, but the cell then either gets a new generation
+    /// from [`clear_leaked_borrows`](AtomicRefCell::clear_leaked_borrows), or is a different
+    /// cell (with its own generation) by the time anything else is at that address.
+    /// 
+    /// NOTE: this is a `Vec`, since a thread is usually only recursing through a few cells at once.
+    static RECURSIVE_BORROWS: core::cell::RefCell<std::vec::Vec<(usize, usize, usize)>> = const { core::cell::RefCell::new(std::vec::Vec::new()) };
+}
+
+/// The next generation to give a cell (see [`RECURSIVE_BORROWS`]), so that no two are ever the same.
+#[cfg(feature = "std")]
+static NEXT_GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+/// Panics if a borrow can't be taken with these orderings. See [`AtomicRefCell::try_borrow_explicit`].
+#[track_caller]
+fn check_borrow_orderings(success: Ordering, failure: Ordering) {
+    match success {
+        Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst => {},
+        _ => panic!("borrowing with {success:?} wouldn't synchronize with the previous borrow"),
+    }
+    match failure {
+        Ordering::Release => panic!("there is no such thing as a release failure ordering"),
+        Ordering::AcqRel => panic!("there is no such thing as an acquire-release failure ordering"),
+        _ => {},
+    }
+}
+
+/// Lets the OS run something else while waiting for a borrow.
+#[cfg(feature = "std")]
+#[inline]
+fn relax() {
+    core::hint::spin_loop();
+    std::thread::yield_now();
+}
+
 impl<T> AtomicRefCell<T> {
     /// Creates a new [`AtomicRefCell`] containing `value`.
     pub const fn new(value: T) -> Self {
         AtomicRefCell {
             borrows: AtomicIsize::new(0),
+            #[cfg(feature = "std")]
+            generation: AtomicUsize::new(0),
             value: SyncUnsafeCell::new(value)
         }
     }
     
+    /// Creates a new [`AtomicRefCell`] that starts out exclusively borrowed, and calls `f` with that borrow.
+    /// 
+    /// The cell is returned once `f` is done with it, along with whatever `f` returned. This is
+    /// for when the value needs to be set up (through the [`AtomicRefMut`] API) before anyone
+    /// else gets to see it, without a separate `try_borrow_mut().unwrap()`.
+    /// 
+    /// The guard can't outlive `f`, since the cell hasn't been moved to where it's being returned
+    /// yet. If it gets leaked (with [`mem::forget`] or [`AtomicRefMut::leak`]), the cell comes back
+    /// still exclusively borrowed, just like any other leaked guard.
+    /// 
+    /// NOTE: if you don't need a guard, [`get_mut`](AtomicRefCell::get_mut) on a freshly made cell does the same thing.
+    /// 
+    /// [`mem::forget`]: core::mem::forget
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowState};
+    /// 
+    /// let (x, len) = AtomicRefCell::new_exclusive(vec![1, 2], |mut guard| {
+    ///     guard.push(3);
+    ///     guard.len()
+    /// });
+    /// assert_eq!(len, 3);
+    /// assert_eq!(x.borrow_state(), BorrowState::Unborrowed);
+    /// assert_eq!(*x.try_borrow().unwrap(), [1, 2, 3]);
+    /// ```
+    pub fn new_exclusive<R>(value: T, f: impl for<'b> FnOnce(AtomicRefMut<'b, T>) -> R) -> (Self, R) {
+        let cell = AtomicRefCell {
+            borrows: AtomicIsize::new(-1),
+            #[cfg(feature = "std")]
+            generation: AtomicUsize::new(0),
+            value: SyncUnsafeCell::new(value)
+        };
+        let result = f(AtomicRefMut::new(&cell));
+        (cell, result)
+    }
+    
     /// Consumes an [`AtomicRefCell`] and returns the wrapped value.
     /// 
     /// See [`Box::into_inner`], [`Cell::into_inner`](std::cell::Cell::into_inner),
@@ -54,6 +171,57 @@ impl<T> AtomicRefCell<T> {
 }
 
 impl<T: ?Sized> AtomicRefCell<T> {
+    /// Moves an already boxed value into a new boxed [`AtomicRefCell`].
+    /// 
+    /// Like any other struct whose last field is unsized, a `Box<AtomicRefCell<T>>` can just be
+    /// coerced into a `Box<AtomicRefCell<dyn Trait>>` (or `Box<AtomicRefCell<[T]>>`). This is for
+    /// when the value is already in a `Box<dyn Trait>`, and its type isn't known anymore.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// use std::fmt::Display;
+    /// 
+    /// let coerced: Box<AtomicRefCell<dyn Display>> = Box::new(AtomicRefCell::new(5));
+    /// assert_eq!(coerced.try_borrow().unwrap().to_string(), "5");
+    /// 
+    /// let value: Box<dyn Display> = Box::new("hello");
+    /// let x = AtomicRefCell::from_box(value);
+    /// assert_eq!(x.try_borrow().unwrap().to_string(), "hello");
+    /// 
+    /// let x: Box<AtomicRefCell<[i32]>> = AtomicRefCell::from_box(vec![1, 2, 3].into_boxed_slice());
+    /// x.try_borrow_mut().unwrap()[1] = 5;
+    /// assert_eq!(*x.try_borrow().unwrap(), [1, 5, 3]);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_box(value: Box<T>) -> Box<Self> {
+        use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+        
+        let value = Box::into_raw(value);
+        let metadata = core::ptr::metadata(value);
+        
+        // SAFETY: the metadata came from a valid `Box<T>`, so the cell's size fits in an `isize` too
+        let layout = unsafe { Layout::for_value_raw(core::ptr::from_raw_parts::<Self>(core::ptr::null::<()>(), metadata)) };
+        // NOTE: the borrow counter means this is never zero sized
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() { handle_alloc_error(layout) }
+        let cell = core::ptr::from_raw_parts_mut::<Self>(ptr, metadata);
+        
+        // SAFETY: `cell` was just allocated with the right layout, and `SyncUnsafeCell<T>` has the
+        //         same layout as `T`, so the value can just be copied over. The old box gets freed
+        //         without dropping the value, since it got moved.
+        unsafe {
+            (&raw mut (*cell).borrows).write(AtomicIsize::new(0));
+            (&raw mut (*cell).generation).write(AtomicUsize::new(0));
+            let value_layout = Layout::for_value_raw(value);
+            (&raw mut (*cell).value).cast::<u8>().copy_from_nonoverlapping(value.cast::<u8>(), value_layout.size());
+            if value_layout.size() != 0 {
+                dealloc(value.cast(), value_layout);
+            }
+            Box::from_raw(cell)
+        }
+    }
+    
     /// Get a mutable reference to the underlying data.
     /// 
     /// This function borrows the [`AtomicRefCell`] mutably at compile time,
@@ -105,10 +273,57 @@ impl<T: ?Sized> AtomicRefCell<T> {
     /// ```
     pub fn clear_leaked_borrows(&mut self) {
         *self.borrows.get_mut() = 0;
+        // any leaked `ReentrantBorrow`s don't hold the cell anymore, so their entries can't count either
+        #[cfg(feature = "std")]
+        { *self.generation.get_mut() = 0; }
     }
     
+    /// Returns how many borrows of the [`AtomicRefCell`] currently exist.
+    /// 
+    /// This is the number of [`AtomicRef`]s for shared borrows (even if a writer is waiting for
+    /// them to be dropped), or `-1` if there is an [`AtomicRefMut`]. Like with
+    /// [`borrow_state`](AtomicRefCell::borrow_state), this might already be out of date by the
+    /// time it returns.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// assert_eq!(x.active_borrows(), 0);
+    /// let a = x.try_borrow().unwrap();
+    /// let b = x.try_borrow().unwrap();
+    /// assert_eq!(x.active_borrows(), 2);
+    /// drop((a, b));
+    /// let c = x.try_borrow_mut().unwrap();
+    /// assert_eq!(x.active_borrows(), -1);
+    /// ```
     pub fn active_borrows(&self) -> isize {
-        todo!()
+        match self.borrow_state() {
+            BorrowState::Unborrowed => 0,
+            BorrowState::Shared(n) | BorrowState::WritePending(n) => n as isize,
+            BorrowState::Exclusive => -1,
+        }
+    }
+    
+    /// Returns the current state of the borrow counter.
+    /// 
+    /// Note that by the time this returns, other threads may have already
+    /// changed the state, so this is mostly only useful as a hint (or for
+    /// passing into [`try_transition`](AtomicRefCell::try_transition)).
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowState};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// assert_eq!(x.borrow_state(), BorrowState::Unborrowed);
+    /// let a = x.try_borrow().unwrap();
+    /// let b = x.try_borrow().unwrap();
+    /// assert_eq!(x.borrow_state(), BorrowState::Shared(2));
+    /// ```
+    pub fn borrow_state(&self) -> BorrowState {
+        BorrowState::decode(self.borrows.load(Ordering::Relaxed))
     }
     
     /// Tries to acquire shared access to the [`AtomicRefCell`].
@@ -119,8 +334,11 @@ impl<T: ?Sized> AtomicRefCell<T> {
     /// exclusively borrowed. If other shared borrows (or no borrows) currently
     /// exist, this method will return an `Ok(`[`AtomicRef`]`)`.
     /// 
+    /// This also fails while a writer is waiting in
+    /// [`try_borrow_mut_blocking_new_readers`](AtomicRefCell::try_borrow_mut_blocking_new_readers).
+    /// 
     /// # Panics
-    /// If the resulting borrow count would become equal to [`isize::MAX`].
+    /// If the resulting borrow count would overflow.
     /// 
     /// # Examples
     /// ```rust
@@ -140,12 +358,57 @@ impl<T: ?Sized> AtomicRefCell<T> {
     /// drop(guard_mut);
     /// assert!(x.try_borrow().is_ok());
     /// ```
+    #[track_caller]
     pub fn try_borrow(&self) -> Result<AtomicRef<'_, T>, BorrowError> {
-        match self.borrows.fetch_update(Ordering::Acquire, Ordering::Relaxed, |value| {
-            if value == isize::MAX { panic!("AtomicRefCell borrow counter overflowed.") }
-            if value >= 0 { Some(value + 1) } else { None }
+        self.try_borrow_explicit(Ordering::Acquire, Ordering::Relaxed)
+    }
+    
+    /// [`try_borrow`](AtomicRefCell::try_borrow), but with the memory orderings of the update to
+    /// the borrow counter given explicitly, like [`AtomicIsize::compare_exchange`].
+    /// 
+    /// This is for when the cell is part of some bigger protocol that does its own memory
+    /// ordering reasoning (e.g: one that needs every borrow to be in the single total order of
+    /// `SeqCst` operations). `success` is used when the borrow is taken, and `failure` is used
+    /// for the load when it isn't.
+    /// 
+    /// NOTE: dropping the guard still releases the borrow with [`Release`](Ordering::Release).
+    /// 
+    /// # Panics
+    /// If `success` doesn't include [`Acquire`](Ordering::Acquire), since then whatever the last
+    /// writer did to the value wouldn't have to be visible yet. Also, if `failure` is
+    /// [`Release`](Ordering::Release) or [`AcqRel`](Ordering::AcqRel), since those don't make
+    /// sense for a load. (just like [`AtomicIsize::compare_exchange`])
+    /// 
+    /// Also if the resulting borrow count would overflow.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// use std::sync::atomic::Ordering;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// assert_eq!(*x.try_borrow_explicit(Ordering::SeqCst, Ordering::SeqCst).unwrap(), 5);
+    /// ```
+    /// 
+    /// ```rust,should_panic
+    /// use lockfree::cell::AtomicRefCell;
+    /// use std::sync::atomic::Ordering;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let _ = x.try_borrow_explicit(Ordering::Relaxed, Ordering::Relaxed);
+    /// ```
+    /// 
+    /// [`AtomicIsize::compare_exchange`]: core::sync::atomic::AtomicIsize::compare_exchange
+    #[track_caller]
+    pub fn try_borrow_explicit(&self, success: Ordering, failure: Ordering) -> Result<AtomicRef<'_, T>, BorrowError> {
+        check_borrow_orderings(success, failure);
+        match self.borrows.fetch_update(success, failure, |value| {
+            if value >= 0 && value & WRITE_PENDING == 0 && value != MAX_SHARED_BORROWS { Some(value + 1) } else { None }
         }) {
-            Ok(_) => Ok(AtomicRef { inner: self, _phantom: PhantomData }),
+            Ok(_) => Ok(AtomicRef::new(self)),
+            // NOTE: this panics out here instead of in the closure, so that it points at the caller
+            Err(MAX_SHARED_BORROWS) => panic!("AtomicRefCell borrow counter overflowed."),
+            Err(value) if value > 0 => Err(BorrowError::WritePending),
             Err(_) => Err(BorrowError::BorrowedExclusive)
         }
     }
@@ -176,10 +439,40 @@ impl<T: ?Sized> AtomicRefCell<T> {
     /// assert!(x.try_borrow_mut().is_ok());
     /// ```
     pub fn try_borrow_mut(&self) -> Result<AtomicRefMut<'_, T>, BorrowError> {
-        match self.borrows.compare_exchange(0, -1, Ordering::Acquire, Ordering::Relaxed) {
-            Ok(_) => Ok(AtomicRefMut{ inner: self, _phantom: PhantomData }),
+        self.try_borrow_mut_explicit(Ordering::Acquire, Ordering::Relaxed)
+    }
+    
+    /// [`try_borrow_mut`](AtomicRefCell::try_borrow_mut), with explicit memory orderings.
+    /// 
+    /// The orderings work just like in [`try_borrow_explicit`](AtomicRefCell::try_borrow_explicit),
+    /// and are checked the same way.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// use std::sync::atomic::Ordering;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// *x.try_borrow_mut_explicit(Ordering::AcqRel, Ordering::Acquire).unwrap() += 1;
+    /// assert_eq!(x.into_inner(), 6);
+    /// ```
+    /// 
+    /// ```rust,should_panic
+    /// use lockfree::cell::AtomicRefCell;
+    /// use std::sync::atomic::Ordering;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let _ = x.try_borrow_mut_explicit(Ordering::SeqCst, Ordering::Release);
+    /// ```
+    #[track_caller]
+    pub fn try_borrow_mut_explicit(&self, success: Ordering, failure: Ordering) -> Result<AtomicRefMut<'_, T>, BorrowError> {
+        check_borrow_orderings(success, failure);
+        match self.borrows.compare_exchange(0, -1, success, failure) {
+            Ok(_) => Ok(AtomicRefMut::new(self)),
             Err(_num_borrows) => {
-                if _num_borrows > 0 {
+                if _num_borrows > 0 && _num_borrows & WRITE_PENDING != 0 {
+                    Err(BorrowError::WritePending)
+                } else if _num_borrows > 0 {
                     Err(BorrowError::BorrowedShared)
                 } else {
                     Err(BorrowError::BorrowedExclusive)
@@ -187,6 +480,326 @@ impl<T: ?Sized> AtomicRefCell<T> {
             },
         }
     }
+    
+    /// Acquires exclusive access to the [`AtomicRefCell`], without letting new readers starve it.
+    /// 
+    /// Unlike [`try_borrow_mut`](AtomicRefCell::try_borrow_mut), this doesn't
+    /// fail if there are shared borrows. Instead, it marks the cell as "write
+    /// pending", which makes every new [`try_borrow`](AtomicRefCell::try_borrow)
+    /// fail with [`BorrowError::WritePending`], and then spins until all of
+    /// the existing shared borrows are dropped.
+    /// 
+    /// Existing [`AtomicRef`]s can still be cloned while the writer is waiting, since
+    /// [`Clone`] can't fail. Readers that keep handing off their borrow to a clone should use
+    /// [`AtomicRef::try_clone`] instead, or else the writer might never get a turn.
+    /// 
+    /// This fails immediately if the cell is already exclusively borrowed, or
+    /// if another writer is already waiting.
+    /// 
+    /// NOTE: if the current thread holds a shared borrow of this cell, this will spin forever.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowError};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// *x.try_borrow_mut_blocking_new_readers().unwrap() += 1;
+    /// 
+    /// let guard = x.try_borrow_mut().unwrap();
+    /// assert!(matches!(x.try_borrow_mut_blocking_new_readers(), Err(BorrowError::BorrowedExclusive)));
+    /// drop(guard);
+    /// assert_eq!(x.into_inner(), 6);
+    /// ```
+    pub fn try_borrow_mut_blocking_new_readers(&self) -> Result<AtomicRefMut<'_, T>, BorrowError> {
+        // reserve the write intent, so no new readers can get in
+        if let Err(value) = self.borrows.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            if value >= 0 && value & WRITE_PENDING == 0 { Some(value | WRITE_PENDING) } else { None }
+        }) {
+            return Err(if value > 0 { BorrowError::WritePending } else { BorrowError::BorrowedExclusive })
+        }
+        
+        // wait for the existing readers to drain
+        while self.borrows.compare_exchange_weak(WRITE_PENDING, -1, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        
+        Ok(AtomicRefMut::new(self))
+    }
+    
+    /// Acquires shared access to the [`AtomicRefCell`], blocking the current thread until it can.
+    /// 
+    /// This has the same name and blocking behavior as [`RwLock::read`], so that an
+    /// [`AtomicRefCell`] can be swapped in for one, except that there's no poisoning to
+    /// deal with. While waiting, the thread spins, yielding to the OS between attempts.
+    /// 
+    /// Like [`try_borrow`](AtomicRefCell::try_borrow), this waits for any pending writer
+    /// to go first.
+    /// 
+    /// NOTE: if the current thread holds an exclusive borrow of this cell, this will block forever.
+    /// 
+    /// [`RwLock::read`]: std::sync::RwLock::read
+    /// 
+    /// # Panics
+    /// If the resulting borrow count would overflow.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let a = x.read();
+    /// let b = x.read();
+    /// assert_eq!(*a + *b, 10);
+    /// ```
+    #[cfg(feature = "std")]
+    #[track_caller]
+    pub fn read(&self) -> AtomicRef<'_, T> {
+        loop {
+            match self.try_borrow() {
+                Ok(guard) => return guard,
+                Err(_) => relax(),
+            }
+        }
+    }
+    
+    /// Acquires shared access to the [`AtomicRefCell`], reusing the current thread's borrow if it already has one.
+    /// 
+    /// Only the outermost `borrow_recursive` on each thread actually borrows the cell. The nested
+    /// ones just bump a thread-local depth counter, and the borrow is released once the last of
+    /// them is dropped. So a recursive visitor over a shared graph doesn't touch the shared counter
+    /// every time it comes back around to the same cell, and a nested borrow can't fail just
+    /// because a writer started waiting in the middle of the recursion (which [`try_borrow`] would,
+    /// leaving the visitor stuck halfway through).
+    /// 
+    /// Borrows made through [`try_borrow`] don't count, so the outermost one still fails if the
+    /// cell is exclusively borrowed or a writer is waiting.
+    /// 
+    /// [`try_borrow`]: AtomicRefCell::try_borrow
+    /// 
+    /// # Panics
+    /// If the outermost borrow would overflow the borrow counter.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowState, ReentrantBorrow};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let a = x.borrow_recursive().unwrap();
+    /// let b = x.borrow_recursive().unwrap();
+    /// assert_eq!(*a + *b, 10);
+    /// assert_eq!(ReentrantBorrow::depth(&b), 2);
+    /// assert_eq!(x.borrow_state(), BorrowState::Shared(1));
+    /// drop((a, b));
+    /// assert_eq!(x.borrow_state(), BorrowState::Unborrowed);
+    /// ```
+    #[cfg(feature = "std")]
+    #[track_caller]
+    pub fn borrow_recursive(&self) -> Result<ReentrantBorrow<'_, T>, BorrowError> {
+        let key = core::ptr::from_ref(&self.borrows).addr();
+        let generation = self.generation();
+        let nested = RECURSIVE_BORROWS.with_borrow_mut(|depths| {
+            let i = depths.iter().position(|&(k, _, _)| k == key)?;
+            if depths[i].1 != generation {
+                // left over from a leaked borrow, which doesn't hold the cell anymore
+                depths.swap_remove(i);
+                return None
+            }
+            depths[i].2 += 1;
+            Some(depths[i].2)
+        });
+        let depth = match nested {
+            Some(depth) => depth,
+            None => {
+                // NOTE: this is done out here instead of in the closure, so that overflow panics point at the caller
+                // the last `ReentrantBorrow` to be dropped releases this instead
+                core::mem::forget(self.try_borrow()?);
+                RECURSIVE_BORROWS.with_borrow_mut(|depths| depths.push((key, generation, 1)));
+                1
+            }
+        };
+        
+        // SAFETY: `UnsafeCell::get` never returns null
+        let value = unsafe { NonNull::new_unchecked(self.value.get()) };
+        Ok(ReentrantBorrow { value, borrows: &self.borrows, generation, depth, _phantom: PhantomData })
+    }
+    
+    /// The cell's generation (see [`RECURSIVE_BORROWS`]), giving it a new one if it doesn't have one yet.
+    /// 
+    /// NOTE: new cells don't get one right away, since `new` is `const`, and most cells are never borrowed recursively.
+    #[cfg(feature = "std")]
+    fn generation(&self) -> usize {
+        let generation = self.generation.load(Ordering::Relaxed);
+        if generation != 0 { return generation }
+        let new = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        match self.generation.compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => new,
+            Err(generation) => generation,
+        }
+    }
+    
+    /// Acquires exclusive access to the [`AtomicRefCell`], blocking the current thread until it can.
+    /// 
+    /// This has the same name and blocking behavior as [`RwLock::write`], so that an
+    /// [`AtomicRefCell`] can be swapped in for one, except that there's no poisoning to
+    /// deal with. While waiting, the thread spins, yielding to the OS between attempts.
+    /// 
+    /// This goes through [`try_borrow_mut_blocking_new_readers`](AtomicRefCell::try_borrow_mut_blocking_new_readers),
+    /// so a steady stream of readers can't keep it waiting forever.
+    /// 
+    /// NOTE: if the current thread holds any borrow of this cell, this will block forever.
+    /// 
+    /// [`RwLock::write`]: std::sync::RwLock::write
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// *x.write() += 1;
+    /// assert_eq!(*x.read(), 6);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write(&self) -> AtomicRefMut<'_, T> {
+        loop {
+            match self.try_borrow_mut_blocking_new_readers() {
+                Ok(guard) => return guard,
+                Err(_) => relax(),
+            }
+        }
+    }
+    
+    /// Exclusively borrows the [`AtomicRefCell`] just long enough to call `f` on the inner value.
+    /// 
+    /// Like [`try_borrow_mut`](AtomicRefCell::try_borrow_mut), this fails if any other borrows exist.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// 
+    /// let x = AtomicRefCell::new(vec![1, 2]);
+    /// x.update(|v| v.push(3)).unwrap();
+    /// assert_eq!(*x.try_borrow().unwrap(), [1, 2, 3]);
+    /// ```
+    /// 
+    /// ```rust
+    /// use lockfree::cell::AtomicRefCell;
+    /// 
+    /// let x = AtomicRefCell::new(vec![1, 2]);
+    /// let guard = x.try_borrow().unwrap();
+    /// assert!(x.update(|v| v.push(3)).is_err());
+    /// drop(guard);
+    /// assert_eq!(x.into_inner(), [1, 2]);
+    /// ```
+    pub fn update<F>(&self, f: F) -> Result<(), BorrowError>
+    where
+        F : FnOnce(&mut T)
+    {
+        self.update_returning(f)
+    }
+    
+    /// Same as [`update`](AtomicRefCell::update), but returns whatever `f` returns.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowError};
+    /// 
+    /// let x = AtomicRefCell::new(vec![1, 2, 3]);
+    /// assert_eq!(x.update_returning(|v| v.pop()).unwrap(), Some(3));
+    /// 
+    /// let guard = x.try_borrow_mut().unwrap();
+    /// assert!(matches!(x.update_returning(|v| v.pop()), Err(BorrowError::BorrowedExclusive)));
+    /// drop(guard);
+    /// assert_eq!(x.into_inner(), [1, 2]);
+    /// ```
+    pub fn update_returning<R, F>(&self, f: F) -> Result<R, BorrowError>
+    where
+        F : FnOnce(&mut T) -> R
+    {
+        let mut guard = self.try_borrow_mut()?;
+        Ok(f(&mut guard))
+    }
+    
+    /// Atomically moves the borrow counter from `from` to `to`, returning a guard for the new borrow.
+    /// 
+    /// This is the low-level operation underlying [`try_borrow`] and
+    /// [`try_borrow_mut`], and is meant for building custom synchronization
+    /// on top of an [`AtomicRefCell`]. If the cell is not in the `from` state,
+    /// this method fails and returns the state that was actually observed.
+    /// 
+    /// The only legal transitions are ones that create exactly one new borrow:
+    ///  - [`Unborrowed`] to [`Exclusive`], which returns an [`AtomicRefMut`]
+    ///  - [`Unborrowed`] to [`Shared(1)`], which returns an [`AtomicRef`]
+    ///  - [`Shared(n)`] to [`Shared(n + 1)`], which returns an [`AtomicRef`]
+    /// 
+    /// [`try_borrow`]: AtomicRefCell::try_borrow
+    /// [`try_borrow_mut`]: AtomicRefCell::try_borrow_mut
+    /// [`Unborrowed`]: BorrowState::Unborrowed
+    /// [`Exclusive`]: BorrowState::Exclusive
+    /// [`Shared(1)`]: BorrowState::Shared
+    /// [`Shared(n)`]: BorrowState::Shared
+    /// [`Shared(n + 1)`]: BorrowState::Shared
+    /// 
+    /// # Panics
+    /// If the transition from `from` to `to` is not one of the legal transitions listed above.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowGuard, BorrowState};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let Ok(BorrowGuard::Exclusive(mut guard)) = x.try_transition(BorrowState::Unborrowed, BorrowState::Exclusive) else { panic!() };
+    /// *guard += 1;
+    /// assert_eq!(x.borrow_state(), BorrowState::Exclusive);
+    /// drop(guard);
+    /// assert_eq!(x.borrow_state(), BorrowState::Unborrowed);
+    /// assert_eq!(*x.try_borrow().unwrap(), 6);
+    /// ```
+    /// 
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowGuard, BorrowState};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let Ok(BorrowGuard::Shared(a)) = x.try_transition(BorrowState::Unborrowed, BorrowState::Shared(1)) else { panic!() };
+    /// let Ok(BorrowGuard::Shared(b)) = x.try_transition(BorrowState::Shared(1), BorrowState::Shared(2)) else { panic!() };
+    /// assert_eq!(*a + *b, 10);
+    /// assert_eq!(x.borrow_state(), BorrowState::Shared(2));
+    /// drop((a, b));
+    /// assert_eq!(x.borrow_state(), BorrowState::Unborrowed);
+    /// ```
+    /// 
+    /// ```rust
+    /// use lockfree::cell::{AtomicRefCell, BorrowState};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let guard = x.try_borrow().unwrap();
+    /// assert_eq!(x.try_transition(BorrowState::Unborrowed, BorrowState::Exclusive).err(), Some(BorrowState::Shared(1)));
+    /// assert_eq!(x.try_transition(BorrowState::Shared(2), BorrowState::Shared(3)).err(), Some(BorrowState::Shared(1)));
+    /// ```
+    /// 
+    /// ```rust,should_panic
+    /// use lockfree::cell::{AtomicRefCell, BorrowState};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let _ = x.try_transition(BorrowState::Exclusive, BorrowState::Unborrowed);
+    /// ```
+    #[track_caller]
+    pub fn try_transition(&self, from: BorrowState, to: BorrowState) -> Result<BorrowGuard<'_, T>, BorrowState> {
+        let is_legal = match (from, to) {
+            (BorrowState::Unborrowed, BorrowState::Exclusive) => true,
+            (BorrowState::Unborrowed, BorrowState::Shared(1)) => true,
+            (BorrowState::Shared(n), BorrowState::Shared(m)) => n >= 1 && Some(m) == n.checked_add(1),
+            _ => false,
+        };
+        if !is_legal { panic!("Invalid AtomicRefCell borrow transition from {from:?} to {to:?}") }
+        
+        match self.borrows.compare_exchange(from.encode(), to.encode(), Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Ok(match to {
+                BorrowState::Exclusive => BorrowGuard::Exclusive(AtomicRefMut::new(self)),
+                _ => BorrowGuard::Shared(AtomicRef::new(self)),
+            }),
+            Err(actual) => Err(BorrowState::decode(actual))
+        }
+    }
 }
 
 #[derive(core::fmt::Debug)]
@@ -195,37 +808,190 @@ pub enum BorrowError {
     BorrowedShared,
     /// Attempted to borrow an [`AtomicRefCell`] while an exclusive reference to it already existed.
     BorrowedExclusive,
+    /// Attempted to borrow an [`AtomicRefCell`] while a writer was waiting for the shared references to it to be dropped.
+    WritePending,
+}
+
+/// The state of an [`AtomicRefCell`]'s borrow counter.
+#[derive(core::fmt::Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorrowState {
+    /// No borrows currently exist.
+    Unborrowed,
+    /// The given (nonzero) number of [`AtomicRef`]s currently exist.
+    Shared(usize),
+    /// An [`AtomicRefMut`] currently exists.
+    Exclusive,
+    /// A writer is waiting for the given number of [`AtomicRef`]s to be dropped.
+    /// 
+    /// See [`AtomicRefCell::try_borrow_mut_blocking_new_readers`].
+    WritePending(usize),
+}
+
+impl BorrowState {
+    fn decode(borrows: isize) -> Self {
+        match borrows {
+            0 => BorrowState::Unborrowed,
+            n if n > 0 && n & WRITE_PENDING != 0 => BorrowState::WritePending((n & !WRITE_PENDING) as usize),
+            n if n > 0 => BorrowState::Shared(n as usize),
+            _ => BorrowState::Exclusive,
+        }
+    }
+    
+    #[track_caller]
+    fn encode(self) -> isize {
+        match self {
+            BorrowState::Unborrowed => 0,
+            BorrowState::Shared(n) => isize::try_from(n).ok().filter(|&n| n < MAX_SHARED_BORROWS).expect("AtomicRefCell borrow counter overflowed."),
+            BorrowState::Exclusive => -1,
+            BorrowState::WritePending(n) => WRITE_PENDING | isize::try_from(n).ok().filter(|&n| n <= MAX_SHARED_BORROWS).expect("AtomicRefCell borrow counter overflowed."),
+        }
+    }
+}
+
+/// A guard returned by [`AtomicRefCell::try_transition`].
+pub enum BorrowGuard<'b, T: ?Sized> {
+    /// The transition ended in [`BorrowState::Shared`].
+    Shared(AtomicRef<'b, T>),
+    /// The transition ended in [`BorrowState::Exclusive`].
+    Exclusive(AtomicRefMut<'b, T>),
 }
 
 
 /// An RAII structure used to manage shared access to an [`AtomicRefCell`].
 pub struct AtomicRef<'b, T: ?Sized> {
-    inner: &'b AtomicRefCell<T>,
+    /// NOTE: this might only be part of the cell's value (see [`AtomicRef::filter_map`])
+    value: NonNull<T>,
+    borrows: &'b AtomicIsize,
     _phantom: PhantomData<&'b T>
 }
 
+// SAFETY: these are the same bounds as before the guard held a raw pointer (i.e: the same as
+//         for `&AtomicRefCell<T>`), since the guard can be used to release the borrow anywhere.
+unsafe impl<T: ?Sized + Send + Sync> Send for AtomicRef<'_, T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for AtomicRef<'_, T> {}
+
 impl<'b, T: ?Sized> AtomicRef<'b, T> {
+    /// Makes a guard for the whole value of `cell`, which has to already be counted as a shared borrow.
+    fn new(cell: &'b AtomicRefCell<T>) -> Self {
+        // SAFETY: `UnsafeCell::get` never returns null
+        let value = unsafe { NonNull::new_unchecked(cell.value.get()) };
+        AtomicRef { value, borrows: &cell.borrows, _phantom: PhantomData }
+    }
+    
     /// Attempt to upgrade this [`AtomicRef`] into an [`AtomicRefMut`] if able.
     /// 
     /// This can only succeed if this is the only Ref to this [`AtomicRefCell`].
     /// If any other references exist, it will return `Err(self)`.
     pub fn upgrade(value: Self) -> Result<AtomicRefMut<'b, T>, AtomicRef<'b, T>> {
-        match value.inner.borrows.compare_exchange(1, -1, Ordering::AcqRel, Ordering::Relaxed) {
-            Ok(_) => Ok(AtomicRefMut{ inner: value.inner, _phantom: PhantomData }),
+        match value.borrows.compare_exchange(1, -1, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => {
+                let (ptr, borrows) = (value.value, value.borrows);
+                // the shared borrow just turned into the exclusive one, so it can't be released
+                core::mem::forget(value);
+                Ok(AtomicRefMut { value: ptr, borrows, _phantom: PhantomData })
+            },
             Err(_) => Err(value)
         }
     }
+    
+    /// Makes another shared borrow of the same data, unless a writer is waiting.
+    /// 
+    /// [`clone`](Clone::clone) can't fail, so it lets new borrows in even while a writer is waiting in
+    /// [`try_borrow_mut_blocking_new_readers`](AtomicRefCell::try_borrow_mut_blocking_new_readers). That means
+    /// a reader that keeps cloning its guard and dropping the old one can keep the writer waiting forever,
+    /// since the borrow count never gets down to zero. This is the version of `clone` that turns away new
+    /// borrows just like [`try_borrow`](AtomicRefCell::try_borrow) does, so that the writer gets a turn.
+    /// 
+    /// This is an associated function, since `AtomicRef` derefs to `T`.
+    /// 
+    /// # Panics
+    /// If the resulting borrow count would overflow.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRef, AtomicRefCell};
+    /// 
+    /// let x = AtomicRefCell::new(5);
+    /// let a = x.try_borrow().unwrap();
+    /// let b = AtomicRef::try_clone(&a).unwrap();
+    /// assert_eq!(*a + *b, 10);
+    /// ```
+    #[track_caller]
+    pub fn try_clone(orig: &Self) -> Result<Self, BorrowError> {
+        match orig.borrows.fetch_update(Ordering::Acquire, Ordering::Relaxed, |value| {
+            if value & WRITE_PENDING == 0 && value != MAX_SHARED_BORROWS { Some(value + 1) } else { None }
+        }) {
+            Ok(_) => Ok(AtomicRef { value: orig.value, borrows: orig.borrows, _phantom: PhantomData }),
+            Err(MAX_SHARED_BORROWS) => panic!("AtomicRefCell borrow counter overflowed."),
+            Err(_) => Err(BorrowError::WritePending),
+        }
+    }
+    
+    /// Makes a new guard for a part of the borrowed data.
+    /// 
+    /// This is an associated function, since `AtomicRef` derefs to `T`. See [`Ref::map`](core::cell::Ref::map).
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRef, AtomicRefCell};
+    /// 
+    /// let x = AtomicRefCell::new((5, 'b'));
+    /// let guard = AtomicRef::map(x.try_borrow().unwrap(), |t| &t.1);
+    /// assert_eq!(*guard, 'b');
+    /// ```
+    pub fn map<U: ?Sized>(orig: Self, f: impl FnOnce(&T) -> &U) -> AtomicRef<'b, U> {
+        match Self::filter_map(orig, |value| Some(f(value))) {
+            Ok(guard) => guard,
+            Err(_) => unreachable!(),
+        }
+    }
+    
+    /// Makes a new guard for a part of the borrowed data, if `f` returns one.
+    /// 
+    /// If `f` returns `None`, the original guard is given back. This is useful for borrowing
+    /// into a particular enum variant, or the inside of an `Option`. See
+    /// [`Ref::filter_map`](core::cell::Ref::filter_map).
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::cell::{AtomicRef, AtomicRefCell};
+    /// 
+    /// let x = AtomicRefCell::new(Some(5));
+    /// let guard = AtomicRef::filter_map(x.try_borrow().unwrap(), Option::as_ref).unwrap_or_else(|_| panic!());
+    /// assert_eq!(*guard, 5);
+    /// ```
+    pub fn filter_map<U: ?Sized>(orig: Self, f: impl FnOnce(&T) -> Option<&U>) -> Result<AtomicRef<'b, U>, Self> {
+        let Some(value) = f(&orig).map(NonNull::from) else { return Err(orig) };
+        let borrows = orig.borrows;
+        // the borrow carries over to the new guard
+        core::mem::forget(orig);
+        Ok(AtomicRef { value, borrows, _phantom: PhantomData })
+    }
+    
+    /// Converts the guard into a reference to the data, without ever releasing the borrow.
+    /// 
+    /// The cell stays borrowed until [`AtomicRefCell::clear_leaked_borrows`] is called.
+    /// 
+    /// See [`Ref::leak`](core::cell::Ref::leak).
+    pub fn leak(value: Self) -> &'b T {
+        let ptr = value.value;
+        core::mem::forget(value);
+        // SAFETY: the borrow counter will never go back down, so nobody can mutate the value for `'b`
+        unsafe { ptr.as_ref() }
+    }
 }
 
 impl<T: ?Sized> Clone for AtomicRef<'_, T> {
+    #[track_caller]
     fn clone(&self) -> Self {
-        self.inner.borrows.
+        self.borrows.
             fetch_update(Ordering::Acquire, Ordering::Relaxed, |value| {
-                if value == isize::MAX || value < 0 { None }
+                // NOTE: this still works while a writer is pending, since we already have a shared borrow
+                if value & MAX_SHARED_BORROWS == MAX_SHARED_BORROWS || value < 0 { None }
                 else { Some(value + 1) }
             })
             .expect("AtomicRefCell borrow counter overflowed.");
-        AtomicRef { inner: self.inner, _phantom: PhantomData }
+        AtomicRef { value: self.value, borrows: self.borrows, _phantom: PhantomData }
     }
 }
 
@@ -233,46 +999,558 @@ impl<T: ?Sized> Deref for AtomicRef<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         // SAFETY: the existence of this type means that nobody can be mutating the value
-        unsafe { &*self.inner.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
 unsafe impl<T> DerefPure for AtomicRef<'_, T> {}
 
+impl<'b, T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<AtomicRef<'b, U>> for AtomicRef<'b, T> {}
+
 impl<T: ?Sized> Drop for AtomicRef<'_, T> {
     fn drop(&mut self) {
-        self.inner.borrows.fetch_sub(1, Ordering::Release);
+        self.borrows.fetch_sub(1, Ordering::Release);
     }
 }
 
 
 /// An RAII structure used to manage exclusive access to an [`AtomicRefCell`].
 pub struct AtomicRefMut<'b, T: ?Sized> {
-    inner: &'b AtomicRefCell<T>,
+    value: NonNull<T>,
+    borrows: &'b AtomicIsize,
     _phantom: PhantomData<&'b mut T>
 }
 
+// SAFETY: same as for `AtomicRef`
+unsafe impl<T: ?Sized + Send + Sync> Send for AtomicRefMut<'_, T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for AtomicRefMut<'_, T> {}
+
+impl<'b, T: ?Sized> AtomicRefMut<'b, T> {
+    /// Makes a guard for the whole value of `cell`, which has to already be exclusively borrowed.
+    fn new(cell: &'b AtomicRefCell<T>) -> Self {
+        // SAFETY: `UnsafeCell::get` never returns null
+        let value = unsafe { NonNull::new_unchecked(cell.value.get()) };
+        AtomicRefMut { value, borrows: &cell.borrows, _phantom: PhantomData }
+    }
+    
+    /// Converts the guard into a mutable reference to the data, without ever releasing the borrow.
+    /// 
+    /// The cell stays borrowed until [`AtomicRefCell::clear_leaked_borrows`] is called.
+    /// 
+    /// See [`RefMut::leak`](core::cell::RefMut::leak).
+    pub fn leak(value: Self) -> &'b mut T {
+        let mut ptr = value.value;
+        core::mem::forget(value);
+        // SAFETY: the borrow counter will stay at -1, so nobody else can access the value for `'b`
+        unsafe { ptr.as_mut() }
+    }
+}
+
 impl<T: ?Sized> Deref for AtomicRefMut<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.inner.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
 impl<T: ?Sized> DerefMut for AtomicRefMut<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // SAFETY: we know we have exclusive access while this type exists
-        unsafe { &mut *self.inner.value.get() }
+        unsafe { self.value.as_mut() }
     }
 }
 
 unsafe impl<T> DerefPure for AtomicRefMut<'_, T> {}
 
+impl<'b, T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<AtomicRefMut<'b, U>> for AtomicRefMut<'b, T> {}
+
 impl<T: ?Sized> Drop for AtomicRefMut<'_, T> {
     fn drop(&mut self) {
         // NOTE: if compare_exchange does not give -1, something went horribly wrong.
-        self.inner.borrows
+        self.borrows
             .compare_exchange(-1, 0, Ordering::Release, Ordering::Relaxed)
             .expect("Borrow counter should be set to -1 for the entire lifetime of the `AtomicRefMut`.");
     }
 }
+
+
+/// A shared borrow of an [`AtomicRefCell`] made by [`borrow_recursive`](AtomicRefCell::borrow_recursive).
+/// 
+/// The depth is only tracked for the current thread, so unlike an [`AtomicRef`], this can't be sent to another thread.
+#[cfg(feature = "std")]
+pub struct ReentrantBorrow<'b, T: ?Sized> {
+    value: NonNull<T>,
+    borrows: &'b AtomicIsize,
+    generation: usize,
+    depth: usize,
+    _phantom: PhantomData<(&'b T, *const ())>
+}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> ReentrantBorrow<'_, T> {
+    /// How many recursive borrows of the cell the current thread had when this one was made, including itself.
+    /// 
+    /// This is `1` for the outermost one. This is an associated function, since `ReentrantBorrow` derefs to `T`.
+    pub fn depth(orig: &Self) -> usize {
+        orig.depth
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> Deref for ReentrantBorrow<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the cell stays borrowed until the last one of these on this thread is dropped
+        unsafe { self.value.as_ref() }
+    }
+}
+
+#[cfg(feature = "std")]
+unsafe impl<T> DerefPure for ReentrantBorrow<'_, T> {}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> Drop for ReentrantBorrow<'_, T> {
+    fn drop(&mut self) {
+        let key = core::ptr::from_ref(self.borrows).addr();
+        let was_last = RECURSIVE_BORROWS.with_borrow_mut(|depths| {
+            let i = depths.iter().position(|&(k, g, _)| (k, g) == (key, self.generation)).expect("the cell should be borrowed recursively on this thread");
+            depths[i].2 -= 1;
+            if depths[i].2 == 0 { depths.swap_remove(i); true } else { false }
+        });
+        if was_last {
+            self.borrows.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicBool;
+    
+    /// Makes sure new readers are turned away while a writer is waiting on an existing one
+    #[test]
+    fn test_write_pending_blocks_readers() {
+        let cell = AtomicRefCell::new(0);
+        let reader = cell.try_borrow().unwrap();
+        
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| *cell.try_borrow_mut_blocking_new_readers().unwrap() += 1);
+            
+            while cell.borrow_state() != BorrowState::WritePending(1) {
+                core::hint::spin_loop();
+            }
+            // the waiting writer doesn't count as a borrow yet
+            assert_eq!(cell.active_borrows(), 1);
+            assert!(matches!(cell.try_borrow(), Err(BorrowError::WritePending)));
+            assert!(matches!(cell.try_borrow_mut(), Err(BorrowError::WritePending)));
+            assert!(matches!(cell.try_borrow_mut_blocking_new_readers(), Err(BorrowError::WritePending)));
+            assert_eq!(*reader.clone(), 0);
+            
+            drop(reader);
+            writer.join().unwrap();
+        });
+        
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(cell.into_inner(), 1);
+    }
+    
+    /// Has a bunch of readers continuously borrowing the cell, and makes sure a writer still gets through
+    #[test]
+    fn test_writer_not_starved() {
+        const WRITES: usize = 100;
+        
+        let cell = AtomicRefCell::new(0);
+        let done = AtomicBool::new(false);
+        
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        if let Ok(guard) = cell.try_borrow() {
+                            // hold on to it for a bit, so the readers overlap
+                            for _ in 0..100 { core::hint::black_box(*guard); }
+                        }
+                    }
+                });
+            }
+            
+            for _ in 0..WRITES {
+                *cell.try_borrow_mut_blocking_new_readers().unwrap() += 1;
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        
+        assert_eq!(cell.into_inner(), WRITES);
+    }
+    
+    /// Has readers that keep replacing their guards with clones of them, and makes sure a writer still gets through
+    #[test]
+    fn test_writer_not_starved_by_clones() {
+        const READERS: usize = 4;
+        const WRITES: usize = 100;
+        
+        let cell = AtomicRefCell::new(0);
+        let done = AtomicBool::new(false);
+        
+        std::thread::scope(|s| {
+            for _ in 0..READERS {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let Ok(mut guard) = cell.try_borrow() else { continue };
+                        // the count never hits zero while this goes, unless the clones get turned away
+                        while let Ok(clone) = AtomicRef::try_clone(&guard) {
+                            core::hint::black_box(*clone);
+                            guard = clone;
+                        }
+                    }
+                });
+            }
+            
+            for _ in 0..WRITES {
+                *cell.try_borrow_mut_blocking_new_readers().unwrap() += 1;
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(cell.into_inner(), WRITES);
+    }
+    
+    /// Recurses through a cell while a writer is waiting on it, and makes sure the nested borrows still get through
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_borrow_recursive() {
+        const DEPTH: usize = 1000;
+        
+        fn visit(cell: &AtomicRefCell<usize>, depth: usize) -> usize {
+            let guard = cell.borrow_recursive().unwrap();
+            assert_eq!(ReentrantBorrow::depth(&guard), depth);
+            // only the outermost borrow is counted in the cell
+            assert_eq!(cell.borrow_state(), BorrowState::WritePending(1));
+            if depth == DEPTH { *guard } else { *guard + visit(cell, depth + 1) }
+        }
+        
+        let cell = AtomicRefCell::new(1);
+        let outer = cell.borrow_recursive().unwrap();
+        
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| *cell.write() += 1);
+            
+            while cell.borrow_state() != BorrowState::WritePending(1) {
+                core::hint::spin_loop();
+            }
+            assert!(matches!(cell.try_borrow(), Err(BorrowError::WritePending)));
+            assert_eq!(visit(&cell, 2) + *outer, DEPTH);
+            assert!(!writer.is_finished());
+            
+            drop(outer);
+            writer.join().unwrap();
+        });
+        
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        
+        // another thread's recursive borrow doesn't count as this thread's
+        let guard = cell.borrow_recursive().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let guard = cell.borrow_recursive().unwrap();
+                assert_eq!(ReentrantBorrow::depth(&guard), 1);
+                assert_eq!(cell.borrow_state(), BorrowState::Shared(2));
+            });
+        });
+        assert_eq!(*guard, 2);
+        drop(guard);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+    
+    /// Leaks recursive borrows, and makes sure their leftover entries don't hand out unborrowed references
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_borrow_recursive_leaked() {
+        let mut cell = AtomicRefCell::new(1);
+        core::mem::forget(cell.borrow_recursive().unwrap());
+        assert_eq!(cell.borrow_state(), BorrowState::Shared(1));
+        
+        // once the leaked borrow is cleared, the next one has to actually borrow the cell again
+        cell.clear_leaked_borrows();
+        let guard = cell.borrow_recursive().unwrap();
+        assert_eq!(ReentrantBorrow::depth(&guard), 1);
+        assert_eq!(cell.borrow_state(), BorrowState::Shared(1));
+        std::thread::scope(|s| {
+            s.spawn(|| assert!(cell.try_borrow_mut().is_err()));
+        });
+        drop(guard);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        
+        // same thing for a new cell at the same address
+        core::mem::forget(cell.borrow_recursive().unwrap());
+        cell = AtomicRefCell::new(2);
+        let guard = cell.borrow_recursive().unwrap();
+        assert_eq!(ReentrantBorrow::depth(&guard), 1);
+        assert_eq!(cell.borrow_state(), BorrowState::Shared(1));
+        std::thread::scope(|s| {
+            s.spawn(|| assert!(cell.try_borrow_mut().is_err()));
+        });
+        drop(guard);
+        assert_eq!(cell.into_inner(), 2);
+    }
+    
+    /// Has readers and a writer fighting over the cell with the blocking methods
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_read_write_contended() {
+        const READERS: usize = 4;
+        const WRITES: usize = 1000;
+        
+        let cell = AtomicRefCell::new((0, 0));
+        let done = AtomicBool::new(false);
+        
+        std::thread::scope(|s| {
+            let readers: Vec<_> = (0..READERS).map(|_| s.spawn(|| {
+                let mut last = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let guard = cell.read();
+                    // the writer always updates both at once, so they should never be seen torn
+                    assert_eq!(guard.0, guard.1);
+                    assert!(guard.0 >= last);
+                    last = guard.0;
+                }
+            })).collect();
+            
+            for _ in 0..WRITES {
+                let mut guard = cell.write();
+                guard.0 += 1;
+                std::thread::yield_now();
+                guard.1 += 1;
+            }
+            done.store(true, Ordering::Relaxed);
+            
+            readers.into_iter().for_each(|reader| reader.join().unwrap());
+        });
+        
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(cell.into_inner(), (WRITES, WRITES));
+    }
+    
+    #[test]
+    fn test_filter_map() {
+        #[derive(Debug)]
+        enum Shape { Circle(f64), Square(f64) }
+        
+        let cell = AtomicRefCell::new([Shape::Circle(1.0), Shape::Square(2.0)]);
+        let guard = cell.try_borrow().unwrap();
+        
+        let Ok(radius) = AtomicRef::filter_map(guard, |shapes| match &shapes[0] {
+            Shape::Circle(radius) => Some(radius),
+            _ => None,
+        }) else { panic!("should have been a circle") };
+        assert_eq!(*radius, 1.0);
+        
+        // the borrow carried over to the new guard
+        assert_eq!(cell.borrow_state(), BorrowState::Shared(1));
+        assert!(cell.try_borrow_mut().is_err());
+        drop(radius);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+    
+    #[test]
+    fn test_filter_map_none() {
+        let cell = AtomicRefCell::new((None::<i32>, 5));
+        let guard = cell.try_borrow().unwrap();
+        
+        // the original guard should come back, still borrowing the whole thing
+        let Err(guard) = AtomicRef::filter_map(guard, |(x, _)| x.as_ref()) else { panic!("should have been `None`") };
+        assert_eq!(guard.1, 5);
+        assert_eq!(cell.borrow_state(), BorrowState::Shared(1));
+        
+        // and it should still be upgradable afterwards
+        let Ok(mut guard) = AtomicRef::upgrade(guard) else { panic!("should be the only borrow") };
+        guard.0 = Some(1);
+        drop(guard);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(cell.into_inner(), (Some(1), 5));
+    }
+    
+    #[test]
+    fn test_new_exclusive() {
+        let (cell, state) = AtomicRefCell::new_exclusive((0, 0), |mut guard| {
+            guard.0 = 1;
+            guard.1 = 2;
+            // the cell isn't reachable from in here, but the guard should count as a borrow all the same
+            guard.borrows.load(Ordering::Relaxed)
+        });
+        assert_eq!(BorrowState::decode(state), BorrowState::Exclusive);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(*cell.try_borrow().unwrap(), (1, 2));
+        
+        // a leaked guard should leave the cell borrowed, like anywhere else
+        let (mut cell, ()) = AtomicRefCell::new_exclusive(5, |guard| *AtomicRefMut::leak(guard) += 1);
+        assert_eq!(cell.borrow_state(), BorrowState::Exclusive);
+        assert!(cell.try_borrow().is_err());
+        cell.clear_leaked_borrows();
+        assert_eq!(cell.into_inner(), 6);
+    }
+    
+    #[test]
+    fn test_leak() {
+        let mut cell = AtomicRefCell::new(vec![1, 2, 3]);
+        
+        let leaked = AtomicRefMut::leak(cell.try_borrow_mut().unwrap());
+        leaked.push(4);
+        assert!(cell.try_borrow().is_err());
+        assert!(cell.try_borrow_mut().is_err());
+        
+        cell.clear_leaked_borrows();
+        assert_eq!(*cell.try_borrow().unwrap(), [1, 2, 3, 4]);
+        
+        let leaked = AtomicRef::leak(cell.try_borrow().unwrap());
+        assert_eq!(leaked.len(), 4);
+        assert!(cell.try_borrow().is_ok());
+        assert!(cell.try_borrow_mut().is_err());
+        
+        cell.clear_leaked_borrows();
+        cell.try_borrow_mut().unwrap().push(5);
+        assert_eq!(cell.into_inner(), [1, 2, 3, 4, 5]);
+    }
+    
+    /// Makes sure overflow panics point at the code that borrowed, and not at this file's internals
+    #[test]
+    fn test_overflow_panic_location() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::cell::Cell;
+        
+        std::thread_local! {
+            static PANIC_LINE: Cell<Option<u32>> = const { Cell::new(None) };
+        }
+        
+        /// Runs `f`, and returns the line number that it panicked at
+        fn panic_line(f: impl FnOnce()) -> u32 {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(|info| {
+                PANIC_LINE.set(info.location().map(|location| location.line()));
+            }));
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            panic::set_hook(previous);
+            
+            assert!(result.is_err(), "should have panicked");
+            PANIC_LINE.take().expect("the hook should have run")
+        }
+        
+        let cell = AtomicRefCell::new(5);
+        let guard = cell.try_borrow().unwrap();
+        cell.borrows.store(MAX_SHARED_BORROWS, Ordering::Relaxed);
+        
+        let line = line!(); let panicked = panic_line(|| drop(cell.try_borrow()));
+        assert_eq!(panicked, line);
+        let line = line!(); let panicked = panic_line(|| drop(guard.clone()));
+        assert_eq!(panicked, line);
+        
+        cell.borrows.store(1, Ordering::Relaxed);
+        drop(guard);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+    
+    #[test]
+    fn test_explicit_orderings() {
+        use std::panic::AssertUnwindSafe;
+        
+        let cell = AtomicRefCell::new(5);
+        {
+            let a = cell.try_borrow_explicit(Ordering::SeqCst, Ordering::SeqCst).unwrap();
+            let b = cell.try_borrow_explicit(Ordering::SeqCst, Ordering::SeqCst).unwrap();
+            assert_eq!(*a + *b, 10);
+            assert!(matches!(cell.try_borrow_mut_explicit(Ordering::SeqCst, Ordering::SeqCst), Err(BorrowError::BorrowedShared)));
+        }
+        {
+            let mut guard = cell.try_borrow_mut_explicit(Ordering::SeqCst, Ordering::SeqCst).unwrap();
+            *guard += 1;
+            assert!(matches!(cell.try_borrow_explicit(Ordering::SeqCst, Ordering::SeqCst), Err(BorrowError::BorrowedExclusive)));
+        }
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        
+        // a `SeqCst` borrow is still enough to see what the last writer did on another thread
+        let cell = AtomicRefCell::new(Vec::new());
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let cell = &cell;
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        loop {
+                            if let Ok(mut guard) = cell.try_borrow_mut_explicit(Ordering::SeqCst, Ordering::SeqCst) {
+                                guard.push(i);
+                                break
+                            }
+                            core::hint::spin_loop();
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(cell.try_borrow_explicit(Ordering::SeqCst, Ordering::SeqCst).unwrap().len(), 4000);
+        
+        let orderings = [Ordering::Relaxed, Ordering::Release, Ordering::Acquire, Ordering::AcqRel, Ordering::SeqCst];
+        for success in orderings {
+            for failure in orderings {
+                let valid = !matches!(success, Ordering::Relaxed | Ordering::Release) && !matches!(failure, Ordering::Release | Ordering::AcqRel);
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| drop(cell.try_borrow_explicit(success, failure))));
+                assert_eq!(result.is_ok(), valid, "try_borrow_explicit({success:?}, {failure:?})");
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| drop(cell.try_borrow_mut_explicit(success, failure))));
+                assert_eq!(result.is_ok(), valid, "try_borrow_mut_explicit({success:?}, {failure:?})");
+            }
+        }
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+    
+    trait Counter: Send + Sync {
+        fn get(&self) -> usize;
+        fn bump(&mut self);
+    }
+    
+    impl Counter for usize {
+        fn get(&self) -> usize { *self }
+        fn bump(&mut self) { *self += 1 }
+    }
+    
+    /// A counter that also keeps track of whether it got dropped (by holding onto an `Arc`)
+    struct DropTracked(std::sync::Arc<()>, usize);
+    impl Counter for DropTracked {
+        fn get(&self) -> usize { self.1 }
+        fn bump(&mut self) { self.1 += 1 }
+    }
+    
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_unsized() {
+        // through a coerced box
+        let cell: Box<AtomicRefCell<dyn Counter>> = Box::new(AtomicRefCell::new(5usize));
+        let (a, b) = (cell.try_borrow().unwrap(), cell.try_borrow().unwrap());
+        assert_eq!(a.get() + b.get(), 10);
+        assert_eq!(cell.borrow_state(), BorrowState::Shared(2));
+        assert!(cell.try_borrow_mut().is_err());
+        drop((a, b));
+        cell.try_borrow_mut().unwrap().bump();
+        assert_eq!(cell.try_borrow().unwrap().get(), 6);
+        
+        // through a box that was already unsized
+        let tracker = std::sync::Arc::new(());
+        let value: Box<dyn Counter> = Box::new(DropTracked(tracker.clone(), 1));
+        let cell = AtomicRefCell::from_box(value);
+        cell.try_borrow_mut().unwrap().bump();
+        assert_eq!(cell.try_borrow().unwrap().get(), 2);
+        assert_eq!(std::sync::Arc::strong_count(&tracker), 2);
+        drop(cell);
+        assert_eq!(std::sync::Arc::strong_count(&tracker), 1, "the value should be dropped exactly once");
+        
+        // the guards can be coerced too
+        let cell = AtomicRefCell::new(10usize);
+        let guard: AtomicRef<'_, dyn Counter> = cell.try_borrow().unwrap();
+        assert_eq!(guard.get(), 10);
+        drop(guard);
+        let mut guard: AtomicRefMut<'_, dyn Counter> = cell.try_borrow_mut().unwrap();
+        guard.bump();
+        drop(guard);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(cell.into_inner(), 11);
+    }
+}
@@ -0,0 +1,223 @@
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+
+#[cfg(all(target_has_atomic = "128", target_pointer_width = "64"))]
+use core::sync::atomic::AtomicU128;
+#[cfg(not(all(target_has_atomic = "128", target_pointer_width = "64")))]
+use crate::spinlock_mutex::Mutex;
+
+
+/// A raw pointer and a `usize` tag, which are always loaded, stored, and compared together.
+/// 
+/// This is the usual fix for the ABA problem in lock-free data structures: if every change to the
+/// pointer also bumps the tag (see [`compare_exchange_bump`](Self::compare_exchange_bump)), then a
+/// compare-exchange against an old `(pointer, tag)` pair fails even if the pointer has since been
+/// changed back to the same address (i.e: after being freed and reallocated).
+/// 
+/// Where 128-bit atomics are available (i.e: `cmpxchg16b` on x86-64), both halves are packed into
+/// a single [`AtomicU128`]. Otherwise, they're kept behind a spinlock, which isn't lock-free, but
+/// behaves the same.
+pub struct AtomicTaggedPtr<T> {
+    #[cfg(all(target_has_atomic = "128", target_pointer_width = "64"))]
+    inner: AtomicU128,
+    #[cfg(not(all(target_has_atomic = "128", target_pointer_width = "64")))]
+    inner: Mutex<(*mut T, usize)>,
+    _phantom: PhantomData<*mut T>,
+}
+
+// SAFETY: this is just a pointer, like `AtomicPtr<T>`, which is `Send + Sync` no matter what `T` is
+unsafe impl<T> Send for AtomicTaggedPtr<T> {}
+unsafe impl<T> Sync for AtomicTaggedPtr<T> {}
+
+#[cfg(all(target_has_atomic = "128", target_pointer_width = "64"))]
+impl<T> AtomicTaggedPtr<T> {
+    /// Packs the pointer into the low half, and the tag into the high half.
+    /// 
+    /// NOTE: the pointer's provenance gets exposed, so it can be gotten back in `unpack`.
+    fn pack((ptr, tag): (*mut T, usize)) -> u128 {
+        ptr.expose_provenance() as u128 | (tag as u128) << 64
+    }
+    
+    fn unpack(value: u128) -> (*mut T, usize) {
+        (core::ptr::with_exposed_provenance_mut(value as u64 as usize), (value >> 64) as usize)
+    }
+    
+    pub fn new(ptr: *mut T, tag: usize) -> Self {
+        Self { inner: AtomicU128::new(Self::pack((ptr, tag))), _phantom: PhantomData }
+    }
+    
+    /// Loads the pointer and its tag.
+    pub fn load(&self, order: Ordering) -> (*mut T, usize) {
+        Self::unpack(self.inner.load(order))
+    }
+    
+    /// Stores a new pointer and tag.
+    pub fn store(&self, ptr: *mut T, tag: usize, order: Ordering) {
+        self.inner.store(Self::pack((ptr, tag)), order)
+    }
+    
+    /// Stores a new pointer and tag, returning the old ones.
+    pub fn swap(&self, ptr: *mut T, tag: usize, order: Ordering) -> (*mut T, usize) {
+        Self::unpack(self.inner.swap(Self::pack((ptr, tag)), order))
+    }
+    
+    /// Stores `new` if the pointer *and* the tag are the same as `current`.
+    /// 
+    /// Like [`AtomicPtr::compare_exchange`](core::sync::atomic::AtomicPtr::compare_exchange), this
+    /// returns the previous pair, which is wrapped in `Ok` if it was replaced, and `Err` if it wasn't.
+    pub fn compare_exchange(&self, current: (*mut T, usize), new: (*mut T, usize), success: Ordering, failure: Ordering) -> Result<(*mut T, usize), (*mut T, usize)> {
+        self.inner.compare_exchange(Self::pack(current), Self::pack(new), success, failure)
+            .map(Self::unpack)
+            .map_err(Self::unpack)
+    }
+    
+    /// Same as [`compare_exchange`](Self::compare_exchange), but is allowed to spuriously fail.
+    pub fn compare_exchange_weak(&self, current: (*mut T, usize), new: (*mut T, usize), success: Ordering, failure: Ordering) -> Result<(*mut T, usize), (*mut T, usize)> {
+        self.inner.compare_exchange_weak(Self::pack(current), Self::pack(new), success, failure)
+            .map(Self::unpack)
+            .map_err(Self::unpack)
+    }
+    
+    pub fn get_mut(&mut self) -> (*mut T, usize) {
+        Self::unpack(*self.inner.get_mut())
+    }
+    
+    pub fn into_inner(self) -> (*mut T, usize) {
+        Self::unpack(self.inner.into_inner())
+    }
+}
+
+#[cfg(not(all(target_has_atomic = "128", target_pointer_width = "64")))]
+impl<T> AtomicTaggedPtr<T> {
+    // NOTE: the lock synchronizes everything anyways, so the orderings are just ignored here
+    
+    pub fn new(ptr: *mut T, tag: usize) -> Self {
+        Self { inner: Mutex::new((ptr, tag)), _phantom: PhantomData }
+    }
+    
+    /// Loads the pointer and its tag.
+    pub fn load(&self, _order: Ordering) -> (*mut T, usize) {
+        self.inner.with_lock(|pair| *pair)
+    }
+    
+    /// Stores a new pointer and tag.
+    pub fn store(&self, ptr: *mut T, tag: usize, _order: Ordering) {
+        self.inner.with_lock(|pair| *pair = (ptr, tag))
+    }
+    
+    /// Stores a new pointer and tag, returning the old ones.
+    pub fn swap(&self, ptr: *mut T, tag: usize, _order: Ordering) -> (*mut T, usize) {
+        self.inner.with_lock(|pair| core::mem::replace(pair, (ptr, tag)))
+    }
+    
+    /// Stores `new` if the pointer *and* the tag are the same as `current`.
+    /// 
+    /// Like [`AtomicPtr::compare_exchange`](core::sync::atomic::AtomicPtr::compare_exchange), this
+    /// returns the previous pair, which is wrapped in `Ok` if it was replaced, and `Err` if it wasn't.
+    pub fn compare_exchange(&self, current: (*mut T, usize), new: (*mut T, usize), _success: Ordering, _failure: Ordering) -> Result<(*mut T, usize), (*mut T, usize)> {
+        self.inner.with_lock(|pair| match *pair == current {
+            true => Ok(core::mem::replace(pair, new)),
+            false => Err(*pair),
+        })
+    }
+    
+    /// Same as [`compare_exchange`](Self::compare_exchange), but is allowed to spuriously fail.
+    pub fn compare_exchange_weak(&self, current: (*mut T, usize), new: (*mut T, usize), success: Ordering, failure: Ordering) -> Result<(*mut T, usize), (*mut T, usize)> {
+        self.compare_exchange(current, new, success, failure)
+    }
+    
+    pub fn get_mut(&mut self) -> (*mut T, usize) {
+        *self.inner.get_mut()
+    }
+    
+    pub fn into_inner(self) -> (*mut T, usize) {
+        self.inner.into_inner()
+    }
+}
+
+impl<T> AtomicTaggedPtr<T> {
+    /// Replaces the pointer with `new` if the pointer and tag are still `current`, and bumps the tag.
+    /// 
+    /// This is the ABA-safe way to change the pointer: since the tag goes up with every change,
+    /// anybody still holding `current` will fail their own compare-exchange, even if the pointer
+    /// eventually gets set back to `current.0`. The tag wraps around on overflow, but that would
+    /// take `usize::MAX` changes between a load and a compare-exchange to matter.
+    /// 
+    /// Returns the new tag on success, and the actual pair on failure.
+    pub fn compare_exchange_bump(&self, current: (*mut T, usize), new: *mut T, success: Ordering, failure: Ordering) -> Result<usize, (*mut T, usize)> {
+        let new_tag = current.1.wrapping_add(1);
+        self.compare_exchange(current, (new, new_tag), success, failure).map(|_| new_tag)
+    }
+}
+
+impl<T> Default for AtomicTaggedPtr<T> {
+    fn default() -> Self {
+        Self::new(core::ptr::null_mut(), 0)
+    }
+}
+
+impl<T> core::fmt::Debug for AtomicTaggedPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (ptr, tag) = self.load(Ordering::Acquire);
+        f.debug_struct("AtomicTaggedPtr").field("ptr", &ptr).field("tag", &tag).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_aba() {
+        let (mut a, mut b) = (1, 2);
+        let (a, b) = (&raw mut a, &raw mut b);
+        let cell = AtomicTaggedPtr::new(a, 0);
+        
+        // somebody reads the current value...
+        let seen = cell.load(Ordering::Acquire);
+        assert_eq!(seen, (a, 0));
+        
+        // ...then somebody else changes it away and back
+        let tag = cell.compare_exchange_bump(seen, b, Ordering::AcqRel, Ordering::Acquire).unwrap();
+        assert_eq!(tag, 1);
+        let tag = cell.compare_exchange_bump((b, tag), a, Ordering::AcqRel, Ordering::Acquire).unwrap();
+        assert_eq!(tag, 2);
+        
+        // the pointer is the same, but the tag isn't, so the stale compare-exchange has to fail
+        assert_eq!(cell.load(Ordering::Acquire).0, seen.0);
+        assert_eq!(cell.compare_exchange_bump(seen, b, Ordering::AcqRel, Ordering::Acquire), Err((a, 2)));
+        assert_eq!(cell.into_inner(), (a, 2));
+    }
+    
+    #[test]
+    fn test_bump_concurrent() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 10_000;
+        
+        let mut values = [0usize; THREADS];
+        let ptrs: [*mut usize; THREADS] = core::array::from_fn(|i| &raw mut values[i]);
+        let cell = AtomicTaggedPtr::new(ptrs[0], 0);
+        
+        // every successful swap bumps the tag exactly once, even though the pointers keep repeating
+        std::thread::scope(|s| {
+            for &ptr in &ptrs {
+                let cell = &cell;
+                let ptr = ptr.expose_provenance();
+                s.spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        let mut current = cell.load(Ordering::Acquire);
+                        let new = core::ptr::with_exposed_provenance_mut(ptr);
+                        while let Err(actual) = cell.compare_exchange_bump(current, new, Ordering::AcqRel, Ordering::Acquire) {
+                            current = actual;
+                        }
+                    }
+                });
+            }
+        });
+        
+        let (ptr, tag) = cell.load(Ordering::Acquire);
+        assert_eq!(tag, THREADS * ITERATIONS);
+        assert!(ptrs.contains(&ptr));
+    }
+}
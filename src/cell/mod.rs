@@ -3,10 +3,50 @@
 
 mod atomic_cell;
 mod atomic_refcell;
+mod atomic_tagged_ptr;
 mod mutcell;
 mod takecell;
 
 pub use atomic_cell::AtomicCell;
-pub use atomic_refcell::{AtomicRefCell, AtomicRef, AtomicRefMut};
+pub use atomic_refcell::{AtomicRefCell, AtomicRef, AtomicRefMut, BorrowError, BorrowGuard, BorrowState};
+#[cfg(feature = "std")]
+pub use atomic_refcell::ReentrantBorrow;
+pub use atomic_tagged_ptr::AtomicTaggedPtr;
 pub use mutcell::{MutCell, MutCellGuard};
-pub use takecell::TakeCell;
+pub use takecell::{BorrowedTakeCell, TakeCell};
+
+
+/// Moves the value out of a boxed cell, keeping it boxed. This is what the cells' `take_boxed`s use.
+/// 
+/// `value` projects the cell to the field that holds its value. Since the value could be unsized,
+/// it's copied over into a new allocation, and then the old one gets freed.
+/// 
+/// # Safety
+/// `cell` must have come from [`Box::into_raw`], and `value` has to give a pointer into it. Nothing
+/// else in the cell gets dropped, so none of its other fields can need dropping.
+#[cfg(feature = "std")]
+pub(crate) unsafe fn take_boxed<C: ?Sized, T: ?Sized>(cell: *mut C, value: impl FnOnce(*mut C) -> *const core::cell::UnsafeCell<T>) -> Box<T> {
+    use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+    
+    let old_value = core::cell::UnsafeCell::raw_get(value(cell));
+    // SAFETY: `cell` came from a valid box, and `old_value` points into it
+    let (cell_layout, value_layout) = unsafe { (Layout::for_value_raw(cell), Layout::for_value_raw(old_value)) };
+    let ptr = match value_layout.size() {
+        0 => core::ptr::without_provenance_mut::<u8>(value_layout.align()),
+        _ => unsafe { alloc(value_layout) },
+    };
+    if ptr.is_null() { handle_alloc_error(value_layout) }
+    let new_value = core::ptr::from_raw_parts_mut::<T>(ptr, core::ptr::metadata(old_value));
+    
+    // SAFETY: `new_value` was just allocated with the right layout, and `UnsafeCell<T>` has the same
+    //         layout as `T`, so the value can just be copied over. The old box gets freed without
+    //         dropping the value, since it got moved.
+    unsafe {
+        new_value.cast::<u8>().copy_from_nonoverlapping(old_value.cast::<u8>(), value_layout.size());
+        // NOTE: zero sized boxes don't actually own an allocation
+        if cell_layout.size() != 0 {
+            dealloc(cell.cast(), cell_layout);
+        }
+        Box::from_raw(new_value)
+    }
+}
@@ -33,6 +33,16 @@ impl<T: Sized> MutCell<T> {
 }
 
 impl<T: ?Sized> MutCell<T> {
+    /// Moves the value out of a boxed `MutCell`, keeping it boxed.
+    /// 
+    /// This is [`into_inner`](Self::into_inner) for when `T` might be unsized (like a `dyn Trait`
+    /// or a slice), so it can't be moved out onto the stack.
+    #[cfg(feature = "std")]
+    pub fn take_boxed(self: Box<Self>) -> Box<T> {
+        // SAFETY: the pointer came from a box, and the `taken` flag doesn't need dropping
+        unsafe { super::take_boxed(Box::into_raw(self), |cell| &raw const (*cell).value) }
+    }
+    
     /// Given an exclusive reference to the `MutCell`, you can trivially have an exclusive reference to the inner value.
     pub const fn get_mut(&mut self) -> &mut T {
         self.value.get_mut()
@@ -70,6 +80,28 @@ impl<T: ?Sized> MutCell<T> {
             Err(_) => None
         }
     }
+    
+    /// Try to take exclusive access to the inner value, spinning up to `max_spins` times if it is already taken.
+    /// 
+    /// This is useful if whoever currently holds the guard is only expected to
+    /// hold it briefly. This never sleeps or yields to the OS scheduler, so if
+    /// you need to wait for a long time, use an actual mutex instead.
+    pub fn take_spin(&self, max_spins: usize) -> Option<MutCellGuard<'_, T>> {
+        let mut spins = 0;
+        loop {
+            if let Some(guard) = self.take() {
+                return Some(guard)
+            }
+            
+            // only retry the CAS once the cell looks free (same idea as the spinlock `Mutex`)
+            loop {
+                if spins >= max_spins { return None }
+                spins += 1;
+                core::hint::spin_loop();
+                if !self.taken.load(Ordering::Relaxed) { break }
+            }
+        }
+    }
 }
 
 
@@ -108,3 +140,63 @@ impl<T: ?Sized> Drop for MutCellGuard<'_, T> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_take_spin() {
+        const HOLD_SPINS: usize = 100_000;
+        
+        let cell = MutCell::new(0);
+        let held = AtomicBool::new(false);
+        let checked = AtomicBool::new(false);
+        
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut guard = cell.take().unwrap();
+                held.store(true, Ordering::Release);
+                while !checked.load(Ordering::Acquire) { core::hint::spin_loop() }
+                for _ in 0..HOLD_SPINS { core::hint::spin_loop() }
+                *guard += 1;
+            });
+            
+            while !held.load(Ordering::Acquire) { core::hint::spin_loop() }
+            
+            // the other thread is still holding the guard, so not spinning at all can't work
+            assert!(cell.is_taken());
+            assert!(cell.take_spin(0).is_none());
+            checked.store(true, Ordering::Release);
+            
+            // eventually the other thread has to let go
+            let guard = cell.take_spin(usize::MAX).expect("should eventually acquire the guard");
+            assert_eq!(*guard, 1);
+        });
+    }
+    
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_take_boxed() {
+        trait Shape { fn area(&self) -> usize; }
+        struct Square(usize);
+        impl Shape for Square { fn area(&self) -> usize { self.0 * self.0 } }
+        
+        let mut cell: Box<MutCell<dyn Shape>> = Box::new(MutCell::new(Square(3)));
+        assert_eq!(cell.take().unwrap().area(), 9);
+        assert_eq!(cell.get_mut().area(), 9);
+        let shape: Box<dyn Shape> = cell.take_boxed();
+        assert_eq!(shape.area(), 9);
+        
+        // slices (and zero sized values) work too, and the value only gets dropped once
+        let counter = std::rc::Rc::new(());
+        let cell: Box<MutCell<[std::rc::Rc<()>]>> = Box::new(MutCell::new([counter.clone(), counter.clone()]));
+        let slice = cell.take_boxed();
+        assert_eq!(std::rc::Rc::strong_count(&counter), 3);
+        drop(slice);
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+        
+        let cell: Box<MutCell<[u8]>> = Box::new(MutCell::new([]));
+        assert!(cell.take_boxed().is_empty());
+    }
+}
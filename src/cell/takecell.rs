@@ -1,4 +1,4 @@
-use core::{cell::UnsafeCell, sync::atomic::{AtomicBool, Ordering}};
+use core::{cell::UnsafeCell, marker::PhantomData, ptr::NonNull, sync::atomic::{AtomicBool, Ordering}};
 
 pub struct TakeCell<T: ?Sized> {
     taken: AtomicBool,
@@ -18,9 +18,24 @@ impl<T> TakeCell<T> {
     pub const fn into_inner(self) -> T {
         self.value.into_inner()
     }
+    
+    /// Creates a [`BorrowedTakeCell`] over borrowed data. See [`BorrowedTakeCell::new`].
+    pub fn from_mut(value: &mut T) -> BorrowedTakeCell<'_, T> {
+        BorrowedTakeCell::new(value)
+    }
 }
 
 impl<T: ?Sized> TakeCell<T> {
+    /// Moves the value out of a boxed `TakeCell`, keeping it boxed.
+    /// 
+    /// This is [`into_inner`](Self::into_inner) for when `T` might be unsized (like a `dyn Trait`
+    /// or a slice), so it can't be moved out onto the stack.
+    #[cfg(feature = "std")]
+    pub fn take_boxed(self: Box<Self>) -> Box<T> {
+        // SAFETY: the pointer came from a box, and the `taken` flag doesn't need dropping
+        unsafe { super::take_boxed(Box::into_raw(self), |cell| &raw const (*cell).value) }
+    }
+    
     pub fn is_taken(&self) -> bool {
         self.taken.load(Ordering::Relaxed)
     }
@@ -33,7 +48,8 @@ impl<T: ?Sized> TakeCell<T> {
             //    only one thread calling `take` concurrently will observe
             //    `false` from the `swap` call, and so it is sound to create a
             //    mutable reference.
-            false => Some(unsafe { self.steal() })
+            //    (NOTE: this can't go through `steal`, since we already set `taken`)
+            false => Some(unsafe { &mut *self.value.get() })
         }
     }
     
@@ -60,8 +76,196 @@ impl<T: ?Sized> TakeCell<T> {
     }
 }
 
+impl<T> TakeCell<Option<T>> {
+    /// Takes the value out of the slot, if there is one, leaving the cell usable afterwards.
+    /// 
+    /// Unlike [`take`](TakeCell::take), this only marks the cell as taken for as long as it takes
+    /// to move the value out, since there isn't any reference to the inside left behind after
+    /// that. This makes a `TakeCell<Option<T>>` usable as a slot that can be filled (with
+    /// [`put_value`](TakeCell::put_value)) and emptied any number of times.
+    /// 
+    /// This returns `None` if the slot is empty, or if the cell is taken (either permanently by
+    /// [`take`](TakeCell::take), or for a moment by another thread in the middle of `take_value`
+    /// or `put_value`).
+    /// 
+    /// NOTE: while this (or `put_value`) is running on one thread, `take` on other threads will
+    /// fail, even though the cell will go back to being untaken right after.
+    pub fn take_value(&self) -> Option<T> {
+        self.taken.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).ok()?;
+        // SAFETY: we just marked the cell as taken, so nobody else can have a reference to the value
+        let value = unsafe { &mut *self.value.get() }.take();
+        self.taken.store(false, Ordering::Release);
+        value
+    }
+    
+    /// Puts `value` into the slot, if it is empty.
+    /// 
+    /// This gives `value` back if the slot is already full, or if the cell is taken. See
+    /// [`take_value`](TakeCell::take_value) for how this interacts with [`take`](TakeCell::take).
+    pub fn put_value(&self, value: T) -> Result<(), T> {
+        if self.taken.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return Err(value)
+        }
+        // SAFETY: same as in `take_value`
+        let slot = unsafe { &mut *self.value.get() };
+        let result = match slot {
+            Some(_) => Err(value),
+            None => { *slot = Some(value); Ok(()) }
+        };
+        self.taken.store(false, Ordering::Release);
+        result
+    }
+}
+
 impl<T: Default> Default for TakeCell<T> {
     fn default() -> Self {
         TakeCell::new(T::default())
     }
 }
+
+
+/// A [`TakeCell`] over a `&mut T`, instead of an owned `T`.
+/// 
+/// NOTE: this can't just be a `&TakeCell<T>` made out of the `&mut T` (like
+/// [`Cell::from_mut`](core::cell::Cell::from_mut)), since a `TakeCell` also has
+/// to store whether it has been taken or not.
+pub struct BorrowedTakeCell<'a, T: ?Sized> {
+    taken: AtomicBool,
+    value: NonNull<T>,
+    _phantom: PhantomData<&'a mut T>
+}
+
+unsafe impl<T: ?Sized + Send> Send for BorrowedTakeCell<'_, T> {}
+unsafe impl<T: ?Sized + Send> Sync for BorrowedTakeCell<'_, T> {}
+
+impl<'a, T: ?Sized> BorrowedTakeCell<'a, T> {
+    pub fn new(value: &'a mut T) -> Self {
+        Self {
+            taken: AtomicBool::new(false),
+            value: NonNull::from(value),
+            _phantom: PhantomData
+        }
+    }
+    
+    /// Gives back the original reference.
+    pub fn into_inner(self) -> &'a mut T {
+        // SAFETY: we own `self`, so there can't be any references that were taken out of it
+        unsafe { &mut *self.value.as_ptr() }
+    }
+    
+    pub fn is_taken(&self) -> bool {
+        self.taken.load(Ordering::Relaxed)
+    }
+    
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(&self) -> Option<&mut T> {
+        match self.taken.swap(true, Ordering::Relaxed) {
+            true => None,
+            // SAFETY: only one thread can ever observe `false` here (see `TakeCell::take`)
+            false => Some(unsafe { &mut *self.value.as_ptr() })
+        }
+    }
+    
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: since we have exclusive reference to the whole cell, nobody can have a reference to the inner value.
+        unsafe { self.value.as_mut() }
+    }
+    
+    pub fn heal(&mut self) {
+        // since we have exclusive reference to the whole cell, nobody can have a reference to the inner value.
+        self.taken = AtomicBool::new(false);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_take_borrowed() {
+        let mut data = [1, 2, 3];
+        
+        let mut cell = TakeCell::from_mut(&mut data);
+        let was_taken = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| if let Some(data) = cell.take() {
+                    assert!(!was_taken.swap(true, Ordering::Relaxed));
+                    data[0] = 100;
+                });
+            }
+        });
+        assert!(cell.is_taken());
+        assert!(was_taken.load(Ordering::Relaxed));
+        
+        cell.heal();
+        cell.take().unwrap()[1] = 200;
+        cell.into_inner()[2] = 300;
+        assert_eq!(data, [100, 200, 300]);
+    }
+    
+    #[test]
+    fn test_slot_reuse() {
+        let cell = TakeCell::new(None);
+        assert_eq!(cell.take_value(), None);
+        
+        assert_eq!(cell.put_value(1), Ok(()));
+        assert_eq!(cell.put_value(2), Err(2));
+        assert_eq!(cell.take_value(), Some(1));
+        assert_eq!(cell.take_value(), None);
+        assert!(!cell.is_taken());
+        
+        // it should be able to be filled again after being emptied
+        assert_eq!(cell.put_value(3), Ok(()));
+        assert_eq!(cell.take_value(), Some(3));
+        assert_eq!(cell.put_value(4), Ok(()));
+        
+        // but not once the inside has been taken for good
+        let inner = cell.take().unwrap();
+        assert_eq!(cell.take_value(), None);
+        assert_eq!(cell.put_value(5), Err(5));
+        assert_eq!(inner.take(), Some(4));
+    }
+    
+    /// Passes values from one thread to another through a slot
+    #[test]
+    fn test_slot_threads() {
+        const NUM_VALUES: usize = 1000;
+        
+        let cell = TakeCell::new(None);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..NUM_VALUES {
+                    let mut value = std::vec![i; 4];
+                    while let Err(v) = cell.put_value(value) {
+                        value = v;
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            
+            for i in 0..NUM_VALUES {
+                let value = loop {
+                    if let Some(value) = cell.take_value() { break value }
+                    std::thread::yield_now();
+                };
+                assert_eq!(value, [i; 4]);
+            }
+        });
+        assert_eq!(cell.into_inner(), None);
+    }
+    
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_take_boxed() {
+        let mut cell: Box<TakeCell<dyn std::fmt::Display>> = Box::new(TakeCell::new(1234));
+        assert_eq!(cell.take().unwrap().to_string(), "1234");
+        assert_eq!(cell.get_mut().to_string(), "1234");
+        let value: Box<dyn std::fmt::Display> = cell.take_boxed();
+        assert_eq!(value.to_string(), "1234");
+        
+        let cell: Box<TakeCell<[String]>> = Box::new(TakeCell::new([String::from("a"), String::from("b")]));
+        assert_eq!(*cell.take_boxed(), ["a", "b"]);
+    }
+}
@@ -1,7 +1,8 @@
 use std::borrow::Borrow;
-use std::cell::UnsafeCell;
-use std::hash::Hash;
-use std::marker::PhantomData;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::spinlock_mutex::Mutex;
 
 const MAX_CAPACITY: usize = i32::MAX as usize;
 const DEFAULT_CAPACITY: usize = 16;
@@ -9,47 +10,144 @@ const DEFAULT_CAPACITY: usize = 16;
 const DEFAULT_LOAD_FACTOR: f32 = 0.75;
 
 // following along with https://www.youtube.com/watch?v=yQFWmGaFBjk
-struct ConcurrentHashMap<K, V, H = std::collections::hash_map::RandomState> {
-    todo: PhantomData<(K, V)>,
+/// A hash map which can be shared between threads.
+/// 
+/// Every bucket has its own lock, so operations on keys in different buckets
+/// don't contend with each other.
+/// 
+/// Since entries can be removed through a shared reference, this map never
+/// hands out references to its values, and instead clones them out.
+/// 
+/// TODO: resizing. right now the number of buckets is fixed on construction
+pub struct ConcurrentHashMap<K, V, H = RandomState> {
+    buckets: Box<[Mutex<Vec<(K, V)>>]>,
+    len: AtomicUsize,
     hasher: H
 }
 
+impl<K, V> ConcurrentHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+    
+    /// Creates a map with enough buckets to hold `capacity` entries without going over the load factor.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
 impl<K, V, H> ConcurrentHashMap<K, V, H> {
-    fn new() -> Self {
-        todo!()
+    /// Creates a map which uses `hasher` to pick the bucket for each key.
+    /// 
+    /// Like with [`HashMap::with_hasher`](std::collections::HashMap::with_hasher), this can be
+    /// used to plug in a faster hasher, or a keyed one for resistance to HashDoS attacks.
+    pub fn with_hasher(hasher: H) -> Self {
+        Self::with_capacity_and_hasher(DEFAULT_CAPACITY, hasher)
     }
     
-    fn with_capacity(capacity: usize) -> Self {
-        todo!()
+    /// Same as [`with_capacity`](ConcurrentHashMap::with_capacity), but uses `hasher` to pick the bucket for each key.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: H) -> Self {
+        let capacity = capacity.clamp(1, MAX_CAPACITY);
+        let num_buckets = ((capacity as f32 / DEFAULT_LOAD_FACTOR).ceil() as usize).next_power_of_two();
+        
+        Self {
+            buckets: (0..num_buckets).map(|_| Mutex::new(Vec::new())).collect(),
+            len: AtomicUsize::new(0),
+            hasher
+        }
     }
     
-    fn get<Q>(&self, key: &Q) -> Option<&V>
+    /// The map's [`BuildHasher`].
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+}
+
+impl<K: Hash + Eq, V, H: BuildHasher> ConcurrentHashMap<K, V, H> {
+    /// The bucket that `key` goes into.
+    fn bucket<Q: ?Sized + Hash>(&self, key: &Q) -> &Mutex<Vec<(K, V)>> {
+        // NOTE: the number of buckets is always a power of two
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.buckets[hash & (self.buckets.len() - 1)]
+    }
+    
+    /// The number of entries in the map.
+    /// 
+    /// If other threads are inserting or removing entries at the same time,
+    /// this is only a snapshot, and may already be out of date.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Returns a clone of the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
     where
         K : Borrow<Q>,
-        Q : ?Sized + Hash + Eq
+        Q : ?Sized + Hash + Eq,
+        V : Clone
     {
-        todo!()
+        self.bucket(key).with_lock(|bucket| {
+            bucket.iter().find(|(k, _)| k.borrow() == key).map(|(_, v)| v.clone())
+        })
     }
     
-    fn contains_key<Q>(&self, key: &Q) -> bool
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
     where
         K : Borrow<Q>,
         Q : ?Sized + Hash + Eq
     {
-        todo!()
+        self.bucket(key).with_lock(|bucket| bucket.iter().any(|(k, _)| k.borrow() == key))
     }
     
-    fn insert(&self, key: K, value: V) -> Option<V> {
-        
-        todo!()
+    /// Inserts a key-value pair into the map, returning the old value if the key was already present.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.bucket(&key).with_lock(|bucket| {
+            match bucket.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => Some(std::mem::replace(v, value)),
+                None => {
+                    bucket.push((key, value));
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            }
+        })
+    }
+    
+    /// Returns a clone of the value corresponding to the key, inserting `f()` first if the key is absent.
+    /// 
+    /// This all happens while holding the lock on the key's bucket, so if
+    /// multiple threads race to insert the same key, `f` only runs once.
+    /// That also means `f` should be quick, since it blocks every other
+    /// operation on the same bucket. If `f` panics, nothing gets inserted,
+    /// and the bucket is unlocked again.
+    pub fn get_or_insert_with<F>(&self, key: K, f: F) -> V
+    where
+        F : FnOnce() -> V,
+        V : Clone
+    {
+        self.bucket(&key).with_lock(|bucket| {
+            match bucket.iter().find(|(k, _)| *k == key) {
+                Some((_, v)) => v.clone(),
+                None => {
+                    let value = f();
+                    bucket.push((key, value.clone()));
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                    value
+                }
+            }
+        })
     }
     
-    fn remove<Q>(&self, key: &Q) -> Option<V>
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
     where
         K : Borrow<Q>,
         Q : ?Sized + Hash + Eq
     {
-        todo!()
+        self.remove_entry(key).map(|(_, v)| v)
     }
     
     pub fn remove_entry<Q>(&self, key: &Q) -> Option<(K, V)>
@@ -57,6 +155,279 @@ impl<K, V, H> ConcurrentHashMap<K, V, H> {
         K: Borrow<Q>,
         Q: ?Sized + Hash + Eq
     {
-        todo!()
+        self.bucket(key).with_lock(|bucket| {
+            let index = bucket.iter().position(|(k, _)| k.borrow() == key)?;
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            Some(bucket.swap_remove(index))
+        })
+    }
+    
+    /// Calls `f` on every entry in the map.
+    /// 
+    /// This locks each bucket in turn, rather than the whole map at once, so
+    /// it is only weakly consistent: entries that are inserted or removed by
+    /// other threads during iteration may or may not be visited. However,
+    /// every entry that is in the map for the entire call is visited exactly
+    /// once.
+    /// 
+    /// Since `f` is called while holding a bucket's lock, it must not access
+    /// the map itself, or it might deadlock.
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F : FnMut(&K, &V)
+    {
+        for bucket in &self.buckets {
+            bucket.with_lock(|bucket| {
+                for (k, v) in bucket.iter() {
+                    f(k, v)
+                }
+            })
+        }
+    }
+    
+    /// Removes every entry for which `f` returns `false`.
+    /// 
+    /// Like [`for_each`](ConcurrentHashMap::for_each), this locks each bucket in turn, so it is
+    /// only weakly consistent: entries that are inserted by other threads during the call may
+    /// or may not be checked. Every entry that is in the map for the entire call is checked
+    /// exactly once.
+    /// 
+    /// Since `f` is called while holding a bucket's lock, it must not access the map itself,
+    /// or it might deadlock. If `f` panics, the entries removed up to that point stay removed,
+    /// and the rest stay in the map.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F : FnMut(&K, &V) -> bool
+    {
+        /// Takes whatever has been removed so far out of `len`, even if `f` panics partway through a bucket.
+        struct FixLen<'a>(&'a AtomicUsize, usize);
+        impl Drop for FixLen<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(self.1, Ordering::Relaxed);
+            }
+        }
+        
+        for bucket in &self.buckets {
+            bucket.with_lock(|bucket| {
+                let mut removed = FixLen(&self.len, 0);
+                bucket.retain(|(k, v)| {
+                    let keep = f(k, v);
+                    if !keep { removed.1 += 1 }
+                    keep
+                });
+            })
+        }
+    }
+    
+    /// Removes every entry from the map.
+    /// 
+    /// This empties each bucket in turn, so entries inserted by other threads during the call
+    /// might still be in the map afterwards. The removed entries are dropped after their
+    /// bucket's lock is released.
+    pub fn clear(&self) {
+        for bucket in &self.buckets {
+            let removed = bucket.with_lock(std::mem::take);
+            self.len.fetch_sub(removed.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<K, V> Default for ConcurrentHashMap<K, V, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_insert_get_remove() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..100 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(5, 0), Some(10));
+        assert_eq!(map.get(&5), Some(0));
+        assert_eq!(map.get(&6), Some(12));
+        assert_eq!(map.get(&100), None);
+        assert!(map.contains_key(&99));
+        assert_eq!(map.remove(&99), Some(198));
+        assert!(!map.contains_key(&99));
+        assert_eq!(map.remove_entry(&98), Some((98, 196)));
+        assert_eq!(map.remove(&98), None);
+    }
+    
+    #[test]
+    fn test_get_or_insert_with_panic() {
+        let map = ConcurrentHashMap::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| map.get_or_insert_with(1, || panic!("oops"))));
+        assert!(result.is_err());
+        
+        // the bucket can't be left locked
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.get_or_insert_with(1, || 2), 2);
+        assert_eq!(map.len(), 1);
+    }
+    
+    #[test]
+    fn test_retain_panic() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        
+        // remove a few, and then panic partway through
+        let mut calls = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| map.retain(|_, _| {
+            calls += 1;
+            if calls == 50 { panic!("oops") }
+            calls % 2 == 0
+        })));
+        assert!(result.is_err());
+        
+        // the length has to match whatever actually got removed, and nothing can be left locked
+        let mut count = 0;
+        map.for_each(|_, _| count += 1);
+        assert_eq!(map.len(), count);
+        assert!(count < 100);
+        map.clear();
+        assert_eq!(map.len(), 0);
+    }
+    
+    #[test]
+    fn test_borrowed_keys() {
+        let map = ConcurrentHashMap::new();
+        map.insert(String::from("hello"), 1);
+        assert_eq!(map.get("hello"), Some(1));
+        assert_eq!(map.remove("hello"), Some(1));
+        assert!(!map.contains_key("hello"));
+    }
+    
+    /// Has a bunch of threads race to initialize the same key
+    #[test]
+    fn test_get_or_insert_with_runs_once() {
+        const T: usize = 64;
+        
+        let map = ConcurrentHashMap::new();
+        let num_calls = AtomicUsize::new(0);
+        
+        let values: Vec<usize> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..T).map(|i| {
+                let (map, num_calls) = (&map, &num_calls);
+                s.spawn(move || map.get_or_insert_with("key", || {
+                    num_calls.fetch_add(1, Ordering::Relaxed);
+                    i
+                }))
+            }).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        
+        assert_eq!(num_calls.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|&v| v == values[0]));
+        assert_eq!(map.get("key"), Some(values[0]));
+    }
+    
+    /// Hashes integers to themselves, so it's easy to tell which bucket they should end up in
+    #[derive(Clone, Copy, Default)]
+    struct IdentityHasher(u64);
+    
+    impl std::hash::Hasher for IdentityHasher {
+        fn finish(&self) -> u64 { self.0 }
+        fn write(&mut self, bytes: &[u8]) {
+            // not an identity anymore, but anything that isn't a `usize` still needs some kind of hash
+            for &byte in bytes {
+                self.0 = self.0.rotate_left(8) ^ byte as u64;
+            }
+        }
+        fn write_usize(&mut self, i: usize) { self.0 = i as u64 }
+    }
+    
+    fn bucket_index<K: Hash + Eq, V, H: BuildHasher>(map: &ConcurrentHashMap<K, V, H>, key: &K) -> usize {
+        let bucket: *const _ = map.bucket(key);
+        unsafe { bucket.offset_from(map.buckets.as_ptr()) as usize }
+    }
+    
+    #[test]
+    fn test_custom_hasher() {
+        use std::hash::BuildHasherDefault;
+        
+        let map = ConcurrentHashMap::with_capacity_and_hasher(100, BuildHasherDefault::<IdentityHasher>::default());
+        let num_buckets = map.buckets.len();
+        for i in 0..1000usize {
+            map.insert(i, i);
+            assert_eq!(bucket_index(&map, &i), i % num_buckets);
+        }
+        assert_eq!(map.get(&500), Some(500));
+        assert_eq!(map.remove(&500), Some(500));
+        
+        // keys that aren't just a `usize` still get hashed
+        let strings = ConcurrentHashMap::with_hasher(BuildHasherDefault::<IdentityHasher>::default());
+        strings.insert("hello", 1);
+        strings.insert("world", 2);
+        assert_eq!(strings.get("hello"), Some(1));
+        assert_eq!(strings.get("world"), Some(2));
+        
+        // the same (deterministic) hasher should always put keys in the same buckets
+        let hasher = BuildHasherDefault::<std::hash::DefaultHasher>::default();
+        let a = ConcurrentHashMap::<_, (), _>::with_hasher(hasher.clone());
+        let b = ConcurrentHashMap::<_, (), _>::with_hasher(hasher);
+        for key in ["hello", "world", "foo", "bar", "baz"] {
+            assert_eq!(bucket_index(&a, &key), bucket_index(&b, &key));
+        }
+    }
+    
+    #[test]
+    fn test_for_each_and_len() {
+        const N: usize = 1000;
+        
+        let map = ConcurrentHashMap::new();
+        assert!(map.is_empty());
+        for i in 0..N {
+            map.insert(i, i.to_string());
+        }
+        map.insert(0, String::from("zero"));
+        map.get_or_insert_with(N, || N.to_string());
+        assert_eq!(map.len(), N + 1);
+        map.remove(&N);
+        assert_eq!(map.len(), N);
+        
+        let mut seen = vec![0; N];
+        map.for_each(|&k, v| {
+            seen[k] += 1;
+            if k != 0 { assert_eq!(*v, k.to_string()) }
+        });
+        assert!(seen.iter().all(|&n| n == 1));
+    }
+    
+    #[test]
+    fn test_retain_and_clear() {
+        const N: usize = 1000;
+        
+        let map = ConcurrentHashMap::new();
+        for i in 0..N {
+            map.insert(i, i.to_string());
+        }
+        
+        map.retain(|&k, _| k % 3 == 0);
+        assert_eq!(map.len(), N.div_ceil(3));
+        assert!((0..N).all(|i| map.contains_key(&i) == (i % 3 == 0)));
+        assert_eq!(map.get(&999), Some(String::from("999")));
+        
+        // values can be checked too, and keeping everything doesn't change anything
+        map.retain(|_, v| !v.ends_with('0'));
+        map.retain(|_, _| true);
+        assert_eq!(map.len(), (0..N).filter(|i| i % 3 == 0 && i % 10 != 0).count());
+        
+        map.clear();
+        assert!(map.is_empty());
+        assert!((0..N).all(|i| !map.contains_key(&i)));
+        
+        // the map still works after being cleared
+        map.insert(1, String::from("one"));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&1), Some(String::from("one")));
     }
 }
@@ -1,50 +1,193 @@
-use std::sync::atomic;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicPtr, Ordering};
 
+use crate::gc::Gc;
 
 
-pub struct ConcurrentLinkedList<T> {
-    head: LinkedListNode<T>,
+/// A lock-free FIFO queue (i.e: a Michael-Scott queue), which can be shared between threads.
+/// 
+/// Elements get added with [`push_back`](Self::push_back) and taken with [`pop_front`](Self::pop_front).
+/// 
+/// The nodes live in the GC heap, so a node can't get freed (or reused) while any thread can
+/// still see it. This means none of the usual hazard pointer/epoch stuff is needed, and there
+/// is no ABA problem on `head` or `tail`.
+pub struct ConcurrentLinkedList<T: Send + 'static> {
+    /// Always points to a sentinel node, whose value has either been popped already, or never existed.
+    head: AtomicPtr<LinkedListNode<T>>,
+    /// Points to the last node, or (while a push is halfway done) the one right before it.
+    tail: AtomicPtr<LinkedListNode<T>>,
+    _marker: PhantomData<T>,
 }
 
-impl<T> ConcurrentLinkedList<T> {
-    fn push_front(&self, element: T) {
-        todo!()
+impl<T: Send + 'static> ConcurrentLinkedList<T> {
+    pub fn new() -> Self {
+        let sentinel = LinkedListNode::allocate(MaybeUninit::uninit());
+        Self { head: AtomicPtr::new(sentinel), tail: AtomicPtr::new(sentinel), _marker: PhantomData }
+    }
+    
+    /// Adds an element to the back of the list.
+    pub fn push_back(&self, element: T) {
+        let node = LinkedListNode::allocate(MaybeUninit::new(element));
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            // SAFETY: nodes never get freed while we still have a pointer to them
+            let tail_next = unsafe { &(*tail).next };
+            let next = tail_next.load(Ordering::Acquire);
+            
+            if !next.is_null() {
+                // somebody else is halfway through pushing, so help them finish first
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue
+            }
+            
+            if tail_next.compare_exchange(std::ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed).is_ok() {
+                // NOTE: if this fails, some other thread already moved the tail for us
+                let _ = self.tail.compare_exchange(tail, node, Ordering::Release, Ordering::Relaxed);
+                return
+            }
+        }
+    }
+    
+    /// Removes the element at the front of the list, if there is one.
+    pub fn pop_front(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let tail = self.tail.load(Ordering::Acquire);
+            // SAFETY: nodes never get freed while we still have a pointer to them
+            let next = unsafe { &(*head).next }.load(Ordering::Acquire);
+            
+            if next.is_null() {
+                return None
+            }
+            
+            if head == tail {
+                // the tail is lagging behind, so move it along before the head passes it
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                continue
+            }
+            
+            if self.head.compare_exchange(head, next, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                // SAFETY: we won the race to make `next` the new sentinel, so nobody else will
+                //         ever read its value. it was initialized before being linked in.
+                return Some(unsafe { (*(*next).value.get()).assume_init_read() })
+            }
+        }
+    }
+    
+    /// Whether the list was empty at some point during the call.
+    pub fn is_empty(&self) -> bool {
+        let head = self.head.load(Ordering::Acquire);
+        // SAFETY: nodes never get freed while we still have a pointer to them
+        unsafe { &(*head).next }.load(Ordering::Acquire).is_null()
     }
 }
 
-impl<T: Send> ConcurrentLinkedList<T> {
-    fn pop_front(&self) -> Option<T> {
-        todo!()
+impl<T: Send + 'static> Default for ConcurrentLinkedList<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl<T: PartialEq> ConcurrentLinkedList<T> {
-    fn contains(&self, element: T) -> bool {
-        todo!()
+impl<T: Send + 'static> Drop for ConcurrentLinkedList<T> {
+    fn drop(&mut self) {
+        // the nodes don't drop their values, so anything still in the list has to be dropped here
+        while self.pop_front().is_some() {}
     }
 }
 
 struct LinkedListNode<T> {
-    next: atomic::AtomicPtr<LinkedListNode<T>>,
-    refcnt: atomic::AtomicUsize, // what no gc does to a mf 💔
-    value: T
+    next: AtomicPtr<LinkedListNode<T>>,
+    /// NOTE: this is only initialized from when the node is pushed until it gets popped
+    value: UnsafeCell<MaybeUninit<T>>,
 }
 
-impl<T> std::ops::Deref for LinkedListNode<T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target {
-        &self.value
+impl<T: Send + 'static> LinkedListNode<T> {
+    /// Puts a new node (with no next node) into the GC heap.
+    fn allocate(value: MaybeUninit<T>) -> *mut Self {
+        let node = Gc::new(Self { next: AtomicPtr::new(std::ptr::null_mut()), value: UnsafeCell::new(value) });
+        node.as_ptr().cast_mut()
     }
 }
 
-impl<T> LinkedListNode<T> {
-    fn push_next(&self, value: T) {
-        todo!()
-    }
-}
 
-impl<T: Send> LinkedListNode<T> {
-    fn pop_next(&self) -> Option<T> {
-        todo!()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
+    
+    #[test]
+    fn test_fifo() {
+        let list = ConcurrentLinkedList::new();
+        assert!(list.is_empty());
+        for i in 0..100 {
+            list.push_back(i);
+        }
+        assert!(!list.is_empty());
+        assert!((0..100).all(|i| list.pop_front() == Some(i)));
+        assert_eq!(list.pop_front(), None);
+        assert!(list.is_empty());
+    }
+    
+    #[test]
+    fn test_producers_consumers() {
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+        const N: usize = 10000;
+        
+        let list = ConcurrentLinkedList::new();
+        let num_popped = AtomicUsize::new(0);
+        
+        let received: Vec<Vec<(usize, usize)>> = std::thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let list = &list;
+                s.spawn(move || (0..N).for_each(|i| list.push_back((p, i))));
+            }
+            
+            let consumers: Vec<_> = (0..CONSUMERS).map(|_| s.spawn(|| {
+                let mut received = Vec::new();
+                while num_popped.load(Ordering::Relaxed) < PRODUCERS * N {
+                    match list.pop_front() {
+                        Some(x) => {
+                            num_popped.fetch_add(1, Ordering::Relaxed);
+                            received.push(x);
+                        },
+                        None => std::hint::spin_loop(),
+                    }
+                }
+                received
+            })).collect();
+            consumers.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        
+        // every item delivered exactly once
+        let all: HashSet<_> = received.iter().flatten().copied().collect();
+        assert_eq!(received.iter().map(Vec::len).sum::<usize>(), PRODUCERS * N);
+        assert_eq!(all.len(), PRODUCERS * N);
+        
+        // each consumer should see each producer's items in the order they were pushed
+        for items in &received {
+            for p in 0..PRODUCERS {
+                assert!(items.iter().filter(|(q, _)| *q == p).map(|(_, i)| i).is_sorted());
+            }
+        }
+        assert!(list.is_empty());
+    }
+    
+    #[test]
+    fn test_drop_remaining() {
+        use std::sync::Arc;
+        
+        let value = Arc::new(());
+        let list = ConcurrentLinkedList::new();
+        for _ in 0..10 {
+            list.push_back(Arc::clone(&value));
+        }
+        drop(list.pop_front());
+        assert_eq!(Arc::strong_count(&value), 10);
+        drop(list);
+        assert_eq!(Arc::strong_count(&value), 1);
     }
 }
@@ -1,28 +1,385 @@
-use std::{ptr::NonNull, sync::atomic::AtomicUsize};
-use std::marker::PhantomData;
-use std::cell::UnsafeCell;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::hazard_pointer::HazardRegistry;
 
 // https://www.stroustrup.com/lock-free-vector.pdf
 
-struct ConcurrentVec<T> {
-    ptr: NonNull<UnsafeCell<[T]>>,
-    descriptor: ConcurrentVecDescriptor<T>
+/// log2 of the number of slots in the first bucket.
+const FIRST_BUCKET_BITS: u32 = 3;
+const FIRST_BUCKET_SIZE: usize = 1 << FIRST_BUCKET_BITS;
+/// Enough buckets for every index that fits in a `usize`.
+const NUM_BUCKETS: usize = (usize::BITS - FIRST_BUCKET_BITS) as usize;
+
+/// The number of slots in `bucket`.
+fn bucket_size(bucket: usize) -> usize {
+    FIRST_BUCKET_SIZE << bucket
+}
+
+/// Which bucket `index` is in, and where it is in that bucket.
+fn locate(index: usize) -> (usize, usize) {
+    let position = index.checked_add(FIRST_BUCKET_SIZE).expect("index out of range");
+    let high_bit = position.ilog2();
+    ((high_bit - FIRST_BUCKET_BITS) as usize, position ^ (1 << high_bit))
 }
 
+/// A lock-free vector, which any number of threads can push to and pop from at once.
+/// 
+/// The elements live in buckets that each have twice as many slots as the one before, so the
+/// vector never has to move anything to grow. The length lives in a descriptor, which every push
+/// and pop replaces with a CAS. A push can't also put its element into its slot in that same CAS,
+/// so it leaves the write in its descriptor instead, and whoever sees that descriptor next
+/// finishes the write for it. That way, a thread that gets stalled in
+/// the middle of a push can't hold anyone else up.
+/// 
+/// Each element gets its own box, so that finishing a write is a CAS on a pointer. Old
+/// descriptors, and the boxes of popped elements, are freed through a [`HazardRegistry`].
+/// 
+/// NOTE: [`get`](Self::get) needs `T: Copy`, since some other thread could be popping (and then
+///       dropping) the element while it's being read.
+pub struct ConcurrentVec<T: Send> {
+    /// Pointers to the first slot of each bucket, or null if that bucket hasn't been allocated yet.
+    /// 
+    /// NOTE: the buckets are always allocated in order, so only the last ones can be null.
+    buckets: [AtomicPtr<AtomicPtr<T>>; NUM_BUCKETS],
+    /// The current descriptor, which is never null. Old ones are only ever freed by retiring them through `registry`.
+    descriptor: AtomicPtr<ConcurrentVecDescriptor<T>>,
+    registry: HazardRegistry,
+}
+
+// SAFETY: elements only ever get moved in and out, or copied (by `get`, which needs `T: Copy`)
+unsafe impl<T: Send> Send for ConcurrentVec<T> {}
+unsafe impl<T: Send> Sync for ConcurrentVec<T> {}
+
 struct ConcurrentVecDescriptor<T> {
-    size: AtomicUsize,
-    counter: AtomicUsize,
-    write_descriptor: Option<()>,
-    _a: PhantomData<T> // todo
+    size: usize,
+    /// The write that the push which made this descriptor still has to do, if it was a push.
+    write_descriptor: Option<WriteDescriptor<T>>,
+}
+
+// SAFETY: the pointers in the write descriptor are only freed through `retire`, which needs `T: Send`
+unsafe impl<T: Send> Send for ConcurrentVecDescriptor<T> {}
+
+/// A write into a slot, which has to be done before the descriptor that it's in gets replaced.
+struct WriteDescriptor<T> {
+    index: usize,
+    /// What was in the slot before the push, which is either null or a popped element.
+    old: *mut T,
+    /// The pushed element.
+    new: *mut T,
+}
+
+impl<T> Clone for WriteDescriptor<T> {
+    fn clone(&self) -> Self { *self }
+}
+impl<T> Copy for WriteDescriptor<T> {}
+
+impl<T: Send> ConcurrentVec<T> {
+    pub fn new() -> Self {
+        let descriptor = Box::new(ConcurrentVecDescriptor { size: 0, write_descriptor: None });
+        Self {
+            buckets: [const { AtomicPtr::new(std::ptr::null_mut()) }; NUM_BUCKETS],
+            descriptor: AtomicPtr::new(Box::into_raw(descriptor)),
+            registry: HazardRegistry::new(),
+        }
+    }
+    
+    /// The slot for `index`, whose bucket has to be allocated already.
+    fn slot(&self, index: usize) -> &AtomicPtr<T> {
+        let (bucket, offset) = locate(index);
+        let bucket = self.buckets[bucket].load(Ordering::Acquire);
+        assert!(!bucket.is_null(), "bucket for index {index} should have been allocated");
+        // SAFETY: buckets are only freed when the vector is dropped, and `offset` is in bounds
+        unsafe { &*bucket.add(offset) }
+    }
+    
+    /// Makes sure `bucket` is allocated.
+    fn allocate_bucket(&self, bucket: usize) {
+        if !self.buckets[bucket].load(Ordering::Acquire).is_null() { return }
+        
+        let slots: Box<[AtomicPtr<T>]> = (0..bucket_size(bucket)).map(|_| AtomicPtr::new(std::ptr::null_mut())).collect();
+        let slots = Box::into_raw(slots).cast::<AtomicPtr<T>>();
+        if self.buckets[bucket].compare_exchange(std::ptr::null_mut(), slots, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            // somebody else got to it first
+            // SAFETY: nobody else ever saw this one
+            drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(slots, bucket_size(bucket))) });
+        }
+    }
+    
+    /// Finishes the write that a push left in `descriptor`, if nobody has yet.
+    fn complete_write(&self, descriptor: *mut ConcurrentVecDescriptor<T>, write: &WriteDescriptor<T>) {
+        let slot = self.slot(write.index);
+        let current = self.registry.protect(slot);
+        // NOTE: while `descriptor` is still current, the slot can only have `old` or `new` in it.
+        //       after that, `old` could be freed and then reused for another push into the same
+        //       slot, which would make this CAS succeed twice. but since it's protected now, that
+        //       can't happen until this is done.
+        if current.as_ptr() == write.old
+            && self.descriptor.load(Ordering::SeqCst) == descriptor
+            && slot.compare_exchange(write.old, write.new, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+            && !write.old.is_null()
+        {
+            // SAFETY: it isn't in the vector anymore, and its value was already moved out by whoever popped it
+            unsafe { self.registry.retire(write.old.cast::<ManuallyDrop<T>>()) };
+        }
+    }
+    
+    /// Adds an element to the end of the vector.
+    pub fn push(&self, value: T) {
+        let new = Box::into_raw(Box::new(value));
+        loop {
+            let current = self.registry.protect(&self.descriptor);
+            // SAFETY: descriptors are only freed by retiring them, after they've been replaced
+            let descriptor = unsafe { current.as_ref() }.expect("the descriptor is never null");
+            if let Some(write) = &descriptor.write_descriptor {
+                self.complete_write(current.as_ptr(), write);
+            }
+            
+            let index = descriptor.size;
+            self.allocate_bucket(locate(index).0);
+            let old = self.slot(index).load(Ordering::Acquire);
+            let next = Box::into_raw(Box::new(ConcurrentVecDescriptor { size: index + 1, write_descriptor: Some(WriteDescriptor { index, old, new }) }));
+            
+            if self.descriptor.compare_exchange(current.as_ptr(), next, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                // SAFETY: it's been replaced, so nobody can get a new pointer to it
+                unsafe { self.registry.retire(current.as_ptr()) };
+                drop(current);
+                // NOTE: `next` has to be protected again first, since it could already have been replaced (and freed)
+                let current = self.registry.protect(&self.descriptor);
+                // SAFETY: descriptors are only freed by retiring them, after they've been replaced
+                if let Some(write) = &unsafe { current.as_ref() }.expect("the descriptor is never null").write_descriptor {
+                    self.complete_write(current.as_ptr(), write);
+                }
+                return
+            }
+            // SAFETY: nobody else ever saw this one
+            drop(unsafe { Box::from_raw(next) });
+        }
+    }
+    
+    /// Removes the last element and returns it, or returns `None` if the vector is empty.
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let current = self.registry.protect(&self.descriptor);
+            // SAFETY: descriptors are only freed by retiring them, after they've been replaced
+            let descriptor = unsafe { current.as_ref() }.expect("the descriptor is never null");
+            if let Some(write) = &descriptor.write_descriptor {
+                self.complete_write(current.as_ptr(), write);
+            }
+            
+            if descriptor.size == 0 { return None }
+            // NOTE: a push can reuse the slot (and free this) as soon as the CAS goes through
+            let last = self.registry.protect(self.slot(descriptor.size - 1));
+            let next = Box::into_raw(Box::new(ConcurrentVecDescriptor { size: descriptor.size - 1, write_descriptor: None }));
+            
+            if self.descriptor.compare_exchange(current.as_ptr(), next, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                // SAFETY: it's been replaced, so nobody can get a new pointer to it
+                unsafe { self.registry.retire(current.as_ptr()) };
+                // SAFETY: the slot couldn't have changed while `descriptor` was current, and only this
+                //         thread popped it. the box stays in the slot until a push replaces it.
+                return Some(unsafe { last.as_ptr().read() })
+            }
+            // SAFETY: nobody else ever saw this one
+            drop(unsafe { Box::from_raw(next) });
+        }
+    }
+    
+    /// Returns a copy of the element at `index`, if there is one.
+    pub fn get(&self, index: usize) -> Option<T> where T: Copy {
+        let current = self.registry.protect(&self.descriptor);
+        // SAFETY: descriptors are only freed by retiring them, after they've been replaced
+        let descriptor = unsafe { current.as_ref() }.expect("the descriptor is never null");
+        if index >= descriptor.size { return None }
+        if let Some(write) = &descriptor.write_descriptor {
+            self.complete_write(current.as_ptr(), write);
+        }
+        
+        let element = self.registry.protect(self.slot(index));
+        // SAFETY: it can't be freed while it's protected, and elements never change after being
+        //         pushed. (and since `T: Copy`, it's fine if it got popped in the meantime)
+        Some(unsafe { element.as_ptr().read() })
+    }
+    
+    /// The number of elements in the vector.
+    /// 
+    /// NOTE: another thread can always push or pop right after this returns.
+    pub fn len(&self) -> usize {
+        let current = self.registry.protect(&self.descriptor);
+        // SAFETY: descriptors are only freed by retiring them, after they've been replaced
+        unsafe { current.as_ref() }.expect("the descriptor is never null").size
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// The number of slots in the buckets that have been allocated so far.
+    pub fn capacity(&self) -> usize {
+        self.buckets.iter().take_while(|bucket| !bucket.load(Ordering::Acquire).is_null()).enumerate().map(|(bucket, _)| bucket_size(bucket)).sum()
+    }
+    
+    /// Allocates the buckets for (at least) `additional` more elements than there are right now,
+    /// so that pushing them doesn't have to allocate any new buckets.
+    /// 
+    /// NOTE: every push still has to box its element and make a new descriptor.
+    /// 
+    /// # Panics
+    /// If the new capacity would overflow a `usize`.
+    pub fn reserve(&self, additional: usize) {
+        let needed = self.len().checked_add(additional).expect("capacity overflow");
+        let Some(last) = needed.checked_sub(1) else { return };
+        for bucket in 0..=locate(last).0 {
+            self.allocate_bucket(bucket);
+        }
+    }
+}
+
+impl<T: Send> Default for ConcurrentVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send> Drop for ConcurrentVec<T> {
+    fn drop(&mut self) {
+        // SAFETY: nobody else can be using the vector anymore
+        let descriptor_ptr = *self.descriptor.get_mut();
+        if let Some(write) = unsafe { (*descriptor_ptr).write_descriptor } {
+            self.complete_write(descriptor_ptr, &write);
+        }
+        let descriptor = unsafe { Box::from_raw(descriptor_ptr) };
+        
+        for (bucket, slots) in self.buckets.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if slots.is_null() { break }
+            // SAFETY: every bucket came from a `Box<[AtomicPtr<T>]>` of this size
+            let slots = unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(slots, bucket_size(bucket))) };
+            let first_index = bucket_size(bucket) - FIRST_BUCKET_SIZE;
+            for (offset, slot) in slots.iter().enumerate() {
+                let element = slot.load(Ordering::Relaxed);
+                if element.is_null() { continue }
+                if first_index + offset < descriptor.size {
+                    drop(unsafe { Box::from_raw(element) });
+                } else {
+                    // it was popped, so the value was already moved out
+                    drop(unsafe { Box::from_raw(element.cast::<ManuallyDrop<T>>()) });
+                }
+            }
+        }
+        // NOTE: everything that was retired gets freed when `registry` is dropped
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::AtomicUsize;
     
     #[test]
     fn test_new_empty() {
-        let x = Vec::<i32>::new();
+        let x = ConcurrentVec::<i32>::new();
+        assert!(x.is_empty());
+        assert_eq!(x.get(0), None);
+        assert_eq!(x.pop(), None);
+        assert_eq!(x.capacity(), 0);
+    }
+    
+    #[test]
+    fn test_push_pop() {
+        let x = ConcurrentVec::new();
+        for i in 0..1000 {
+            x.push(i);
+        }
+        assert_eq!(x.len(), 1000);
+        assert!((0..1000).all(|i| x.get(i) == Some(i)));
+        assert_eq!(x.get(1000), None);
+        
+        for i in (500..1000).rev() {
+            assert_eq!(x.pop(), Some(i));
+        }
+        assert_eq!(x.len(), 500);
+        assert_eq!(x.get(500), None);
+        
+        // the slots of the popped elements get reused
+        x.push(12345);
+        assert_eq!(x.get(500), Some(12345));
+    }
+    
+    #[test]
+    fn test_reserve() {
+        let x = ConcurrentVec::new();
+        x.push(0);
+        x.reserve(1000);
+        let capacity = x.capacity();
+        assert!(capacity >= 1001);
+        
+        for i in 1..1001 {
+            x.push(i);
+        }
+        assert_eq!(x.capacity(), capacity, "pushing shouldn't have allocated any more buckets");
+        assert!((0..1001).all(|i| x.get(i) == Some(i)));
+    }
+    
+    /// Every element should be dropped exactly once, whether it gets popped or is still in the vector.
+    #[test]
+    fn test_drops() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Tracked(#[allow(unused)] Box<usize>);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        let x = ConcurrentVec::new();
+        for i in 0..100 {
+            x.push(Tracked(Box::new(i)));
+        }
+        for _ in 0..60 {
+            drop(x.pop());
+        }
+        for i in 0..10 {
+            x.push(Tracked(Box::new(i)));
+        }
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 60);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 110);
+    }
+    
+    /// Pushes and pops from a bunch of threads at once, and makes sure nothing got lost or duplicated
+    #[test]
+    fn test_push_pop_stress() {
+        const THREADS: usize = 8;
+        const N: usize = 10_000;
+        
+        let x = ConcurrentVec::new();
+        let mut popped: Vec<usize> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..THREADS).map(|t| {
+                let x = &x;
+                s.spawn(move || {
+                    let mut popped = Vec::new();
+                    for i in 0..N {
+                        x.push(t * N + i);
+                        if i % 3 == 0 && let Some(value) = x.pop() {
+                            popped.push(value);
+                        }
+                        // anything below the length has to be readable, even while other threads push and pop
+                        if let Some(value) = x.get(x.len().saturating_sub(1) / 2) {
+                            assert!(value < THREADS * N);
+                        }
+                    }
+                    popped
+                })
+            }).collect();
+            threads.into_iter().flat_map(|t| t.join().unwrap()).collect()
+        });
+        
+        let len = x.len();
+        assert_eq!(len, THREADS * N - popped.len());
+        let mut remaining: Vec<_> = (0..len).map(|i| x.get(i).expect("everything below the length should be readable")).collect();
         
+        remaining.append(&mut popped);
+        remaining.sort_unstable();
+        assert!(remaining.into_iter().eq(0..THREADS * N), "every pushed element should be either popped or still there, exactly once");
     }
 }
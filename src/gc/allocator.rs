@@ -1,13 +1,17 @@
 use std::alloc::{AllocError, Allocator, Layout};
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Condvar, LazyLock, Mutex, RwLock};
+use std::time::Duration;
 
+mod block_index;
 mod collector;
 mod heap_block_header;
 mod tl_allocator;
 mod os_dependent;
 
-use collector::{DEALLOCATED_CHANNEL, gc_main};
+use block_index::BlockIndex;
+use collector::{DEALLOCATED_CHANNEL, MARKING, gc_main, init_deallocated_channel};
 use heap_block_header::GCHeapBlockHeader;
 use os_dependent::{MemorySource, MemorySourceImpl, MEMORY_SOURCE};
 use thread_local::ThreadLocal;
@@ -16,55 +20,249 @@ use tl_allocator::TLAllocator;
 
 static THREAD_LOCAL_ALLOCATORS: RwLock<ThreadLocal<TLAllocator<MemorySourceImpl>>> = RwLock::new(ThreadLocal::new());
 
+/// Where the blocks in the GC heap are, so walking the heap can skip around.
+static BLOCK_INDEX: BlockIndex = BlockIndex::new();
+
 static GC_CYCLE_NUMBER: Mutex<usize> = Mutex::new(0);
 static GC_CYCLE_SIGNAL: Condvar = Condvar::new();
 
+/// Set by [`GCAllocator::set_pause_target`]. If this is `None`, every cycle stops the world the whole time.
+static PAUSE_TARGET: Mutex<Option<Duration>> = Mutex::new(None);
+/// The pauses from the most recently finished collection cycle.
+static LAST_CYCLE_PAUSES: Mutex<Option<CyclePauses>> = Mutex::new(None);
+
+/// Set by [`GCAllocator::set_collection_callback`].
+static COLLECTION_CALLBACK: Mutex<Option<Box<dyn Fn(CycleEvent<'_>) + Send>>> = Mutex::new(None);
+
+/// Set by [`GCAllocator::shutdown`]. Once this is set, nothing can be allocated anymore.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+/// The background collector thread, if it has been started (and hasn't been shut down yet).
+static COLLECTOR_THREAD: Mutex<Option<std::thread::JoinHandle<()>>> = Mutex::new(None);
+
+#[cfg(test)]
+std::thread_local! {
+    /// How many blocks this thread has sent to the collector to be freed, instead of putting them
+    /// straight back into its own free list.
+    static NUM_SENT_TO_COLLECTOR: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// A range of memory registered with [`GCAllocator::register_root`].
+struct RegisteredRoot {
+    id: usize,
+    data: NonNull<[u8]>,
+    /// Whether every word in `data` is known to be a pointer to a GCed object (i.e: a [`Gc`](super::Gc)),
+    /// so that the collector doesn't have to check which ones look like pointers into the heap.
+    precise: bool,
+}
+
+// SAFETY: the caller of `register_root` promises the memory stays readable until it gets unregistered
+unsafe impl Send for RegisteredRoot {}
+
+/// Extra ranges of memory that the collector scans for roots every cycle.
+/// 
+/// NOTE: this must be locked *before* the heap lock, since registering a root can allocate.
+static REGISTERED_ROOTS: Mutex<Vec<RegisteredRoot>> = Mutex::new(Vec::new());
+static NEXT_ROOT_ID: AtomicUsize = AtomicUsize::new(0);
+
 /// Returns the GC heap block that a given pointer points into.
 fn get_block(ptr: *const ()) -> Option<NonNull<GCHeapBlockHeader>> {
-    if !MEMORY_SOURCE.contains(ptr) {
-        return None
-    }
-    
-    let (block_ptr, heap_size) = MEMORY_SOURCE.raw_data().to_raw_parts();
-    let end = unsafe { block_ptr.byte_add(heap_size).cast() };
-    let mut block_ptr = block_ptr.cast::<GCHeapBlockHeader>();
-    
-    while block_ptr < end {
-        if ptr > block_ptr.as_ptr().cast() { return Some(block_ptr) }
-        block_ptr = unsafe { block_ptr.as_ref() }.next();
-    }
-    if block_ptr != end {
-        error!("Heap corruption detected (expected to end at {end:016x?}, got {block_ptr:016x?})")
-    }
+    // NOTE: blocks never go past the end of a region, so only the one with `ptr` in it has to be walked
+    let heap = MEMORY_SOURCE.regions().into_iter().find(|region| {
+        let start = region.cast::<()>().as_ptr().cast_const();
+        start <= ptr && ptr < start.wrapping_byte_add(region.len())
+    })?;
     
-    None
+    BLOCK_INDEX.find_block(heap, ptr)
+}
+
+/// Gets the current thread's allocator, making one if it doesn't have one yet.
+fn local_allocator(allocators: &ThreadLocal<TLAllocator<MemorySourceImpl>>) -> Result<&TLAllocator<MemorySourceImpl>, GCAllocatorError> {
+    let allocator = allocators.get_or_try(|| TLAllocator::try_new(MEMORY_SOURCE, &BLOCK_INDEX))?;
+    // NOTE: this might have been some exited thread's allocator, so the collector has to know it's being used again
+    allocator.claim();
+    Ok(allocator)
 }
 
 
 #[non_exhaustive]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GCAllocatorError {
     ZeroSized,
     BadAlignment,
-    OutOfMemory,
+    /// The memory source couldn't give the GC heap any more memory.
+    OutOfMemory {
+        /// The number of bytes that the allocator tried to get from the memory source.
+        requested: usize,
+        /// The size of the GC heap (in bytes) when the allocation failed.
+        committed: usize,
+    },
+    /// The GC has been shut down (see [`GCAllocator::shutdown`]), so nothing new can be allocated.
+    ShutDown,
+    /// The memory source gave the GC heap memory that doesn't start on a page boundary.
+    /// 
+    /// This means that the memory source is broken, so the memory that it gave back isn't used.
+    MisalignedMemory {
+        /// The address of the memory that the memory source gave back.
+        address: usize,
+        /// The alignment that the memory should have had (i.e: the page size).
+        page_size: usize,
+    },
+}
+
+
+/// A snapshot of a single thread's allocator. See [`GCAllocator::thread_stats`].
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadAllocStats {
+    /// The thread that owns the allocator.
+    pub thread: std::thread::ThreadId,
+    /// The total number of bytes in the allocator's free list.
+    pub free_bytes: usize,
+    /// The number of blocks in the allocator's free list.
+    pub free_blocks: usize,
+}
+
+
+/// How long the world was stopped for during a collection cycle. See [`GCAllocator::last_cycle_pauses`].
+/// 
+/// NOTE: these don't count the time it takes to stop every thread in the first place.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CyclePauses {
+    /// Finding the roots, at the start of an incremental cycle.
+    /// 
+    /// This is zero if the cycle wasn't incremental (see [`GCAllocator::set_pause_target`]).
+    pub start: Duration,
+    /// Each slice of an incremental mark phase, in order.
+    pub mark_slices: Vec<Duration>,
+    /// Finishing the mark phase and then sweeping, or the whole cycle if it wasn't incremental.
+    pub finish: Duration,
+}
+
+
+/// Something that happened in a collection cycle. See [`GCAllocator::set_collection_callback`].
+#[derive(Debug, Clone, Copy)]
+pub enum CycleEvent<'a> {
+    /// A collection cycle is about to start.
+    /// 
+    /// If the cycle couldn't finish (e.g: because the context of some thread couldn't be read),
+    /// this gets sent again when it gets retried, without an [`End`](CycleEvent::End) in between.
+    Start,
+    /// A collection cycle just finished, and every thread is running again.
+    End {
+        /// How many cycles have finished so far, including this one.
+        cycle: usize,
+        /// How long the world was stopped for during the cycle.
+        pauses: &'a CyclePauses,
+    },
+}
+
+
+/// Somewhere that a pointer to an object was found. See [`GCAllocator::find_roots_to`].
+/// 
+/// Thread ids are the OS's ids for the threads, not [`std::thread::ThreadId`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootLocation {
+    /// In the registers of a (stopped) thread.
+    Registers { thread_id: u32 },
+    /// On the stack of a thread.
+    Stack { thread_id: u32, address: *const () },
+    /// In a writable segment of the executable, like `.data` or `.bss`.
+    Segment { name: &'static str, address: *const () },
+    /// In memory registered with [`GCAllocator::register_root`].
+    RegisteredRoot { address: *const () },
+    /// In a block on the process heap.
+    ProcessHeap { address: *const () },
+    /// Inside another object in the GC heap, whose data starts at `object`.
+    /// 
+    /// NOTE: that object might be garbage itself, in which case it isn't keeping anything alive.
+    GcHeap { object: *const (), address: *const () },
+}
+
+
+/// A read-only view of every live object in the GC heap. See [`GCAllocator::with_heap_snapshot`].
+/// 
+/// This borrows the stopped world, so it can't outlive it.
+pub struct HeapSnapshot<'world> {
+    /// NOTE: this is sorted by address
+    live_blocks: &'world [NonNull<GCHeapBlockHeader>],
 }
 
+/// A single live object in a [`HeapSnapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveObject {
+    /// The address of the object's data.
+    pub address: *const (),
+    /// The number of bytes in the block backing the object (see [`Gc::allocated_size`](super::Gc::allocated_size)).
+    pub size: usize,
+    /// Whether the collector will run a destructor when it frees the object.
+    pub has_destructor: bool,
+}
+
+impl HeapSnapshot<'_> {
+    fn object(block: NonNull<GCHeapBlockHeader>) -> LiveObject {
+        // SAFETY: the world is stopped, so nothing can be freeing or changing the block
+        let block = unsafe { block.as_ref() };
+        LiveObject {
+            address: block.data().cast::<()>().as_ptr(),
+            size: block.size,
+            has_destructor: block.drop_thunk.is_some(),
+        }
+    }
+    
+    /// The number of live objects.
+    pub fn len(&self) -> usize {
+        self.live_blocks.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Every live object, in address order.
+    pub fn objects(&self) -> impl Iterator<Item=LiveObject> {
+        self.live_blocks.iter().map(|&block| Self::object(block))
+    }
+    
+    /// The live object that `ptr` points into, if there is one.
+    pub fn find(&self, ptr: *const ()) -> Option<LiveObject> {
+        let index = self.live_blocks.partition_point(|b| b.as_ptr().cast_const().cast() <= ptr).checked_sub(1)?;
+        let object = Self::object(self.live_blocks[index]);
+        (object.address <= ptr && ptr < object.address.wrapping_byte_add(object.size)).then_some(object)
+    }
+}
 
-pub struct GCAllocator;
+
+pub struct GCAllocator(());
 
 impl GCAllocator {
+    /// Makes a handle to the GC heap that doesn't start the background collector thread.
+    /// 
+    /// Nothing gets collected unless [`drive_once`](Self::drive_once) is called, which is useful for
+    /// embedding the GC into something that wants to control exactly when pauses happen.
+    /// 
+    /// NOTE: the heap itself is global, so this shares it with [`GC_ALLOCATOR`]. If anything uses
+    /// that (e.g: [`Gc::new`](crate::gc::Gc::new)), the collector thread will be started anyways.
+    /// Also, [`wait_for_gc`](Self::wait_for_gc) blocks forever if nothing is driving collections.
+    pub fn new_manual() -> Self {
+        init_deallocated_channel();
+        GCAllocator(())
+    }
+    
     /// Puts the value into the GCed heap.
     pub fn allocate_for_value<T: Send>(&self, value: T) -> Result<NonNull<T>, (GCAllocatorError, T)> {
+        if SHUTDOWN.load(Ordering::Acquire) {
+            return Err((GCAllocatorError::ShutDown, value))
+        }
+        
         let tl_reader = THREAD_LOCAL_ALLOCATORS.read().unwrap();
-        let allocator = match tl_reader.get_or_try(|| TLAllocator::try_new(MEMORY_SOURCE)) {
+        let allocator = match local_allocator(&tl_reader) {
             Ok(a) => a,
             Err(e) => return Err((e, value))
         };
         
         match allocator.allocate_for_value(value) {
             // If the GC was out of memory, then we wait for a GC cycle to free up memory before trying again.
-            Err((GCAllocatorError::OutOfMemory, value)) => {
-                warn!("Got an `OutOfMemory` error on allocation, trying again after GC...");
+            Err((GCAllocatorError::OutOfMemory { requested, committed }, value)) => {
+                warn!("Got an `OutOfMemory` error on allocation (requested 0x{requested:x} bytes with 0x{committed:x} committed), trying again after GC...");
                 self.wait_for_gc();
                 // If the GC is *still* out of memory, just give up.
                 allocator.allocate_for_value(value)
@@ -74,12 +272,153 @@ impl GCAllocator {
         }
     }
     
+    /// The size of the block (in bytes) that backs the allocation starting at `data`.
+    /// 
+    /// This can be larger than the size that was asked for, since block sizes are rounded
+    /// up for alignment, and blocks that are only slightly too big don't get split.
+    /// 
+    /// # Safety
+    /// `data` must point to the start of a block that is currently allocated in the GC heap.
+    pub(crate) unsafe fn block_size(&self, data: NonNull<()>) -> usize {
+        debug_assert!(self.contains(data.as_ptr()));
+        // SAFETY: the header is always right before the data (see `GCHeapBlockHeader::shrink_to_fit`)
+        let header = unsafe { data.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()).as_ref() };
+        header.size
+    }
+    
+    /// Makes the collector free the allocation starting at `data` without running its destructor.
+    /// 
+    /// # Safety
+    /// `data` must point to the start of a block that is currently allocated in the GC heap,
+    /// and nothing else can be accessing its header.
+    pub(crate) unsafe fn forget_destructor(&self, data: NonNull<()>) {
+        debug_assert!(self.contains(data.as_ptr()));
+        // SAFETY: the header is always right before the data (see `GCHeapBlockHeader::shrink_to_fit`)
+        let header = unsafe { data.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()).as_mut() };
+        header.drop_thunk = None;
+    }
+    
+    /// Makes the collector run `T`'s destructor on the allocation starting at `data` when it frees it.
+    /// 
+    /// # Safety
+    /// Same as [`forget_destructor`](Self::forget_destructor). Also, the allocation has to
+    /// hold a valid `T` whenever the collector might free it.
+    pub(crate) unsafe fn set_destructor<T>(&self, data: NonNull<T>) {
+        #[allow(unsafe_op_in_unsafe_fn)]
+        unsafe fn dropper<T>(value: *mut ()) { std::ptr::drop_in_place(value as *mut T) }
+        
+        debug_assert!(self.contains(data.as_ptr()));
+        // SAFETY: the header is always right before the data (see `GCHeapBlockHeader::shrink_to_fit`)
+        let header = unsafe { data.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()).as_mut() };
+        header.drop_thunk = if std::mem::needs_drop::<T>() { Some(dropper::<T>) } else { None };
+    }
+    
+    /// The header of the block whose data starts at `data`.
+    /// 
+    /// # Safety
+    /// `data` must point to the start of a block that is currently allocated in the GC heap.
+    pub(crate) unsafe fn block_header(&self, data: NonNull<()>) -> NonNull<()> {
+        debug_assert!(self.contains(data.as_ptr()));
+        // SAFETY: the header is always right before the data (see `GCHeapBlockHeader::shrink_to_fit`)
+        unsafe { data.byte_sub(size_of::<GCHeapBlockHeader>()) }
+    }
+    
+    /// Whether `data` is the start of a block that is currently allocated in the GC heap, with
+    /// room for at least `size` bytes.
+    /// 
+    /// NOTE: the answer can change as soon as this returns (i.e: if the collector frees the block),
+    /// so it's only useful while something is keeping the block alive. This has to block all
+    /// allocations while it runs (like [`thread_stats`](Self::thread_stats)), since otherwise
+    /// other threads could be splitting or merging the blocks that it walks.
+    pub(crate) fn is_allocation_start(&self, data: *const (), size: usize) -> bool {
+        let _tl_allocators = THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+        let Some(block) = get_block(data) else { return false };
+        // SAFETY: `get_block` only finds real blocks
+        let block = unsafe { block.as_ref() };
+        let block_data = block.data();
+        block.is_allocated() && block_data.cast::<()>().as_ptr().cast_const() == data && size <= block_data.len()
+    }
+    
+    /// Like [`find_roots_to`](Self::find_roots_to), but takes the header of the block instead of its data.
+    /// 
+    /// Since pointers directly to a header don't count, the caller's own pointer to the block doesn't show up.
+    /// 
+    /// # Safety
+    /// `header` must point to the header of a block that is currently allocated in the GC heap.
+    pub(crate) unsafe fn find_roots_to_header(&self, header: NonNull<()>) -> Vec<RootLocation> {
+        collector::find_roots_to(header.cast())
+    }
+    
     /// Return whether or not a pointer points into the GC heap.
     pub fn contains<T: ?Sized>(&self, value: *const T) -> bool {
         MEMORY_SOURCE.contains(value as *const ())
     }
     
+    /// Returns the current state of every thread's allocator (including threads that have exited).
+    /// 
+    /// NOTE: this has to block all allocations while it runs, since the allocators aren't `Sync`.
+    pub fn thread_stats(&self) -> Vec<ThreadAllocStats> {
+        let mut tl_allocators = THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+        tl_allocators.iter_mut().map(|allocator| ThreadAllocStats {
+            thread: allocator.owner(),
+            free_bytes: allocator.free_bytes(),
+            free_blocks: allocator.free_blocks(),
+        }).collect()
+    }
+    
+    /// The number of bytes that could still be allocated without the heap running out of memory.
+    /// 
+    /// This is every thread's free bytes, plus however much of the address space that was set
+    /// aside for the heap hasn't been used yet.
+    /// 
+    /// NOTE: the free bytes are split up between threads (and blocks), so one allocation of this
+    /// size won't necessarily fit, and other threads can always allocate in the meantime. This
+    /// has to block all allocations while it runs, just like [`thread_stats`](Self::thread_stats).
+    pub fn available_bytes(&self) -> usize {
+        let mut tl_allocators = THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+        let free_bytes: usize = tl_allocators.iter_mut().map(|allocator| allocator.free_bytes()).sum();
+        free_bytes + MEMORY_SOURCE.headroom()
+    }
+    
+    /// Whether there is (probably) room in the heap for an object with the given layout.
+    /// 
+    /// This is meant for turning away requests that are way too big, before trying to allocate
+    /// them. Like [`available_bytes`](Self::available_bytes), it's only a hint.
+    pub fn can_allocate(&self, layout: Layout) -> bool {
+        // NOTE: the same restrictions as `TLAllocator::raw_allocate`
+        if layout.size() == 0 || layout.align() > 16 { return false }
+        layout.size().checked_add(size_of::<GCHeapBlockHeader>()).is_some_and(|size| size <= self.available_bytes())
+    }
+    
+    /// Stops the world, and calls `f` with a snapshot of every live object in the GC heap.
+    /// 
+    /// This finds the live objects the same way the collector does, but doesn't free anything
+    /// or run any destructors, which makes it useful for heap profiling and debugging.
+    /// 
+    /// NOTE: since every other thread is stopped while `f` runs, `f` must not allocate in the GC
+    /// heap, or block on anything another thread might be holding (including `stdout`).
+    pub fn with_heap_snapshot<R>(&self, f: impl FnOnce(&HeapSnapshot<'_>) -> R) -> R {
+        collector::with_live_blocks(|live_blocks| f(&HeapSnapshot { live_blocks }))
+    }
+    
+    /// Stops the world, and reports everywhere that a pointer into the object containing `ptr` was found.
+    /// 
+    /// This is meant for figuring out why something isn't getting collected, since the collector
+    /// treats anything that looks like a pointer as one. Returns nothing if `ptr` doesn't point into
+    /// an allocated object in the GC heap.
+    /// 
+    /// NOTE: the current thread gets scanned too, so wherever the caller is keeping `ptr` will
+    /// usually show up as well.
+    pub fn find_roots_to(&self, ptr: *const ()) -> Vec<RootLocation> {
+        match get_block(ptr) {
+            Some(block) if unsafe { block.as_ref() }.is_allocated() => collector::find_roots_to(block),
+            _ => Vec::new(),
+        }
+    }
+    
     /// Blocks until the GC has done a full collection cycle.
+    /// 
+    /// If the GC has been shut down, this returns immediately.
     pub fn wait_for_gc(&self) {
         debug!("Waiting for a GC cycle");
         
@@ -87,10 +426,166 @@ impl GCAllocator {
         let cycle = *guard;
         
         // block until the cycle number has incremented
-        while cycle == *guard {
+        // NOTE: after a shutdown, there might not be any more cycles
+        while cycle == *guard && !SHUTDOWN.load(Ordering::Acquire) {
             guard = GC_CYCLE_SIGNAL.wait(guard).unwrap();
         }
     }
+    
+    /// Sets how long the world can be stopped for at a time while marking, or `None` to stop it
+    /// for the entire cycle (the default).
+    /// 
+    /// With a target, the mark phase is split up into slices that each stop the world for about
+    /// that long, letting every other thread run in between. The OS keeps track of which pages of
+    /// the GC heap get written to in the meantime, and at the end, the roots and anything in those
+    /// pages get scanned again, so that nothing that changed while marking gets missed. This means
+    /// that every write counts, including ones through [`GcCell`](super::GcCell)s, `GcMut`s, or
+    /// any other kind of interior mutability, without them needing a write barrier.
+    /// 
+    /// NOTE: only the mark phase is split up. Finding the roots (at the start and the end of the
+    /// cycle) and sweeping still happen with the world stopped, since scanning a stack or running
+    /// a destructor can't be paused halfway. Also, a slice only checks the time between blocks,
+    /// so a huge object can make it run over.
+    /// 
+    /// This takes effect starting with the next collection cycle.
+    pub fn set_pause_target(&self, target: Option<Duration>) {
+        *PAUSE_TARGET.lock().unwrap() = target;
+    }
+    
+    /// How long the world was stopped for during the most recently finished collection cycle, if
+    /// there has been one.
+    pub fn last_cycle_pauses(&self) -> Option<CyclePauses> {
+        LAST_CYCLE_PAUSES.lock().unwrap().clone()
+    }
+    
+    /// Sets a function to be called at the start and end of every collection cycle, or `None` to
+    /// stop calling the one that was there before.
+    /// 
+    /// This is meant for instrumentation, like logging pauses or keeping track of metrics. The
+    /// callback runs on whichever thread is running the cycle (usually the collector thread, but
+    /// it can be any thread that calls [`drive_once`](Self::drive_once)), and only ever while every
+    /// other thread is running, so it's fine for it to allocate (or take locks that other threads
+    /// might be holding).
+    /// 
+    /// NOTE: the callback must not panic, or start a collection cycle itself (e.g: by calling
+    /// [`drive_once`](Self::drive_once) or [`wait_for_gc`](Self::wait_for_gc)), and it must not
+    /// call this method either, since all of those would deadlock.
+    pub fn set_collection_callback(&self, callback: Option<Box<dyn Fn(CycleEvent<'_>) + Send>>) {
+        *COLLECTION_CALLBACK.lock().unwrap() = callback;
+    }
+    
+    /// Runs exactly one collection cycle inline, on the current thread.
+    /// 
+    /// This stops every other thread while it runs, just like the collector thread does, and
+    /// wakes up anything that is waiting in [`wait_for_gc`](Self::wait_for_gc) when it's done.
+    /// 
+    /// NOTE: this must not be called while the current thread is in the middle of an allocation
+    /// (e.g: from inside a destructor), since that would deadlock.
+    pub fn drive_once(&self) {
+        while let Err(e) = collector::collect_cycle() {
+            warn!("Couldn't get the context of some thread (error 0x{e:x}), trying the cycle again");
+        }
+    }
+    
+    /// Stops the collector thread after it does one last collection cycle, and waits for it to exit.
+    /// 
+    /// After this, every allocation fails with [`GCAllocatorError::ShutDown`], but everything that
+    /// was already allocated stays valid. Nothing else gets collected unless [`drive_once`] is
+    /// called. Calling this more than once doesn't do anything.
+    /// 
+    /// [`drive_once`]: Self::drive_once
+    pub fn shutdown(&self) {
+        if SHUTDOWN.swap(true, Ordering::AcqRel) {
+            return
+        }
+        
+        info!("Shutting down the GC");
+        let collector = COLLECTOR_THREAD.lock().unwrap().take();
+        match collector {
+            Some(thread) => {
+                // wake it up, so it doesn't have to wait out the rest of its sleep
+                thread.thread().unpark();
+                thread.join().expect("the collector thread shouldn't panic");
+            }
+            // there is no collector thread (see `new_manual`), so do the last cycle here
+            None => self.drive_once(),
+        }
+    }
+    
+    /// Does a full collection cycle right now, on the current thread.
+    /// 
+    /// Unlike the collector thread, this doesn't stop (or scan) any other threads, so it is
+    /// completely deterministic, which makes it useful for tests. Everything that gets freed
+    /// is given back to the current thread's allocator.
+    /// 
+    /// # Safety
+    /// There must not be any other threads running that could be holding pointers into the GC
+    /// heap (on their stacks or in their registers). In particular, this means tests using it
+    /// must be run with `--test-threads=1`.
+    #[cfg(test)]
+    pub unsafe fn collect_now_single_threaded(&self) {
+        // make sure the current thread has an allocator to give the garbage back to
+        local_allocator(&THREAD_LOCAL_ALLOCATORS.read().unwrap()).expect("should be able to make an allocator");
+        unsafe { collector::collect_single_threaded() }
+    }
+    
+    /// Registers `len` bytes starting at `ptr` as an additional root, which gets scanned for
+    /// pointers into the GC heap every cycle until the returned [`RootHandle`] is dropped.
+    /// 
+    /// This is for keeping GCed values alive from memory that the collector doesn't already
+    /// know about, like memory that came straight from the OS.
+    /// 
+    /// # Safety
+    /// * `ptr` must be aligned to `align_of::<*const ()>()`
+    /// * the `len` bytes starting at `ptr` must stay valid for reads (from any thread) until
+    ///   the returned handle is dropped
+    pub unsafe fn register_root(&self, ptr: *const (), len: usize) -> RootHandle {
+        assert!(ptr.is_aligned_to(align_of::<*const ()>()), "roots must be pointer aligned");
+        let data = NonNull::from_raw_parts(NonNull::new(ptr as *mut ()).expect("roots must be non-null"), len);
+        
+        let id = NEXT_ROOT_ID.fetch_add(1, Ordering::Relaxed);
+        REGISTERED_ROOTS.lock().unwrap().push(RegisteredRoot { id, data, precise: false });
+        RootHandle { id }
+    }
+    
+    /// Registers a slice of [`Gc`](super::Gc)s as an additional root, which gets rescanned
+    /// every cycle until the returned [`RootHandle`] is dropped.
+    /// 
+    /// Unlike [`register_root`](Self::register_root), every element is treated as a pointer
+    /// into the GC heap, without checking whether it looks like one. This is mostly useful for
+    /// big `Vec<Gc<T>>`s, since the collector otherwise only finds them by (conservatively)
+    /// scanning the whole process heap.
+    /// 
+    /// # Safety
+    /// The slice must stay valid for reads (from any thread) until the returned handle is
+    /// dropped. In particular, a `Vec` it came from can't be reallocated (or dropped) until then.
+    /// Changing the elements is fine though, since they get read again every cycle.
+    pub unsafe fn register_gc_slice<T>(&self, slice: &[super::Gc<T>]) -> RootHandle {
+        // NOTE: `T: Sized`, so every element is just a (thin) pointer
+        let data = NonNull::from(slice).cast::<u8>();
+        let data = NonNull::from_raw_parts(data, size_of_val(slice));
+        
+        let id = NEXT_ROOT_ID.fetch_add(1, Ordering::Relaxed);
+        REGISTERED_ROOTS.lock().unwrap().push(RegisteredRoot { id, data, precise: true });
+        RootHandle { id }
+    }
+}
+
+/// A handle to a root registered with [`GCAllocator::register_root`].
+/// 
+/// The root gets unregistered when this is dropped.
+#[must_use = "the root is unregistered as soon as the handle is dropped"]
+#[derive(Debug)]
+pub struct RootHandle {
+    id: usize,
+}
+
+impl Drop for RootHandle {
+    fn drop(&mut self) {
+        let mut roots = REGISTERED_ROOTS.lock().unwrap();
+        let index = roots.iter().position(|root| root.id == self.id).expect("root should still be registered");
+        roots.swap_remove(index);
+    }
 }
 
 unsafe impl Allocator for GCAllocator {
@@ -99,19 +594,91 @@ unsafe impl Allocator for GCAllocator {
         if layout.size() == 0 {
             return Err(std::alloc::AllocError) // pls no ZSTs thx
         }
+        if SHUTDOWN.load(Ordering::Acquire) {
+            return Err(AllocError)
+        }
         
         let tl_reader = THREAD_LOCAL_ALLOCATORS.read().unwrap();
-        let allocator = tl_reader.get_or_try(|| TLAllocator::try_new(MEMORY_SOURCE)).map_err(|_| AllocError)?;
+        let allocator = local_allocator(&tl_reader).map_err(|_| AllocError)?;
         
         let (_header, block) = allocator.raw_allocate(layout).map_err(|_| AllocError)?;
         
         Ok(block)
     }
     
+    /// Behaves like [`allocate`](Allocator::allocate), but also ensures that the returned memory is zero-initialized.
+    /// 
+    /// Blocks that have never been allocated before are still zeroed from
+    /// when they came from the OS, so this only has to zero reused blocks.
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Err(std::alloc::AllocError) // pls no ZSTs thx
+        }
+        if SHUTDOWN.load(Ordering::Acquire) {
+            return Err(AllocError)
+        }
+        
+        let tl_reader = THREAD_LOCAL_ALLOCATORS.read().unwrap();
+        let allocator = local_allocator(&tl_reader).map_err(|_| AllocError)?;
+        
+        let (header, block) = allocator.raw_allocate(layout).map_err(|_| AllocError)?;
+        
+        if !header.is_pristine() {
+            // SAFETY: we just allocated the block, so nobody else is using it
+            unsafe { block.cast::<u8>().write_bytes(0, block.len()) };
+        }
+        
+        Ok(block)
+    }
+    
+    /// Makes an allocation bigger, moving it only if it can't be grown in place.
+    /// 
+    /// If the block right after it in memory is free (and in the current thread's free list),
+    /// it just gets taken over. Otherwise, this allocates a new block and copies everything over,
+    /// along with the old block's destructor (if it had one).
+    /// 
+    /// # Safety
+    /// See [`Allocator::grow`].
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() >= old_layout.size(), "`new_layout` has to be at least as big as `old_layout`");
+        assert!(self.contains(ptr.as_ptr()), "Grown pointer should point into the GC heap");
+        if SHUTDOWN.load(Ordering::Acquire) {
+            return Err(AllocError)
+        }
+        
+        // SAFETY: the header is always right before the data (see `GCHeapBlockHeader::shrink_to_fit`)
+        let block = unsafe { ptr.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()) };
+        
+        if ptr.is_aligned_to(new_layout.align()) {
+            let tl_reader = THREAD_LOCAL_ALLOCATORS.read().unwrap();
+            // SAFETY: the block is allocated, and the free list is only ever used by its own thread
+            if let Some(allocator) = tl_reader.get()
+                && unsafe { allocator.try_grow_in_place(block, new_layout.size()) }
+            {
+                return Ok(unsafe { block.as_ref() }.data())
+            }
+        }
+        
+        // it has to be moved
+        let new = self.allocate(new_layout)?;
+        // SAFETY: `new` is a fresh allocation, so it can't overlap with `ptr`
+        unsafe { ptr.copy_to_nonoverlapping(new.cast(), old_layout.size()) };
+        
+        // keep the destructor with the value (`deallocate` gets rid of the old one)
+        let new_block = unsafe { new.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()) };
+        unsafe { (*new_block.as_ptr()).drop_thunk = (*block.as_ptr()).drop_thunk };
+        unsafe { self.deallocate(ptr, old_layout) };
+        
+        Ok(new)
+    }
+    
     /// Frees a piece of memory in the GC heap referenced by `ptr`.
     /// 
     /// This does **not** run any destructor associated with the type in the heap.
     /// 
+    /// If the memory came from the current thread's allocator, it goes straight back
+    /// into its free list. Otherwise, it gets sent to the collector to be freed later.
+    /// 
     /// # Safety
     /// (taken from [`Allocator::deallocate`])
     /// * `ptr` must denote a block of memory [*currently allocated*] via this allocator
@@ -121,13 +688,31 @@ unsafe impl Allocator for GCAllocator {
         // sanity check
         assert!(ptr.is_aligned_to(layout.align()));
         
+        assert!(self.contains(ptr.as_ptr()), "Freed pointer should point into the GC heap");
+        
         let data: NonNull<[u8]> = NonNull::from_raw_parts(ptr, layout.size());
         
         // If we got here, we can't run the destructor again
-        // TODO: should we just `unwrap_unchecked` here? this is a pretty reasonable precondition
-        let block = get_block(ptr.as_ptr() as _).expect("Freed pointer should point into the GC heap").as_ptr();
-        unsafe { (*block).drop_thunk = None };
+        // SAFETY: the header is always right before the data (see `GCHeapBlockHeader::shrink_to_fit`)
+        let block = unsafe { ptr.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()) };
+        unsafe { (*block.as_ptr()).drop_thunk = None };
+        
+        // NOTE: this has to be `try_read`, since destructors run by the collector (which holds
+        // the write lock) can also deallocate things
+        // NOTE: while an incremental mark phase is going, the block might be waiting to be
+        // scanned, so it can't be reused (or merged into another block) until the cycle is done.
+        // (`MARKING` only changes while the collector holds the write lock)
+        if let Ok(tl_reader) = THREAD_LOCAL_ALLOCATORS.try_read()
+            && !MARKING.load(Ordering::Relaxed)
+            && let Some(allocator) = tl_reader.get()
+            && allocator.owns(block)
+        {
+            allocator.reclaim_block(block);
+            return
+        }
         
+        #[cfg(test)]
+        NUM_SENT_TO_COLLECTOR.set(NUM_SENT_TO_COLLECTOR.get() + 1);
         DEALLOCATED_CHANNEL.wait().send(data.into()).expect("The GC thread shouldn't ever exit");
     }
 }
@@ -145,6 +730,565 @@ pub static GC_ALLOCATOR: LazyLock<GCAllocator> = LazyLock::new(|| {
     ).unwrap();
     
     // start collector thread
-    std::thread::spawn(gc_main);
-    GCAllocator
+    let allocator = GCAllocator::new_manual();
+    let collector = std::thread::Builder::new().name("gc collector".into()).spawn(gc_main).expect("should be able to start the collector thread");
+    *COLLECTOR_THREAD.lock().unwrap() = Some(collector);
+    allocator
 });
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn is_zeroed(block: NonNull<[u8]>) -> bool {
+        unsafe { block.as_ref() }.iter().all(|&b| b == 0)
+    }
+    
+    #[test]
+    fn test_allocate_zeroed() {
+        const N: usize = 32;
+        let layout = Layout::new::<[u64; 32]>();
+        
+        // fresh blocks
+        let blocks: Vec<_> = (0..N).map(|_| GC_ALLOCATOR.allocate_zeroed(layout).unwrap()).collect();
+        for &block in &blocks {
+            assert!(is_zeroed(block));
+            unsafe { block.cast::<u8>().write_bytes(0xAB, block.len()) };
+        }
+        
+        // give the (now dirty) blocks back to the GC, so they can be reused
+        for block in blocks {
+            unsafe { GC_ALLOCATOR.deallocate(block.cast(), layout) };
+        }
+        GC_ALLOCATOR.wait_for_gc();
+        
+        // whether or not these got reused, they should all be zeroed
+        let mut num_reused = 0;
+        for _ in 0..4*N {
+            let block = GC_ALLOCATOR.allocate_zeroed(layout).unwrap();
+            let header = unsafe { block.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()).as_ref() };
+            if !header.is_pristine() { num_reused += 1 }
+            assert!(is_zeroed(block));
+        }
+        debug!("test_allocate_zeroed: reused {num_reused} blocks");
+        
+        // the dirty blocks all went back into this thread's free lists (either right away, or during
+        // the cycle), in front of anything that was already there, so they have to get handed out again
+        assert!(num_reused > 0, "none of the dirty blocks got reused, so zeroing them was never tested");
+    }
+    
+    #[test]
+    fn test_out_of_memory_error() {
+        let tl_reader = THREAD_LOCAL_ALLOCATORS.read().unwrap();
+        let allocator = tl_reader.get_or_try(|| TLAllocator::try_new(MEMORY_SOURCE, &BLOCK_INDEX)).unwrap();
+        
+        // way bigger than the amount of address space that is reserved for the heap
+        let layout = Layout::from_size_align(1 << 60, 8).unwrap();
+        let err = allocator.raw_allocate(layout).map(|_| ()).unwrap_err();
+        
+        let GCAllocatorError::OutOfMemory { requested, committed } = err else {
+            panic!("expected an `OutOfMemory` error, got {err:?}")
+        };
+        assert!(requested >= layout.size());
+        assert!(committed > 0);
+        assert_eq!(err, GCAllocatorError::OutOfMemory { requested, committed });
+        assert_ne!(err, GCAllocatorError::ZeroSized);
+        
+        assert_eq!(allocator.raw_allocate(Layout::new::<()>()).map(|_| ()).unwrap_err(), GCAllocatorError::ZeroSized);
+    }
+    
+    #[test]
+    fn test_available_bytes() {
+        // big enough that whatever the other tests free in the meantime doesn't matter
+        let layout = Layout::array::<u8>(1 << 26).unwrap();
+        assert!(GC_ALLOCATOR.can_allocate(layout));
+        
+        let before = GC_ALLOCATOR.available_bytes();
+        let data = GC_ALLOCATOR.allocate(layout).unwrap();
+        let after = GC_ALLOCATOR.available_bytes();
+        assert!(after < before, "available bytes should go down after allocating ({before} -> {after})");
+        unsafe { GC_ALLOCATOR.deallocate(data.cast(), layout) };
+        
+        // an exabyte is never going to fit
+        assert!(!GC_ALLOCATOR.can_allocate(Layout::from_size_align(1 << 60, 8).unwrap()));
+        assert!(!GC_ALLOCATOR.can_allocate(Layout::new::<()>()));
+    }
+    
+    #[test]
+    fn test_thread_stats() {
+        const T: usize = 4;
+        
+        let handles: Vec<_> = (0..T).map(|i| std::thread::spawn(move || {
+            // allocate more on some threads than others
+            let values: Vec<_> = (0..100 * (i + 1)).map(|j| crate::gc::Gc::new([j; 4])).collect();
+            let stats = GC_ALLOCATOR.thread_stats();
+            let ours = stats.iter().find(|s| s.thread == std::thread::current().id()).expect("should have stats for this thread");
+            assert!(ours.free_blocks > 0);
+            drop(values);
+            std::thread::current().id()
+        })).collect();
+        let ids: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        
+        // the stats should stick around, even after the threads exit
+        let stats = GC_ALLOCATOR.thread_stats();
+        for id in ids {
+            assert_eq!(stats.iter().filter(|s| s.thread == id).count(), 1);
+        }
+    }
+    
+    /// Memory that was freed on a thread that has since exited should get handed to some thread that's still running.
+    #[test]
+    fn test_exited_thread_memory_reused() {
+        let layout = Layout::array::<u8>(1 << 16).unwrap();
+        let address = std::thread::spawn(move || {
+            let data = GC_ALLOCATOR.allocate(layout).unwrap();
+            // this usually goes right back into this thread's free list (see `test_local_reclamation`)
+            unsafe { GC_ALLOCATOR.deallocate(data.cast(), layout) };
+            data.addr().get()
+        }).join().unwrap();
+        
+        // NOTE: the first cycle might have already been going before the thread exited
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        
+        // NOTE: this is just for comparing addresses, so it doesn't need any provenance
+        let block = NonNull::<GCHeapBlockHeader>::without_provenance(std::num::NonZero::new(address - size_of::<GCHeapBlockHeader>()).unwrap());
+        let live_threads = os_dependent::get_live_thread_ids();
+        let mut tl_allocators = THREAD_LOCAL_ALLOCATORS.write().unwrap();
+        let allocator = tl_allocators.iter_mut().find(|allocator| allocator.owns(block)).expect("some allocator should have the block's memory");
+        assert!(live_threads.contains(&allocator.os_thread_id()), "the block's memory still belongs to an exited thread");
+    }
+    
+    /// Blocks freed by the collector should go back to the thread whose memory they're in (even if
+    /// some other thread has less free memory), and get merged with their neighbours on the way.
+    #[test]
+    fn test_freed_blocks_go_home() {
+        const N: usize = 2000;
+        let barrier = std::sync::Barrier::new(3);
+        
+        std::thread::scope(|s| {
+            let heavy = s.spawn(|| {
+                // these are all garbage right away
+                for i in 0..N {
+                    std::hint::black_box(crate::gc::Gc::new([i; 4]));
+                }
+                barrier.wait();
+                barrier.wait();
+            });
+            let light = s.spawn(|| {
+                std::hint::black_box(crate::gc::Gc::new(0usize));
+                barrier.wait();
+                barrier.wait();
+            });
+            
+            barrier.wait();
+            // NOTE: the first cycle might have already been going before the threads were done
+            GC_ALLOCATOR.wait_for_gc();
+            GC_ALLOCATOR.wait_for_gc();
+            let stats = GC_ALLOCATOR.thread_stats();
+            barrier.wait();
+            
+            let [heavy, light] = [heavy.thread().id(), light.thread().id()].map(|id| {
+                *stats.iter().find(|s| s.thread == id).expect("should have stats for the thread")
+            });
+            // (a few of them might still be pointed to from some stale stack slot)
+            let freed = (N - 10) * size_of::<[usize; 4]>();
+            assert!(heavy.free_bytes >= freed, "only 0x{:x} of the 0x{freed:x} freed bytes went back to the thread that allocated them", heavy.free_bytes);
+            assert!(light.free_bytes < freed, "the other thread got 0x{:x} free bytes", light.free_bytes);
+            assert!(heavy.free_blocks < N / 10, "the freed blocks should have been merged, but there are still {} of them", heavy.free_blocks);
+        });
+    }
+    
+    #[test]
+    fn test_heap_snapshot() {
+        use crate::gc::{Gc, GcMut};
+        
+        struct HasDrop(#[allow(unused)] u64);
+        impl Drop for HasDrop {
+            fn drop(&mut self) {}
+        }
+        
+        let plain: Vec<Gc<[u64; 4]>> = (0..10).map(|i| Gc::new([i; 4])).collect();
+        let with_drop = GcMut::new(HasDrop(5));
+        let nested = Gc::new(Gc::new(123u64)); // the inner one is only reachable through the outer one
+        
+        // NOTE: the snapshot can't allocate, so just pull out what we need
+        let (plain_objects, drop_object, inner_object, num_objects) = GC_ALLOCATOR.with_heap_snapshot(|snapshot| {
+            let plain_objects = [0, 5, 9].map(|i| snapshot.find(plain[i].as_ptr().cast()));
+            let drop_object = snapshot.find(with_drop.as_ptr().cast());
+            let inner_object = snapshot.find((*nested).as_ptr().cast());
+            (plain_objects, drop_object, inner_object, snapshot.objects().count())
+        });
+        
+        for (object, i) in plain_objects.into_iter().zip([0, 5, 9]) {
+            let object = object.expect("should be in the live set");
+            assert_eq!(object.address, plain[i].as_ptr().cast());
+            assert!(object.size >= size_of::<[u64; 4]>());
+            assert!(!object.has_destructor);
+        }
+        assert!(drop_object.expect("should be in the live set").has_destructor);
+        assert!(inner_object.is_some());
+        assert!(num_objects >= plain.len() + 3);
+        
+        // nothing should have been freed
+        assert!(plain.iter().enumerate().all(|(i, x)| **x == [i as u64; 4]));
+        assert_eq!(**nested, 123);
+    }
+    
+    /// Freeing a `GcMut` should put its block right back into this thread's free list, without waiting for the GC
+    #[test]
+    fn test_local_reclamation() {
+        const N: usize = 100;
+        
+        let sent_before = NUM_SENT_TO_COLLECTOR.get();
+        let mut num_reused = 0;
+        for i in 0..N {
+            let x = crate::gc::GcMut::new([i; 5]);
+            let ptr = x.as_ptr();
+            drop(x);
+            let y = crate::gc::GcMut::new([i + 1; 5]);
+            if y.as_ptr() == ptr { num_reused += 1 }
+        }
+        let num_sent = NUM_SENT_TO_COLLECTOR.get() - sent_before;
+        
+        // NOTE: the collector can (rarely) add other blocks to the free list in between, and blocks
+        // freed while it's marking have to go through it anyways
+        assert!(num_reused > N / 2, "only reused {num_reused} blocks out of {N}");
+        assert!(num_sent < N / 10, "sent {num_sent} out of {N} blocks to the collector");
+        
+        // blocks that belong to another thread's allocator can't be reclaimed here
+        let blocks: Vec<_> = (0..N).map(|i| crate::gc::GcMut::new([i; 5])).collect();
+        let num_sent = std::thread::spawn(move || {
+            drop(blocks);
+            NUM_SENT_TO_COLLECTOR.get()
+        }).join().unwrap();
+        assert!(num_sent > 0, "none of the other thread's blocks were sent to the collector");
+    }
+    
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+    
+    struct DropCounter(usize);
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    /// Allocates a `Gc` and stores it *only* into `region`, so that nothing on the stack refers to it.
+    #[inline(never)]
+    fn store_gc(region: *mut crate::gc::Gc<DropCounter>) {
+        unsafe { region.write(crate::gc::Gc::new(DropCounter(1234))) }
+    }
+    
+    #[test]
+    fn test_register_root() {
+        use windows_sys::Win32::System::Memory::{MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE, VirtualAlloc, VirtualFree};
+        const SIZE: usize = 4096;
+        
+        // NOTE: this can't just be a `Box`, since the collector already scans the process heap.
+        let region = unsafe { VirtualAlloc(std::ptr::null(), SIZE, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE) }.cast::<crate::gc::Gc<DropCounter>>();
+        assert!(!region.is_null());
+        
+        let handle = unsafe { GC_ALLOCATOR.register_root(region as *const (), SIZE) };
+        store_gc(region);
+        
+        // the only thing keeping the value alive is the registration
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
+        assert_eq!(unsafe { region.read() }.0, 1234);
+        
+        drop(handle);
+        unsafe { VirtualFree(region.cast(), 0, MEM_RELEASE) };
+    }
+    
+    #[test]
+    fn test_grow() {
+        let mut vec: Vec<usize, &GCAllocator> = Vec::new_in(&GC_ALLOCATOR);
+        vec.extend(0..10_000);
+        assert!(vec.iter().enumerate().all(|(i, &x)| x == i));
+        
+        // a block right before a free one (in this thread's free list) should grow into it
+        let small = Layout::array::<usize>(16).unwrap();
+        let big = Layout::array::<usize>(32).unwrap();
+        let header_of = |data: NonNull<usize>| unsafe { data.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()) };
+        
+        // NOTE: two allocations in a row are usually next to each other, but they might not be
+        //       if they got fit into leftover free blocks, so keep going until a pair is
+        let mut leftovers = Vec::new();
+        let (ptr, next) = loop {
+            let ptr = GC_ALLOCATOR.allocate(small).unwrap().cast::<usize>();
+            let next = GC_ALLOCATOR.allocate(small).unwrap().cast::<usize>();
+            if unsafe { header_of(ptr).as_ref() }.next() == header_of(next) { break (ptr, next) }
+            leftovers.extend([ptr, next]);
+        };
+        for i in 0..16 {
+            unsafe { ptr.add(i).write(i) };
+        }
+        unsafe { GC_ALLOCATOR.deallocate(next.cast(), small) };
+        let grown = unsafe { GC_ALLOCATOR.grow(ptr.cast(), small, big) }.unwrap();
+        assert_eq!(grown.cast::<usize>(), ptr, "should have grown in place");
+        assert!(grown.len() >= big.size());
+        assert!((0..16).all(|i| unsafe { ptr.add(i).read() } == i));
+        unsafe { GC_ALLOCATOR.deallocate(grown.cast(), big) };
+        for leftover in leftovers {
+            unsafe { GC_ALLOCATOR.deallocate(leftover.cast(), small) };
+        }
+        
+        // the destructor has to stay with the value, whether or not it gets moved
+        struct NeedsDrop(usize);
+        impl Drop for NeedsDrop {
+            fn drop(&mut self) {}
+        }
+        
+        let old_layout = Layout::new::<NeedsDrop>();
+        let new_layout = Layout::from_size_align(0x10000, align_of::<NeedsDrop>()).unwrap();
+        let ptr = GC_ALLOCATOR.allocate_for_value(NeedsDrop(5)).map_err(|(e, _)| e).unwrap();
+        // NOTE: this usually ends up right after it, so it can't grow in place
+        let blocker = GC_ALLOCATOR.allocate(old_layout).unwrap();
+        let grown = unsafe { GC_ALLOCATOR.grow(ptr.cast(), old_layout, new_layout) }.unwrap();
+        let header = unsafe { grown.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()).as_ref() };
+        assert!(header.drop_thunk.is_some());
+        assert!(header.size >= new_layout.size());
+        assert_eq!(unsafe { grown.cast::<NeedsDrop>().as_ref() }.0, 5);
+        
+        unsafe { GC_ALLOCATOR.deallocate(blocker.cast(), old_layout) };
+    }
+    
+    /// Keeps spawning threads (which spawn more threads) while collections are happening, and
+    /// makes sure nothing that only a brand new thread's stack points to gets collected
+    #[test]
+    fn test_spawn_during_collection() {
+        use std::sync::atomic::AtomicBool;
+        const THREADS: usize = 64;
+        static DROPPED: [AtomicBool; 2 * THREADS] = [const { AtomicBool::new(false) }; 2 * THREADS];
+        
+        struct Flagged(usize);
+        impl Drop for Flagged {
+            fn drop(&mut self) {
+                DROPPED[self.0].store(true, Ordering::Relaxed);
+            }
+        }
+        
+        fn check(i: usize) {
+            let value = crate::gc::Gc::new(Flagged(i));
+            // give the collector a chance to run while this stack is the only thing pointing to it
+            GC_ALLOCATOR.wait_for_gc();
+            assert!(!DROPPED[i].load(Ordering::Relaxed), "value {i} got collected while it was still alive");
+            assert_eq!(value.0, i);
+        }
+        
+        std::thread::scope(|s| {
+            for i in 0..THREADS {
+                s.spawn(move || {
+                    // NOTE: this might get started while the world is being stopped
+                    let child = std::thread::spawn(move || check(THREADS + i));
+                    check(i);
+                    child.join().unwrap();
+                });
+            }
+        });
+    }
+    
+    #[test]
+    fn test_register_gc_slice() {
+        use crate::gc::Gc;
+        const N: usize = 100_000;
+        
+        let mut values: Vec<Gc<usize>> = (0..N).map(Gc::new).collect();
+        let handle = unsafe { GC_ALLOCATOR.register_gc_slice(&values) };
+        
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        assert!(values.iter().enumerate().all(|(i, value)| **value == i));
+        
+        // the slice gets read again every cycle, so new elements should be kept alive too
+        for value in values.iter_mut().step_by(2) {
+            *value = Gc::new(**value * 2);
+        }
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        assert!(values.iter().enumerate().all(|(i, value)| **value == if i % 2 == 0 { i * 2 } else { i }));
+        
+        drop(handle);
+    }
+    
+    /// Collecting with a manual allocator shouldn't need the collector thread at all
+    #[test]
+    fn test_manual_drive() {
+        const N: usize = 100;
+        static NUM_MANUAL_DROPS: AtomicUsize = AtomicUsize::new(0);
+        
+        struct ManualDropCounter(#[allow(unused)] [usize; 4]);
+        impl Drop for ManualDropCounter {
+            fn drop(&mut self) {
+                NUM_MANUAL_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        /// Makes sure none of the pointers are still sitting around in this stack frame
+        #[inline(never)]
+        fn make_garbage(allocator: &GCAllocator) {
+            for i in 0..N {
+                allocator.allocate_for_value(ManualDropCounter([i; 4])).map_err(|(e, _)| e).unwrap();
+            }
+        }
+        
+        let allocator = GCAllocator::new_manual();
+        make_garbage(&allocator);
+        allocator.drive_once();
+        
+        // NOTE: some of them can still be in registers or something, since the scan is conservative
+        let num_drops = NUM_MANUAL_DROPS.load(Ordering::Relaxed);
+        assert!(num_drops > N / 2, "only reclaimed {num_drops} objects out of {N}");
+    }
+    
+    #[test]
+    fn test_incremental_pauses() {
+        use crate::gc::{Gc, GcCell};
+        
+        const N: usize = 50_000;
+        const TARGET: Duration = Duration::from_millis(1);
+        
+        struct Node {
+            value: usize,
+            next: Option<Gc<Node>>,
+        }
+        
+        // a long list, so that marking it takes a bunch of slices
+        let mut head = None;
+        for value in 0..N {
+            head = Some(Gc::new(Node { value, next: head }));
+        }
+        
+        // keeps storing new objects in an old one while the world is running between slices
+        let cell = GcCell::new(Gc::new(0usize));
+        let stop = AtomicBool::new(false);
+        
+        let allocator = GCAllocator::new_manual();
+        allocator.set_pause_target(Some(TARGET));
+        let (pauses, last) = std::thread::scope(|s| {
+            let mutator = s.spawn(|| {
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    i += 1;
+                    cell.set(Gc::new(i));
+                }
+                i
+            });
+            allocator.drive_once();
+            stop.store(true, Ordering::Relaxed);
+            (allocator.last_cycle_pauses().unwrap(), mutator.join().unwrap())
+        });
+        allocator.set_pause_target(None);
+        
+        // NOTE: how long each slice actually took depends on the machine, so this just checks that
+        // the cycle took the incremental path, and that the list was too long to mark in one slice
+        assert_ne!(pauses.start, Duration::ZERO, "the cycle wasn't incremental: {pauses:?}");
+        assert!(pauses.mark_slices.len() > 1, "the mark phase wasn't split up: {pauses:?}");
+        assert_ne!(pauses.finish, Duration::ZERO, "{pauses:?}");
+        
+        // nothing that was still reachable should have been freed
+        assert_eq!(*cell.get(), last);
+        let mut node = head.as_deref();
+        for value in (0..N).rev() {
+            let current = node.expect("the list should still be all there");
+            assert_eq!(current.value, value);
+            node = current.next.as_deref();
+        }
+        assert!(node.is_none());
+    }
+    
+    #[test]
+    fn test_collection_callback() {
+        /// The cycle number of every `End` event, or `None` for `Start` events
+        static EVENTS: Mutex<Vec<Option<usize>>> = Mutex::new(Vec::new());
+        
+        let allocator = GCAllocator::new_manual();
+        allocator.set_collection_callback(Some(Box::new(|event: CycleEvent<'_>| {
+            // NOTE: this allocates, which should be fine since the world isn't stopped
+            EVENTS.lock().unwrap().push(match event {
+                CycleEvent::Start => None,
+                CycleEvent::End { cycle, .. } => Some(cycle),
+            });
+        })));
+        for _ in 0..3 {
+            allocator.drive_once();
+        }
+        allocator.set_collection_callback(None);
+        let events = std::mem::take(&mut *EVENTS.lock().unwrap());
+        
+        // NOTE: the collector thread might have run some cycles of its own in between
+        let ends = Vec::from_iter(events.iter().flatten().copied());
+        assert!(ends.len() >= 3, "{events:?}");
+        assert!(ends.windows(2).all(|w| w[0] < w[1]), "cycle numbers should go up: {events:?}");
+        // every cycle from `drive_once` should have been started first
+        assert!(events.iter().filter(|e| e.is_none()).count() >= 3);
+        
+        // nothing should get called after it's been unset
+        allocator.drive_once();
+        assert!(EVENTS.lock().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_find_roots_to() {
+        use std::sync::{Barrier, mpsc};
+        use windows_sys::Win32::System::Threading::GetCurrentThreadId;
+        
+        let (sender, reciever) = mpsc::channel();
+        let barrier = Barrier::new(2);
+        
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let x = crate::gc::Gc::new([0x1234usize; 4]);
+                // make sure `x` actually lives somewhere on this thread's stack
+                let slot = std::hint::black_box(&x) as *const _ as *const ();
+                sender.send((x.as_ptr() as *const (), unsafe { GetCurrentThreadId() }, slot)).unwrap();
+                barrier.wait();
+                drop(std::hint::black_box(x));
+            });
+            
+            let (ptr, thread_id, slot) = reciever.recv().unwrap();
+            let roots = GC_ALLOCATOR.find_roots_to(ptr);
+            barrier.wait();
+            
+            assert!(
+                roots.contains(&RootLocation::Stack { thread_id, address: slot }),
+                "didn't find the pointer on the other thread's stack (found {roots:016x?})"
+            );
+        });
+        
+        assert!(GC_ALLOCATOR.find_roots_to(std::ptr::null()).is_empty());
+    }
+    
+    /// NOTE: shutting down is permanent (and affects every other test), so this runs in its own process.
+    #[test]
+    #[ignore = "shuts down the GC for the whole process, it gets run by `test_shutdown`"]
+    fn shutdown_child() {
+        let x = crate::gc::GcMut::new(1234u64);
+        GC_ALLOCATOR.shutdown();
+        
+        // the collector thread should have been joined
+        assert!(COLLECTOR_THREAD.lock().unwrap().is_none());
+        
+        assert!(matches!(crate::gc::GcMut::try_new(5u64), Err((GCAllocatorError::ShutDown, 5))));
+        assert!(GC_ALLOCATOR.allocate(Layout::new::<u64>()).is_err());
+        assert!(GC_ALLOCATOR.allocate_zeroed(Layout::new::<u64>()).is_err());
+        
+        // old allocations should still work, and nothing should block forever
+        assert_eq!(*x, 1234);
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.shutdown();
+    }
+    
+    #[test]
+    fn test_shutdown() {
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["gc::allocator::tests::shutdown_child", "--exact", "--ignored"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
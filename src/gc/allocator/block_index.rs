@@ -0,0 +1,241 @@
+//! A coarse summary of where blocks are in the GC heap, so that walking it doesn't have to visit every single block.
+
+use std::num::NonZero;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use super::heap_block_header::GCHeapBlockHeader;
+
+
+/// The number of bytes of the heap that each [`Chunk`] keeps track of.
+const CHUNK_SIZE: usize = 0x4000;
+/// The number of chunks in each [`ChunkTable`], so each table covers 64MiB of memory.
+const CHUNKS_PER_TABLE: usize = 0x1000;
+/// The number of tables in each [`Directory`], so each directory covers 1TiB of memory.
+const TABLES_PER_DIRECTORY: usize = 0x4000;
+/// The number of bits in a user-mode address.
+const ADDRESS_BITS: u32 = 47;
+/// The number of directories needed to cover every possible address.
+const NUM_DIRECTORIES: usize = (1 << ADDRESS_BITS) / (CHUNK_SIZE * CHUNKS_PER_TABLE * TABLES_PER_DIRECTORY);
+
+/// What is known about a single [`CHUNK_SIZE`]-byte range of the heap.
+#[derive(Default)]
+struct Chunk {
+    /// The address of the first block header in this chunk, or `0` if there isn't one
+    /// (i.e: the whole chunk is in the middle of some bigger block).
+    /// 
+    /// NOTE: this is just an address, and not a pointer, so that it doesn't count as a root.
+    /// (even if it did, pointers directly to block headers get ignored by the collector)
+    first_block: AtomicUsize,
+    /// The number of allocated blocks whose headers are in this chunk.
+    num_allocated: AtomicUsize,
+}
+
+type ChunkTable = [Chunk; CHUNKS_PER_TABLE];
+type Directory = [AtomicPtr<ChunkTable>; TABLES_PER_DIRECTORY];
+
+/// Keeps track of where blocks start, and how many of them are allocated, for every
+/// [`CHUNK_SIZE`] bytes of the heap.
+/// 
+/// This lets [`find_block`](Self::find_block) start walking the heap right before the block it's
+/// looking for, and [`blocks`](Self::blocks) skip over whole chunks that have nothing allocated in
+/// them, instead of both having to go through every block from the start of the heap.
+/// 
+/// Block headers mostly only get created (by expanding the heap or splitting blocks), so all
+/// this needs to be told about is new blocks, blocks being allocated or freed, and the rare
+/// free block getting merged into the one before it (when growing an allocation in place).
+/// 
+/// Chunks are looked up by their address (like a page table), since the heap can be made of
+/// multiple regions anywhere in the address space. The directories and tables are allocated
+/// the first time a block is put in them, since the heap only ever uses a tiny part of that.
+pub(super) struct BlockIndex {
+    directories: [AtomicPtr<Directory>; NUM_DIRECTORIES],
+}
+
+/// Gets the array that `slot` points to, allocating it (filled with `T::default()`) if it hasn't been yet.
+fn load_or_insert<T: Default, const N: usize>(slot: &AtomicPtr<[T; N]>) -> &[T; N] {
+    if slot.load(Ordering::Acquire).is_null() {
+        let array: Box<[T]> = (0..N).map(|_| T::default()).collect();
+        let array: Box<[T; N]> = array.try_into().unwrap_or_else(|_| unreachable!());
+        let array = Box::into_raw(array);
+        if slot.compare_exchange(std::ptr::null_mut(), array, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            // somebody else got to it first, so just use theirs
+            // SAFETY: this array never got shared with anyone
+            drop(unsafe { Box::from_raw(array) });
+        }
+    }
+    
+    // SAFETY: arrays only get freed when the index gets dropped
+    unsafe { &*slot.load(Ordering::Acquire) }
+}
+
+impl BlockIndex {
+    pub(super) const fn new() -> Self {
+        Self { directories: [const { AtomicPtr::new(std::ptr::null_mut()) }; NUM_DIRECTORIES] }
+    }
+    
+    /// The index of the chunk that an address is in.
+    fn chunk_index(addr: usize) -> usize {
+        addr / CHUNK_SIZE
+    }
+    
+    /// Which directory and table a chunk is in, and where it is in that table.
+    fn split_index(index: usize) -> (usize, usize, usize) {
+        let table = index / CHUNKS_PER_TABLE;
+        (table / TABLES_PER_DIRECTORY, table % TABLES_PER_DIRECTORY, index % CHUNKS_PER_TABLE)
+    }
+    
+    /// Gets a chunk, if its table has been allocated.
+    fn chunk(&self, index: usize) -> Option<&Chunk> {
+        let (directory, table, chunk) = Self::split_index(index);
+        // SAFETY: directories and tables only get freed when the index gets dropped
+        let directory = unsafe { self.directories.get(directory)?.load(Ordering::Acquire).as_ref() }?;
+        let table = unsafe { directory[table].load(Ordering::Acquire).as_ref() }?;
+        Some(&table[chunk])
+    }
+    
+    /// Gets a chunk, allocating its directory and table if they haven't been yet.
+    fn chunk_or_insert(&self, index: usize) -> &Chunk {
+        let (directory, table, chunk) = Self::split_index(index);
+        let directory = load_or_insert(self.directories.get(directory).expect("address should fit in `ADDRESS_BITS` bits"));
+        &load_or_insert(&directory[table])[chunk]
+    }
+    
+    /// Records that there is a (new) block header at `block`.
+    pub(super) fn record_block(&self, block: NonNull<GCHeapBlockHeader>) {
+        let addr = block.addr().get();
+        let chunk = self.chunk_or_insert(Self::chunk_index(addr));
+        let _ = chunk.first_block.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |first| {
+            (first == 0 || addr < first).then_some(addr)
+        });
+    }
+    
+    /// Records that a (previously recorded) block just got allocated.
+    pub(super) fn block_allocated(&self, block: NonNull<GCHeapBlockHeader>) {
+        let chunk = self.chunk(Self::chunk_index(block.addr().get())).expect("block should have been recorded");
+        chunk.num_allocated.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// Records that a (previously recorded) block just got freed.
+    pub(super) fn block_freed(&self, block: NonNull<GCHeapBlockHeader>) {
+        let chunk = self.chunk(Self::chunk_index(block.addr().get())).expect("block should have been recorded");
+        let old = chunk.num_allocated.fetch_sub(1, Ordering::Relaxed);
+        debug_assert_ne!(old, 0, "freed a block in a chunk with nothing allocated");
+    }
+    
+    /// Records that the (free) block header at `block` got merged into the block before it,
+    /// which now ends at `following`.
+    /// 
+    /// NOTE: `following` might just be the end of the heap, but then that's where the next block
+    ///       header goes once the heap grows, so it's fine for it to be in the index early.
+    pub(super) fn block_removed(&self, block: NonNull<GCHeapBlockHeader>, following: NonNull<GCHeapBlockHeader>) {
+        let addr = block.addr().get();
+        let index = Self::chunk_index(addr);
+        let chunk = self.chunk(index).expect("block should have been recorded");
+        
+        // if it was the first block in its chunk, the one after the merged block is now
+        let replacement = if Self::chunk_index(following.addr().get()) == index { following.addr().get() } else { 0 };
+        let _ = chunk.first_block.compare_exchange(addr, replacement, Ordering::Relaxed, Ordering::Relaxed);
+    }
+    
+    /// The closest block header at or before `ptr`, in the heap region `heap`.
+    pub(super) fn block_before(&self, heap: NonNull<[u8]>, ptr: *const ()) -> NonNull<GCHeapBlockHeader> {
+        let start = heap.cast::<GCHeapBlockHeader>();
+        (Self::chunk_index(start.addr().get())..=Self::chunk_index(ptr.addr())).rev().find_map(|index| {
+            let first = self.chunk(index)?.first_block.load(Ordering::Relaxed);
+            (start.addr().get() <= first && first <= ptr.addr()).then(|| start.with_addr(NonZero::new(first).unwrap()))
+        }).unwrap_or(start)
+    }
+    
+    /// The first block header after the chunk at `index` that is in a chunk with something allocated in it.
+    fn next_allocated_chunk(&self, index: usize, end: NonNull<GCHeapBlockHeader>) -> Option<NonNull<GCHeapBlockHeader>> {
+        let last = Self::chunk_index(end.addr().get() - 1);
+        (index + 1..=last).find_map(|index| {
+            let chunk = self.chunk(index)?;
+            if chunk.num_allocated.load(Ordering::Relaxed) == 0 { return None }
+            NonZero::new(chunk.first_block.load(Ordering::Relaxed)).map(|first| end.with_addr(first))
+        })
+    }
+    
+    /// Every block from `start` to the end of the heap, in order.
+    pub(super) fn walk(mut block_ptr: NonNull<GCHeapBlockHeader>, end: NonNull<GCHeapBlockHeader>) -> impl Iterator<Item=NonNull<GCHeapBlockHeader>> {
+        gen move {
+            while block_ptr < end {
+                let next = unsafe { block_ptr.as_ref() }.next();
+                yield block_ptr;
+                block_ptr = next;
+            }
+            if block_ptr != end {
+                error!("Heap corruption detected (expected to end at {end:016x?}, got {block_ptr:016x?})")
+            }
+        }
+    }
+    
+    /// Finds the block in `heap` that `ptr` points into.
+    pub(super) fn find_block(&self, heap: NonNull<[u8]>, ptr: *const ()) -> Option<NonNull<GCHeapBlockHeader>> {
+        let (start, size) = heap.to_raw_parts();
+        let end = unsafe { start.byte_add(size) }.cast();
+        Self::walk(self.block_before(heap, ptr), end).find(|block| ptr < unsafe { block.as_ref() }.next().as_ptr().cast())
+    }
+    
+    /// Whether `ptr` could be pointing into an allocated block. If this returns `false`, the block
+    /// it points into is definitely free, so the (slower) [`find_block`](Self::find_block) can be skipped.
+    /// 
+    /// This only looks at the chunk `ptr` is in: if the block it points into starts in that chunk
+    /// and nothing in the chunk is allocated, then that block must be free. Otherwise this just
+    /// says it might be allocated.
+    /// 
+    /// NOTE: this has to work for pointers into the middle of blocks (which keep them alive just
+    ///       the same), so it can't just check whether `ptr` is the start of some block.
+    pub(super) fn might_be_allocated(&self, ptr: *const ()) -> bool {
+        let Some(chunk) = self.chunk(Self::chunk_index(ptr.addr())) else { return true };
+        let first = chunk.first_block.load(Ordering::Relaxed);
+        first == 0 || ptr.addr() < first || chunk.num_allocated.load(Ordering::Relaxed) != 0
+    }
+    
+    /// Every block in `heap`, except the free ones in chunks with nothing allocated in them.
+    /// 
+    /// So every allocated block is in here, but (depending on where they are) some free ones might be too.
+    pub(super) fn blocks(&self, heap: NonNull<[u8]>) -> impl Iterator<Item=NonNull<GCHeapBlockHeader>> + '_ {
+        gen move {
+            let (start, size) = heap.to_raw_parts();
+            let end = unsafe { start.byte_add(size) }.cast::<GCHeapBlockHeader>();
+            let mut block_ptr = start.cast::<GCHeapBlockHeader>();
+            
+            while block_ptr < end {
+                let index = Self::chunk_index(block_ptr.addr().get());
+                if self.chunk(index).is_some_and(|chunk| chunk.num_allocated.load(Ordering::Relaxed) == 0) {
+                    // nothing in the rest of this chunk is allocated, so skip to the next chunk that has something
+                    block_ptr = self.next_allocated_chunk(index, end).unwrap_or(end);
+                    continue
+                }
+                
+                let next = unsafe { block_ptr.as_ref() }.next();
+                yield block_ptr;
+                block_ptr = next;
+            }
+            if block_ptr != end {
+                error!("Heap corruption detected (expected to end at {end:016x?}, got {block_ptr:016x?})")
+            }
+        }
+    }
+}
+
+impl Drop for BlockIndex {
+    fn drop(&mut self) {
+        for directory in &mut self.directories {
+            let directory = *directory.get_mut();
+            if directory.is_null() { continue }
+            
+            // SAFETY: the directory came from `Box::into_raw`, and nobody else can be using it anymore
+            let mut directory = unsafe { Box::from_raw(directory) };
+            for table in directory.iter_mut() {
+                let table = *table.get_mut();
+                if !table.is_null() {
+                    // SAFETY: same as for the directory
+                    drop(unsafe { Box::from_raw(table) });
+                }
+            }
+        }
+    }
+}
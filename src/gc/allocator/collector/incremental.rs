@@ -0,0 +1,195 @@
+use std::collections::{BTreeSet, HashSet};
+use std::ptr::NonNull;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use super::super::{get_block, CyclePauses, BLOCK_INDEX, MEMORY_SOURCE, MemorySource};
+use super::super::heap_block_header::GCHeapBlockHeader;
+use super::{Heap, StopAllThreads, MARKING};
+use super::{free_blocks, finish_cycle, get_root_blocks, scan_current_thread, scan_heap, scan_other_threads, scan_static_roots, sweep_garbage, wait_for_other_threads};
+use super::scanning::scan_block;
+
+/// The progress of a mark phase, which can be stopped and picked back up later.
+/// 
+/// This is a tri-color mark: white blocks (not in either set) haven't been found yet, grey
+/// blocks are known to be live but haven't been scanned, and black blocks have been scanned.
+pub(super) struct MarkState {
+    /// Blocks known to be live, whose data hasn't been scanned yet.
+    grey: BTreeSet<NonNull<GCHeapBlockHeader>>,
+    /// Blocks known to be live, whose data has already been scanned.
+    black: HashSet<NonNull<GCHeapBlockHeader>>,
+}
+
+impl MarkState {
+    pub(super) fn new() -> Self {
+        Self { grey: BTreeSet::new(), black: HashSet::new() }
+    }
+    
+    /// Marks `blocks` as live, if they haven't been already.
+    pub(super) fn add_roots(&mut self, blocks: impl IntoIterator<Item=NonNull<GCHeapBlockHeader>>) {
+        for block in blocks {
+            if !self.black.contains(&block) {
+                self.grey.insert(block);
+            }
+        }
+    }
+    
+    /// Scans grey blocks until there aren't any left, or until `deadline` passes.
+    /// 
+    /// Returns whether there is nothing left to scan.
+    /// 
+    /// NOTE: the world has to be stopped while this runs, so that nothing gets changed (or freed) mid-scan.
+    pub(super) fn mark(&mut self, deadline: Option<Instant>) -> bool {
+        while let Some(block) = self.grey.pop_first() {
+            // NOTE: this goes first, so that a block pointing to itself doesn't get scanned twice
+            self.black.insert(block);
+            
+            for (_, new_ptr) in scan_block(unsafe { block.as_ref() }) {
+                debug!("Found new live pointer in GC heap {new_ptr:016x?}");
+                // most words that look like pointers into a mostly-free part of the heap can be thrown out right away
+                if !BLOCK_INDEX.might_be_allocated(new_ptr) { continue }
+                let new_block = get_block(new_ptr).expect("scan_block only gives pointers that we know are in the GC heap");
+                if !self.black.contains(&new_block) && unsafe { new_block.as_ref() }.is_allocated() {
+                    self.grey.insert(new_block);
+                }
+            }
+            
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) { break }
+        }
+        self.grey.is_empty()
+    }
+    
+    /// Makes every black block that overlaps one of the (`page_size` byte) `pages` grey again,
+    /// since something might have stored a new pointer in it after it got scanned.
+    pub(super) fn rescan_dirty(&mut self, pages: &[NonNull<u8>], page_size: usize) {
+        for &page in pages {
+            let page_end = page.as_ptr().wrapping_add(page_size);
+            let Some(mut block) = get_block(page.as_ptr().cast_const().cast()) else { continue };
+            loop {
+                if self.black.remove(&block) {
+                    trace!("Block @ {block:016x?} was written to while marking, scanning it again");
+                    self.grey.insert(block);
+                }
+                
+                let next = unsafe { block.as_ref() }.next();
+                if next.as_ptr().cast::<u8>() >= page_end || !MEMORY_SOURCE.contains(next.as_ptr().cast_const().cast()) { break }
+                block = next;
+            }
+        }
+    }
+    
+    /// Every block that was found to be live.
+    pub(super) fn into_live_blocks(self) -> HashSet<NonNull<GCHeapBlockHeader>> {
+        debug_assert!(self.grey.is_empty(), "the mark phase should be finished");
+        self.black
+    }
+}
+
+/// Runs one collection cycle, but only stops the world for about `target` at a time while marking.
+/// 
+/// See [`GCAllocator::set_pause_target`](super::super::GCAllocator::set_pause_target).
+/// 
+/// If the context of some thread couldn't be read, this returns the OS error code, and nothing is freed.
+pub(super) fn collect_cycle_incremental(target: Duration) -> Result<(), u32> {
+    info!("Starting incremental GC Cycle");
+    let mut pauses = CyclePauses::default();
+    let mut mark = MarkState::new();
+    
+    // Find the roots -------------------------------
+    {
+        let registered_roots = super::super::REGISTERED_ROOTS.lock().unwrap();
+        let heap = Heap::new().unwrap();
+        let heap_lock = heap.lock().unwrap();
+        let tl_allocators = super::super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+        let t = StopAllThreads::new();
+        let start = Instant::now();
+        
+        // anything written to the GC heap from here on gets looked at again at the end
+        MEMORY_SOURCE.reset_dirty_pages();
+        
+        let mut roots = Vec::new();
+        scan_heap(&mut roots, heap_lock);
+        scan_static_roots(&mut roots, &registered_roots);
+        drop(registered_roots);
+        scan_other_threads(&mut roots, &t)?;
+        scan_current_thread(&mut roots);
+        
+        roots.sort();
+        roots.dedup();
+        mark.add_roots(get_root_blocks(roots));
+        
+        // NOTE: this has to be set while holding the write lock (see `GCAllocator::deallocate`)
+        MARKING.store(true, Ordering::Relaxed);
+        pauses.start = start.elapsed();
+        drop(t);
+        drop(tl_allocators);
+    }
+    
+    // Mark, a slice at a time ----------------------
+    loop {
+        // give the other threads a chance to actually run
+        std::thread::sleep(target);
+        
+        let heap = Heap::new().unwrap();
+        let heap_lock = heap.lock().unwrap();
+        let tl_allocators = super::super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+        let t = StopAllThreads::new();
+        let start = Instant::now();
+        
+        if let Err(code) = wait_for_other_threads(&t) {
+            warn!("Couldn't get the context of some thread (error 0x{code:x}), trying the slice again");
+            continue
+        }
+        // NOTE: none of the stopped threads can be holding the heap lock, so marking can allocate now
+        drop(heap_lock);
+        
+        let done = mark.mark(Some(start + target));
+        pauses.mark_slices.push(start.elapsed());
+        drop(t);
+        drop(tl_allocators);
+        
+        debug!("Finished a mark slice ({} blocks left)", mark.grey.len());
+        if done { break }
+    }
+    
+    // Finish up ------------------------------------
+    let registered_roots = super::super::REGISTERED_ROOTS.lock().unwrap();
+    let heap = Heap::new().unwrap();
+    let heap_lock = heap.lock().unwrap();
+    let mut tl_allocators = super::super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+    let t = StopAllThreads::new();
+    let start = Instant::now();
+    
+    // everything that could have changed while the world was running has to be scanned again
+    let mut roots = Vec::new();
+    scan_heap(&mut roots, heap_lock);
+    scan_static_roots(&mut roots, &registered_roots);
+    drop(registered_roots);
+    if let Err(code) = scan_other_threads(&mut roots, &t) {
+        // NOTE: this throws away all the marking, but it's safer than trying to keep it around
+        MARKING.store(false, Ordering::Relaxed);
+        return Err(code)
+    }
+    scan_current_thread(&mut roots);
+    
+    let dirty_pages = MEMORY_SOURCE.take_dirty_pages();
+    debug!("{} pages of the GC heap were written to while marking", dirty_pages.len());
+    mark.rescan_dirty(&dirty_pages, MEMORY_SOURCE.page_size());
+    
+    roots.sort();
+    roots.dedup();
+    mark.add_roots(get_root_blocks(roots));
+    mark.mark(None);
+    MARKING.store(false, Ordering::Relaxed);
+    
+    // sweep (i.e: drop) and free all the dead stuff in the heap
+    free_blocks(sweep_garbage(mark.into_live_blocks()), &mut tl_allocators);
+    
+    info!("Freed all dead blocks");
+    
+    pauses.finish = start.elapsed();
+    drop(t);
+    drop(tl_allocators);
+    finish_cycle(pauses);
+    Ok(())
+}
@@ -1,77 +1,101 @@
-use std::collections::{BinaryHeap, HashSet};
+use std::collections::HashSet;
 use std::ptr::{NonNull, Unique};
-use std::sync::{mpsc, OnceLock};
-use std::time::Duration;
+use std::sync::{mpsc, Mutex, Once, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use thread_local::ThreadLocal;
 use windows_sys::Win32::System::Threading::GetThreadId;
 
-use super::os_dependent::{MemorySource, get_writable_segments, get_all_threads, get_thread_stack_bounds, StopAllThreads, heap_scan::WinHeap as Heap};
+use super::os_dependent::{MemorySource, context_stack_pointer, get_writable_segments, get_all_threads, get_live_thread_ids, get_thread_stack_bounds, StopAllThreads, heap_scan::WinHeap as Heap};
 
 use super::tl_allocator::TLAllocator;
-use super::{get_block, MEMORY_SOURCE, MemorySourceImpl};
+use super::{get_block, CycleEvent, CyclePauses, MEMORY_SOURCE, MemorySourceImpl, RegisteredRoot, RootLocation};
 use super::heap_block_header::GCHeapBlockHeader;
 
+mod incremental;
 mod scanning;
 mod sweeping;
 
-use scanning::{scan_block, scan_heap, scan_registers, scan_segment, scan_stack};
+use incremental::{MarkState, collect_cycle_incremental};
+use scanning::{scan_block, scan_block_with, scan_heap, scan_heap_with, scan_registers, scan_segment, scan_segment_with, scan_stack, scan_stack_with};
 use sweeping::sweep_heap;
 
 // NOTE: this has to be `Unique` since `NonNull` is not `Send`. why does rust
 // do this with raw pointers come onnnn its not even needed
 pub(super) static DEALLOCATED_CHANNEL: OnceLock<mpsc::Sender<std::ptr::Unique<[u8]>>> = OnceLock::new();
+/// The other end of [`DEALLOCATED_CHANNEL`].
+/// 
+/// NOTE: this is only ever locked while holding the write lock on `THREAD_LOCAL_ALLOCATORS`
+static DEALLOCATED_RECIEVER: OnceLock<Mutex<mpsc::Receiver<Unique<[u8]>>>> = OnceLock::new();
+
+/// Whether an incremental mark phase is going on (see [`collect_cycle_incremental`]).
+/// 
+/// While this is set, nothing can go straight back into a free list, since the collector might
+/// still be planning on scanning it. Instead, it gets sent through [`DEALLOCATED_CHANNEL`].
+/// 
+/// NOTE: this only changes while the collector holds the write lock on `THREAD_LOCAL_ALLOCATORS`
+pub(super) static MARKING: AtomicBool = AtomicBool::new(false);
 
 fn get_root_blocks(roots: Vec<*const ()>) -> impl IntoIterator<Item=NonNull<GCHeapBlockHeader>> {
-    let (block_ptr, heap_size) = MEMORY_SOURCE.raw_data().to_raw_parts();
-    let mut block_ptr = block_ptr.cast::<GCHeapBlockHeader>();
-    trace!("Traversing block {block_ptr:016x?}[0x{:x}]", unsafe { block_ptr.as_ref().size });
-    let end = unsafe { block_ptr.byte_add(heap_size) };
-    
     debug_assert!(roots.is_sorted());
     
+    let mut roots = roots.into_iter().peekable();
     let mut marked_blocks = Vec::new();
     
-    for root in roots.into_iter() {
-        let mut current_block = unsafe { block_ptr.as_mut() };
-        let mut next_block = current_block.next();
+    // NOTE: the regions are sorted too, so each one just has to take the roots that are in it
+    for heap in MEMORY_SOURCE.regions() {
+        if heap.is_empty() { continue }
         
-        if current_block.size == 0 {
-            error!("Heap corruption detected at block {block_ptr:016x?}: allocations of size zero should not exist")
-        }
+        let (block_ptr, heap_size) = heap.to_raw_parts();
+        let mut block_ptr = block_ptr.cast::<GCHeapBlockHeader>();
+        trace!("Traversing block {block_ptr:016x?}[0x{:x}]", unsafe { block_ptr.as_ref().size });
+        let end = unsafe { block_ptr.byte_add(heap_size) };
         
-        while root.cast() >= next_block.as_ptr() {
-            block_ptr = next_block;
-            current_block = unsafe { block_ptr.as_mut() };
-            trace!("Traversing block {block_ptr:016x?}[0x{:x}]", current_block.size);
-            next_block = current_block.next();
-        }
-        if block_ptr >= end { break }
+        // these aren't in any region
+        while roots.next_if(|root| root.cast() < block_ptr.as_ptr()).is_some() {}
         
-        assert!(root.cast() >= block_ptr.as_ptr());
-        let block_range_len = size_of::<GCHeapBlockHeader>() + current_block.size;
+        for root in std::iter::from_fn(|| roots.next_if(|root| root.cast() < end.as_ptr())) {
+            let mut current_block = unsafe { block_ptr.as_mut() };
+            let mut next_block = current_block.next();
         
-        // NOTE: if there is a pointer DIRECTLY to a given block header,
-        // then it almost certainly is an internal GC thing thats just stored on the heap  
-        if root.cast() == block_ptr.as_ptr() {
-            info!("found direct free block pointer ({root:016x?}[{block_range_len:x}])");
-            continue
-        }
+            if current_block.size == 0 {
+                error!("Heap corruption detected at block {block_ptr:016x?}: allocations of size zero should not exist")
+            }
         
-        if !current_block.is_allocated() {
-            warn!("dangling pointer detected ({root:016x?} points to block {block_ptr:016x?}[{block_range_len:x}], which is free)");
-            // std::process::exit(1);
-            continue
-        }
+            while root.cast() >= next_block.as_ptr() {
+                block_ptr = next_block;
+                current_block = unsafe { block_ptr.as_mut() };
+                trace!("Traversing block {block_ptr:016x?}[0x{:x}]", current_block.size);
+                next_block = current_block.next();
+            }
+            if block_ptr >= end { break }
         
-        if marked_blocks.last() == Some(&block_ptr.cast()) {
-            // we just got a pointer to it
-            trace!("Ignoring additional pointer to {block_ptr:016x?} (just marked it)");
-            continue
-        }
+            assert!(root.cast() >= block_ptr.as_ptr());
+            let block_range_len = size_of::<GCHeapBlockHeader>() + current_block.size;
         
-        debug!("Marked block @ {block_ptr:016x?} (pointer was {root:016x?})");
-        marked_blocks.push(block_ptr);
+            // NOTE: if there is a pointer DIRECTLY to a given block header,
+            // then it almost certainly is an internal GC thing thats just stored on the heap
+            if root.cast() == block_ptr.as_ptr() {
+                info!("found direct free block pointer ({root:016x?}[{block_range_len:x}])");
+                continue
+            }
+            
+            if !current_block.is_allocated() {
+                warn!("dangling pointer detected ({root:016x?} points to block {block_ptr:016x?}[{block_range_len:x}], which is free)");
+                // std::process::exit(1);
+                continue
+            }
+            
+            if marked_blocks.last() == Some(&block_ptr.cast()) {
+                // we just got a pointer to it
+                trace!("Ignoring additional pointer to {block_ptr:016x?} (just marked it)");
+                continue
+            }
+            
+            debug!("Marked block @ {block_ptr:016x?} (pointer was {root:016x?})");
+            marked_blocks.push(block_ptr);
+        }
     }
     debug!("Done marking roots");
     
@@ -81,60 +105,522 @@ fn get_root_blocks(roots: Vec<*const ()>) -> impl IntoIterator<Item=NonNull<GCHe
 
 /// Returns all the live blocks on the GC heap.
 fn get_live_blocks(roots: impl IntoIterator<Item=NonNull<GCHeapBlockHeader>>) -> HashSet<NonNull<GCHeapBlockHeader>> {
-    use std::collections::BTreeSet;
-    let mut roots = BTreeSet::from_iter(roots); // should be fast bc roots is sorted
-    let mut scanned = HashSet::<NonNull<GCHeapBlockHeader>>::with_capacity(roots.len()*2);
+    let mut mark = MarkState::new();
+    mark.add_roots(roots);
+    mark.mark(None);
+    mark.into_live_blocks()
+}
+
+fn free_blocks(
+    blocks: impl IntoIterator<Item=NonNull<GCHeapBlockHeader>>,
+    tl_allocs: &mut ThreadLocal<TLAllocator<MemorySourceImpl>>
+) {
+    // NOTE: `ThreadLocal` doesn't get rid of an allocator when its thread exits, so anything in
+    //       there would never get used again (unless some new thread happens to get it)
+    let live_threads = get_live_thread_ids();
+    let (exited, mut live): (Vec<_>, Vec<_>) = tl_allocs.iter_mut().partition(|allocator| !live_threads.contains(&allocator.os_thread_id()));
+    if live.is_empty() {
+        // nobody else to give them to
+        live = exited;
+    } else {
+        for allocator in exited {
+            // NOTE: this picks again for each one, so that they get spread out instead of all going to the same thread
+            let target = live.iter_mut().min_by_key(|allocator| allocator.free_bytes()).unwrap();
+            if allocator.free_bytes() > 0 {
+                info!("Giving 0x{:x} free bytes from the allocator of an exited thread to thread {:x}", allocator.free_bytes(), target.os_thread_id());
+            }
+            allocator.give_everything_to(target);
+        }
+    }
     
-    debug!("Rooted blocks: {roots:016x?}");
+    // every block goes back to whichever allocator owns its memory, so it can be merged with its
+    // neighbours (and so that the thread that allocated it can reuse it without asking the OS)
+    let mut regions: Vec<_> = live.iter().enumerate().flat_map(|(owner, allocator)| {
+        allocator.regions().into_iter().map(move |region| {
+            let start = region.cast::<GCHeapBlockHeader>();
+            (start, unsafe { start.byte_add(region.len()) }, owner)
+        })
+    }).collect();
+    regions.sort_unstable_by_key(|&(start, _, _)| start);
+    
+    let mut freed = vec![Vec::new(); live.len()];
+    for block in blocks {
+        let (_, _, owner) = regions.partition_point(|&(start, _, _)| start <= block).checked_sub(1)
+            .map(|i| regions[i])
+            .filter(|&(_, end, _)| block < end)
+            .expect("every block should be in memory that some allocator owns");
+        freed[owner].push(block);
+    }
+    
+    for (allocator, blocks) in live.into_iter().zip(freed) {
+        allocator.reclaim_blocks(blocks);
+        
+        #[cfg(debug_assertions)]
+        // SAFETY: every other thread is stopped, so nobody else is using the free lists
+        unsafe { allocator.verify_heap() };
+    }
+}
+
+
+/// Scans global (mutable) static memory and any manually registered roots.
+fn scan_static_roots(roots: &mut Vec<*const ()>, registered_roots: &[RegisteredRoot]) {
+    // Scan global (mutable) static memory
+    for (name, segment_data) in get_writable_segments() {
+        info!("Scanning {name} segment");
+        for (_, root) in unsafe { scan_segment(segment_data) } {
+            debug!("Found pointer to {root:016x?} in {name} segment");
+            roots.push(root);
+        }
+    }
     
-    while let Some(block) = roots.pop_first() {
-        let block_ref = unsafe { block.as_ref() };
+    // Scan manually registered roots
+    for registered in registered_roots {
+        info!("Scanning registered root at {:016x?}", registered.data);
+        if registered.precise {
+            // every word is a `Gc`, so there's nothing to check
+            // NOTE: `Gc`s of ZSTs don't point into the heap, but `get_root_blocks` ignores those anyways
+            let (base, len) = registered.data.to_raw_parts();
+            let words = NonNull::slice_from_raw_parts(base.cast::<*const ()>(), len / size_of::<*const ()>());
+            roots.extend_from_slice(unsafe { words.as_ref() });
+            continue
+        }
+        for (_, root) in unsafe { scan_segment(registered.data) } {
+            debug!("Found pointer to {root:016x?} in registered root");
+            roots.push(root);
+        }
+    }
+}
+
+/// Scans the registers and stack of every other thread, which all have to be stopped.
+fn scan_other_threads(roots: &mut Vec<*const ()>, t: &StopAllThreads) -> Result<(), u32> {
+    for thread in get_all_threads().into_iter().map(Result::unwrap) {
+        let id = unsafe { GetThreadId(thread) };
+        debug!("Scanning thread {id:x?}");
         
-        for new_ptr in scan_block(block_ref).into_iter() {
-            debug!("Found new live pointer in GC heap {new_ptr:016x?}");
-            let block: NonNull<GCHeapBlockHeader> = get_block(new_ptr).expect("scan_block only gives pointers that we know are in the GC heap");
-            if !scanned.contains(&block) {
-                roots.insert(block);
+        // Scan thread registers
+        let context = match unsafe { t.get_thread_context(thread) } {
+            Ok(c) => c,
+            Err(code) => {
+                error!("Collector: get_thread_context failed with code {code:x}");
+                return Err(code)
             }
+        };
+        for ptr in scan_registers(&context) {
+            debug!("Found pointer to {ptr:016x?} in thread registers");
+            roots.push(ptr);
+        }
+        
+        // scan thread stacks
+        let bounds = get_thread_stack_bounds(thread).unwrap();
+        let stack_ptr = bounds.0.with_addr(context_stack_pointer(&context)) as *const ();
+        for (_, ptr) in unsafe { scan_stack(bounds, stack_ptr) } {
+            debug!("Found pointer to {ptr:016x?} in thread stack");
+            roots.push(ptr);
         }
         
-        scanned.insert(block);
+        // TODO: scan thread local storage
     }
+    warn!("TODO: Scan thread local storage");
     
-    scanned
+    Ok(())
 }
 
-fn free_blocks(
-    blocks: impl IntoIterator<Item=NonNull<GCHeapBlockHeader>>,
-    tl_allocs: &mut ThreadLocal<TLAllocator<MemorySourceImpl>>
+/// Waits for every other thread to actually be stopped, since `SuspendThread` doesn't wait for that.
+/// 
+/// Getting the context of a thread does though, so this just does that for each one.
+fn wait_for_other_threads(t: &StopAllThreads) -> Result<(), u32> {
+    for thread in get_all_threads().into_iter().map(Result::unwrap) {
+        unsafe { t.get_thread_context(thread) }?;
+    }
+    Ok(())
+}
+
+/// Scans the registers and stack of the thread that calls this.
+#[inline(never)]
+fn scan_current_thread(roots: &mut Vec<*const ()>) {
+    use windows_sys::Win32::System::Diagnostics::Debug::{CONTEXT, RtlCaptureContext};
+    use windows_sys::Win32::System::Threading::GetCurrentThread;
+    
+    let mut context: CONTEXT = unsafe { std::mem::zeroed() };
+    unsafe { RtlCaptureContext(&mut context) };
+    for ptr in scan_registers(&context) {
+        debug!("Found pointer to {ptr:016x?} in current thread registers");
+        roots.push(ptr);
+    }
+    let bounds = get_thread_stack_bounds(unsafe { GetCurrentThread() }).unwrap();
+    let stack_ptr = bounds.0.with_addr(context_stack_pointer(&context)) as *const ();
+    for (_, ptr) in unsafe { scan_stack(bounds, stack_ptr) } {
+        debug!("Found pointer to {ptr:016x?} in current thread stack");
+        roots.push(ptr);
+    }
+}
+
+/// Marks everything reachable from `roots`, and returns every block that can be freed.
+/// 
+/// This runs the destructors of dead blocks as they are iterated over, so it must be fully
+/// consumed while the world is still stopped.
+fn collect_garbage(mut roots: Vec<*const ()>) -> impl Iterator<Item=NonNull<GCHeapBlockHeader>> {
+    roots.sort();
+    roots.dedup();
+    
+    debug!("Root pointers: {roots:016x?}");
+    
+    let root_blocks = get_root_blocks(roots);
+    
+    info!("finished getting rooted blocks");
+    
+    // Scan the GC heap, starting from the roots
+    let live_blocks = get_live_blocks(root_blocks);
+    
+    sweep_garbage(live_blocks)
+}
+
+/// Returns every block that can be freed, given every block that is still live.
+/// 
+/// Like [`collect_garbage`], this runs the destructors of dead blocks as they are iterated over.
+fn sweep_garbage(live_blocks: HashSet<NonNull<GCHeapBlockHeader>>) -> impl Iterator<Item=NonNull<GCHeapBlockHeader>> {
+    debug!("Live blocks ({}): {live_blocks:016x?}", live_blocks.len());
+    
+    // NOTE: if it werent for absolutely stupid Drop implementations,
+    // we could soundly let all the threads go *now*, and asynchronously
+    // start dropping and freeing up all the dead stuff. but since people
+    // can (and DO) put literally everything in Drop, we have to run them
+    // in a controlled environment where we can make sure they arent
+    // creating dangling references. (NOTE: you can also start new threads
+    // during Drop. i know this is a problem, but idk how much yet. at the
+    // LEAST we have to monitor all memory accesses during it, but idk how)
+    
+    // Free everything that we know we can free (bc we recieved them over the channel)
+    // NOTE: these have to come before the sweep, so that it sees them as already freed
+    let reciever = DEALLOCATED_RECIEVER.get().map(|r| r.lock().unwrap());
+    let explicitly_freed: Vec<_> = reciever.iter().flat_map(|r| r.try_iter()).map(|data| {
+        let data = NonNull::from(data);
+        let data_len = data.len();
+        // SAFETY: data needs to be a pointer to a heap allocation
+        let block_ptr = unsafe { data.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()) };
+        let block_len = unsafe { (*block_ptr.as_ptr()).size };
+        assert!(data_len <= block_len, "Length of data (0x{data_len:x}) was larger than the block length (0x{block_len:x})");
+        block_ptr
+    }).collect();
+    drop(reciever);
+    
+    // sweep (i.e: drop) and free the rest of the dead stuff in the heap
+    #[cfg(not(feature = "checked-drops"))]
+    let swept = sweep_heap(live_blocks);
+    #[cfg(feature = "checked-drops")]
+    let swept = {
+        let dead_blocks: Vec<_> = sweep_heap(live_blocks.clone()).into_iter().collect();
+        // NOTE: if no destructors ran, nothing could have been resurrected
+        if dead_blocks.iter().any(|b| unsafe { b.as_ref() }.drop_thunk.is_some()) {
+            abort_if_resurrected(&dead_blocks, &live_blocks);
+        }
+        dead_blocks
+    };
+    
+    explicitly_freed.into_iter().chain(swept)
+}
+
+/// Aborts the process if a destructor stashed a pointer to any of the (already dropped) `dead_blocks`.
+/// 
+/// This rescans everything that could have been written to while the destructors ran (i.e:
+/// the live blocks, the process heap, and the static roots) for pointers into `dead_blocks`.
+/// A resurrected block has already been dropped, so there's no sound way to keep using it,
+/// and the only option left is to abort before anything else gets a chance to read it.
+/// 
+/// NOTE: this is basically a second mark phase, so it's about as expensive as the first one.
+/// It also can't catch destructors that spawn threads, or anything else that happens after
+/// they return.
+#[cfg(feature = "checked-drops")]
+fn abort_if_resurrected(
+    dead_blocks: &[NonNull<GCHeapBlockHeader>],
+    live_blocks: &HashSet<NonNull<GCHeapBlockHeader>>
 ) {
-    struct FreeByteComparer<'a>(&'a mut TLAllocator<MemorySourceImpl>);
-    impl PartialEq for FreeByteComparer<'_> {
-        fn eq(&self, other: &Self) -> bool { self.0.free_bytes().eq(&other.0.free_bytes()) }
+    // NOTE: the sweep goes through the heap in order, so this is sorted
+    debug_assert!(dead_blocks.is_sorted());
+    
+    // the dead block that `ptr` points into the data of, if any
+    let find_dead = |ptr: *const ()| -> Option<NonNull<GCHeapBlockHeader>> {
+        let index = dead_blocks.partition_point(|b| b.as_ptr().cast_const().cast() < ptr).checked_sub(1)?;
+        let block = unsafe { dead_blocks[index].as_ref() };
+        let data = block.data().cast::<()>().as_ptr().cast_const();
+        (data <= ptr && ptr < block.next().as_ptr().cast_const().cast()).then_some(dead_blocks[index])
+    };
+    
+    // NOTE: every other thread is still stopped, so none of them can be holding these locks
+    let mut roots = Vec::new();
+    let registered_roots = super::REGISTERED_ROOTS.lock().unwrap();
+    let heap = Heap::new().unwrap();
+    scan_heap(&mut roots, heap.lock().unwrap());
+    scan_static_roots(&mut roots, &registered_roots);
+    drop(registered_roots);
+    
+    for &block in live_blocks {
+        roots.extend(scan_block(unsafe { block.as_ref() }).into_iter().map(|(_, ptr)| ptr));
+    }
+    
+    if let Some(block) = roots.into_iter().find_map(find_dead) {
+        error!("Block @ {block:016x?} was resurrected by a destructor, aborting");
+        std::process::abort()
+    }
+}
+
+/// Stops the world, and calls `f` with every live block in the GC heap (in address order).
+/// 
+/// Nothing gets freed, and no destructors are run. Since every other thread is stopped, and
+/// the current thread's allocator is locked, `f` can't allocate in the GC heap, or block on
+/// anything that another thread might be holding.
+pub(super) fn with_live_blocks<R>(f: impl FnOnce(&[NonNull<GCHeapBlockHeader>]) -> R) -> R {
+    // NOTE: these are locked in the same order as in `gc_main`, so that this can't
+    // deadlock with it (or any thread that's currently allocating)
+    info!("Taking a heap snapshot");
+    let registered_roots = super::REGISTERED_ROOTS.lock().unwrap();
+    let heap = Heap::new().unwrap();
+    let heap_lock = heap.lock().unwrap();
+    let tl_allocators = super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+    let t = StopAllThreads::new();
+    
+    let mut roots = Vec::new();
+    scan_heap(&mut roots, heap_lock);
+    scan_static_roots(&mut roots, &registered_roots);
+    drop(registered_roots);
+    scan_other_threads(&mut roots, &t).expect("should be able to get the context of every thread");
+    scan_current_thread(&mut roots);
+    
+    roots.sort();
+    roots.dedup();
+    let mut live_blocks = Vec::from_iter(get_live_blocks(get_root_blocks(roots)));
+    live_blocks.sort();
+    
+    let result = f(&live_blocks);
+    
+    drop(t);
+    drop(tl_allocators);
+    result
+}
+
+/// Stops the world, and finds everywhere that there is a pointer into `target`'s data.
+/// 
+/// Everything the collector scans for roots gets checked, along with every allocated block in the
+/// GC heap (even ones that are garbage themselves). [`Gc::try_into_unique`](crate::gc::Gc::try_into_unique)
+/// relies on this finding *every* reference into the object (like `&bytes[1]` into a `Gc<[u8]>`).
+pub(super) fn find_roots_to(target: NonNull<GCHeapBlockHeader>) -> Vec<RootLocation> {
+    use windows_sys::Win32::System::Diagnostics::Debug::{CONTEXT, RtlCaptureContext};
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, GetCurrentThreadId};
+    
+    // NOTE: pointers directly to the header don't count, same as in `get_root_blocks`
+    let start = target.as_ptr().cast_const().cast::<()>();
+    let end = unsafe { target.as_ref() }.next().as_ptr().cast_const().cast::<()>();
+    let points_to_target = |ptr: *const ()| start < ptr && ptr < end;
+    
+    // NOTE: these are locked in the same order as in `collect_cycle`, so that this can't
+    // deadlock with it (or any thread that's currently allocating)
+    info!("Finding roots to {target:016x?}");
+    let registered_roots = super::REGISTERED_ROOTS.lock().unwrap();
+    let heap = Heap::new().unwrap();
+    let heap_lock = heap.lock().unwrap();
+    let tl_allocators = super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+    let t = StopAllThreads::new();
+    
+    let mut found = Vec::new();
+    scan_heap_with(&mut found, heap_lock, |address, ptr| {
+        points_to_target(ptr).then_some(RootLocation::ProcessHeap { address: address.cast() })
+    });
+    
+    for (name, segment_data) in get_writable_segments() {
+        for (address, _) in unsafe { scan_segment_with(segment_data, points_to_target) } {
+            found.push(RootLocation::Segment { name, address: address.cast() });
+        }
     }
-    impl Eq for FreeByteComparer<'_> {}
-    impl PartialOrd for FreeByteComparer<'_> {
-        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(other)) }
+    for registered in registered_roots.iter() {
+        for (address, _) in unsafe { scan_segment_with(registered.data, points_to_target) } {
+            found.push(RootLocation::RegisteredRoot { address: address.cast() });
+        }
     }
-    impl Ord for FreeByteComparer<'_> {
-        fn cmp(&self, other: &Self) -> std::cmp::Ordering { other.0.free_bytes().cmp(&self.0.free_bytes()) }
+    drop(registered_roots);
+    
+    for thread in get_all_threads().into_iter().map(Result::unwrap) {
+        let thread_id = unsafe { GetThreadId(thread) };
+        let context = unsafe { t.get_thread_context(thread) }.expect("should be able to get the context of every thread");
+        if scan_registers(&context).into_iter().any(points_to_target) {
+            found.push(RootLocation::Registers { thread_id });
+        }
+        let bounds = get_thread_stack_bounds(thread).unwrap();
+        let stack_ptr = bounds.0.with_addr(context_stack_pointer(&context)) as *const ();
+        for (address, _) in unsafe { scan_stack_with(bounds, stack_ptr, points_to_target) } {
+            found.push(RootLocation::Stack { thread_id, address: address.cast() });
+        }
     }
     
-    let mut prio_queue: BinaryHeap<FreeByteComparer> = BinaryHeap::from_iter(tl_allocs.iter_mut().map(FreeByteComparer));
-    let blocks = blocks.into_iter();
+    // NOTE: this includes the caller's frames, which usually still have `target` in them somewhere
+    let thread_id = unsafe { GetCurrentThreadId() };
+    let mut context: CONTEXT = unsafe { std::mem::zeroed() };
+    unsafe { RtlCaptureContext(&mut context) };
+    if scan_registers(&context).into_iter().any(points_to_target) {
+        found.push(RootLocation::Registers { thread_id });
+    }
+    let bounds = get_thread_stack_bounds(unsafe { GetCurrentThread() }).unwrap();
+    let stack_ptr = bounds.0.with_addr(context_stack_pointer(&context)) as *const ();
+    for (address, _) in unsafe { scan_stack_with(bounds, stack_ptr, points_to_target) } {
+        found.push(RootLocation::Stack { thread_id, address: address.cast() });
+    }
     
-    // TODO: allocate blocks to each thread actually intelligently
-    for block in blocks {
-        let min_thread = prio_queue.pop().expect("Should be more than zero threads");
-        min_thread.0.reclaim_block(block);
-        prio_queue.push(min_thread);
+    // other objects in the GC heap
+    for heap in MEMORY_SOURCE.regions() {
+        let (block_ptr, heap_size) = heap.to_raw_parts();
+        let heap_end = unsafe { block_ptr.byte_add(heap_size) }.cast::<GCHeapBlockHeader>();
+        let mut block_ptr = block_ptr.cast::<GCHeapBlockHeader>();
+        while block_ptr < heap_end {
+            let block = unsafe { block_ptr.as_ref() };
+            if block.is_allocated() && block_ptr != target {
+                let object = block.data().cast::<()>().as_ptr().cast_const();
+                for (address, _) in scan_block_with(block, points_to_target) {
+                    found.push(RootLocation::GcHeap { object, address: address.cast() });
+                }
+            }
+            block_ptr = block.next();
+        }
+    }
+    
+    drop(t);
+    drop(tl_allocators);
+    found
+}
+
+/// Calls the callback set with [`GCAllocator::set_collection_callback`](super::GCAllocator::set_collection_callback), if there is one.
+/// 
+/// NOTE: this has to be called while the world is running, since the callback is allowed to allocate.
+fn run_collection_callback(event: CycleEvent<'_>) {
+    if let Some(callback) = &*super::COLLECTION_CALLBACK.lock().unwrap() {
+        callback(event);
     }
 }
 
+/// Wakes any threads waiting for garbage to have been cleaned up.
+/// 
+/// NOTE: the world has to have been started again before this gets called.
+fn finish_cycle(pauses: CyclePauses) {
+    debug!("Pauses: {pauses:?}");
+    *super::LAST_CYCLE_PAUSES.lock().unwrap() = Some(pauses.clone());
+    let cycle = {
+        let mut cycle_number = super::GC_CYCLE_NUMBER.lock().unwrap();
+        *cycle_number += 1;
+        *cycle_number
+    };
+    super::GC_CYCLE_SIGNAL.notify_all();
+    
+    info!("Finished garbage collection");
+    run_collection_callback(CycleEvent::End { cycle, pauses: &pauses });
+}
+
+/// Does a full collection cycle synchronously on the current thread, without stopping any
+/// other threads. Only the current thread's stack and registers get scanned.
+/// 
+/// # Safety
+/// No other threads can be running (or hold any pointers into the GC heap on their stacks or
+/// in their registers), other than the collector thread itself.
+#[cfg(test)]
+pub(super) unsafe fn collect_single_threaded() {
+    // NOTE: these are locked in the same order as in `gc_main`, so that this can't
+    // deadlock with it (or any thread that's currently allocating)
+    info!("Starting single-threaded GC Cycle");
+    let start = Instant::now();
+    let registered_roots = super::REGISTERED_ROOTS.lock().unwrap();
+    let heap = Heap::new().unwrap();
+    let heap_lock = heap.lock().unwrap();
+    let mut tl_allocators = super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+    
+    // Scan for roots ------------------------------
+    let mut roots = Vec::new();
+    
+    info!("Scanning process heap");
+    scan_heap(&mut roots, heap_lock);
+    scan_static_roots(&mut roots, &registered_roots);
+    drop(registered_roots);
+    
+    // Scan our own registers and stack
+    scan_current_thread(&mut roots);
+    
+    // Give everything back to the current thread, so that it's predictable where it ends up
+    let current: *const TLAllocator<MemorySourceImpl> = tl_allocators.get().expect("the current thread should have an allocator");
+    let allocator = tl_allocators.iter_mut().find(|a| std::ptr::eq(&**a, current)).unwrap();
+    for block in collect_garbage(roots) {
+        allocator.reclaim_block(block);
+    }
+    
+    info!("Freed all dead blocks");
+    
+    let pauses = CyclePauses { finish: start.elapsed(), ..CyclePauses::default() };
+    drop(tl_allocators);
+    finish_cycle(pauses);
+}
+
+/// Sets up [`DEALLOCATED_CHANNEL`] and [`DEALLOCATED_RECIEVER`], if they haven't been already.
+pub(super) fn init_deallocated_channel() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let (sender, reciever) = mpsc::channel::<Unique<[u8]>>();
+        DEALLOCATED_RECIEVER.set(Mutex::new(reciever)).expect("Nobody but here sets `DEALLOCATED_RECIEVER`");
+        DEALLOCATED_CHANNEL.set(sender).expect("Nobody but here sets `DEALLOCATED_CHANNEL`");
+    });
+}
+
+/// Runs one full collection cycle on the current thread, stopping every other thread while it does.
+/// 
+/// The current thread gets scanned too, so this works from any thread, not just the collector thread.
+/// 
+/// If the context of some thread couldn't be read, this returns the OS error code, and nothing is freed.
+/// 
+/// If there is a pause target (see [`GCAllocator::set_pause_target`](super::GCAllocator::set_pause_target)),
+/// the mark phase is done incrementally instead.
+pub(super) fn collect_cycle() -> Result<(), u32> {
+    run_collection_callback(CycleEvent::Start);
+    
+    if let Some(target) = *super::PAUSE_TARGET.lock().unwrap() {
+        return collect_cycle_incremental(target)
+    }
+    
+    // make sure no threads are currently allocating so we dont deadlock
+    info!("Starting GC Cycle");
+    let registered_roots = super::REGISTERED_ROOTS.lock().unwrap();
+    let heap = Heap::new().unwrap();
+    let heap_lock = heap.lock().unwrap();
+    let mut tl_allocators = super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
+    let t = StopAllThreads::new();
+    let start = Instant::now();
+    
+    std::thread::sleep(Duration::from_millis(20));
+    
+    // Scan for roots ------------------------------
+    let mut roots = Vec::new();
+    
+    // Scan heap
+    info!("Scanning process heap");
+    scan_heap(&mut roots, heap_lock);
+    // NOTE: we can allocate without deadlocking again since `heap_lock` got used
+    
+    scan_static_roots(&mut roots, &registered_roots);
+    drop(registered_roots);
+    
+    // Scan each thread's memory
+    info!("Scanning threads");
+    scan_other_threads(&mut roots, &t)?;
+    // NOTE: for the collector thread this is (almost) nothing, but `drive_once` can be called from anywhere
+    scan_current_thread(&mut roots);
+    
+    // sweep (i.e: drop) and free all the dead stuff in the heap
+    free_blocks(collect_garbage(roots), &mut tl_allocators);
+    
+    info!("Freed all dead blocks");
+    
+    let pauses = CyclePauses { finish: start.elapsed(), ..CyclePauses::default() };
+    drop(t);
+    drop(tl_allocators);
+    finish_cycle(pauses);
+    Ok(())
+}
 
-pub(super) fn gc_main() -> ! {
-    let (sender, reciever) = mpsc::channel::<Unique<[u8]>>();
-    DEALLOCATED_CHANNEL.set(sender).expect("Nobody but here sets `DEALLOCATED_CHANNEL`");
+pub(super) fn gc_main() {
+    init_deallocated_channel();
     
     // GC CYCLE PROCEDURE:
     //  0. wait until ..? (TODO)
@@ -158,118 +644,31 @@ pub(super) fn gc_main() -> ! {
     //       defer_dealloc(obj)
     //  7. call `start_the_world`
     //  8. work on actually freeing the memory
+    // 
+    // TODO: compaction. anything found by conservative scanning has to stay where it is (since
+    //       whatever points to it might really just be an integer), but once there's precise
+    //       tracing (i.e: a `Trace` trait), the precisely traced objects could be moved to the
+    //       start of the heap between 5 and 6, fixing up the `Gc`s found while tracing them.
+    // TODO: if that `Trace` trait gets added, it'll want a `#[derive(Trace)]` (in a companion
+    //       proc-macro crate, since this one can't export one) that visits every field, with a
+    //       `#[trace(skip)]` for fields that can't hold `Gc`s. writing impls by hand for every
+    //       struct with a handful of fields gets tedious fast.
     
     info!("Starting GC main thread");
     
-    'main: loop {
+    loop {
         // TODO: make a better way to know when to GC
-        std::thread::sleep(Duration::from_secs(2));
-        
-        // make sure no threads are currently allocating so we dont deadlock
-        info!("Starting GC Cycle");
-        let heap = Heap::new().unwrap();
-        let heap_lock = heap.lock().unwrap();
-        let mut tl_allocators = super::THREAD_LOCAL_ALLOCATORS.write().expect("nowhere should panic during allocations");
-        let t = StopAllThreads::new();
+        // NOTE: `GCAllocator::shutdown` unparks this, so it doesn't have to wait for the whole timeout
+        std::thread::park_timeout(Duration::from_secs(2));
         
-        std::thread::sleep(Duration::from_millis(20));
-        
-        // Scan for roots ------------------------------
-        let mut roots = Vec::new();
-        
-        // Scan heap
-        info!("Scanning process heap");
-        scan_heap(&mut roots, heap_lock);
-        // NOTE: we can allocate without deadlocking again since `heap_lock` got used
-        
-        // Scan global (mutable) static memory
-        for (name, segment_data) in get_writable_segments() {
-            info!("Scanning {name} segment");
-            for root in unsafe { scan_segment(segment_data) } {
-                debug!("Found pointer to {root:016x?} in {name} segment");
-                roots.push(root);
-            }
+        if super::SHUTDOWN.load(Ordering::Acquire) {
+            info!("Stopping GC main thread");
+            // one last cycle, so anything that's already dead still gets dropped
+            while collect_cycle().is_err() {}
+            return
         }
         
-        // Scan each thread's memory
-        info!("Scanning threads");
-        for thread in get_all_threads().into_iter().map(Result::unwrap) {
-            let id = unsafe { GetThreadId(thread) };
-            debug!("Scanning thread {id:x?}");
-            
-            // Scan thread registers
-            let context = match unsafe { t.get_thread_context(thread) } {
-                Ok(c) => c,
-                Err(code) => {
-                    error!("Collector: get_thread_context failed with code {code:x}");
-                    continue 'main
-                }
-            };
-            for ptr in scan_registers(&context) {
-                debug!("Found pointer to {ptr:016x?} in thread registers");
-                roots.push(ptr);
-            }
-            
-            // scan thread stacks
-            let bounds = get_thread_stack_bounds(thread).unwrap();
-            let stack_ptr = bounds.0.with_addr(context.Rsp as usize) as *const ();
-            for ptr in unsafe { scan_stack(bounds, stack_ptr) } {
-                debug!("Found pointer to {ptr:016x?} in thread stack");
-                roots.push(ptr);
-            }
-            
-            // TODO: scan thread local storage
-        }
-        warn!("TODO: Scan thread local storage");
-        
-        roots.sort();
-        roots.dedup();
-        
-        debug!("Root pointers: {roots:016x?}");
-        
-        let root_blocks = get_root_blocks(roots);
-        
-        info!("finished getting rooted blocks");
-        
-        // Scan the GC heap, starting from the roots
-        let live_blocks = get_live_blocks(root_blocks);
-        
-        debug!("Live blocks ({}): {live_blocks:016x?}", live_blocks.len());
-        
-        // NOTE: if it werent for absolutely stupid Drop implementations,
-        // we could soundly let all the threads go *now*, and asynchronously
-        // start dropping and freeing up all the dead stuff. but since people
-        // can (and DO) put literally everything in Drop, we have to run them
-        // in a controlled environment where we can make sure they arent
-        // creating dangling references. (NOTE: you can also start new threads
-        // during Drop. i know this is a problem, but idk how much yet. at the
-        // LEAST we have to monitor all memory accesses during it, but idk how)
-        
-        // Free everything that we know we can free (bc we recieved them over the channel)
-        free_blocks(
-            reciever.try_iter().map(|data| {
-                let data = NonNull::from(data);
-                let data_len = data.len();
-                // SAFETY: data needs to be a pointer to a heap allocation
-                let block_ptr = unsafe { data.cast::<GCHeapBlockHeader>().byte_sub(size_of::<GCHeapBlockHeader>()) };
-                let block_len = unsafe { (*block_ptr.as_ptr()).size };
-                assert!(data_len <= block_len, "Length of data (0x{data_len:x}) was larger than the block length (0x{block_len:x})");
-                block_ptr
-            }),
-            &mut tl_allocators
-        );
-        
-        info!("Freed explicit deallocations");
-        
-        // sweep (i.e: drop) and free the rest of the dead stuff in the heap
-        free_blocks(sweep_heap(live_blocks), &mut tl_allocators);
-        
-        info!("Freed all dead blocks");
-        
-        // Wake any threads waiting for garbage to have been cleaned up
-        *super::GC_CYCLE_NUMBER.try_lock().unwrap() += 1;
-        super::GC_CYCLE_SIGNAL.notify_all();
-        
-        info!("Finished garbage collection");
+        // if some thread's context couldn't be read, just try again next time
+        let _ = collect_cycle();
     }
 }
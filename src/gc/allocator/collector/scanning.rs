@@ -4,6 +4,16 @@ use super::super::{MEMORY_SOURCE, MemorySource};
 use super::super::heap_block_header::GCHeapBlockHeader;
 use super::super::os_dependent::heap_scan::WinHeapLock;
 
+/// Whether a word found in memory could be a pointer into the GC heap.
+/// 
+/// NOTE: this doesn't skip words that aren't aligned, since a reference to a byte in the middle
+/// of an object (e.g: `&s[1..]` into a `Gc<str>`, or a `&u8` field) has to keep it alive just
+/// like a `Gc` does. Only the range check can rule a word out.
+#[inline]
+fn could_be_heap_pointer(value: *const ()) -> bool {
+    MEMORY_SOURCE.contains(value)
+}
+
 pub(super) fn scan_registers(c: &windows_sys::Win32::System::Diagnostics::Debug::CONTEXT) -> impl IntoIterator<Item=*const ()> {
     gen move {
         let n = size_of_val(c) / size_of::<*const ()>();
@@ -17,7 +27,13 @@ pub(super) fn scan_registers(c: &windows_sys::Win32::System::Diagnostics::Debug:
     }
 }
 
-pub(super) unsafe fn scan_stack(bounds: (*const (), *const ()), rsp: *const ()) -> impl IntoIterator<Item=*const ()> {
+/// Yields every pointer into the GC heap on the stack, along with where it was found.
+pub(super) unsafe fn scan_stack(bounds: (*const (), *const ()), rsp: *const ()) -> impl IntoIterator<Item=(*const *const (), *const ())> {
+    unsafe { scan_stack_with(bounds, rsp, could_be_heap_pointer) }
+}
+
+/// Like [`scan_stack`], but yields every word that `keep` returns `true` for.
+pub(super) unsafe fn scan_stack_with(bounds: (*const (), *const ()), rsp: *const (), keep: impl Fn(*const ()) -> bool) -> impl IntoIterator<Item=(*const *const (), *const ())> {
     gen move {
         let (top, base) = bounds;
         assert!(top < base, "stack always grows downwards");
@@ -26,39 +42,57 @@ pub(super) unsafe fn scan_stack(bounds: (*const (), *const ()), rsp: *const ())
         let n = unsafe { base.offset_from(rsp) } as usize;
         for i in 0..n {
             let x = unsafe { rsp.add(i).read_volatile() };
-            if MEMORY_SOURCE.contains(x) {
-                yield x
+            if keep(x) {
+                yield (rsp.wrapping_add(i), x)
             }
         }
     }
 }
 
-pub(super) unsafe fn scan_segment(data: NonNull<[u8]>) -> impl IntoIterator<Item=*const ()> {
+/// Yields every pointer into the GC heap in `data`, along with where it was found.
+pub(super) unsafe fn scan_segment(data: NonNull<[u8]>) -> impl IntoIterator<Item=(*const *const (), *const ())> {
+    unsafe { scan_segment_with(data, could_be_heap_pointer) }
+}
+
+/// Like [`scan_segment`], but yields every word that `keep` returns `true` for.
+pub(super) unsafe fn scan_segment_with(data: NonNull<[u8]>, keep: impl Fn(*const ()) -> bool) -> impl IntoIterator<Item=(*const *const (), *const ())> {
     gen move {
         let (base, len) = data.to_raw_parts();
         let base = base.cast::<*const ()>();
         let len = len * size_of::<u8>() / size_of::<*const ()>();
         for i in 0..len {
             let value = unsafe { base.add(i).read_volatile() };
-            if MEMORY_SOURCE.contains(value) {
-                yield value
+            if keep(value) {
+                yield (base.as_ptr().cast_const().wrapping_add(i), value)
             }
         }
     }
 }
 
-pub(super) fn scan_heap(roots: &mut Vec<*const ()>, mut lock: WinHeapLock) {
+pub(super) fn scan_heap(roots: &mut Vec<*const ()>, lock: WinHeapLock) {
+    scan_heap_with(roots, lock, |_, ptr| could_be_heap_pointer(ptr).then_some(ptr))
+}
+
+/// Calls `f` with every word in the process heap (and where it was found), and pushes whatever
+/// it returns into `found`.
+/// 
+/// NOTE: this doesn't check whether the words look like pointers into the GC heap, so that's
+/// up to `f` (i.e: with [`could_be_heap_pointer`]).
+/// 
+/// NOTE: `f` may get called more than once for the same pointer, since the whole heap has to
+/// be rescanned whenever `found` needs to grow.
+pub(super) fn scan_heap_with<T>(found: &mut Vec<T>, mut lock: WinHeapLock, mut f: impl FnMut(*const *const (), *const ()) -> Option<T>) {
     // TODO: tune these values
     const MINIMUM_CAP: usize = 64;
     const GROWTH_FACTOR: usize = 4;
     
-    let initial_length = roots.len();
+    let initial_length = found.len();
     'main: loop {
         // Allocate more if the vector is full
-        if roots.len() == roots.capacity() {
+        if found.len() == found.capacity() {
             lock.with_unlocked(|| {
-                let num_to_reserve = std::cmp::max(MINIMUM_CAP - roots.len(), (GROWTH_FACTOR - 1) * roots.capacity()); 
-                roots.reserve(num_to_reserve)
+                let num_to_reserve = std::cmp::max(MINIMUM_CAP - found.len(), (GROWTH_FACTOR - 1) * found.capacity());
+                found.reserve(num_to_reserve)
             })
         }
         
@@ -66,23 +100,22 @@ pub(super) fn scan_heap(roots: &mut Vec<*const ()>, mut lock: WinHeapLock) {
             if !b.is_allocated() { continue }
             let block_data = b.data().cast::<*const ()>();
             
-            if block_data == roots.as_ptr().cast() {
+            if block_data == found.as_ptr().cast() {
                 // we found the allocation containing our roots vector LOL
                 continue
             }
             
-            let n = b.data_size() / size_of::<*const ()>();
-            for i in 0..n {
-                let ptr = unsafe { block_data.add(i).read_volatile() };
-                if MEMORY_SOURCE.contains(ptr) {
-                    debug!("Found pointer to {ptr:016x?} in heap (at address {:016x?})", block_data.wrapping_add(i));
-                    match roots.push_within_capacity(ptr) {
-                        Ok(()) => (),
-                        Err(_) => {
-                            // we need to rescan the whole heap, since we are gonna allocate more
-                            roots.truncate(initial_length);
-                            continue 'main
-                        }
+            // SAFETY: the heap is locked, so the block can't get freed from under us
+            for (i, ptr) in unsafe { b.words() }.enumerate() {
+                let address = block_data.wrapping_add(i);
+                let Some(item) = f(address, ptr) else { continue };
+                debug!("Found pointer to {ptr:016x?} in heap (at address {address:016x?})");
+                match found.push_within_capacity(item) {
+                    Ok(()) => (),
+                    Err(_) => {
+                        // we need to rescan the whole heap, since we are gonna allocate more
+                        found.truncate(initial_length);
+                        continue 'main
                     }
                 }
             }
@@ -92,18 +125,66 @@ pub(super) fn scan_heap(roots: &mut Vec<*const ()>, mut lock: WinHeapLock) {
     }
 }
 
-pub(super) fn scan_block(block: &GCHeapBlockHeader) -> impl IntoIterator<Item=*const ()> {
-    gen {
+/// Yields every pointer into the GC heap in the block's data, along with where it was found.
+pub(super) fn scan_block(block: &GCHeapBlockHeader) -> impl IntoIterator<Item=(*const *const (), *const ())> {
+    scan_block_with(block, could_be_heap_pointer)
+}
+
+/// Like [`scan_block`], but yields every word that `keep` returns `true` for.
+pub(super) fn scan_block_with(block: &GCHeapBlockHeader, keep: impl Fn(*const ()) -> bool) -> impl IntoIterator<Item=(*const *const (), *const ())> {
+    gen move {
         let (ptr, len) = block.data().to_raw_parts();
         let ptr = ptr.cast::<*const ()>();
         
         let n = len / size_of::<*const ()>();
         for i in 0..n {
             let value = unsafe { ptr.add(i).read() };
-            if MEMORY_SOURCE.contains(value) {
-                yield value;
+            if keep(value) {
+                yield (ptr.as_ptr().cast_const().wrapping_add(i), value);
             }
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+    
+    struct Bytes([u8; 16]);
+    impl Drop for Bytes {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    /// Makes a `Gc`, and only gives back a reference to an odd byte in the middle of it.
+    #[inline(never)]
+    fn make_interior_byte() -> &'static u8 {
+        let x = crate::gc::Gc::new(Bytes(std::array::from_fn(|i| i as u8)));
+        // SAFETY: the value is never mutated, and the returned reference keeps it alive
+        unsafe { &*x.as_ptr().cast::<u8>().add(3) }
+    }
+    
+    #[test]
+    fn test_misaligned_is_root() {
+        let x = crate::gc::Gc::new([0u64; 4]);
+        let ptr = x.as_ptr() as *const ();
+        
+        let words = [ptr.wrapping_byte_add(1), ptr, ptr.wrapping_byte_add(3)];
+        let data = NonNull::from_raw_parts(NonNull::from(&words).cast::<u8>(), size_of_val(&words));
+        let found: Vec<_> = unsafe { scan_segment(data) }.into_iter().map(|(_, value)| value).collect();
+        assert_eq!(found, words);
+        
+        // an odd pointer into the object is the only thing left keeping it alive
+        let interior = std::hint::black_box(make_interior_byte());
+        crate::gc::allocator::GC_ALLOCATOR.wait_for_gc();
+        crate::gc::allocator::GC_ALLOCATOR.wait_for_gc();
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
+        assert_eq!(*interior, 3);
+    }
+}
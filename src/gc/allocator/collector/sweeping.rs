@@ -1,4 +1,4 @@
-use super::{MEMORY_SOURCE, super::MemorySource};
+use super::{MEMORY_SOURCE, super::{BLOCK_INDEX, MemorySource}};
 use super::GCHeapBlockHeader;
 use std::collections::HashSet;
 use std::ptr::NonNull;
@@ -34,21 +34,14 @@ fn destruct_block_data(block: &mut GCHeapBlockHeader) -> Result<(), Box<dyn std:
 
 pub(super) fn sweep_heap(live_blocks: HashSet<NonNull<GCHeapBlockHeader>>) -> impl IntoIterator<Item=NonNull<GCHeapBlockHeader>> {
     gen move {
-        let (block_ptr, heap_size) = MEMORY_SOURCE.raw_data().to_raw_parts();
-        let end = unsafe { block_ptr.byte_add(heap_size) };
-        let mut block_ptr = block_ptr.cast::<GCHeapBlockHeader>();
-        
-        while block_ptr < end.cast() {
-            let next_block = unsafe { block_ptr.as_ref() }.next();
-            
+        // NOTE: this skips over all the parts of the heap with nothing allocated in them
+        for mut block_ptr in MEMORY_SOURCE.regions().into_iter().flat_map(|heap| BLOCK_INDEX.blocks(heap)) {
             if !unsafe { block_ptr.as_ref().is_allocated() } {
                 // not even allocated, dont free it again lol
-                block_ptr = next_block;
                 continue
             }
             
             if live_blocks.contains(&block_ptr) {
-                block_ptr = next_block;
                 continue // can't free this yet
             }
             
@@ -59,16 +52,11 @@ pub(super) fn sweep_heap(live_blocks: HashSet<NonNull<GCHeapBlockHeader>>) -> im
             
             // TODO: check to make sure the destructor didn't do anything evil.
             //       if it did, just `std::process::exit(1)` or something.
+            //       (with `checked-drops`, `collect_garbage` aborts if anything got stashed,
+            //       but that only works since it collects everything before freeing any of it)
             
             // Actually mark the stuff as freed
             yield block_ptr;
-            
-            // go to the next
-            block_ptr = next_block;
-        }
-        
-        if block_ptr != end.cast() {
-            error!("Heap corruption detected (expected to end at {end:016x?}, got {block_ptr:016x?})")
         }
     }
 }
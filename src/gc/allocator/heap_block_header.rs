@@ -8,13 +8,26 @@ pub(super) type HeaderFlag = usize;
 pub(super) const HEADERFLAG_NONE: HeaderFlag = 0x00;
 /// whether the heap block is allocated
 /// 
-/// TODO: also using `self.next == None` for this, can this be removed?
-/// if so, what is the "end of list" sentinel value?
+/// This is the only place that says so: a `next_free` of `None` just means the block is at the end
+/// of its free list (or not in one at all), so it can't be used to tell allocated blocks apart.
 pub(super) const HEADERFLAG_ALLOCATED: HeaderFlag = 0x01;
+/// whether the heap block's data has never been handed out before, and so is
+/// still zeroed from when the memory source gave it to us
+pub(super) const HEADERFLAG_PRISTINE: HeaderFlag = 0x02;
+
+/// The smallest leftover (header included) that's worth splitting off into its own free block.
+/// 
+/// Anything smaller just gets handed out along with the rest of the block, since tiny blocks
+/// can't fit much of anything, and only make the free list longer. This also makes sure every
+/// free block has room for its [`prev_free`](GCHeapBlockHeader::prev_free) link.
+pub(super) const MIN_SPLIT_SIZE: usize = 2 * size_of::<GCHeapBlockHeader>();
 
 /// NOTE: this struct must be followed by `self.size` contiguous bytes after it in memory.
 #[repr(C, align(16))]
 pub(super) struct GCHeapBlockHeader {
+    /// The block after this one in the free list.
+    /// 
+    /// This is `None` for the end of the free list, and for allocated blocks.
     pub(super) next_free: Option<NonNull<GCHeapBlockHeader>>,
     pub(super) size: usize,
     pub(super) flags: HeaderFlag,
@@ -24,39 +37,53 @@ pub(super) struct GCHeapBlockHeader {
 #[derive(Clone, Debug)]
 pub(super) enum BlockFittingError {
     BlockTooSmall,
-    CantFitNextBlock,
     NotEnoughAlignedRoom,
 }
 
 impl GCHeapBlockHeader {
     /// Checks if the block is allocated.
     pub(super) fn is_allocated(&self) -> bool {
-        if self.flags & HEADERFLAG_ALLOCATED != 0 { assert!(self.next_free.is_none()) }
         self.flags & HEADERFLAG_ALLOCATED != 0
     }
     
+    /// Checks if the block's data is still zeroed, since it has never been allocated before.
+    pub(super) fn is_pristine(&self) -> bool {
+        self.flags & HEADERFLAG_PRISTINE != 0
+    }
+    
     /// Marks this block as allocated.
     /// 
-    /// This is done by setting the appropriate flag, and setting the `next` pointer to null.
+    /// This is done by setting the appropriate flag. The block should already be unlinked from its
+    /// free list, but its links get cleared anyways so nothing dangling gets left around.
     pub(super) fn set_allocated(&mut self) {
         if self.is_allocated() {
             error!("Block at {:016x?} was already allocated", self as *const _);
         }
         assert!(!self.is_allocated(), "Block at {:016x?} was already allocated", self as *const _);
+        // NOTE: this also re-zeroes the first word of the data, so pristine blocks stay zeroed
+        self.set_prev_free(None);
         self.flags |= HEADERFLAG_ALLOCATED;
-        self.next_free = None; // if its allocated, its obviously not in the free list anymore
+        self.next_free = None;
     }
     
-    /// Unmarks this block as deallocated.
+    /// Unmarks this block as deallocated, and puts it at the front of the free list starting at `next`.
     /// 
-    /// This is done by setting the appropriate flag, and setting the `next` pointer to null.
+    /// This is done by setting the appropriate flag, and linking this block and `next` together.
+    /// Since the data was handed out, it also can't be considered pristine anymore.
     pub(super) fn set_free(&mut self, next: Option<NonNull<GCHeapBlockHeader>>) {
         if !self.is_allocated() {
             error!("Block at {:016x?} was already deallocated", self as *const _);
         }
         assert!(self.is_allocated(), "Block at {:016x?} was already deallocated", self as *const _);
-        self.flags &= !HEADERFLAG_ALLOCATED;
+        self.flags &= !(HEADERFLAG_ALLOCATED | HEADERFLAG_PRISTINE);
         self.next_free = next;
+        self.set_prev_free(None);
+        if let Some(next) = next {
+            // SAFETY: `next` is the head of a free list, so nobody else is using it
+            unsafe { (*next.as_ptr()).set_prev_free(Some(self.into())) };
+        }
+        // raw allocations don't set a destructor, so don't leave the old one around for them
+        self.drop_thunk = None;
     }
     
     /// Gets the data associated with this value.
@@ -76,6 +103,37 @@ impl GCHeapBlockHeader {
         unsafe { NonNull::from(self).byte_add(size_of_val(self) + self.size) }
     }
     
+    /// The block before this one in the free list, so it can be unlinked without traversing the list.
+    /// 
+    /// This is `None` for the head of the free list. Only free blocks have one, since it's kept in
+    /// the first word of the block's data (instead of in the header, so allocated blocks don't
+    /// have to pay for it). Every block's data is big enough for that (see [`MIN_SPLIT_SIZE`]).
+    /// 
+    /// NOTE: this is garbage if the block isn't in a free list.
+    pub(super) fn prev_free(&self) -> Option<NonNull<Self>> {
+        // SAFETY: the data is always at least a word long, and aligned to 16 bytes
+        unsafe { self.data().cast::<Option<NonNull<Self>>>().read() }
+    }
+    
+    /// Sets the block before this one in the free list. See [`prev_free`](Self::prev_free).
+    /// 
+    /// NOTE: this overwrites the first word of the data, so the block can't be allocated.
+    pub(super) fn set_prev_free(&mut self, prev: Option<NonNull<Self>>) {
+        // SAFETY: same as in `prev_free`
+        unsafe { self.data().cast::<Option<NonNull<Self>>>().write(prev) };
+    }
+    
+    /// Links `block` into the free list directly after this block.
+    fn insert_free_after(&mut self, block: &mut Self) {
+        block.set_prev_free(Some(self.into()));
+        block.next_free = self.next_free;
+        if let Some(next) = self.next_free {
+            // SAFETY: `next` is in the same free list as `self`, so nobody else is using it
+            unsafe { (*next.as_ptr()).set_prev_free(Some(block.into())) };
+        }
+        self.next_free = Some(block.into());
+    }
+    
     pub(super) fn shrink_to_fit(&mut self, layout: Layout) -> Result<(&mut Self, usize), BlockFittingError> {
         assert!(!self.is_allocated());
         assert!(self.size >= align_of::<Self>());
@@ -92,62 +150,71 @@ impl GCHeapBlockHeader {
         
         // block data is already aligned
         if self.data().is_aligned_to(align) {
-            if self.data().len() > padded_size + size_of::<Self>() {
-                // split off another block (of size > 0) at end
+            if self.data().len() - padded_size >= MIN_SPLIT_SIZE {
+                // split off another block at the end
                 
                 let next_block_size = self.data().len() - padded_size - size_of::<Self>();
                 assert!(next_block_size > 0); // sanity check
                 let next_block = unsafe { self.data().byte_add(padded_size).cast::<MaybeUninit<Self>>().as_mut() };
                 let next_block = next_block.write(GCHeapBlockHeader {
-                    next_free: self.next_free,
-                    flags: HEADERFLAG_NONE,
+                    next_free: None,
+                    flags: self.flags & HEADERFLAG_PRISTINE,
                     size: next_block_size,
                     drop_thunk: None
                 });
                 
-                self.next_free = Some(next_block.into());
+                self.insert_free_after(next_block);
                 self.size = padded_size;
                 
                 // this block is the one that fits the layout
                 return Ok((self, size_of::<Self>()))
             }
             
-            // no need to split off a block at the end, since theres no next block
-            if self.next_free.is_none() && self.data().len() >= padded_size {
-                return Ok((self, 0))
-            }
-            
-            // cant fit next block data at the end
-            return Err(BlockFittingError::CantFitNextBlock)
+            // the leftover isn't worth splitting off, so just hand out the whole block
+            return Ok((self, 0))
         }
         
         // NOTE: now we know that align is greater than align_of::<Self>()
         
-        let next_aligned = self.data().cast::<()>().map_addr(|a| unsafe {
-            std::num::NonZero::new((usize::from(a) + size_of::<Self>() + 1).next_multiple_of(align)).unwrap_unchecked()
-        }).cast::<MaybeUninit<Self>>();
-        let data_end = unsafe { self.data().cast::<()>().byte_add(self.data().len()) };
+        // The aligned block's *data* needs to be aligned, so its header goes directly before it.
+        // This block also needs to keep at least `align_of::<Self>()` bytes, so it isn't zero-sized.
+        let data_start = self.data().cast::<()>();
+        let data_end = unsafe { data_start.byte_add(self.data().len()) };
+        let aligned_data = data_start.map_addr(|a| unsafe {
+            std::num::NonZero::new((usize::from(a) + align_of::<Self>() + size_of::<Self>()).next_multiple_of(align)).unwrap_unchecked()
+        });
+        let aligned_data_end = unsafe { aligned_data.byte_add(padded_size) };
         
-        if unsafe { next_aligned.byte_add(padded_size) } > data_end.cast() {
+        if aligned_data_end > data_end {
             // not enough room to allocate layout
             return Err(BlockFittingError::NotEnoughAlignedRoom)
         }
         
         // split off into this block, and the new aligned block
-        let aligned_block = unsafe { &mut *next_aligned.as_ptr() };
+        let aligned_header = unsafe { aligned_data.byte_sub(size_of::<Self>()) }.cast::<MaybeUninit<Self>>();
+        let aligned_block = unsafe { &mut *aligned_header.as_ptr() };
         let aligned_block = aligned_block.write(GCHeapBlockHeader {
-            next_free: self.next_free,
-            size: usize::from(data_end.addr()) - usize::from(next_aligned.addr()),
-            flags: HEADERFLAG_NONE,
+            next_free: None,
+            size: usize::from(data_end.addr()) - usize::from(aligned_data.addr()),
+            flags: self.flags & HEADERFLAG_PRISTINE,
             drop_thunk: None
         });
-        self.next_free = Some(aligned_block.into());
-        self.size = usize::from(next_aligned.addr()) - usize::from(self.data().addr());
+        self.insert_free_after(aligned_block);
+        self.size = usize::from(aligned_header.addr()) - usize::from(data_start.addr());
         
-        //  [self]  |          | [new block] | [layout (aligned)] ... | 
-        if unsafe { next_aligned.byte_add(padded_size + size_of::<Self>()).cast() } < data_end {
+        //  [self]  |          | [new block] | [layout (aligned)] ... | [trailing block] | ... |
+        if usize::from(data_end.addr()) - usize::from(aligned_data_end.addr()) >= MIN_SPLIT_SIZE {
             // there is enough memory to split off an extra block from the aligned block
-            todo!("Split off extra data from aligned block");
+            let trailing_block = unsafe { aligned_data_end.cast::<MaybeUninit<Self>>().as_mut() };
+            let trailing_block = trailing_block.write(GCHeapBlockHeader {
+                next_free: None,
+                size: usize::from(data_end.addr()) - usize::from(aligned_data_end.addr()) - size_of::<Self>(),
+                flags: self.flags & HEADERFLAG_PRISTINE,
+                drop_thunk: None
+            });
+            
+            aligned_block.insert_free_after(trailing_block);
+            aligned_block.size = padded_size;
             
             return Ok((aligned_block, 2 * size_of::<Self>()))
         }
@@ -155,3 +222,230 @@ impl GCHeapBlockHeader {
         Ok((aligned_block, size_of::<Self>()))
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const HEADER_SIZE: usize = size_of::<GCHeapBlockHeader>();
+    
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+    
+    /// Makes a single free block spanning the first `len` bytes of `page`.
+    fn make_block(page: &mut Page, len: usize) -> &mut GCHeapBlockHeader {
+        let header = unsafe { &mut *(page as *mut Page).cast::<MaybeUninit<GCHeapBlockHeader>>() };
+        header.write(GCHeapBlockHeader {
+            next_free: None,
+            size: len - HEADER_SIZE,
+            flags: HEADERFLAG_NONE,
+            drop_thunk: None
+        })
+    }
+    
+    #[test]
+    fn test_shrink_aligned_with_trailing_split() {
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 1024);
+        let block_ptr = NonNull::from(&mut *block);
+        let end = unsafe { block_ptr.byte_add(1024) };
+        
+        let (aligned, new_header_bytes) = block.shrink_to_fit(Layout::from_size_align(64, 64).unwrap()).unwrap();
+        let aligned_ptr = NonNull::from(&mut *aligned);
+        
+        assert_eq!(new_header_bytes, 2 * HEADER_SIZE);
+        assert!(aligned.data().is_aligned_to(64));
+        assert_eq!(aligned.size, 64);
+        
+        // free list should be `block -> aligned -> trailing -> None`, and in that order in memory
+        let block = unsafe { block_ptr.as_ref() };
+        let trailing_ptr = aligned.next_free.expect("should have split off a trailing block");
+        let trailing = unsafe { trailing_ptr.as_ref() };
+        assert_eq!(block.next_free, Some(aligned_ptr));
+        assert_eq!(trailing.next_free, None);
+        assert_eq!(aligned.prev_free(), Some(block_ptr));
+        assert_eq!(trailing.prev_free(), Some(aligned_ptr));
+        assert_eq!(block.next(), aligned_ptr);
+        assert_eq!(aligned.next(), trailing_ptr);
+        assert_eq!(trailing.next(), end);
+        
+        // none of the blocks should be allocated or empty
+        for b in [block, &*aligned, trailing] {
+            assert!(!b.is_allocated());
+            assert!(b.size > 0);
+        }
+        
+        // no bytes went missing
+        assert_eq!(block.size + aligned.size + trailing.size + new_header_bytes, 1024 - HEADER_SIZE);
+    }
+    
+    #[test]
+    fn test_shrink_aligned_exact() {
+        // the aligned data for this layout starts at 0x80, so it ends exactly at the end of the block
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 0xC0);
+        let block_ptr = NonNull::from(&mut *block);
+        let end = unsafe { block_ptr.byte_add(0xC0) };
+        
+        let (aligned, new_header_bytes) = block.shrink_to_fit(Layout::from_size_align(64, 64).unwrap()).unwrap();
+        let aligned_ptr = NonNull::from(&mut *aligned);
+        
+        assert_eq!(new_header_bytes, HEADER_SIZE);
+        assert!(aligned.data().is_aligned_to(64));
+        assert_eq!(aligned.size, 64);
+        
+        // free list should be `block -> aligned -> None`
+        let block = unsafe { block_ptr.as_ref() };
+        assert_eq!(block.next_free, Some(aligned_ptr));
+        assert_eq!(aligned.next_free, None);
+        assert_eq!(block.next(), aligned_ptr);
+        assert_eq!(aligned.next(), end);
+        
+        assert_eq!(block.size + aligned.size + new_header_bytes, 0xC0 - HEADER_SIZE);
+    }
+    
+    #[test]
+    fn test_pristine_flag() {
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 1024);
+        block.flags = HEADERFLAG_PRISTINE;
+        let block_ptr = NonNull::from(&mut *block);
+        
+        // blocks split off from a pristine block are also pristine
+        let (aligned, _) = block.shrink_to_fit(Layout::from_size_align(64, 64).unwrap()).unwrap();
+        let trailing = unsafe { aligned.next_free.unwrap().as_ref() };
+        assert!(unsafe { block_ptr.as_ref() }.is_pristine());
+        assert!(aligned.is_pristine());
+        assert!(trailing.is_pristine());
+        
+        // the free list link kept in the data gets cleared when it's handed out, so it's still zeroed
+        assert_eq!(aligned.prev_free(), Some(block_ptr));
+        aligned.set_allocated();
+        assert!(aligned.is_pristine());
+        assert!(unsafe { aligned.data().as_ref() }.iter().all(|&b| b == 0));
+        
+        // but once they get handed out and freed again, they aren't
+        aligned.set_free(None);
+        assert!(!aligned.is_pristine());
+    }
+    
+    #[test]
+    fn test_header_size() {
+        // allocated blocks shouldn't have to pay for the free list's back links
+        assert_eq!(HEADER_SIZE, 0x20);
+    }
+    
+    #[test]
+    fn test_no_tiny_fragments() {
+        let layout = Layout::from_size_align(40, 8).unwrap();
+        
+        // splitting this would leave a block with only 16 bytes of data, so it should get handed out whole
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 128);
+        let (fitted, new_header_bytes) = block.shrink_to_fit(layout).unwrap();
+        assert_eq!(new_header_bytes, 0);
+        assert_eq!(fitted.size, 128 - HEADER_SIZE);
+        assert_eq!(fitted.next_free, None);
+        
+        // same thing if it isn't the last block in the free list
+        let mut other_page = Box::new(Page([0; 4096]));
+        let other_block = NonNull::from(make_block(&mut other_page, 128));
+        let block = make_block(&mut page, 128);
+        block.next_free = Some(other_block);
+        let (fitted, new_header_bytes) = block.shrink_to_fit(layout).unwrap();
+        assert_eq!(new_header_bytes, 0);
+        assert_eq!(fitted.size, 128 - HEADER_SIZE);
+        assert_eq!(fitted.next_free, Some(other_block));
+        
+        // carve up a whole page, and make sure none of the resulting blocks are tiny
+        let block_ptr = NonNull::from(make_block(&mut page, 4096));
+        let end = unsafe { block_ptr.byte_add(4096) };
+        let mut current = block_ptr;
+        let mut total_header_bytes = HEADER_SIZE;
+        loop {
+            let (fitted, new_header_bytes) = unsafe { current.as_mut() }.shrink_to_fit(layout).unwrap();
+            assert_eq!(NonNull::from(&mut *fitted), current);
+            total_header_bytes += new_header_bytes;
+            match fitted.next_free {
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+        
+        let mut total_data_bytes = 0;
+        let mut current = block_ptr;
+        while current < end {
+            let block = unsafe { current.as_ref() };
+            assert!(block.size >= MIN_SPLIT_SIZE - HEADER_SIZE, "block @ {current:x?} is too small ({} bytes)", block.size);
+            total_data_bytes += block.size;
+            current = block.next();
+        }
+        assert_eq!(current, end);
+        assert_eq!(total_data_bytes + total_header_bytes, 4096);
+    }
+    
+    #[test]
+    fn test_end_of_free_list_is_not_allocated() {
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 1024);
+        let (fitted, _) = block.shrink_to_fit(Layout::from_size_align(64, 16).unwrap()).unwrap();
+        
+        // the trailing block is the end of the free list, but it's still free
+        let trailing = unsafe { fitted.next_free.unwrap().as_mut() };
+        assert_eq!(trailing.next_free, None);
+        assert!(!trailing.is_allocated());
+        
+        // and a lone free block (the end of a one-block free list) is too
+        let mut other_page = Box::new(Page([0; 4096]));
+        let lone = make_block(&mut other_page, 1024);
+        assert_eq!(lone.next_free, None);
+        assert!(!lone.is_allocated());
+    }
+    
+    #[test]
+    fn test_allocated_state() {
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 1024);
+        let (fitted, _) = block.shrink_to_fit(Layout::from_size_align(64, 16).unwrap()).unwrap();
+        let fitted_ptr = NonNull::from(&mut *fitted);
+        let trailing_ptr = fitted.next_free.unwrap();
+        
+        // allocating only goes off of the flag, and doesn't leave any links behind (even in the data)
+        fitted.set_allocated();
+        assert!(fitted.is_allocated());
+        assert_eq!(fitted.next_free, None);
+        assert_eq!(unsafe { fitted.data().cast::<usize>().read() }, 0);
+        
+        // freeing it in front of another free block links the two together
+        unsafe { (*trailing_ptr.as_ptr()).set_prev_free(None) };
+        fitted.set_free(Some(trailing_ptr));
+        assert!(!fitted.is_allocated());
+        assert_eq!(fitted.next_free, Some(trailing_ptr));
+        assert_eq!(unsafe { trailing_ptr.as_ref() }.prev_free(), Some(fitted_ptr));
+        
+        // freeing it as the only block in a free list leaves it free, with no links
+        fitted.set_allocated();
+        fitted.set_free(None);
+        assert!(!fitted.is_allocated());
+        assert_eq!((fitted.next_free, fitted.prev_free()), (None, None));
+    }
+    
+    #[test]
+    #[should_panic = "already allocated"]
+    fn test_double_allocate() {
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 1024);
+        block.set_allocated();
+        block.set_allocated();
+    }
+    
+    #[test]
+    fn test_shrink_aligned_not_enough_room() {
+        let mut page = Box::new(Page([0; 4096]));
+        let block = make_block(&mut page, 0xB0);
+        
+        let result = block.shrink_to_fit(Layout::from_size_align(64, 64).unwrap());
+        assert!(matches!(result, Err(BlockFittingError::NotEnoughAlignedRoom)));
+    }
+}
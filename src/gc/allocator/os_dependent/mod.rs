@@ -14,17 +14,65 @@ pub trait MemorySource {
     
     /// Get `num_pages * self.page_size()` bytes of memory.
     /// 
-    /// The memory is not necessarily initialized.
+    /// The memory is not necessarily initialized, but it must start on a page boundary (i.e: be
+    /// aligned to [`page_size`](MemorySource::page_size)), since block headers get put right at the
+    /// start of it. The allocator checks this, and fails with [`GCAllocatorError::MisalignedMemory`]
+    /// if it isn't.
+    /// 
+    /// [`GCAllocatorError::MisalignedMemory`]: super::GCAllocatorError::MisalignedMemory
     fn grow_by(&self, num_pages: usize) -> Option<NonNull<[u8]>>;
     
+    /// Same as [`grow_by`](MemorySource::grow_by), but preferably gets memory that is local to the
+    /// NUMA node `node`, so that threads running on that node don't have to go across to another
+    /// socket's memory to use it.
+    /// 
+    /// This is only a hint, so sources that don't know about NUMA (or about that node) can just
+    /// fall back to `grow_by`.
+    fn grow_by_on_node(&self, num_pages: usize, node: u32) -> Option<NonNull<[u8]>> {
+        let _ = node;
+        self.grow_by(num_pages)
+    }
+    
     /// Removes pages from the pool of allocated memory.
+    /// 
+    /// If [`GROWS_ZEROED`](MemorySource::GROWS_ZEROED) is `true`, the removed
+    /// pages must be zeroed again before they are handed out by another call to
+    /// [`grow_by`](MemorySource::grow_by).
     unsafe fn shrink_by(&self, num_pages: usize);
     
+    /// Whether memory returned from [`grow_by`](MemorySource::grow_by) is always zeroed.
+    const GROWS_ZEROED: bool = false;
+    
     /// Whether the given pointer points into the memory pool.
     fn contains(&self, ptr: *const ()) -> bool;
     
-    /// A pointer into the entire pool of committed memory.
-    fn raw_data(&self) -> NonNull<[u8]>;
+    /// All of the memory that has been handed out by [`grow_by`](MemorySource::grow_by), as
+    /// contiguous regions, sorted by address.
+    /// 
+    /// Every piece of memory that `grow_by` returns is entirely inside one of these.
+    fn regions(&self) -> Vec<NonNull<[u8]>>;
+    
+    /// How many more bytes [`grow_by`](MemorySource::grow_by) can hand out from address space
+    /// that has already been set aside for it.
+    /// 
+    /// NOTE: some sources can get more address space once this runs out, so this isn't
+    ///       necessarily a hard limit.
+    fn headroom(&self) -> usize;
+    
+    /// Starts keeping track of which pages get written to, forgetting about any earlier writes.
+    fn reset_dirty_pages(&self) {}
+    
+    /// The start of every page that has been written to since the last call to this (or to
+    /// [`reset_dirty_pages`](MemorySource::reset_dirty_pages)), sorted by address.
+    /// 
+    /// This also resets the tracking, so the same write never gets returned twice. Sources that
+    /// can't keep track of writes just return every page.
+    fn take_dirty_pages(&self) -> Vec<NonNull<u8>> {
+        let page_size = self.page_size();
+        self.regions().into_iter().flat_map(|region| {
+            (0..region.len()).step_by(page_size).map(move |offset| unsafe { region.cast::<u8>().add(offset) })
+        }).collect()
+    }
 }
 
 #[cfg(target_os="windows")]
@@ -43,6 +91,6 @@ pub(super) static MEMORY_SOURCE: &LazyLock<MemorySourceImpl> = if cfg!(windows)
 
 
 #[cfg(target_os="windows")]
-pub use windows::{get_all_threads, get_thread_stack_bounds, StopAllThreads, heap_scan};
+pub use windows::{context_stack_pointer, current_numa_node, current_thread_id, get_all_threads, get_live_thread_ids, get_thread_stack_bounds, StopAllThreads, heap_scan};
 
 
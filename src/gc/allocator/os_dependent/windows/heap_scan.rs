@@ -4,6 +4,7 @@
 
 #![allow(dead_code)]
 
+use std::ops::Range;
 use std::ptr::NonNull;
 
 
@@ -37,6 +38,28 @@ impl WinHeap {
         // TODO: make better errors than a u32 error code?
         WinHeapLock::new(self)
     }
+    
+    /// Walks the whole heap once, and adds up how much of it is in use.
+    /// 
+    /// This is for figuring out where the process heap's memory is going (which is separate
+    /// from the GC heap). The heap stays locked during the walk, so it's a consistent snapshot.
+    pub fn census(&self) -> Result<HeapCensus, u32> {
+        let lock = self.lock()?;
+        
+        // NOTE: nothing in here can allocate, since the heap is locked
+        let mut census = HeapCensus::default();
+        for entry in lock.walk() {
+            if entry.is_allocated() {
+                census.total_allocated += entry.data_size();
+                census.block_count += 1;
+            } else if !entry.is_region() && !entry.is_uncommitted_range() {
+                census.total_free += entry.data_size();
+                census.largest_free = census.largest_free.max(entry.data_size());
+            }
+        }
+        
+        Ok(census)
+    }
 }
 
 impl Drop for WinHeap {
@@ -50,6 +73,20 @@ impl Drop for WinHeap {
 }
 
 
+/// A summary of everything in a [`WinHeap`], from [`WinHeap::census`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapCensus {
+    /// The number of bytes in allocated blocks (not counting the heap's own overhead).
+    pub total_allocated: usize,
+    /// The number of bytes in free blocks.
+    pub total_free: usize,
+    /// The number of allocated blocks.
+    pub block_count: usize,
+    /// The size of the biggest free block, in bytes.
+    pub largest_free: usize,
+}
+
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct WinHeapEntry(windows_sys::Win32::System::Memory::PROCESS_HEAP_ENTRY);
@@ -176,6 +213,28 @@ impl WinHeapEntry {
     pub fn data_size(&self) -> usize {
         self.0.cbData as usize
     }
+    
+    /// The range of addresses in this entry that are scannable for pointers.
+    /// 
+    /// NOTE: this only includes whole pointer-sized words, so any trailing bytes are left out.
+    pub fn data_range(&self) -> Range<*const ()> {
+        let start = self.data().cast::<*const ()>();
+        let end = start.wrapping_add(self.data_size() / size_of::<*const ()>());
+        start.cast()..end.cast()
+    }
+    
+    /// Reads each pointer-sized word in [`data_range`](Self::data_range).
+    /// 
+    /// # Safety
+    /// This entry has to be an allocated block, which must not have been freed since it
+    /// was walked (i.e: the heap should still be locked).
+    pub unsafe fn words(&self) -> impl Iterator<Item=*const ()> {
+        let Range { start, end } = self.data_range();
+        let (start, end) = (start.cast::<*const ()>(), end.cast::<*const ()>());
+        let n = unsafe { end.offset_from(start) } as usize;
+        // SAFETY: guaranteed by caller
+        (0..n).map(move |i| unsafe { start.add(i).read_volatile() })
+    }
 }
 
 #[must_use = "if unused the heap will immediately unlock"]
@@ -305,3 +364,47 @@ pub fn get_all_heaps() -> impl Iterator<Item=WinHeap> {
     
     heap_handles.into_iter().map(|h| unsafe { WinHeap::from_handle(h).unwrap_unchecked() })
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_entry_words() {
+        let _allocated = Box::new([0usize; 64]);
+        
+        let heap = WinHeap::new().unwrap();
+        let lock = heap.lock().unwrap();
+        
+        // NOTE: nothing in here can allocate (including panicking), since the heap is locked
+        let (mut num_blocks, mut num_mismatched) = (0, 0);
+        for entry in lock.walk() {
+            if !entry.is_allocated() { continue }
+            num_blocks += 1;
+            if unsafe { entry.words() }.count() != entry.data_size() / size_of::<usize>() {
+                num_mismatched += 1;
+            }
+        }
+        drop(lock);
+        
+        assert!(num_blocks > 0);
+        assert_eq!(num_mismatched, 0);
+    }
+    
+    #[test]
+    fn test_census() {
+        // NOTE: this is small enough that it doesn't get its own `VirtualAlloc`ed block
+        const SIZE: usize = 0x40000;
+        
+        let heap = WinHeap::new().unwrap();
+        let before = heap.census().unwrap();
+        let allocated = std::hint::black_box(vec![1u8; SIZE]);
+        let after = heap.census().unwrap();
+        drop(allocated);
+        
+        assert!(after.block_count > 0);
+        assert!(after.total_allocated >= before.total_allocated + SIZE, "{before:?} -> {after:?}");
+        assert!(after.largest_free <= after.total_free);
+    }
+}
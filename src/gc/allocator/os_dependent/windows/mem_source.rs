@@ -1,24 +1,185 @@
 use std::ptr::NonNull;
-use std::sync::{LazyLock, RwLock};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
 use windows_sys::Win32::Foundation::GetLastError;
-use windows_sys::Win32::System::Memory::{MEM_RESERVE, MEM_COMMIT, PAGE_READWRITE, VirtualAlloc};
+use windows_sys::Win32::System::Memory::{MEM_RESERVE, MEM_COMMIT, PAGE_READWRITE, VirtualAlloc, VirtualAllocExNuma, GetWriteWatch, ResetWriteWatch};
+use windows_sys::Win32::System::SystemServices::{MEM_WRITE_WATCH, WRITE_WATCH_FLAG_RESET};
+use windows_sys::Win32::System::Threading::{GetCurrentProcess, GetNumaHighestNodeNumber};
 
-struct MemSizes {
-    /// The current size of the heap
-    length: usize,
-    /// the "capacity" of the heap
+/// A single contiguous range of reserved address space.
+struct Region {
+    data: *mut (),
+    /// maximum allowed capacity of the region
+    reserved: usize, // constant
+    /// The current size of the region (shared with its [`RegionBounds`], and only changed while
+    /// holding the write lock on its shard)
+    length: Arc<AtomicUsize>,
+    /// the "capacity" of the region
     committed: usize,
+    /// The NUMA node that the region's memory should preferably come from, if any.
+    node: Option<u32>, // constant
+}
+
+impl Region {
+    /// Reserves `size` bytes of address space, without committing any of it.
+    /// 
+    /// If `node` is given, the OS prefers to back the region with physical memory from that NUMA
+    /// node once it gets committed. The OS keeps track of which pages get written to (see
+    /// [`take_dirty_pages`](Self::take_dirty_pages)).
+    fn reserve(size: usize, node: Option<u32>) -> Option<Self> {
+        let data = match node {
+            None => unsafe { VirtualAlloc(std::ptr::null(), size, MEM_RESERVE | MEM_WRITE_WATCH, PAGE_READWRITE) },
+            // NOTE: the preferred node only gets picked when the address space is reserved, committing ignores it
+            Some(node) => unsafe { VirtualAllocExNuma(GetCurrentProcess(), std::ptr::null(), size, MEM_RESERVE | MEM_WRITE_WATCH, PAGE_READWRITE, node) },
+        } as *mut ();
+        if data.is_null() {
+            let err = unsafe { GetLastError() };
+            error!("Reserve failed with code {:x}", err);
+            return None
+        }
+        Some(Self { data, reserved: size, length: Arc::new(AtomicUsize::new(0)), committed: 0, node })
+    }
+    
+    fn length(&self) -> usize {
+        self.length.load(Ordering::Acquire)
+    }
+    
+    fn set_length(&self, length: usize) {
+        self.length.store(length, Ordering::Release);
+    }
+    
+    fn bounds(&self) -> RegionBounds {
+        RegionBounds { start: self.data.addr(), length: self.length.clone() }
+    }
+    
+    /// Commits (at least) the first `size` bytes of the region.
+    fn commit(&mut self, size: usize) -> Option<()> {
+        while self.committed < size {
+            // place to allocate more memory from
+            let new_base = self.data.wrapping_byte_add(self.committed);
+            
+            // allocate more memory, growing geometrically (but not past the end of the reservation)
+            let amount = self.committed.max(size - self.committed).min(self.reserved - self.committed);
+            let rv = unsafe { VirtualAlloc(new_base as _, amount, MEM_COMMIT, PAGE_READWRITE) } as *mut ();
+            if rv.is_null() {
+                let err = unsafe { GetLastError() };
+                error!("Commit failed with code {:x}", err);
+                return None
+            }
+            
+            self.committed += amount;
+        }
+        Some(())
+    }
+    
+    /// Gets `num_bytes` more bytes from the end of the region, if there's enough room left.
+    fn grow_by(&mut self, num_bytes: usize) -> Option<NonNull<[u8]>> {
+        let old_length = self.length();
+        if self.reserved - old_length < num_bytes {
+            return None
+        }
+        
+        self.commit(old_length + num_bytes)?;
+        self.set_length(old_length + num_bytes);
+        
+        // SAFETY: entire address space in [`data`, `data+length`) is valid, and old_length ≤ length
+        let ptr = unsafe { self.data.byte_add(old_length) };
+        Some(NonNull::<[u8]>::from_raw_parts(NonNull::new(ptr)?, num_bytes))
+    }
+    
+    #[cfg(test)]
+    fn contains(&self, ptr: *const ()) -> bool {
+        self.bounds().contains(ptr)
+    }
+    
+    /// The memory that has been handed out from this region.
+    fn used(&self) -> NonNull<[u8]> {
+        NonNull::from_raw_parts(NonNull::new(self.data).expect("region pointer is never null"), self.length())
+    }
+    
+    fn reset_dirty_pages(&self) {
+        let length = self.length();
+        if length == 0 { return }
+        if unsafe { ResetWriteWatch(self.data.cast_const().cast(), length) } != 0 {
+            error!("ResetWriteWatch failed with code {:x}", unsafe { GetLastError() });
+        }
+    }
+    
+    /// Pushes every page in the used part of the region that has been written to since the last
+    /// reset into `pages`, and resets the tracking for them.
+    fn take_dirty_pages(&self, pages: &mut Vec<NonNull<u8>>) {
+        // the most pages to ask for at once
+        const BATCH_SIZE: usize = 0x400;
+        
+        let length = self.length();
+        if length == 0 { return }
+        loop {
+            pages.reserve(BATCH_SIZE);
+            let buffer = pages.spare_capacity_mut();
+            let mut count = buffer.len();
+            let mut granularity = 0;
+            let rv = unsafe { GetWriteWatch(WRITE_WATCH_FLAG_RESET, self.data.cast_const().cast(), length, buffer.as_mut_ptr().cast(), &mut count, &mut granularity) };
+            if rv != 0 {
+                // just say that everything was written to, since that's always correct
+                error!("GetWriteWatch failed with code {:x}", unsafe { GetLastError() });
+                let used = self.used().cast::<u8>();
+                pages.extend((0..length).step_by(WindowsMemorySource::PAGE_SIZE).map(|offset| unsafe { used.add(offset) }));
+                return
+            }
+            debug_assert_eq!(granularity as usize, WindowsMemorySource::PAGE_SIZE);
+            
+            // SAFETY: `GetWriteWatch` wrote `count` (non-null) page addresses into the spare capacity
+            let filled = count == buffer.len();
+            unsafe { pages.set_len(pages.len() + count) };
+            
+            // if the buffer got filled up, there might be more (the ones we got are reset already)
+            if !filled { return }
+        }
+    }
+}
+
+/// Where a [`Region`] is, so that it can be checked without locking its shard.
+#[derive(Clone)]
+struct RegionBounds {
+    start: usize,
+    length: Arc<AtomicUsize>,
 }
 
+impl RegionBounds {
+    fn contains(&self, ptr: *const ()) -> bool {
+        let end = self.start + self.length.load(Ordering::Acquire);
+        self.start <= ptr.addr() && ptr.addr() < end
+    }
+}
+
+/// A memory source that reserves big regions of address space, and commits them as needed.
+/// 
+/// Once a region runs out of room, another one gets reserved, so the heap can keep growing
+/// as long as the OS has address space to give it. The leftover bit at the end of the old
+/// region just never gets used.
+/// 
+/// The regions are split up into shards: one for memory without a preferred NUMA node (which
+/// is what [`grow_by`](MemorySource::grow_by) uses), and one for each node (which is what
+/// [`grow_by_on_node`](MemorySource::grow_by_on_node) uses). Each shard has its own lock, so
+/// threads on different nodes don't contend with each other when growing the heap.
 pub struct WindowsMemorySource {
-    data: *mut (),
-    /// maximum allowed capacity of the heap
-    reserved: usize, // constant
-    sizes: RwLock<MemSizes>,
+    /// how much address space to reserve at once
+    region_size: usize, // constant
+    /// Every region reserved so far, with the ones for NUMA node `n` in `shards[n + 1]`, and the
+    /// rest in `shards[0]`. Only the last one in each shard ever grows.
+    shards: Box<[RwLock<Vec<Region>>]>,
+    /// The bounds of every region in `shards`, so that [`contains`](MemorySource::contains) doesn't
+    /// have to lock any of them. This only gets replaced when a new region is reserved.
+    bounds: AtomicPtr<Box<[RegionBounds]>>,
+    /// Every snapshot that `bounds` used to point to. Other threads might still be reading them,
+    /// so they only get freed along with the source (regions are rarely reserved, so it's not much).
+    // NOTE: readers go through the outer box to find the slice, so it has to stay allocated too
+    #[allow(clippy::vec_box)]
+    old_bounds: Mutex<Vec<Box<Box<[RegionBounds]>>>>,
 }
 
-// SAFETY: `data` is the only thing not `Send`/`Sync` here, but we dont actually ever change it
+// SAFETY: the regions' `data` pointers are the only thing not `Send`/`Sync` here, and they never change
 unsafe impl Send for WindowsMemorySource {}
 unsafe impl Sync for WindowsMemorySource {}
 
@@ -30,35 +191,72 @@ impl WindowsMemorySource {
     
     /// default size is 32MiB
     const FIRST_COMMIT_SIZE: usize = 0x2000000;
-    /// default max size is 2GiB
-    const DEFAULT_MAX_SIZE: usize = 0x20000000000;
+    /// default size of each region is 2TiB
+    const DEFAULT_REGION_SIZE: usize = 0x20000000000;
     
-    fn new(max_size: usize) -> Self {
-        // Reserve maximum capacity
-        let base_ptr = unsafe { VirtualAlloc(std::ptr::null(), max_size, MEM_RESERVE, PAGE_READWRITE) } as *mut ();
-        if base_ptr.is_null() {
-            let err = unsafe { GetLastError() };
-            panic!("First reserve failed with code {:x}", err);
-        }
+    fn new(region_size: usize) -> Self {
+        // Reserve the first region
+        let mut region = Region::reserve(region_size, None).expect("First reserve failed");
         
-        // Commit first page
+        // Commit the first few pages
         // TODO: make Self::FIRST_PAGE_SIZE a parameter ?
-        let page = unsafe { VirtualAlloc(base_ptr as _, Self::FIRST_COMMIT_SIZE, MEM_COMMIT, PAGE_READWRITE) } as *mut ();
-        if page.is_null() {
-            let err = unsafe { GetLastError() };
-            panic!("First commit failed with code {:x}", err);
+        region.commit(Self::FIRST_COMMIT_SIZE.min(region_size)).expect("First commit failed");
+        
+        // the per-node shards don't get any regions until something asks for memory on that node
+        let mut highest_node = 0;
+        if unsafe { GetNumaHighestNodeNumber(&mut highest_node) } == 0 {
+            warn!("GetNumaHighestNodeNumber failed with code {:x}", unsafe { GetLastError() });
         }
+        let bounds = Box::new(Box::from([region.bounds()]));
+        let shards = std::iter::once(vec![region])
+            .chain((0..=highest_node).map(|_| Vec::new()))
+            .map(RwLock::new)
+            .collect();
         
-        assert_eq!(page, base_ptr);
+        Self { region_size, shards, bounds: AtomicPtr::new(Box::into_raw(bounds)), old_bounds: Mutex::new(Vec::new()) }
+    }
+    
+    /// The bounds of every region reserved so far.
+    fn bounds(&self) -> &[RegionBounds] {
+        // SAFETY: snapshots are only freed when the source is dropped
+        unsafe { &*self.bounds.load(Ordering::Acquire) }
+    }
+    
+    /// Replaces the bounds snapshot with one that also has `region` in it.
+    fn publish(&self, region: &Region) {
+        // NOTE: this also keeps threads growing different shards from overwriting each other's snapshots
+        let mut old_bounds = self.old_bounds.lock().unwrap();
+        let new_bounds: Box<[RegionBounds]> = self.bounds().iter().cloned().chain([region.bounds()]).collect();
+        let old = self.bounds.swap(Box::into_raw(Box::new(new_bounds)), Ordering::AcqRel);
+        old_bounds.push(unsafe { Box::from_raw(old) });
+    }
+    
+    /// Gets `num_bytes` more bytes from the last region in `shard`, reserving a new region if it runs out.
+    fn grow_shard(&self, shard: usize, num_bytes: usize) -> Option<NonNull<[u8]>> {
+        let mut regions = self.shards[shard].write().ok()?; // panic safety: we don't already hold the write lock
         
-        Self {
-            data: base_ptr,
-            reserved: max_size,
-            sizes: RwLock::new(MemSizes {
-                length: 0,
-                committed: Self::FIRST_COMMIT_SIZE
-            })
+        if let Some(last) = regions.last_mut() && let Some(memory) = last.grow_by(num_bytes) {
+            return Some(memory)
         }
+        
+        // not enough room left in the current region, so reserve another one (big enough for this)
+        let node = shard.checked_sub(1).map(|node| node as u32);
+        let mut region = Region::reserve(self.region_size.max(num_bytes), node)?;
+        let memory = region.grow_by(num_bytes)?;
+        debug!("Reserved a new region at {:016x?}[0x{:x}] (node {node:?})", region.data, region.reserved);
+        self.publish(&region);
+        regions.push(region);
+        Some(memory)
+    }
+    
+    /// The NUMA node that the region containing `ptr` was reserved for.
+    /// 
+    /// This is `None` if `ptr` isn't in any region, and `Some(None)` if it is in one without a node.
+    #[cfg(test)]
+    fn node_of(&self, ptr: *const ()) -> Option<Option<u32>> {
+        self.shards.iter().find_map(|shard| {
+            shard.read().unwrap().iter().find(|region| region.contains(ptr)).map(|region| region.node)
+        })
     }
 }
 
@@ -68,65 +266,208 @@ impl super::super::MemorySource for WindowsMemorySource {
     }
     
     fn grow_by(&self, num_pages: usize) -> Option<NonNull<[u8]>> {
-        // TODO: improve readability at some point
-        let MemSizes { length, committed } = &mut *self.sizes.write().ok()?; // panic safety: we don't already hold the write lock
-        let old_length = *length;
-        *length += num_pages * self.page_size();
-        
-        // not enough memory for the requested allocation
-        if *length > self.reserved {
-            *length = old_length;
-            return None;
-        }
-        
-        while committed < length {
-            // place to allocate more memory from
-            let new_base = self.data.wrapping_byte_offset(*committed as isize);
-            
-            // allocate more memory, growing geometrically
-            let rv = unsafe { VirtualAlloc(new_base as _, *committed, MEM_COMMIT, PAGE_READWRITE) } as *mut ();
-            if rv.is_null() {
-                let err = unsafe { GetLastError() };
-                error!("Commit failed with code {:x}", err);
-                return None;
-            }
-            
-            // amount of committed memory just grew by `*committed` bytes
-            *committed += *committed;
+        self.grow_shard(0, num_pages * self.page_size())
+    }
+    
+    fn grow_by_on_node(&self, num_pages: usize, node: u32) -> Option<NonNull<[u8]>> {
+        match node as usize + 1 {
+            shard if shard < self.shards.len() => self.grow_shard(shard, num_pages * self.page_size()),
+            _ => self.grow_by(num_pages),
         }
-        
-        // SAFETY: entire address space in [`data`, `data+length`) is valid, and old_length ≤ length
-        let ptr = unsafe { self.data.byte_offset(old_length as isize) };
-        let len = num_pages * self.page_size();
-        
-        Some(NonNull::<[u8]>::from_raw_parts(NonNull::new(ptr)?, len))
     }
     
+    // NOTE: this can only take back memory from `grow_by`, not from `grow_by_on_node`
     unsafe fn shrink_by(&self, num_pages: usize) {
-        let MemSizes { length, .. } = &mut *self.sizes.write().expect("Should never panic while holding lock");
-        *length -= num_pages * self.page_size();
+        let regions = self.shards[0].write().expect("Should never panic while holding lock");
+        let last = regions.last().expect("there is always at least one region");
+        let length = last.length().checked_sub(num_pages * self.page_size()).expect("can only shrink the last region");
+        last.set_length(length);
+        
+        // These pages stay committed, so they have to be re-zeroed to uphold `GROWS_ZEROED`
+        // SAFETY: the entire address space in [`data`, `data+committed`) is valid
+        unsafe { last.data.byte_add(length).cast::<u8>().write_bytes(0, num_pages * self.page_size()) };
     }
     
+    // `VirtualAlloc` zero-fills pages when they are committed
+    const GROWS_ZEROED: bool = true;
+    
+    // NOTE: this gets called for every word the collector scans, so it doesn't take any locks
     fn contains(&self, ptr: *const ()) -> bool {
-        let min = self.data.addr();
-        let max = min + self.sizes.read().unwrap().length;
-        let value = ptr.addr();
-        min <= value && value < max
+        self.bounds().iter().any(|bounds| bounds.contains(ptr))
+    }
+    
+    fn regions(&self) -> Vec<NonNull<[u8]>> {
+        let mut regions: Vec<_> = self.shards.iter().flat_map(|shard| {
+            shard.read().unwrap().iter().map(Region::used).collect::<Vec<_>>()
+        }).collect();
+        regions.sort_by_key(|region| region.addr());
+        regions
+    }
+    
+    // NOTE: this is only what's left in the last region of each shard, even though more can be reserved
+    fn headroom(&self) -> usize {
+        self.shards.iter().filter_map(|shard| {
+            shard.read().unwrap().last().map(|last| last.reserved - last.length())
+        }).sum()
     }
     
-    fn raw_data(&self) -> NonNull<[u8]> {
-        NonNull::from_raw_parts(
-            NonNull::new(self.data).expect("heap pointer is never null"),
-            self.sizes.read().unwrap().length
-        )
+    fn reset_dirty_pages(&self) {
+        for shard in &self.shards {
+            shard.read().unwrap().iter().for_each(Region::reset_dirty_pages);
+        }
+    }
+    
+    // Regions are reserved with `MEM_WRITE_WATCH`, so the OS keeps track of this for us
+    fn take_dirty_pages(&self) -> Vec<NonNull<u8>> {
+        let mut pages = Vec::new();
+        for shard in &self.shards {
+            for region in shard.read().unwrap().iter() {
+                region.take_dirty_pages(&mut pages);
+            }
+        }
+        pages.sort();
+        pages
+    }
+}
+
+impl Drop for WindowsMemorySource {
+    fn drop(&mut self) {
+        // SAFETY: nothing else can be reading the current snapshot anymore (the old ones get dropped with `old_bounds`)
+        drop(unsafe { Box::from_raw(*self.bounds.get_mut()) });
     }
 }
 
-/// Default maximum memory: 2GiB
-pub static WIN_ALLOCATOR: LazyLock<WindowsMemorySource> = LazyLock::new(|| WindowsMemorySource::new(WindowsMemorySource::DEFAULT_MAX_SIZE));
+/// Reserves 2TiB at a time
+pub static WIN_ALLOCATOR: LazyLock<WindowsMemorySource> = LazyLock::new(|| WindowsMemorySource::new(WindowsMemorySource::DEFAULT_REGION_SIZE));
 
 #[cfg(test)]
 mod tests {
+    use std::alloc::Layout;
     
+    use super::*;
+    use super::super::super::MemorySource;
+    use super::super::super::super::block_index::BlockIndex;
+    use super::super::super::super::tl_allocator::TLAllocator;
+    
+    /// Small enough to run out of quickly
+    const REGION_SIZE: usize = 0x10000;
+    
+    #[test]
+    fn test_grow_past_region() {
+        let source = WindowsMemorySource::new(REGION_SIZE);
+        let first = source.grow_by(REGION_SIZE / WindowsMemorySource::PAGE_SIZE).expect("first region should fit");
+        let second = source.grow_by(1).expect("should reserve a second region");
+        
+        assert_eq!(source.regions().len(), 2);
+        assert!(source.contains(first.cast().as_ptr()));
+        assert!(source.contains(second.cast().as_ptr()));
+        
+        // both have to actually be committed
+        unsafe { first.cast::<u8>().write_bytes(0xAB, first.len()) };
+        unsafe { second.cast::<u8>().write_bytes(0xCD, second.len()) };
+    }
+    
+    #[test]
+    fn test_allocate_past_region() {
+        let source: &'static WindowsMemorySource = Box::leak(Box::new(WindowsMemorySource::new(REGION_SIZE)));
+        let block_index: &'static BlockIndex = Box::leak(Box::new(BlockIndex::new()));
+        let allocator = TLAllocator::try_new(source, block_index).unwrap();
+        
+        let layout = Layout::from_size_align(512, 8).unwrap();
+        let blocks: Vec<_> = (0..REGION_SIZE * 3 / 2 / layout.size())
+            .map(|_| allocator.raw_allocate(layout).expect("allocation should continue in another region").1)
+            .collect();
+        
+        let regions = source.regions();
+        assert!(regions.len() >= 2);
+        
+        // NOTE: the regions are sorted by address, so the first one might not be first in the list
+        let region_of = |block: &NonNull<[u8]>| regions.iter().position(|region| {
+            let (start, len) = region.to_raw_parts();
+            start <= block.cast() && block.cast() < unsafe { start.byte_add(len) }
+        }).expect("every block should be in some region");
+        assert!(blocks.iter().any(|block| region_of(block) != region_of(&blocks[0])));
+        
+        unsafe { allocator.verify_heap() };
+    }
+    
+    #[test]
+    fn test_grow_on_node() {
+        let source = WindowsMemorySource::new(REGION_SIZE);
+        let mut highest_node = 0;
+        assert_ne!(unsafe { GetNumaHighestNodeNumber(&mut highest_node) }, 0);
+        
+        // every node gets its own region, separate from the one `grow_by` uses
+        for node in 0..=highest_node {
+            let memory = source.grow_by_on_node(1, node).expect("should reserve a region for the node");
+            assert_eq!(source.node_of(memory.cast().as_ptr()), Some(Some(node)));
+            unsafe { memory.cast::<u8>().write_bytes(0xAB, memory.len()) };
+        }
+        assert_eq!(source.regions().len(), highest_node as usize + 2);
+        
+        // a node that doesn't exist just gets the memory without a preferred node
+        let memory = source.grow_by_on_node(1, highest_node + 1).expect("should fall back to `grow_by`");
+        assert_eq!(source.node_of(memory.cast().as_ptr()), Some(None));
+    }
+    
+    #[test]
+    fn test_allocate_on_local_node() {
+        use windows_sys::Win32::System::Kernel::PROCESSOR_NUMBER;
+        use windows_sys::Win32::System::SystemInformation::GROUP_AFFINITY;
+        use windows_sys::Win32::System::Threading::{GetCurrentProcessorNumberEx, GetCurrentThread, SetThreadGroupAffinity};
+        use super::super::current_numa_node;
+        
+        let source: &'static WindowsMemorySource = Box::leak(Box::new(WindowsMemorySource::new(REGION_SIZE)));
+        std::thread::spawn(move || {
+            // pin the thread to whatever processor it's on, so its node can't change partway through
+            let mut processor = PROCESSOR_NUMBER { Group: 0, Number: 0, Reserved: 0 };
+            unsafe { GetCurrentProcessorNumberEx(&mut processor) };
+            let affinity = GROUP_AFFINITY { Mask: 1 << processor.Number, Group: processor.Group, Reserved: [0; 3] };
+            assert_ne!(unsafe { SetThreadGroupAffinity(GetCurrentThread(), &affinity, std::ptr::null_mut()) }, 0);
+            let node = current_numa_node().expect("should know which node the thread is on");
+            
+            let block_index: &'static BlockIndex = Box::leak(Box::new(BlockIndex::new()));
+            let allocator = TLAllocator::try_new(source, block_index).unwrap();
+            
+            // enough to need more than the first page
+            let layout = Layout::from_size_align(512, 8).unwrap();
+            let blocks: Vec<_> = (0..2 * WindowsMemorySource::PAGE_SIZE / layout.size())
+                .map(|_| allocator.raw_allocate(layout).unwrap().1)
+                .collect();
+            
+            for block in &blocks {
+                assert_eq!(source.node_of(block.cast().as_ptr()), Some(Some(node)));
+            }
+            unsafe { allocator.verify_heap() };
+        }).join().unwrap();
+    }
+    
+    #[test]
+    fn test_dirty_pages() {
+        const PAGE_SIZE: usize = WindowsMemorySource::PAGE_SIZE;
+        
+        let source = WindowsMemorySource::new(REGION_SIZE);
+        let memory = source.grow_by(4).expect("should fit in the region").cast::<u8>();
+        source.reset_dirty_pages();
+        assert!(source.take_dirty_pages().is_empty());
+        
+        unsafe { memory.add(PAGE_SIZE + 8).write(1) };
+        unsafe { memory.add(3 * PAGE_SIZE).write(2) };
+        assert_eq!(source.take_dirty_pages(), [unsafe { memory.add(PAGE_SIZE) }, unsafe { memory.add(3 * PAGE_SIZE) }]);
+        
+        // taking them resets the tracking
+        assert!(source.take_dirty_pages().is_empty());
+    }
+    
+    #[test]
+    fn test_contains_without_locking() {
+        let source = WindowsMemorySource::new(REGION_SIZE);
+        let memory = source.grow_by(1).expect("should fit in the region");
+        
+        // holding every shard's lock shouldn't block `contains` (or deadlock, on the same thread)
+        let _guards: Vec<_> = source.shards.iter().map(|shard| shard.write().unwrap()).collect();
+        assert!(source.contains(memory.cast().as_ptr()));
+        assert!(!source.contains(unsafe { memory.cast::<u8>().add(memory.len()) }.cast().as_ptr()));
+    }
 }
 
@@ -3,10 +3,11 @@ pub mod heap_scan;
 mod thread;
 pub mod mem_source;
 
+use std::collections::HashSet;
 use std::ptr::NonNull;
 
 pub use stack_scan::get_thread_stack_bounds;
-pub use thread::get_all_threads;
+pub use thread::{current_numa_node, current_thread_id, get_all_threads, get_live_thread_ids};
 use windows_sys::Win32::System::Diagnostics::Debug::CONTEXT;
 
 
@@ -37,30 +38,52 @@ use windows_sys::Win32::System::Diagnostics::Debug::CONTEXT;
 //     }
 // }
 
-pub struct StopAllThreads(());
+pub struct StopAllThreads {
+    /// The IDs of every thread that got suspended, so exactly those can be resumed again.
+    suspended: HashSet<u32>,
+}
 
 impl StopAllThreads {
-    /// pauses the execution of all other threads
-    fn stop_the_world() {
+    /// pauses the execution of all other threads, and returns the IDs of the ones that got suspended
+    fn stop_the_world() -> HashSet<u32> {
         use windows_sys::Win32::Foundation::GetLastError;
         use windows_sys::Win32::System::Threading::{GetThreadId, SuspendThread};
         
+        let mut suspended = HashSet::new();
+        // every thread that was tried, including the ones that couldn't be suspended
+        let mut seen = HashSet::new();
+        
         // NOTE: doing this does not create deadlocks that weren't already there.
         //       The OS can suspend and resume threads at any time however it likes,
         //       and we are just doing that
-        for thread_handle in get_all_threads().into_iter().filter_map(|r| {
-            match r {
-                Ok(t) => Some(t),
-                Err(n) => { if n != 5 { warn!("unable to open thread (code 0x{n:x})") } None }
-            }
-        }) {
-            if unsafe { SuspendThread(thread_handle) } == u32::MAX {
-                // TODO: why does this happen??? and only very inconsistently?
-                match unsafe { GetLastError() } {
-                    0x05 => trace!("access denied to thread 0x{:x}", unsafe { GetThreadId(thread_handle) }),
-                    error => warn!("couldnt suspend thread (error code 0x{error:x}): HANDLE {thread_handle:016x?}")
+        // NOTE: a thread that hasn't been suspended yet can still spawn new ones, which might
+        //       not show up in the same pass, so keep going until a pass doesn't find any new
+        //       threads. (once every thread is suspended, nobody is left to start new ones)
+        loop {
+            let mut found_new = false;
+            for thread_handle in get_all_threads().into_iter().filter_map(|r| {
+                match r {
+                    Ok(t) => Some(t),
+                    Err(n) => { if n != 5 { warn!("unable to open thread (code 0x{n:x})") } None }
+                }
+            }) {
+                let id = unsafe { GetThreadId(thread_handle) };
+                if !seen.insert(id) { continue }
+                found_new = true;
+                
+                if unsafe { SuspendThread(thread_handle) } == u32::MAX {
+                    // TODO: why does this happen??? and only very inconsistently?
+                    match unsafe { GetLastError() } {
+                        0x05 => trace!("access denied to thread 0x{id:x}"),
+                        error => warn!("couldnt suspend thread (error code 0x{error:x}): HANDLE {thread_handle:016x?}")
+                    }
+                } else {
+                    suspended.insert(id);
                 }
             }
+            
+            if !found_new { break suspended }
+            trace!("Found new threads while stopping the world, checking again");
         }
     }
     
@@ -78,25 +101,31 @@ impl StopAllThreads {
         unsafe { FlushProcessWriteBuffers() };
     }
     
-    /// resumes the execution of all other threads
-    pub fn start_the_world() {
-        use windows_sys::Win32::Foundation::GetLastError;
-        use windows_sys::Win32::System::Threading::ResumeThread;
+    /// resumes the execution of every thread that got suspended
+    fn start_the_world(&self) {
+        use windows_sys::Win32::Foundation::{CloseHandle, GetLastError};
+        use windows_sys::Win32::System::Threading::{OpenThread, ResumeThread, THREAD_SUSPEND_RESUME};
         
-        for thread_handle in get_all_threads().into_iter().filter_map(|r| r.ok()) {
+        for &id in &self.suspended {
+            let thread_handle = unsafe { OpenThread(THREAD_SUSPEND_RESUME, 0, id) };
+            if thread_handle.is_null() {
+                error!("couldnt open thread 0x{id:x} to resume it (error code 0x{:x})", unsafe { GetLastError() });
+                continue
+            }
             if unsafe { ResumeThread(thread_handle) } == u32::MAX {
                 error!("couldnt resume thread (error code 0x{:x})", unsafe { GetLastError() });
             }
+            unsafe { CloseHandle(thread_handle) };
         }
     }
     
     pub fn new() -> Self {
-        Self::stop_the_world();
+        let suspended = Self::stop_the_world();
         
         // TODO: does this actually synchronize all the threads? or do we need `GetThreadContext`
         Self::flush_process_write_buffers();
         
-        Self(())
+        Self { suspended }
     }
     
     pub unsafe fn get_thread_context(&self, thread_handle: *mut std::ffi::c_void) -> Result<Box<CONTEXT>, u32> {
@@ -146,9 +175,23 @@ impl StopAllThreads {
     }
 }
 
+/// Gets the stack pointer out of a thread's context.
+/// 
+/// NOTE: only x86-64 actually gets run, but this should also be checked on the other
+/// architectures when touching any of the `CONTEXT` code, with
+/// `cargo check --target {i686,aarch64,thumbv7a}-pc-windows-msvc`
+pub fn context_stack_pointer(context: &CONTEXT) -> usize {
+    #[cfg(target_arch="x86_64")] let sp = context.Rsp;
+    #[cfg(target_arch="x86")] let sp = context.Esp;
+    #[cfg(target_arch="arm")] let sp = context.Sp;
+    #[cfg(target_arch="aarch64")] let sp = context.Sp;
+    
+    sp as usize
+}
+
 impl Drop for StopAllThreads {
     fn drop(&mut self) {
-        Self::start_the_world();
+        self.start_the_world();
     }
 }
 
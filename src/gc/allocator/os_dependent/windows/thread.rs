@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::mem::MaybeUninit;
 
 use windows_sys::Win32::Foundation::{HANDLE, NTSTATUS};
@@ -53,6 +54,45 @@ pub fn get_all_threads() -> impl IntoIterator<Item=Result<HANDLE, NTSTATUS>> {
     }
 }
 
+/// The OS id of the thread that calls this.
+pub fn current_thread_id() -> u32 {
+    unsafe { windows_sys::Win32::System::Threading::GetCurrentThreadId() }
+}
+
+/// The NUMA node of the processor that the calling thread is running on.
+/// 
+/// NOTE: the OS can move the thread to a processor on another node at any time (unless its
+///       affinity says otherwise), so this is only a hint.
+pub fn current_numa_node() -> Option<u32> {
+    use windows_sys::Win32::System::Kernel::PROCESSOR_NUMBER;
+    use windows_sys::Win32::System::Threading::{GetCurrentProcessorNumberEx, GetNumaProcessorNodeEx};
+    
+    let mut processor = MaybeUninit::<PROCESSOR_NUMBER>::uninit();
+    unsafe { GetCurrentProcessorNumberEx(processor.as_mut_ptr()) };
+    let mut node = 0;
+    // SAFETY: `GetCurrentProcessorNumberEx` always fills in the processor number
+    if unsafe { GetNumaProcessorNodeEx(processor.as_ptr(), &mut node) } == 0 {
+        return None
+    }
+    Some(node.into())
+}
+
+/// The OS ids of every thread in the current process that hasn't exited yet (including the current one).
+/// 
+/// NOTE: threads that have exited can still show up in [`get_all_threads`] for a bit (until every
+///       handle to them is closed), so this checks whether each one is actually still running.
+pub fn get_live_thread_ids() -> HashSet<u32> {
+    use windows_sys::Win32::Foundation::WAIT_TIMEOUT;
+    use windows_sys::Win32::System::Threading::{GetThreadId, WaitForSingleObject};
+    
+    get_all_threads().into_iter()
+        .filter_map(Result::ok)
+        .filter(|&thread| unsafe { WaitForSingleObject(thread, 0) } == WAIT_TIMEOUT)
+        .map(|thread| unsafe { GetThreadId(thread) })
+        .chain([current_thread_id()])
+        .collect()
+}
+
 
 #[repr(C)]
 pub struct ThreadInformationBlock {
@@ -2,24 +2,63 @@ use std::alloc::Layout;
 use std::cell::Cell;
 use std::mem::MaybeUninit;
 use std::ptr::NonNull;
+use std::thread::ThreadId;
 
-use crate::gc::allocator::heap_block_header::HEADERFLAG_NONE;
+use crate::gc::allocator::heap_block_header::{HeaderFlag, HEADERFLAG_NONE, HEADERFLAG_PRISTINE, MIN_SPLIT_SIZE};
 
-use super::os_dependent::MemorySource;
+use super::os_dependent::{current_numa_node, current_thread_id, MemorySource};
 
+use super::block_index::BlockIndex;
 use super::heap_block_header::GCHeapBlockHeader;
 use super::GCAllocatorError;
 
+/// The number of size classes that only have blocks of a single size (rounded down to a multiple of 16 bytes).
+const NUM_EXACT_CLASSES: usize = 16;
+/// The number of size classes after those, which each have blocks within a power of two of each other.
+const NUM_POWER_CLASSES: usize = 8;
+/// The number of free lists each allocator has, including the last one, which has every block too big for the others.
+const NUM_SIZE_CLASSES: usize = NUM_EXACT_CLASSES + NUM_POWER_CLASSES + 1;
+
+/// The free list that a free block of `size` bytes goes in.
+/// 
+/// Blocks smaller than 256 bytes each get a free list per 16 bytes, so a block in the list for some
+/// (padded) size always fits it. Bigger blocks share a list with the ones up to twice their size,
+/// up to 64KiB. Everything bigger than that goes in the last list.
+fn size_class(size: usize) -> usize {
+    const STEP: usize = align_of::<GCHeapBlockHeader>();
+    const EXACT_LIMIT: usize = NUM_EXACT_CLASSES * STEP;
+    
+    if size < EXACT_LIMIT { return size / STEP }
+    let power = (size / EXACT_LIMIT).ilog2() as usize;
+    NUM_EXACT_CLASSES + power.min(NUM_POWER_CLASSES)
+}
+
+#[cfg(test)]
+std::thread_local! {
+    /// How many free blocks [`TLAllocator::find_good_block`] has looked at on this thread.
+    static NUM_BLOCKS_VISITED: Cell<usize> = const { Cell::new(0) };
+}
+
 pub(super) struct TLAllocator<M: MemorySource + 'static> {
     memory_source: &'static M,
-    /// The start of this thread's free list.
+    /// Where the blocks in `memory_source` are. This has to be told about every new block.
+    block_index: &'static BlockIndex,
+    /// The start of each of this thread's free lists, one for each size class (see [`size_class`]).
     /// 
-    /// TODO: the GC thread should try to put the freed blocks back into these
-    free_list_head: Cell<Option<NonNull<GCHeapBlockHeader>>>,
+    /// Every free block in memory this allocator owns is in one of these, and nothing else is
+    /// (so the collector has to give freed blocks back to the allocator whose memory they are in).
+    free_lists: [Cell<Option<NonNull<GCHeapBlockHeader>>>; NUM_SIZE_CLASSES],
     /// The amount of free memory this allocator has.
     num_free_bytes: Cell<usize>,
     /// A list of blocks that this allocator got
     alloced_blocks: Cell<Option<Vec<NonNull<[u8]>>>>,
+    /// The thread that this allocator belongs to.
+    owner: ThreadId,
+    /// The OS id of the thread that is using this allocator.
+    /// 
+    /// This is usually just `owner`, but `ThreadLocal` hands an exited thread's allocator to the
+    /// next new thread that asks for one, so it gets updated by [`claim`](Self::claim).
+    os_thread_id: Cell<u32>,
 }
 
 unsafe impl<M: MemorySource + Sync> Send for TLAllocator<M> {}
@@ -39,7 +78,8 @@ impl<M: MemorySource> TLAllocator<M> {
         
         let type_layout = std::alloc::Layout::new::<T>();
         
-        let result = unsafe { self.raw_allocate_with_drop(type_layout, Some(dropper::<T>)) };
+        let drop_in_place = if std::mem::needs_drop::<T>() { Some(dropper::<T> as unsafe fn(*mut ())) } else { None };
+        let result = unsafe { self.raw_allocate_with_drop(type_layout, drop_in_place) };
         
         let result = match result {
             Ok(r) => r,
@@ -59,12 +99,37 @@ impl<M: MemorySource> TLAllocator<M> {
     }
 }
 
+/// Makes sure that memory straight from `source` starts on a page boundary (see [`MemorySource::grow_by`]).
+/// 
+/// NOTE: if it doesn't, the memory just gets leaked, since the memory source can't be trusted to take it back.
+fn check_alignment<M: MemorySource>(source: &M, mem: NonNull<[u8]>) -> Result<(), GCAllocatorError> {
+    let page_size = source.page_size();
+    // NOTE: a page always has to fit a block header, so this also makes sure the header is aligned
+    debug_assert_eq!(page_size % align_of::<GCHeapBlockHeader>(), 0);
+    if mem.cast::<u8>().is_aligned_to(page_size) { return Ok(()) }
+    
+    error!("Memory source gave back memory at {mem:016x?}, which isn't aligned to the page size (0x{page_size:x})");
+    Err(GCAllocatorError::MisalignedMemory { address: mem.addr().get(), page_size })
+}
+
+/// Gets `num_pages` more pages from `source`, preferably on the NUMA node the current thread is running on.
+fn grow_local<M: MemorySource>(source: &M, num_pages: usize) -> Option<NonNull<[u8]>> {
+    match current_numa_node() {
+        Some(node) => source.grow_by_on_node(num_pages, node),
+        None => source.grow_by(num_pages),
+    }
+}
+
 impl<M: MemorySource> TLAllocator<M> {
-    pub(super) fn try_new(source: &'static M) -> Result<Self, GCAllocatorError> {
-        let mem = source.grow_by(1).ok_or(GCAllocatorError::OutOfMemory)?;
-        
-        // sanity check
-        assert!(mem.is_aligned_to(align_of::<GCHeapBlockHeader>()));
+    /// The flags for a block made out of memory straight from the memory source.
+    const FRESH_BLOCK_FLAGS: HeaderFlag = if M::GROWS_ZEROED { HEADERFLAG_PRISTINE } else { HEADERFLAG_NONE };
+    
+    pub(super) fn try_new(source: &'static M, block_index: &'static BlockIndex) -> Result<Self, GCAllocatorError> {
+        let mem = grow_local(source, 1).ok_or_else(|| GCAllocatorError::OutOfMemory {
+            requested: source.page_size(),
+            committed: source.regions().iter().map(|region| region.len()).sum()
+        })?;
+        check_alignment(source, mem)?;
         
         let header = unsafe { mem.cast::<MaybeUninit<GCHeapBlockHeader>>().as_mut() };
         let length = mem.len() - size_of::<GCHeapBlockHeader>();
@@ -73,16 +138,22 @@ impl<M: MemorySource> TLAllocator<M> {
         let header = header.write(GCHeapBlockHeader {
             next_free: None,
             size: length,
-            flags: HEADERFLAG_NONE,
+            flags: Self::FRESH_BLOCK_FLAGS,
             drop_thunk: None
         });
+        block_index.record_block(header.into());
         
-        Ok(Self {
+        let allocator = Self {
             memory_source: source,
-            free_list_head: Cell::new(Some(header.into())),
+            block_index,
+            free_lists: [const { Cell::new(None) }; NUM_SIZE_CLASSES],
             num_free_bytes: Cell::new(length),
             alloced_blocks: Cell::new(Some(vec![mem])),
-        })
+            owner: std::thread::current().id(),
+            os_thread_id: Cell::new(current_thread_id()),
+        };
+        allocator.push_free(header.into());
+        Ok(allocator)
     }
     
     /// The total number of free bytes in the heap
@@ -90,18 +161,97 @@ impl<M: MemorySource> TLAllocator<M> {
         self.num_free_bytes.get()
     }
     
+    /// The total number of free bytes in the free list, found by actually walking it.
+    /// 
+    /// `num_free_bytes` is just a cached version of this, so that the collector doesn't have
+    /// to walk every free list whenever it hands out freed blocks.
+    fn count_free_bytes(&self) -> usize {
+        (0..NUM_SIZE_CLASSES).flat_map(|class| self.free_list(class)).map(|block| unsafe { block.as_ref() }.size).sum()
+    }
+    
+    /// Makes sure the cached free byte count hasn't drifted from what's actually in the free list.
+    /// 
+    /// NOTE: this walks the whole free list, so it only does anything with `debug_assertions`.
+    #[inline]
+    fn debug_check_free_bytes(&self) {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            self.num_free_bytes.get(), self.count_free_bytes(),
+            "cached free byte count doesn't match the free list"
+        );
+    }
+    
+    /// The number of blocks in the free lists.
+    pub(super) fn free_blocks(&self) -> usize {
+        (0..NUM_SIZE_CLASSES).map(|class| self.free_list(class).count()).sum()
+    }
+    
+    /// The thread that this allocator belongs to.
+    pub(super) fn owner(&self) -> ThreadId {
+        self.owner
+    }
+    
+    /// The OS id of the thread that is using this allocator.
+    pub(super) fn os_thread_id(&self) -> u32 {
+        self.os_thread_id.get()
+    }
+    
+    /// Marks this allocator as being used by the current thread.
+    /// 
+    /// This has to be called whenever a thread gets its allocator, since it might have
+    /// belonged to some other thread that already exited.
+    pub(super) fn claim(&self) {
+        self.os_thread_id.set(current_thread_id());
+    }
+    
+    /// Gives all of this allocator's memory (both its free list, and the memory it got from its
+    /// memory source) to `other`, leaving this one empty.
+    /// 
+    /// This is for when the thread that was using this allocator has exited, since otherwise its
+    /// free memory would just sit there until some new thread happens to get this allocator.
+    /// 
+    /// NOTE: the memory doesn't go back to the memory source, since that can only take back the
+    ///       pages at the very end of the heap (see [`MemorySource::shrink_by`]).
+    pub(super) fn give_everything_to(&mut self, other: &mut Self) {
+        for (list, other_list) in self.free_lists.iter().zip(&other.free_lists) {
+            let Some(head) = list.take() else { continue };
+            // just put the whole free list in front of `other`'s
+            let mut tail = head;
+            while let Some(next) = unsafe { tail.as_ref() }.next_free {
+                tail = next;
+            }
+            let other_head = other_list.replace(Some(head));
+            unsafe { (*tail.as_ptr()).next_free = other_head };
+            if let Some(other_head) = other_head {
+                unsafe { (*other_head.as_ptr()).set_prev_free(Some(tail)) };
+            }
+        }
+        other.num_free_bytes.update(|n| n + self.num_free_bytes.replace(0));
+        
+        let mut blocks = self.alloced_blocks.replace(Some(Vec::new())).expect("");
+        other.alloced_blocks.get_mut().as_mut().expect("").append(&mut blocks);
+        
+        self.debug_check_free_bytes();
+        other.debug_check_free_bytes();
+    }
+    
     /// Whether the heap has ZERO free memory
     fn has_no_memory(&self) -> bool {
-        assert_eq!(self.free_list_head.get().is_none(), self.free_bytes() == 0);
-        self.free_list_head.get().is_none()
+        let empty = self.free_lists.iter().all(|list| list.get().is_none());
+        assert_eq!(empty, self.free_bytes() == 0);
+        empty
     }
     
     // Expands the heap by at least the given number of bytes, and returns the block
-    fn expand_by(&self, num_bytes: usize, last_block: Option<&mut GCHeapBlockHeader>) -> Result<NonNull<GCHeapBlockHeader>, GCAllocatorError> {
+    fn expand_by(&self, num_bytes: usize) -> Result<NonNull<GCHeapBlockHeader>, GCAllocatorError> {
         // Get (at least) the requested amount of memory
         let page_size = self.memory_source.page_size();
         let num_pages = (num_bytes + size_of::<GCHeapBlockHeader>()).div_ceil(page_size);
-        let new_ptr = self.memory_source.grow_by(num_pages).ok_or(GCAllocatorError::OutOfMemory)?;
+        let new_ptr = grow_local(self.memory_source, num_pages).ok_or_else(|| GCAllocatorError::OutOfMemory {
+            requested: num_pages * page_size,
+            committed: self.memory_source.regions().iter().map(|region| region.len()).sum()
+        })?;
+        check_alignment(self.memory_source, new_ptr)?;
         
         debug!("Expanded heap by 0x{:x} bytes (block @ {:016x?})", new_ptr.len(), new_ptr);
         
@@ -118,15 +268,12 @@ impl<M: MemorySource> TLAllocator<M> {
             block_ptr.write(GCHeapBlockHeader {
                 next_free: None,
                 size: block_size,
-                flags: HEADERFLAG_NONE,
+                flags: Self::FRESH_BLOCK_FLAGS,
                 drop_thunk: None
             });
         }
-        
-        match last_block {
-            None => self.free_list_head.set(Some(block_ptr)),
-            Some(block) => block.next_free = Some(block_ptr)
-        }
+        self.block_index.record_block(block_ptr);
+        self.push_free(block_ptr);
         
         // Update the amount of free bytes we have
         self.num_free_bytes.update(|n| n + block_size);
@@ -134,94 +281,298 @@ impl<M: MemorySource> TLAllocator<M> {
         Ok(block_ptr)
     }
     
+    /// Whether a block is in memory that this allocator got from its memory source.
+    pub(super) fn owns(&self, block_ptr: NonNull<GCHeapBlockHeader>) -> bool {
+        let blocks = self.alloced_blocks.replace(None).expect("");
+        let result = blocks.iter().any(|region| {
+            let (start, len) = region.to_raw_parts();
+            let start = start.cast::<GCHeapBlockHeader>();
+            start <= block_ptr && block_ptr < unsafe { start.byte_add(len) }
+        });
+        self.alloced_blocks.set(Some(blocks));
+        result
+    }
+    
+    /// Every region of memory that this allocator got from its memory source.
+    pub(super) fn regions(&self) -> Vec<NonNull<[u8]>> {
+        let blocks = self.alloced_blocks.replace(None).expect("");
+        let result = blocks.clone();
+        self.alloced_blocks.set(Some(blocks));
+        result
+    }
+    
+    /// Puts a bunch of freed blocks (which all have to be in memory this allocator owns) back into
+    /// the free lists, merging each one with any free blocks right after it.
+    /// 
+    /// The blocks get reclaimed from the end of the heap backwards, so a run of blocks that were
+    /// freed all at once ends up as one big block.
+    /// 
+    /// NOTE: this can only be done while nothing else is touching the heap (i.e: during a collection),
+    ///       since it reads the headers of the blocks after the freed ones.
+    pub(super) fn reclaim_blocks(&mut self, mut blocks: Vec<NonNull<GCHeapBlockHeader>>) {
+        let mut regions = self.regions();
+        regions.sort_unstable_by_key(|region| region.cast::<u8>());
+        blocks.sort_unstable_by(|a, b| b.cmp(a));
+        
+        for block_ptr in blocks {
+            let region = regions.partition_point(|region| region.cast::<GCHeapBlockHeader>() <= block_ptr).checked_sub(1)
+                .map(|i| regions[i])
+                .filter(|region| block_ptr < unsafe { region.cast::<GCHeapBlockHeader>().byte_add(region.len()) })
+                .expect("reclaimed blocks should be in memory that this allocator owns");
+            let region_end = unsafe { region.cast::<GCHeapBlockHeader>().byte_add(region.len()) };
+            
+            let block = unsafe { &mut *block_ptr.as_ptr() };
+            self.block_index.block_freed(block_ptr);
+            block.set_free(None);
+            
+            // NOTE: the next block is in this allocator's memory, so if it's free, it's in one of our free lists
+            loop {
+                let next_ptr = block.next();
+                if next_ptr >= region_end || unsafe { next_ptr.as_ref() }.is_allocated() { break }
+                
+                let next_size = unsafe { next_ptr.as_ref() }.size;
+                trace!("Merging free block @ {next_ptr:016x?} into {block_ptr:016x?}");
+                unsafe { self.unlink(next_ptr) };
+                self.num_free_bytes.update(|n| n - next_size);
+                block.size += size_of::<GCHeapBlockHeader>() + next_size;
+                self.block_index.block_removed(next_ptr, block.next());
+            }
+            
+            self.push_free(block_ptr);
+            self.num_free_bytes.update(|n| n + block.size);
+        }
+        
+        self.debug_check_free_bytes();
+    }
+    
     /// Adds a block into the heap.
-    pub(super) fn reclaim_block(&mut self, mut block_ptr: NonNull<GCHeapBlockHeader>) {
+    pub(super) fn reclaim_block(&self, mut block_ptr: NonNull<GCHeapBlockHeader>) {
         let block = unsafe { block_ptr.as_mut() };
+        self.block_index.block_freed(block_ptr);
         self.num_free_bytes.update(|n| n + block.size);
-        self.free_list_head.update(|old| {
+        self.free_lists[size_class(block.size)].update(|old| {
             block.set_free(old);
             Some(block_ptr)
         });
     }
     
-    /// Given a pointer to a heap block in the free list, pop the next one out.
+    /// Every block in the free list for `class`, in order.
     /// 
-    /// If given `None`, pop out the first item from the free list.
+    /// NOTE: the free list can't be changed while this is being used.
+    fn free_list(&self, class: usize) -> impl Iterator<Item=NonNull<GCHeapBlockHeader>> + '_ {
+        std::iter::successors(self.free_lists[class].get(), |block| unsafe { block.as_ref() }.next_free)
+    }
+    
+    /// Puts a (free, but not in any free list) block at the front of the free list for its size.
     /// 
-    /// SAFETY: nowhere else can be using the free list!!!
-    unsafe fn pop_next(&self, ptr: Option<NonNull<GCHeapBlockHeader>>) -> Option<NonNull<GCHeapBlockHeader>> {
-        match ptr {
-            Some(ptr) => unsafe {
-                let our_next = &mut (*ptr.as_ptr()).next_free;
-                let old_next = *our_next;
-                let new_next = match old_next {
-                    None => return None,
-                    Some(next) => (*next.as_ptr()).next_free,
-                };
-                *our_next = new_next;
-                old_next
-            }
+    /// This doesn't touch `num_free_bytes`, since the block was usually just split off of a free one.
+    fn push_free(&self, block_ptr: NonNull<GCHeapBlockHeader>) {
+        let block = unsafe { &mut *block_ptr.as_ptr() };
+        let old_head = self.free_lists[size_class(block.size)].replace(Some(block_ptr));
+        block.next_free = old_head;
+        block.set_prev_free(None);
+        if let Some(old_head) = old_head {
+            unsafe { (*old_head.as_ptr()).set_prev_free(Some(block_ptr)) };
+        }
+    }
+    
+    /// Takes a block out of the free list, wherever it is.
+    /// 
+    /// This doesn't touch `num_free_bytes`, since the block usually gets allocated right after.
+    /// 
+    /// SAFETY: the block has to be in this allocator's free list, and nowhere else can be using the free list!!!
+    unsafe fn unlink(&self, block_ptr: NonNull<GCHeapBlockHeader>) {
+        let class = size_class(unsafe { block_ptr.as_ref() }.size);
+        unsafe { self.unlink_from(class, block_ptr) }
+    }
+    
+    /// Takes a block out of the free list for `class`, which it doesn't necessarily belong in
+    /// anymore (i.e: if it was just split up).
+    /// 
+    /// SAFETY: same as [`unlink`](Self::unlink), but the block has to be in the free list for `class`.
+    unsafe fn unlink_from(&self, class: usize, block_ptr: NonNull<GCHeapBlockHeader>) {
+        let block = unsafe { &mut *block_ptr.as_ptr() };
+        
+        let prev = block.prev_free();
+        match prev {
+            Some(prev) => unsafe { (*prev.as_ptr()).next_free = block.next_free },
             None => {
-                let old_head = self.free_list_head.get();
-                let new_next = match old_head {
-                    None => return None,
-                    Some(next) => unsafe { next.as_ref().next_free }
-                };
-                self.free_list_head.set(new_next);
-                old_head
+                assert_eq!(self.free_lists[class].get(), Some(block_ptr), "only the head of the free list has no `prev_free`");
+                self.free_lists[class].set(block.next_free);
             }
         }
+        if let Some(next) = block.next_free {
+            unsafe { (*next.as_ptr()).set_prev_free(prev) };
+        }
+        
+        block.next_free = None;
+        block.set_prev_free(None);
+    }
+    
+    /// Tries to make an allocated block big enough to hold `new_size` bytes, without moving it.
+    /// 
+    /// This works if the block already has enough room, or if the block right after it in memory
+    /// is free, in *this* allocator's free list, and big enough. (free blocks in any other free
+    /// list belong to some other thread, so they can't be touched) Whatever is left over after
+    /// taking over the next block gets put back into the free list, if it's worth splitting off.
+    /// 
+    /// NOTE: this walks every free list to look for the next block, since it can't know which
+    ///       one it would be in without reading it.
+    /// 
+    /// SAFETY: `block_ptr` has to be allocated, and nowhere else can be using the free list!!!
+    pub(super) unsafe fn try_grow_in_place(&self, block_ptr: NonNull<GCHeapBlockHeader>, new_size: usize) -> bool {
+        let block = unsafe { &mut *block_ptr.as_ptr() };
+        assert!(block.is_allocated(), "can only grow allocated blocks");
+        
+        if block.size >= new_size {
+            return true
+        }
+        
+        // NOTE: this has to be found in the free list before it gets read, since `next` might be
+        //       the end of the heap, or a block that some other thread is in the middle of using
+        let next_ptr = block.next();
+        if !(0..NUM_SIZE_CLASSES).any(|class| self.free_list(class).any(|free_block| free_block == next_ptr)) {
+            return false
+        }
+        
+        let next_size = unsafe { next_ptr.as_ref() }.size;
+        let merged_size = block.size + size_of::<GCHeapBlockHeader>() + next_size;
+        if merged_size < new_size {
+            return false
+        }
+        
+        trace!("Growing block @ {block_ptr:016x?} into {next_ptr:016x?}");
+        unsafe { self.unlink(next_ptr) };
+        self.num_free_bytes.update(|n| n - next_size);
+        block.size = merged_size;
+        self.block_index.block_removed(next_ptr, block.next());
+        
+        // give back whatever isn't needed
+        let padded_size = new_size.next_multiple_of(align_of::<GCHeapBlockHeader>());
+        if block.size - padded_size >= MIN_SPLIT_SIZE {
+            let trailing_ptr = unsafe { block.data().byte_add(padded_size) }.cast::<GCHeapBlockHeader>();
+            let trailing_size = block.size - padded_size - size_of::<GCHeapBlockHeader>();
+            unsafe {
+                // NOTE: this might have some of the old header in it, so it isn't pristine
+                trailing_ptr.write(GCHeapBlockHeader {
+                    next_free: None,
+                    size: trailing_size,
+                    flags: HEADERFLAG_NONE,
+                    drop_thunk: None
+                });
+            }
+            self.push_free(trailing_ptr);
+            block.size = padded_size;
+            self.block_index.record_block(trailing_ptr);
+            self.num_free_bytes.update(|n| n + trailing_size);
+        }
+        
+        self.debug_check_free_bytes();
+        true
+    }
+    
+    /// Checks that the free lists are consistent, panicking if they aren't.
+    /// 
+    /// This makes sure every block in the free lists is actually free (and in the right one for
+    /// its size), that the `prev_free` and `next_free` links agree with each other, and that the
+    /// free byte count is right.
+    /// 
+    /// SAFETY: nowhere else can be using the free list!!!
+    pub(super) unsafe fn verify_heap(&self) {
+        let mut total_bytes = 0;
+        
+        for class in 0..NUM_SIZE_CLASSES {
+            let mut previous: Option<NonNull<GCHeapBlockHeader>> = None;
+            for block_ptr in self.free_list(class) {
+                let block = unsafe { block_ptr.as_ref() };
+                assert!(!block.is_allocated(), "block @ {block_ptr:016x?} is in the free list, but allocated");
+                assert_eq!(size_class(block.size), class, "block @ {block_ptr:016x?} is in the wrong free list for its size (0x{:x})", block.size);
+                assert_eq!(
+                    block.prev_free(), previous,
+                    "block @ {block_ptr:016x?} has the wrong `prev_free` (should be {previous:016x?})"
+                );
+                
+                total_bytes += block.size;
+                previous = Some(block_ptr);
+            }
+        }
+        
+        assert_eq!(total_bytes, self.free_bytes(), "free list has a different amount of bytes than expected");
     }
     
     /// Finds (or creates) a block to fit `layout`, and pops it out of the free list.
+    /// 
+    /// This starts at the free list for `layout`'s size, and goes up from there. For small sizes,
+    /// every block in that list fits (and so does every block in the lists after it), so this
+    /// almost always just takes the first block it looks at.
     fn find_good_block(&self, layout: Layout) -> Result<&mut GCHeapBlockHeader, GCAllocatorError> {
-        // traverse the free list, looking for a block that can handle this layout
-        let mut previous: Option<NonNull<_>> = None;
-        let mut current = self.free_list_head.get().expect("should have some free memory...");
+        let padded_size = layout.size().next_multiple_of(align_of::<GCHeapBlockHeader>());
         
-        loop {
-            // SAFETY: nobody else is traversing the free list, since this type is !Sync
-            let current_block = unsafe { current.as_mut() };
-            
-            // sanity check
-            assert!(!current_block.is_allocated(), "block @ {:x?} is already allocated", current_block as *const _);
-            
-            // see if the block can fit `layout` into it
-            if let Ok((block, new_header_bytes)) = current_block.shrink_to_fit(layout) {
-                // check if we split off a block from the beginning, if so, update `previous`
-                if current != block.into() {
-                    assert_eq!(unsafe { (*current.as_ptr()).next_free }, Some(block.into())); // sanity check
-                    previous = Some(current);
-                    current = block.into();
-                }
+        // look for a block that can fit `layout` into it
+        // NOTE: `shrink_to_fit` splits the block in place, with any new blocks linked in after it
+        let mut found = None;
+        'search: for class in size_class(padded_size)..NUM_SIZE_CLASSES {
+            for block_ptr in self.free_list(class) {
+                // SAFETY: nobody else is traversing the free list, since this type is !Sync
+                let block = unsafe { &mut *block_ptr.as_ptr() };
+                #[cfg(test)]
+                NUM_BLOCKS_VISITED.set(NUM_BLOCKS_VISITED.get() + 1);
                 
-                // we split off a block from the end, so update that
-                self.num_free_bytes.update(|n| n.checked_sub(new_header_bytes).expect("should have enough bytes"));
+                // sanity check
+                assert!(!block.is_allocated(), "block @ {block_ptr:x?} is already allocated");
                 
-                // either way, we found a block!
-                break
+                if let Ok(fit) = block.shrink_to_fit(layout) {
+                    found = Some((class, block_ptr, fit));
+                    break 'search
+                }
             }
-            
-            // that block didn't work, so lets go to the next one
-            previous = Some(current);
-            match current_block.next_free {
-                Some(ptr) => current = ptr,
-                None => {
-                    // we made it all the way to the end of the list and found nothing, so add more memory
-                    current = self.expand_by(layout.size(), Some(current_block))?;
-                },
+        }
+        
+        let (class, block_ptr, (result_block, new_header_bytes)) = match found {
+            Some(found) => found,
+            None => {
+                // nothing was big enough, so add more memory
+                let block_ptr = self.expand_by(layout.size())?;
+                let block = unsafe { &mut *block_ptr.as_ptr() };
+                (size_class(block.size), block_ptr, block.shrink_to_fit(layout).expect("new memory should be big enough"))
             }
+        };
+        let current = NonNull::from(&mut *result_block);
+        
+        // check if we split off a block from the beginning
+        let mut trailing_header_bytes = new_header_bytes;
+        let leading = (current != block_ptr).then(|| {
+            assert_eq!(unsafe { block_ptr.as_ref() }.next_free, Some(current)); // sanity check
+            self.block_index.record_block(current);
+            trailing_header_bytes -= size_of::<GCHeapBlockHeader>();
+            block_ptr
+        });
+        
+        // and then from the end
+        let trailing = (trailing_header_bytes != 0).then(|| result_block.next());
+        if let Some(trailing) = trailing {
+            self.block_index.record_block(trailing);
         }
         
+        // we split off a block from the end, so update that
+        self.num_free_bytes.update(|n| n.checked_sub(new_header_bytes).expect("should have enough bytes"));
+        
         trace!("Found block @ {:016x?}", current);
         
-        // pop out the block from the linked list
-        let mut result_block = unsafe { self.pop_next(previous).expect("We know we have a block to pop") };
+        // pop all the pieces out of the free list they were split up in, and put the leftovers back where they belong now
+        for block in leading.into_iter().chain([current]).chain(trailing) {
+            unsafe { self.unlink_from(class, block) };
+        }
+        for block in leading.into_iter().chain(trailing) {
+            self.push_free(block);
+        }
+        
         // SAFETY: we have exclusive access rn
-        let result_block = unsafe { result_block.as_mut() };
+        let result_block = unsafe { &mut *current.as_ptr() };
         
-        // Mark the block as allocated (which also sets `next` to `None`)
+        // Mark the block as allocated (it was already unlinked from the free list above)
         result_block.set_allocated();
+        self.block_index.block_allocated(current);
         self.num_free_bytes.update(|n| n.checked_sub(result_block.size).expect("should have free bytes in block"));
         
         Ok(result_block)
@@ -239,7 +590,7 @@ impl<M: MemorySource> TLAllocator<M> {
         
         // get more memory if needed
         if self.free_bytes() < layout.size() {
-            self.expand_by(layout.size(), None)?;
+            self.expand_by(layout.size())?;
         }
         
         assert!(!self.has_no_memory()); // sanity check
@@ -247,6 +598,8 @@ impl<M: MemorySource> TLAllocator<M> {
         let result_block = self.find_good_block(layout)?;
         let data = result_block.data();
         
+        self.debug_check_free_bytes();
+        
         Ok((result_block, data))
     }
     
@@ -260,3 +613,445 @@ impl<M: MemorySource> TLAllocator<M> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A memory source that just hands out pages from a fixed buffer, so tests don't touch the real GC heap.
+    struct TestMemorySource {
+        pages: NonNull<[u8]>,
+        used_pages: Cell<usize>,
+    }
+    
+    impl TestMemorySource {
+        const PAGE_SIZE: usize = 4096;
+        
+        fn leak(num_pages: usize) -> &'static Self {
+            let layout = Layout::from_size_align(num_pages * Self::PAGE_SIZE, Self::PAGE_SIZE).unwrap();
+            let pages = unsafe { std::alloc::alloc_zeroed(layout) };
+            let pages = NonNull::from_raw_parts(NonNull::new(pages).unwrap(), layout.size());
+            Box::leak(Box::new(Self { pages, used_pages: Cell::new(0) }))
+        }
+    }
+    
+    impl MemorySource for TestMemorySource {
+        fn page_size(&self) -> usize {
+            Self::PAGE_SIZE
+        }
+        
+        fn grow_by(&self, num_pages: usize) -> Option<NonNull<[u8]>> {
+            let start = self.used_pages.get();
+            if (start + num_pages) * Self::PAGE_SIZE > self.pages.len() { return None }
+            self.used_pages.set(start + num_pages);
+            let ptr = unsafe { self.pages.cast::<u8>().byte_add(start * Self::PAGE_SIZE) };
+            Some(NonNull::from_raw_parts(ptr, num_pages * Self::PAGE_SIZE))
+        }
+        
+        unsafe fn shrink_by(&self, num_pages: usize) {
+            self.used_pages.update(|n| n - num_pages);
+        }
+        
+        const GROWS_ZEROED: bool = true;
+        
+        fn contains(&self, ptr: *const ()) -> bool {
+            let start = self.pages.cast::<()>().as_ptr().cast_const();
+            start <= ptr && ptr < start.wrapping_byte_add(self.used_pages.get() * Self::PAGE_SIZE)
+        }
+        
+        fn regions(&self) -> Vec<NonNull<[u8]>> {
+            vec![NonNull::from_raw_parts(self.pages.cast::<u8>(), self.used_pages.get() * Self::PAGE_SIZE)]
+        }
+        
+        fn headroom(&self) -> usize {
+            self.pages.len() - self.used_pages.get() * Self::PAGE_SIZE
+        }
+    }
+    
+    /// A broken memory source, which hands out memory that isn't page aligned after the first few calls.
+    struct MisalignedMemorySource {
+        inner: &'static TestMemorySource,
+        /// How many more calls to `grow_by` get to be aligned.
+        aligned_calls: Cell<usize>,
+    }
+    
+    impl MisalignedMemorySource {
+        const OFFSET: usize = 16;
+    }
+    
+    impl MemorySource for MisalignedMemorySource {
+        fn page_size(&self) -> usize {
+            self.inner.page_size()
+        }
+        
+        fn grow_by(&self, num_pages: usize) -> Option<NonNull<[u8]>> {
+            let mem = self.inner.grow_by(num_pages)?;
+            if self.aligned_calls.get() > 0 {
+                self.aligned_calls.update(|n| n - 1);
+                return Some(mem)
+            }
+            let ptr = unsafe { mem.cast::<u8>().byte_add(Self::OFFSET) };
+            Some(NonNull::from_raw_parts(ptr, mem.len() - Self::OFFSET))
+        }
+        
+        unsafe fn shrink_by(&self, num_pages: usize) {
+            unsafe { self.inner.shrink_by(num_pages) }
+        }
+        
+        fn contains(&self, ptr: *const ()) -> bool {
+            self.inner.contains(ptr)
+        }
+        
+        fn regions(&self) -> Vec<NonNull<[u8]>> {
+            self.inner.regions()
+        }
+        
+        fn headroom(&self) -> usize {
+            self.inner.headroom()
+        }
+    }
+    
+    /// An allocator (with its own block index) over a fresh [`TestMemorySource`] with `num_pages` pages.
+    fn test_allocator(num_pages: usize) -> TLAllocator<TestMemorySource> {
+        let source = TestMemorySource::leak(num_pages);
+        let block_index = Box::leak(Box::new(BlockIndex::new()));
+        TLAllocator::try_new(source, block_index).unwrap()
+    }
+    
+    /// Every free list, in order (from the smallest size class to the biggest).
+    fn free_list<M: MemorySource>(allocator: &TLAllocator<M>) -> Vec<NonNull<GCHeapBlockHeader>> {
+        (0..NUM_SIZE_CLASSES).flat_map(|class| allocator.free_list(class)).collect()
+    }
+    
+    #[test]
+    fn test_unlink_anywhere() {
+        let allocator = test_allocator(4);
+        let layout = Layout::new::<[u64; 4]>();
+        
+        // allocate a bunch of blocks, and free every other one, so the free list has a few blocks in it
+        let blocks: Vec<_> = (0..8).map(|_| NonNull::from(allocator.raw_allocate(layout).unwrap().0)).collect();
+        for &block in blocks.iter().step_by(2) {
+            allocator.reclaim_block(block);
+        }
+        unsafe { allocator.verify_heap() };
+        
+        // the free list is `6 -> 4 -> 2 -> 0 -> rest of the page`
+        let list = free_list(&allocator);
+        assert_eq!(list.len(), 5);
+        
+        // take blocks out of the middle, the end, and the front
+        for index in [2, 4, 0] {
+            let block = list[index];
+            let size = unsafe { block.as_ref() }.size;
+            unsafe { allocator.unlink(block) };
+            allocator.num_free_bytes.update(|n| n - size);
+            unsafe { allocator.verify_heap() };
+            assert!(!free_list(&allocator).contains(&block));
+        }
+        assert_eq!(free_list(&allocator), [list[1], list[3]]);
+        
+        // and then empty it out completely
+        for block in [list[3], list[1]] {
+            let size = unsafe { block.as_ref() }.size;
+            unsafe { allocator.unlink(block) };
+            allocator.num_free_bytes.update(|n| n - size);
+            unsafe { allocator.verify_heap() };
+        }
+        assert!(allocator.has_no_memory());
+        
+        // expanding the heap should still work after that
+        allocator.raw_allocate(layout).unwrap();
+        unsafe { allocator.verify_heap() };
+    }
+    
+    /// Does a bunch of allocations (of different sizes and alignments) and frees, making sure the
+    /// free byte count never drifts from what's actually in the free list.
+    #[test]
+    fn test_free_bytes_consistent() {
+        let allocator = test_allocator(64);
+        let mut live = Vec::new();
+        
+        // simple LCG, so that this is deterministic
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize
+        };
+        
+        for _ in 0..2000 {
+            if live.is_empty() || next() % 3 != 0 {
+                let layout = Layout::from_size_align(1 + next() % 200, 1 << (next() % 5)).unwrap();
+                live.push(NonNull::from(allocator.raw_allocate(layout).unwrap().0));
+            } else {
+                let block = live.swap_remove(next() % live.len());
+                allocator.reclaim_block(block);
+            }
+            assert_eq!(allocator.free_bytes(), allocator.count_free_bytes());
+        }
+        unsafe { allocator.verify_heap() };
+    }
+    
+    /// Allocates and frees a mix of mostly small (and some big) sizes, at random.
+    /// 
+    /// Besides timing it, this also checks that allocating doesn't have to look through much of
+    /// the free list, since that's the whole point of splitting it up into size classes.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_mixed_sizes() {
+        const OPS: usize = 200_000;
+        let allocator = test_allocator(1 << 14);
+        let mut live = Vec::new();
+        
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize
+        };
+        
+        let visited_before = NUM_BLOCKS_VISITED.get();
+        let mut num_allocations = 0;
+        let start = std::time::Instant::now();
+        for _ in 0..OPS {
+            if live.is_empty() || next() % 3 != 0 {
+                let size = if next() % 50 == 0 { 1 + next() % 8192 } else { 1 + next() % 256 };
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                live.push(NonNull::from(allocator.raw_allocate(layout).unwrap().0));
+                num_allocations += 1;
+            } else {
+                let block = live.swap_remove(next() % live.len());
+                allocator.reclaim_block(block);
+            }
+        }
+        let elapsed = start.elapsed();
+        let visited_per_allocation = (NUM_BLOCKS_VISITED.get() - visited_before) as f64 / num_allocations as f64;
+        unsafe { allocator.verify_heap() };
+        println!(
+            "{OPS} operations in {elapsed:?} ({:.0} ops/s, {visited_per_allocation:.2} free blocks visited per allocation, {} free blocks at the end)",
+            OPS as f64 / elapsed.as_secs_f64(), allocator.free_blocks()
+        );
+        
+        // a single first-fit free list would have to look through hundreds of blocks here
+        assert!(visited_per_allocation < 2.0, "visited {visited_per_allocation:.2} free blocks per allocation");
+    }
+    
+    #[test]
+    fn test_grow_in_place() {
+        let allocator = test_allocator(4);
+        let small = Layout::new::<[u64; 4]>();
+        
+        // right after it is the rest of the page, which is free
+        let (block, _) = allocator.raw_allocate(small).unwrap();
+        let block = NonNull::from(block);
+        unsafe { block.as_ref().data().cast::<u64>().write(1234) };
+        assert!(unsafe { allocator.try_grow_in_place(block, 256) });
+        assert!(unsafe { block.as_ref() }.size >= 256);
+        assert_eq!(unsafe { block.as_ref().data().cast::<u64>().read() }, 1234);
+        unsafe { allocator.verify_heap() };
+        
+        // the leftover went back into the free list, so this should end up right after it
+        let (other, _) = allocator.raw_allocate(small).unwrap();
+        let other = NonNull::from(other);
+        assert_eq!(unsafe { block.as_ref() }.next(), other);
+        
+        // so now it's stuck, and has to be moved instead
+        assert!(!unsafe { allocator.try_grow_in_place(block, 1024) });
+        assert!(unsafe { block.as_ref() }.size < 1024);
+        
+        // but there's still room in `other`, and the block index should still find everything
+        assert!(unsafe { allocator.try_grow_in_place(other, 1024) });
+        unsafe { allocator.verify_heap() };
+        let heap = allocator.memory_source.regions()[0];
+        for block in [block, other] {
+            let ptr = unsafe { block.as_ref() }.data().cast::<()>().as_ptr().cast_const();
+            assert_eq!(allocator.block_index.find_block(heap, ptr), Some(block));
+        }
+        let allocated = allocator.block_index.blocks(heap).filter(|block| unsafe { block.as_ref() }.is_allocated()).count();
+        assert_eq!(allocated, 2);
+    }
+    
+    /// A memory source that breaks the alignment guarantee of `grow_by` should give an error, instead of a corrupted heap
+    #[test]
+    fn test_misaligned_memory_source() {
+        let inner = TestMemorySource::leak(4);
+        let misaligned_start = inner.pages.addr().get() + MisalignedMemorySource::OFFSET;
+        let source = Box::leak(Box::new(MisalignedMemorySource { inner, aligned_calls: Cell::new(0) }));
+        let block_index = Box::leak(Box::new(BlockIndex::new()));
+        let Err(err) = TLAllocator::try_new(source, block_index) else { panic!("should have noticed the misaligned memory") };
+        assert_eq!(err, GCAllocatorError::MisalignedMemory { address: misaligned_start, page_size: TestMemorySource::PAGE_SIZE });
+        
+        // same thing, but for when the heap has to grow later on
+        let source = Box::leak(Box::new(MisalignedMemorySource { inner: TestMemorySource::leak(4), aligned_calls: Cell::new(1) }));
+        let block_index = Box::leak(Box::new(BlockIndex::new()));
+        let allocator = TLAllocator::try_new(source, block_index).unwrap();
+        let big = Layout::array::<u8>(2 * TestMemorySource::PAGE_SIZE).unwrap();
+        let err = allocator.raw_allocate(big).map(|_| ()).unwrap_err();
+        assert!(matches!(err, GCAllocatorError::MisalignedMemory { page_size: TestMemorySource::PAGE_SIZE, .. }), "{err:?}");
+        
+        // the allocator should still work fine with the memory it already had
+        allocator.raw_allocate(Layout::new::<[u64; 4]>()).unwrap();
+        unsafe { allocator.verify_heap() };
+    }
+    
+    /// An exited thread's allocator should be able to hand all of its memory over to another one.
+    #[test]
+    fn test_give_everything_to() {
+        let source = TestMemorySource::leak(16);
+        let block_index = Box::leak(Box::new(BlockIndex::new()));
+        let mut exited = TLAllocator::try_new(source, block_index).unwrap();
+        let mut alive = TLAllocator::try_new(source, block_index).unwrap();
+        let layout = Layout::new::<[u64; 4]>();
+        
+        let blocks: Vec<_> = (0..8).map(|_| NonNull::from(exited.raw_allocate(layout).unwrap().0)).collect();
+        let freed: Vec<_> = blocks.iter().step_by(2).copied().collect();
+        for &block in &freed {
+            exited.reclaim_block(block);
+        }
+        alive.raw_allocate(layout).unwrap();
+        let total = exited.free_bytes() + alive.free_bytes();
+        
+        exited.give_everything_to(&mut alive);
+        unsafe { exited.verify_heap() };
+        unsafe { alive.verify_heap() };
+        assert!(exited.has_no_memory());
+        assert_eq!(alive.free_bytes(), total);
+        assert!(blocks.iter().all(|&block| alive.owns(block) && !exited.owns(block)));
+        
+        // the freed blocks are at the front of the free list now, so they get reused first
+        for _ in 0..freed.len() {
+            let block = NonNull::from(alive.raw_allocate(layout).unwrap().0);
+            assert!(freed.contains(&block), "{block:016x?} wasn't one of the freed blocks");
+        }
+        
+        // and the empty one can still get more memory
+        exited.raw_allocate(layout).unwrap();
+        unsafe { exited.verify_heap() };
+    }
+    
+    /// Reclaiming a bunch of blocks at once should merge the ones that are next to each other.
+    #[test]
+    fn test_reclaim_blocks_merges() {
+        let source = TestMemorySource::leak(16);
+        let block_index = Box::leak(Box::new(BlockIndex::new()));
+        let mut allocator = TLAllocator::try_new(source, block_index).unwrap();
+        let mut other = TLAllocator::try_new(source, block_index).unwrap();
+        let layout = Layout::new::<[u64; 4]>();
+        
+        let blocks: Vec<_> = (0..12).map(|_| NonNull::from(allocator.raw_allocate(layout).unwrap().0)).collect();
+        // `other`'s memory comes right after `allocator`'s, but they shouldn't get merged together
+        let other_block = NonNull::from(other.raw_allocate(layout).unwrap().0);
+        
+        // one on its own, and two runs (the second of which runs into the free rest of the heap)
+        allocator.reclaim_block(blocks[1]);
+        let freed = vec![blocks[7], blocks[3], blocks[9], blocks[4], blocks[5], blocks[10], blocks[11]];
+        let free_bytes = allocator.free_bytes();
+        allocator.reclaim_blocks(freed);
+        unsafe { allocator.verify_heap() };
+        
+        let mut list = free_list(&allocator);
+        list.sort();
+        assert_eq!(list, [blocks[1], blocks[3], blocks[7], blocks[9]]);
+        let size = |block: NonNull<GCHeapBlockHeader>| unsafe { block.as_ref() }.size;
+        assert_eq!(size(blocks[3]), blocks[6].addr().get() - blocks[3].addr().get() - size_of::<GCHeapBlockHeader>());
+        assert_eq!(size(blocks[7]), size(blocks[1]));
+        let region = allocator.regions()[0];
+        assert_eq!(unsafe { blocks[9].as_ref() }.next(), unsafe { region.cast::<GCHeapBlockHeader>().byte_add(region.len()) });
+        // the headers that got merged away are free memory now too
+        assert_eq!(allocator.free_bytes(), free_bytes + 7 * size(blocks[1]) + 5 * size_of::<GCHeapBlockHeader>());
+        
+        // the merged blocks can be allocated from like normal
+        let big = Layout::array::<u64>(8).unwrap();
+        assert_eq!(NonNull::from(allocator.raw_allocate(big).unwrap().0), blocks[3]);
+        unsafe { allocator.verify_heap() };
+        assert!(unsafe { other_block.as_ref() }.is_allocated());
+        unsafe { other.verify_heap() };
+    }
+    
+    /// Fills a heap with small blocks, frees all but a few of them, and makes sure that the block
+    /// index gets to skip most of the (now free) heap.
+    #[test]
+    fn test_block_index_skips_free_chunks() {
+        let allocator = test_allocator(256);
+        let layout = Layout::new::<[u64; 8]>();
+        
+        let mut blocks = Vec::new();
+        while let Ok((block, _)) = allocator.raw_allocate(layout) {
+            blocks.push(NonNull::from(block));
+        }
+        
+        // only keep a handful of blocks alive, all near the end of the heap
+        let live: Vec<_> = blocks.iter().rev().step_by(50).take(4).copied().collect();
+        for &block in blocks.iter().filter(|block| !live.contains(block)) {
+            allocator.reclaim_block(block);
+        }
+        unsafe { allocator.verify_heap() };
+        
+        let heap = allocator.memory_source.regions()[0];
+        let (start, len) = heap.to_raw_parts();
+        let end = unsafe { start.byte_add(len) }.cast();
+        let every_block = BlockIndex::walk(start.cast(), end).count();
+        
+        // skipping should still find every allocated block, while looking at way fewer blocks
+        let visited: Vec<_> = allocator.block_index.blocks(heap).collect();
+        let mut allocated: Vec<_> = visited.iter().copied().filter(|block| unsafe { block.as_ref() }.is_allocated()).collect();
+        let mut live_sorted = live.clone();
+        allocated.sort();
+        live_sorted.sort();
+        assert_eq!(allocated, live_sorted);
+        assert!(visited.len() * 10 < every_block, "visited {} of {every_block} blocks", visited.len());
+        
+        // and finding a block should start right before it, instead of at the start of the heap
+        for &block in &live {
+            let ptr = unsafe { block.as_ref() }.data().cast::<()>().as_ptr().cast_const();
+            assert_eq!(allocator.block_index.find_block(heap, ptr), Some(block));
+            let start = allocator.block_index.block_before(heap, ptr);
+            let steps = BlockIndex::walk(start, end).position(|b| b == block).unwrap();
+            assert!(steps * 10 < every_block, "took {steps} steps out of {every_block} blocks");
+        }
+    }
+    
+    /// The fast reject in the block index should never throw out a pointer into an allocated
+    /// block (even one into the middle of it), but should throw out most pointers into free ones.
+    #[test]
+    fn test_block_index_fast_reject() {
+        let allocator = test_allocator(256);
+        let small = Layout::new::<[u64; 8]>();
+        
+        // a mix of small blocks and ones that span multiple chunks
+        let mut blocks = Vec::new();
+        for i in 0.. {
+            let layout = if i % 100 == 99 { Layout::array::<u8>(0x6000).unwrap() } else { small };
+            let Ok((block, _)) = allocator.raw_allocate(layout) else { break };
+            blocks.push(NonNull::from(block));
+        }
+        // (including one of the big ones, since pointers into it can be chunks away from its header)
+        let live: Vec<_> = blocks.iter().enumerate().filter(|&(i, _)| i % 401 == 0 || i == 199).map(|(_, &block)| block).collect();
+        for &block in blocks.iter().filter(|block| !live.contains(block)) {
+            allocator.reclaim_block(block);
+        }
+        unsafe { allocator.verify_heap() };
+        
+        // no false negatives, for any word in any live block
+        for &block in &live {
+            let block = unsafe { block.as_ref() };
+            let (data, len) = block.data().to_raw_parts();
+            for offset in (0..len).step_by(size_of::<usize>()) {
+                let ptr = data.as_ptr().cast_const().wrapping_byte_add(offset);
+                assert!(allocator.block_index.might_be_allocated(ptr), "rejected {ptr:016x?} (in block {block:p})");
+            }
+        }
+        
+        // but most pointers into free blocks should get rejected
+        let heap = allocator.memory_source.regions()[0];
+        let (start, len) = heap.to_raw_parts();
+        let end = unsafe { start.byte_add(len) }.cast();
+        let (mut free, mut rejected) = (0, 0);
+        for block in BlockIndex::walk(start.cast(), end) {
+            let block = unsafe { block.as_ref() };
+            if block.is_allocated() { continue }
+            free += 1;
+            let ptr = block.data().cast::<()>().as_ptr().cast_const();
+            if !allocator.block_index.might_be_allocated(ptr) { rejected += 1 }
+        }
+        assert!(rejected * 2 > free, "only rejected {rejected} of {free} free blocks");
+    }
+}
@@ -0,0 +1,485 @@
+//! An ordered map, whose nodes live in the GC heap.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+use super::{GcCell, GcVec};
+
+
+/// The minimum number of children of every node (except the root and the leaves).
+/// 
+/// Every node other than the root has between `B - 1` and `2 * B - 1` entries.
+const B: usize = 6;
+const MAX_ENTRIES: usize = 2 * B - 1;
+
+struct Node<K: 'static, V: 'static> {
+    /// Sorted by key.
+    entries: GcVec<(K, V)>,
+    /// Empty if this is a leaf. Otherwise, there is one more of these than there are entries,
+    /// and every key in `children[i]` goes between `entries[i - 1]` and `entries[i]`.
+    children: GcVec<GcCell<Node<K, V>>>,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(entries: GcVec<(K, V)>, children: GcVec<GcCell<Node<K, V>>>) -> Self {
+        Self { entries, children }
+    }
+    
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+    
+    /// Where `target` is in this node, as either `Ok(index of the entry)` or `Err(index of the
+    /// child it would be under)`.
+    fn search<Q: ?Sized + Ord>(&self, target: Target<'_, Q>) -> Result<usize, usize> where K: Borrow<Q> {
+        match target {
+            Target::Key(key) => self.entries.binary_search_by(|(k, _)| k.borrow().cmp(key)),
+            Target::First if self.is_leaf() => Ok(0),
+            Target::First => Err(0),
+            Target::Last if self.is_leaf() => Ok(self.entries.len() - 1),
+            Target::Last => Err(self.entries.len()),
+        }
+    }
+}
+
+/// Which entry [`GcBTreeMap::remove_from`] should remove.
+enum Target<'q, Q: ?Sized> {
+    Key(&'q Q),
+    /// The entry with the smallest key (i.e: the successor of whatever is right before this subtree).
+    First,
+    /// The entry with the biggest key (i.e: the predecessor of whatever is right after this subtree).
+    Last,
+}
+
+// NOTE: these can't be derived, since that would require `Q: Copy`
+impl<Q: ?Sized> Clone for Target<'_, Q> {
+    fn clone(&self) -> Self { *self }
+}
+impl<Q: ?Sized> Copy for Target<'_, Q> {}
+
+/// An ordered map, implemented as a B-tree whose nodes are [`GcCell`]s.
+/// 
+/// Nodes are never freed by the map itself: when rebalancing merges two nodes together, the one
+/// that got emptied out is just dropped from its parent, and the collector takes care of it once
+/// nothing else points to it. Since the entries are in [`GcVec`]s, any [`Gc`](super::Gc)s in
+/// the keys or values are traced along with the nodes.
+/// 
+/// Lookups only hold the borrow of one node at a time, so it's cheap to look things up from
+/// a bunch of threads at once. Changing the map needs `&mut self`.
+/// 
+/// NOTE: since each node is only borrowed for a moment, [`get`](Self::get) and
+/// [`iter`](Self::iter) hand out clones of the keys and values (which is cheap for things like
+/// [`Gc`](super::Gc)s), instead of references.
+pub struct GcBTreeMap<K: 'static, V: 'static> {
+    root: Option<GcCell<Node<K, V>>>,
+    len: usize,
+}
+
+impl<K: Ord + Send + Sync, V: Send + Sync> GcBTreeMap<K, V> {
+    pub const fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+    
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    
+    /// Returns a clone of the value for `key`, if there is one.
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<V> where K: Borrow<Q>, V: Clone {
+        let mut node = self.root?;
+        loop {
+            let next = {
+                let n = node.borrow();
+                match n.search(Target::Key(key)) {
+                    Ok(i) => return Some(n.entries[i].1.clone()),
+                    Err(_) if n.is_leaf() => return None,
+                    Err(i) => n.children[i],
+                }
+            };
+            node = next;
+        }
+    }
+    
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool where K: Borrow<Q> {
+        let Some(mut node) = self.root else { return false };
+        loop {
+            let next = {
+                let n = node.borrow();
+                match n.search(Target::Key(key)) {
+                    Ok(_) => return true,
+                    Err(_) if n.is_leaf() => return false,
+                    Err(i) => n.children[i],
+                }
+            };
+            node = next;
+        }
+    }
+    
+    /// Inserts a key-value pair into the map, returning the old value if the key was already there.
+    /// 
+    /// NOTE: just like [`BTreeMap::insert`](std::collections::BTreeMap::insert), the key isn't
+    /// replaced if it was already there.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let root = match self.root {
+            None => {
+                let mut entries = GcVec::with_capacity(MAX_ENTRIES);
+                entries.push((key, value));
+                self.root = Some(GcCell::new(Node::new(entries, GcVec::new())));
+                self.len += 1;
+                return None
+            }
+            // split a full root before going down, so the tree grows taller from the top
+            Some(root) if root.borrow().entries.len() == MAX_ENTRIES => {
+                let mut children = GcVec::with_capacity(MAX_ENTRIES + 1);
+                children.push(root);
+                let new_root = GcCell::new(Node::new(GcVec::with_capacity(MAX_ENTRIES), children));
+                Self::split_child(&mut new_root.borrow_mut(), 0);
+                self.root = Some(new_root);
+                new_root
+            }
+            Some(root) => root,
+        };
+        
+        let old = Self::insert_nonfull(root, key, value);
+        if old.is_none() { self.len += 1 }
+        old
+    }
+    
+    /// Inserts into the subtree under `node`, which can't be full.
+    fn insert_nonfull(mut node: GcCell<Node<K, V>>, key: K, value: V) -> Option<V> {
+        loop {
+            let mut n = node.borrow_mut();
+            let mut i = match n.search(Target::Key(&key)) {
+                Ok(i) => return Some(std::mem::replace(&mut n.entries[i].1, value)),
+                Err(i) if n.is_leaf() => {
+                    n.entries.insert(i, (key, value));
+                    return None
+                }
+                Err(i) => i,
+            };
+            
+            // make sure there's room in the child, so that it can take whatever gets split off of its children
+            if n.children[i].borrow().entries.len() == MAX_ENTRIES {
+                Self::split_child(&mut n, i);
+                match key.cmp(&n.entries[i].0) {
+                    Ordering::Equal => return Some(std::mem::replace(&mut n.entries[i].1, value)),
+                    Ordering::Greater => i += 1,
+                    Ordering::Less => {},
+                }
+            }
+            let child = n.children[i];
+            drop(n);
+            node = child;
+        }
+    }
+    
+    /// Splits the full child at `i` in half, moving its middle entry up into `parent`.
+    fn split_child(parent: &mut Node<K, V>, i: usize) {
+        let child = parent.children[i];
+        let mut c = child.borrow_mut();
+        
+        let right_entries = c.entries.split_off(B);
+        let middle = c.entries.pop().expect("a full node has a middle entry");
+        let right_children = if c.is_leaf() { GcVec::new() } else { c.children.split_off(B) };
+        
+        parent.entries.insert(i, middle);
+        parent.children.insert(i + 1, GcCell::new(Node::new(right_entries, right_children)));
+    }
+    
+    /// Removes a key from the map, returning its value if it was there.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q> {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+    
+    /// Removes a key from the map, returning the stored key and its value if it was there.
+    pub fn remove_entry<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<(K, V)> where K: Borrow<Q> {
+        let root = self.root?;
+        let removed = Self::remove_from(root, Target::Key(key));
+        
+        // the root is the only node that's allowed to run out of entries
+        let replacement = {
+            let r = root.borrow();
+            match r.entries.len() {
+                0 if r.is_leaf() => Some(None),
+                0 => Some(Some(r.children[0])),
+                _ => None,
+            }
+        };
+        if let Some(new_root) = replacement {
+            self.root = new_root;
+        }
+        
+        if removed.is_some() { self.len -= 1 }
+        removed
+    }
+    
+    /// Removes `target` from the subtree under `node`.
+    /// 
+    /// Unless `node` is the root, it has to have at least `B` entries, so that taking one out
+    /// can't leave it with too few. On the way down, this makes sure the same goes for each child
+    /// that it goes into, by borrowing an entry from a sibling or merging with one.
+    fn remove_from<Q: ?Sized + Ord>(mut node: GcCell<Node<K, V>>, target: Target<'_, Q>) -> Option<(K, V)> where K: Borrow<Q> {
+        loop {
+            let mut n = node.borrow_mut();
+            match n.search(target) {
+                Ok(i) if n.is_leaf() => return Some(n.entries.remove(i)),
+                Ok(i) => {
+                    // swap the entry with its predecessor or successor, which are always in a leaf
+                    let (left, right) = (n.children[i], n.children[i + 1]);
+                    if left.borrow().entries.len() >= B {
+                        let predecessor = Self::remove_from(left, Target::Last).expect("children are never empty");
+                        return Some(std::mem::replace(&mut n.entries[i], predecessor))
+                    }
+                    if right.borrow().entries.len() >= B {
+                        let successor = Self::remove_from(right, Target::First).expect("children are never empty");
+                        return Some(std::mem::replace(&mut n.entries[i], successor))
+                    }
+                    // both are as small as they can be, so the entry goes down into their merged node
+                    Self::merge_children(&mut n, i);
+                    drop(n);
+                    node = left;
+                }
+                Err(_) if n.is_leaf() => return None,
+                Err(i) => {
+                    let i = Self::fill_child(&mut n, i);
+                    let child = n.children[i];
+                    drop(n);
+                    node = child;
+                }
+            }
+        }
+    }
+    
+    /// Makes sure the child at `i` has at least `B` entries, returning where it ended up.
+    fn fill_child(parent: &mut Node<K, V>, i: usize) -> usize {
+        let child = parent.children[i];
+        if child.borrow().entries.len() >= B { return i }
+        
+        // borrow an entry from the left sibling, through the parent
+        if i > 0 && parent.children[i - 1].borrow().entries.len() >= B {
+            let (left, mut c) = (parent.children[i - 1], child.borrow_mut());
+            let mut l = left.borrow_mut();
+            let moved = l.entries.pop().expect("the sibling has entries to spare");
+            let separator = std::mem::replace(&mut parent.entries[i - 1], moved);
+            c.entries.insert(0, separator);
+            if let Some(grandchild) = l.children.pop() {
+                c.children.insert(0, grandchild);
+            }
+            return i
+        }
+        
+        // or from the right sibling
+        if i < parent.entries.len() && parent.children[i + 1].borrow().entries.len() >= B {
+            let (right, mut c) = (parent.children[i + 1], child.borrow_mut());
+            let mut r = right.borrow_mut();
+            let moved = r.entries.remove(0);
+            let separator = std::mem::replace(&mut parent.entries[i], moved);
+            c.entries.push(separator);
+            if !r.is_leaf() {
+                c.children.push(r.children.remove(0));
+            }
+            return i
+        }
+        
+        // both siblings are as small as they can be, so merge with one of them
+        if i < parent.entries.len() {
+            Self::merge_children(parent, i);
+            i
+        } else {
+            Self::merge_children(parent, i - 1);
+            i - 1
+        }
+    }
+    
+    /// Merges the child at `i + 1` (and the entry between them) into the child at `i`.
+    /// 
+    /// The node that used to be at `i + 1` is left empty, for the collector to pick up.
+    fn merge_children(parent: &mut Node<K, V>, i: usize) {
+        let separator = parent.entries.remove(i);
+        let right = parent.children.remove(i + 1);
+        let (mut l, mut r) = (parent.children[i].borrow_mut(), right.borrow_mut());
+        l.entries.push(separator);
+        l.entries.append(&mut r.entries);
+        l.children.append(&mut r.children);
+    }
+    
+    /// Iterates over clones of every entry, in order of their keys.
+    pub fn iter(&self) -> impl Iterator<Item=(K, V)> + '_ where K: Clone, V: Clone {
+        /// Pushes `node` and the leftmost path under it.
+        fn push_leftmost<K, V>(stack: &mut Vec<(GcCell<Node<K, V>>, usize)>, mut node: GcCell<Node<K, V>>) {
+            loop {
+                stack.push((node, 0));
+                let Some(&child) = node.borrow().children.first() else { return };
+                node = child;
+            }
+        }
+        
+        gen move {
+            // each node on the path down to the next entry, and the index of the next entry in it
+            let mut stack = Vec::new();
+            if let Some(root) = self.root {
+                push_leftmost(&mut stack, root);
+            }
+            
+            while let Some((node, i)) = stack.pop() {
+                // NOTE: the borrow can't be held across the `yield`
+                let (entry, next_child) = {
+                    let n = node.borrow();
+                    let Some(entry) = n.entries.get(i) else { continue };
+                    (entry.clone(), n.children.get(i + 1).copied())
+                };
+                stack.push((node, i + 1));
+                if let Some(child) = next_child {
+                    push_leftmost(&mut stack, child);
+                }
+                yield entry;
+            }
+        }
+    }
+}
+
+impl<K: Ord + Send + Sync, V: Send + Sync> Default for GcBTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Send + Sync + Clone + Debug, V: Send + Sync + Clone + Debug> Debug for GcBTreeMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord + Send + Sync, V: Send + Sync> FromIterator<(K, V)> for GcBTreeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord + Send + Sync, V: Send + Sync> Extend<(K, V)> for GcBTreeMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::gc::Gc;
+    use crate::gc::allocator::GC_ALLOCATOR;
+    
+    /// Checks every B-tree invariant, and returns the number of entries in the tree.
+    fn check_invariants<K: Ord + Send + Sync + Clone + Debug, V: Send + Sync>(map: &GcBTreeMap<K, V>) -> usize {
+        /// Returns the number of entries under `node`, and its height.
+        fn check_node<K: Ord + Clone + Debug, V>(node: GcCell<Node<K, V>>, is_root: bool, lower: Option<&K>, upper: Option<&K>) -> (usize, usize) {
+            let n = node.borrow();
+            let min_entries = if is_root { 1 } else { B - 1 };
+            assert!((min_entries..=MAX_ENTRIES).contains(&n.entries.len()), "node has {} entries", n.entries.len());
+            
+            let keys: Vec<&K> = n.entries.iter().map(|(k, _)| k).collect();
+            assert!(keys.is_sorted_by(|a, b| a < b), "keys aren't strictly increasing: {keys:?}");
+            assert!(lower.is_none_or(|lower| lower < keys[0]) && upper.is_none_or(|upper| keys[keys.len() - 1] < upper), "keys {keys:?} aren't between {lower:?} and {upper:?}");
+            
+            if n.is_leaf() { return (n.entries.len(), 1) }
+            assert_eq!(n.children.len(), n.entries.len() + 1);
+            
+            let mut count = n.entries.len();
+            let mut height = None;
+            for (i, &child) in n.children.iter().enumerate() {
+                let lower = if i == 0 { lower } else { Some(keys[i - 1]) };
+                let upper = keys.get(i).copied().or(upper);
+                let (child_count, child_height) = check_node(child, false, lower, upper);
+                assert!(height.is_none_or(|h| h == child_height), "leaves aren't all at the same depth");
+                height = Some(child_height);
+                count += child_count;
+            }
+            (count, height.unwrap() + 1)
+        }
+        
+        let count = map.root.map_or(0, |root| check_node(root, true, None, None).0);
+        assert_eq!(count, map.len());
+        count
+    }
+    
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = GcBTreeMap::new();
+        assert_eq!(map.get(&0), None::<i32>);
+        
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        check_invariants(&map);
+        assert_eq!(map.insert(500, 0), Some(1000));
+        assert_eq!(map.get(&500), Some(0));
+        assert!(map.contains_key(&999) && !map.contains_key(&1000));
+        assert!(map.iter().map(|(k, _)| k).eq(0..1000));
+        
+        // remove every other key, then the rest, in an order that hits every rebalancing case
+        for i in (0..1000).step_by(2) {
+            assert_eq!(map.remove(&i), Some(if i == 500 { 0 } else { i * 2 }));
+        }
+        check_invariants(&map);
+        assert_eq!(map.remove(&0), None);
+        for i in (1..1000).step_by(2).rev() {
+            assert_eq!(map.remove(&i), Some(i * 2));
+            check_invariants(&map);
+        }
+        assert!(map.is_empty());
+        assert!(map.root.is_none());
+    }
+    
+    /// Does a bunch of random inserts and removes, and compares the map against a `BTreeMap` the whole time
+    #[test]
+    fn test_against_btreemap() {
+        let mut map = GcBTreeMap::new();
+        let mut expected = BTreeMap::new();
+        
+        let mut state = 0x2545f4914f6cdd1du64;
+        for step in 0..20_000 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let key = (state >> 33) % 2000;
+            if (state >> 20).is_multiple_of(3) {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, step), expected.insert(key, step));
+            }
+            if step % 1000 == 0 {
+                check_invariants(&map);
+            }
+        }
+        
+        check_invariants(&map);
+        assert!(map.iter().eq(expected.iter().map(|(&k, &v)| (k, v))));
+        assert!((0..2000).all(|key| map.get(&key) == expected.get(&key).copied()));
+    }
+    
+    /// The nodes (and the `Gc`s in them) should survive collections, even after a bunch of
+    /// rebalancing has turned some of the old nodes into garbage
+    #[test]
+    fn test_survives_collection() {
+        let mut map: GcBTreeMap<Gc<str>, Gc<usize>> = (0..500).map(|i| (Gc::from_str(&i.to_string()), Gc::new(i))).collect();
+        for i in (0..500).step_by(3) {
+            assert_eq!(map.remove(&*i.to_string()).map(|v| *v), Some(i));
+        }
+        
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        
+        check_invariants(&map);
+        for i in 0..500 {
+            assert_eq!(map.get(&*i.to_string()).map(|v| *v), (i % 3 != 0).then_some(i));
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! A garbage collected, interior-mutable cell.
+//! 
+//! This is mostly just sugar over `Gc<AtomicRefCell<T>>`, which is what you
+//! end up reaching for any time you want a mutable object graph on the GC heap.
+
+use std::fmt::Debug;
+
+use crate::cell::{AtomicRef, AtomicRefCell, AtomicRefMut, BorrowError};
+
+use super::Gc;
+use super::gc_debug::{debug_tracked, GcDebug};
+
+
+/// A shared, mutable, Garbage Collected (GCed) value.
+/// 
+/// This is a `Gc<`[`AtomicRefCell`]`<T>>` under the hood, but exposes the borrowing API
+/// directly, so that building things like mutable graphs doesn't require nesting smart
+/// pointer and cell types manually. Just like a [`Gc`], this type is [`Copy`], so there is no
+/// reference counting overhead when passing it around.
+/// 
+/// Unlike [`AtomicRefCell`], the [`borrow`] and [`borrow_mut`] methods panic on failure (in
+/// the same way as [`RefCell`]). Use [`try_borrow`] and [`try_borrow_mut`] for the
+/// non-panicking versions.
+/// 
+/// The collector scans whatever is in the cell at the start of each cycle, so after a `Gc`
+/// inside it gets replaced, the old target can be collected (as long as nothing else points
+/// to it), and the new one is kept alive.
+/// 
+/// TODO: once there's precise tracing (i.e: a `Trace` trait), this should trace its current
+/// contents under a shared borrow, instead of getting its bytes scanned conservatively.
+/// 
+/// [`borrow`]: Self::borrow
+/// [`borrow_mut`]: Self::borrow_mut
+/// [`try_borrow`]: Self::try_borrow
+/// [`try_borrow_mut`]: Self::try_borrow_mut
+/// [`RefCell`]: std::cell::RefCell
+#[repr(transparent)]
+pub struct GcCell<T: 'static>(Gc<AtomicRefCell<T>>);
+
+impl<T> Copy for GcCell<T> {}
+impl<T> Clone for GcCell<T> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<T> GcCell<T> {
+    /// Moves a value into a new GCed cell.
+    /// 
+    /// Requires `T: Send` since the GC thread will gain ownership of the value in order to drop it.
+    pub fn new(value: T) -> Self where T: Send {
+        Self(Gc::new(AtomicRefCell::new(value)))
+    }
+    
+    /// Tries to acquire shared access to the inner value.
+    /// 
+    /// See [`AtomicRefCell::try_borrow`].
+    pub fn try_borrow(&self) -> Result<AtomicRef<'_, T>, BorrowError> {
+        self.0.try_borrow()
+    }
+    
+    /// Tries to acquire exclusive access to the inner value.
+    /// 
+    /// See [`AtomicRefCell::try_borrow_mut`].
+    pub fn try_borrow_mut(&self) -> Result<AtomicRefMut<'_, T>, BorrowError> {
+        self.0.try_borrow_mut()
+    }
+    
+    /// Acquires shared access to the inner value.
+    /// 
+    /// # Panics
+    /// If the value is currently exclusively borrowed.
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
+        self.try_borrow().expect("GcCell was already mutably borrowed")
+    }
+    
+    /// Acquires exclusive access to the inner value.
+    /// 
+    /// # Panics
+    /// If the value is currently borrowed in any way.
+    pub fn borrow_mut(&self) -> AtomicRefMut<'_, T> {
+        self.try_borrow_mut().expect("GcCell was already borrowed")
+    }
+    
+    /// Returns a copy of the inner value.
+    /// 
+    /// # Panics
+    /// If the value is currently exclusively borrowed.
+    pub fn get(&self) -> T where T: Copy {
+        *self.borrow()
+    }
+    
+    /// Sets the inner value, dropping the old one.
+    /// 
+    /// # Panics
+    /// If the value is currently borrowed in any way.
+    pub fn set(&self, value: T) {
+        *self.borrow_mut() = value;
+    }
+    
+    /// Replaces the inner value, returning the old one.
+    /// 
+    /// # Panics
+    /// If the value is currently borrowed in any way.
+    pub fn replace(&self, value: T) -> T {
+        std::mem::replace(&mut *self.borrow_mut(), value)
+    }
+    
+    /// Returns the underlying `Gc<AtomicRefCell<T>>`.
+    pub fn as_gc(self) -> Gc<AtomicRefCell<T>> {
+        self.0
+    }
+    
+    /// Returns a pointer to the underlying cell.
+    pub fn as_ptr(&self) -> *const AtomicRefCell<T> {
+        self.0.as_ptr()
+    }
+    
+    /// Formats the value with [`Debug`], printing a marker instead of recursing forever if it points back to itself.
+    /// 
+    /// See [`GcDebug`].
+    pub fn debug_cyclic(&self) -> GcDebug<'_, Self> where T: Debug {
+        GcDebug::new(self)
+    }
+}
+
+impl<T> From<Gc<AtomicRefCell<T>>> for GcCell<T> {
+    fn from(value: Gc<AtomicRefCell<T>>) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Debug> Debug for GcCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        debug_tracked(self.as_ptr().cast(), f, |f| match self.try_borrow() {
+            Ok(value) => f.debug_tuple("GcCell").field(&*value).finish(),
+            Err(_) => f.write_str("GcCell(<borrowed>)"),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    struct Node {
+        value: i32,
+        prev: Option<GcCell<Node>>,
+        next: Option<GcCell<Node>>,
+    }
+    
+    #[test]
+    fn test_get_set() {
+        let x = GcCell::new(5);
+        let y = x;
+        assert_eq!(y.get(), 5);
+        x.set(6);
+        assert_eq!(y.get(), 6);
+        assert_eq!(y.replace(7), 6);
+        assert_eq!(x.get(), 7);
+    }
+    
+    #[test]
+    fn test_borrow_conflicts() {
+        let x = GcCell::new(String::from("hello"));
+        let guard = x.borrow();
+        assert!(x.try_borrow().is_ok());
+        assert!(x.try_borrow_mut().is_err());
+        drop(guard);
+        x.borrow_mut().push_str(" world");
+        assert_eq!(&*x.borrow(), "hello world");
+    }
+    
+    /// Builds a doubly-linked list out of `GcCell` nodes, and mutates it through the links
+    #[test]
+    fn test_doubly_linked_graph() {
+        const N: i32 = 10;
+        
+        let nodes: Vec<GcCell<Node>> = (0..N).map(|value| GcCell::new(Node { value, prev: None, next: None })).collect();
+        for pair in nodes.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            a.borrow_mut().next = Some(b);
+            b.borrow_mut().prev = Some(a);
+        }
+        let (first, last) = (nodes[0], nodes[N as usize - 1]);
+        drop(nodes);
+        
+        // make sure the GC doesn't collect nodes that are only reachable through the graph
+        crate::gc::allocator::GC_ALLOCATOR.wait_for_gc();
+        
+        // mutate a node by traversing the links
+        let third = first.borrow().next.unwrap().borrow().next.unwrap();
+        third.borrow_mut().value = 100;
+        
+        // walk forwards
+        let mut current = first;
+        let mut forwards = vec![];
+        loop {
+            forwards.push(current.borrow().value);
+            let next = current.borrow().next;
+            match next {
+                Some(next) => current = next,
+                None => break
+            }
+        }
+        assert_eq!(forwards, [0, 1, 100, 3, 4, 5, 6, 7, 8, 9]);
+        
+        // walk backwards
+        let mut current = last;
+        let mut backwards = vec![];
+        loop {
+            backwards.push(current.borrow().value);
+            let prev = current.borrow().prev;
+            match prev {
+                Some(prev) => current = prev,
+                None => break
+            }
+        }
+        forwards.reverse();
+        assert_eq!(backwards, forwards);
+    }
+    
+    static DROPPED: [std::sync::atomic::AtomicBool; 20] = [const { std::sync::atomic::AtomicBool::new(false) }; 20];
+    
+    struct Tracked(usize);
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPPED[self.0].store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+    
+    /// Allocates the new target in its own frame, so that it doesn't stick around on the test's stack.
+    #[inline(never)]
+    fn retarget(holder: GcCell<Option<Gc<Tracked>>>, id: usize) {
+        holder.set(Some(Gc::new(Tracked(id))));
+    }
+    
+    /// Changes what a `GcCell` points to between collections
+    #[test]
+    fn test_retarget_between_collections() {
+        use crate::gc::allocator::GC_ALLOCATOR;
+        use std::sync::atomic::Ordering;
+        
+        let holder = GcCell::new(None);
+        for id in 0..DROPPED.len() {
+            retarget(holder, id);
+            GC_ALLOCATOR.wait_for_gc();
+            
+            // the new target is only reachable through the cell
+            assert!(!DROPPED[id].load(Ordering::Relaxed));
+            assert_eq!(holder.get().unwrap().0, id);
+        }
+        GC_ALLOCATOR.wait_for_gc();
+        
+        // NOTE: since the scanning is conservative, a couple old targets might still be kept alive by stray values
+        let num_dropped = DROPPED.iter().filter(|d| d.load(Ordering::Relaxed)).count();
+        assert!(num_dropped > DROPPED.len() / 2, "only {num_dropped} old targets were collected");
+        assert!(!DROPPED[DROPPED.len() - 1].load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,105 @@
+//! [`Debug`] formatting for object graphs that might have cycles in them.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+
+
+std::thread_local! {
+    /// The objects that are in the middle of being formatted on this thread, if it's inside a [`GcDebug`].
+    static ANCESTORS: RefCell<Option<HashSet<*const ()>>> = const { RefCell::new(None) };
+}
+
+/// Formats a GCed object graph with [`Debug`], without recursing forever if it has cycles.
+/// 
+/// The [`Debug`] impls of [`Gc`](super::Gc) and [`GcCell`](super::GcCell) just forward to the
+/// value they point to, so formatting something that (indirectly) points back to itself would
+/// overflow the stack. While this is being formatted, they keep track of which objects they are
+/// in the middle of formatting on the current thread, and when one comes back around, they print
+/// `<cycle to 0x...>` (with the address of the object) instead of formatting it again.
+/// 
+/// NOTE: only the objects that are currently being formatted count, so an object that can be
+/// reached in multiple ways without a cycle (e.g: in a DAG) still gets formatted every time.
+/// 
+/// See [`Gc::debug_cyclic`](super::Gc::debug_cyclic) and [`GcCell::debug_cyclic`](super::GcCell::debug_cyclic).
+pub struct GcDebug<'a, T: ?Sized>(&'a T);
+
+impl<'a, T: ?Sized + Debug> GcDebug<'a, T> {
+    pub fn new(value: &'a T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for GcDebug<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) { ANCESTORS.set(None) }
+        }
+        
+        // if this is nested inside another `GcDebug`, that one is already keeping track of everything
+        let outermost = ANCESTORS.with_borrow_mut(|ancestors| {
+            let outermost = ancestors.is_none();
+            ancestors.get_or_insert_default();
+            outermost
+        });
+        let _reset = outermost.then(|| Reset);
+        self.0.fmt(f)
+    }
+}
+
+/// Formats the object at `ptr` with `fmt`, unless it is already being formatted further up the stack.
+/// 
+/// Outside of a [`GcDebug`], this just calls `fmt`.
+pub(super) fn debug_tracked(ptr: *const (), f: &mut Formatter<'_>, fmt: impl FnOnce(&mut Formatter<'_>) -> std::fmt::Result) -> std::fmt::Result {
+    // NOTE: the thread local can't stay borrowed while `fmt` runs, since it will probably end up back in here
+    match ANCESTORS.with_borrow_mut(|ancestors| ancestors.as_mut().map(|ancestors| ancestors.insert(ptr))) {
+        None => fmt(f),
+        Some(false) => write!(f, "<cycle to {ptr:p}>"),
+        Some(true) => {
+            let result = fmt(f);
+            ANCESTORS.with_borrow_mut(|ancestors| ancestors.as_mut().map(|ancestors| ancestors.remove(&ptr)));
+            result
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::{Gc, GcCell};
+    
+    #[derive(Debug)]
+    struct Node {
+        name: &'static str,
+        next: GcCell<Option<Gc<Node>>>,
+    }
+    
+    #[test]
+    fn test_debug_cycle() {
+        let a = Gc::new(Node { name: "a", next: GcCell::new(None) });
+        let b = Gc::new(Node { name: "b", next: GcCell::new(Some(a)) });
+        a.next.set(Some(b));
+        
+        assert_eq!(
+            format!("{:?}", a.debug_cyclic()),
+            format!(r#"Node {{ name: "a", next: GcCell(Some(Node {{ name: "b", next: GcCell(Some(<cycle to {:p}>)) }})) }}"#, a.as_ptr()),
+        );
+        // nesting them shouldn't reset anything
+        assert_eq!(format!("{:?}", GcDebug::new(&b.debug_cyclic())), format!("{:?}", b.debug_cyclic()));
+        
+        // a cell that points back to itself
+        #[derive(Debug)]
+        struct Loop(Option<GcCell<Loop>>);
+        let cell = GcCell::new(Loop(None));
+        cell.borrow_mut().0 = Some(cell);
+        assert_eq!(format!("{:?}", cell.debug_cyclic()), format!("GcCell(Loop(Some(<cycle to {:p}>)))", cell.as_ptr()));
+        
+        // without a cycle, the same object can show up more than once
+        let leaf = Gc::new(Node { name: "leaf", next: GcCell::new(None) });
+        let pair = Gc::new((leaf, leaf));
+        let leaf = r#"Node { name: "leaf", next: GcCell(None) }"#;
+        assert_eq!(format!("{:?}", pair.debug_cyclic()), format!("({leaf}, {leaf})"));
+    }
+}
@@ -0,0 +1,144 @@
+//! A lazily initialized [`Gc`], for global singletons.
+
+use std::cell::UnsafeCell;
+use std::fmt::Debug;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::Gc;
+
+
+const UNINIT: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+/// A [`Gc<T>`] that gets allocated the first time it's accessed.
+/// 
+/// This is meant to be put in a `static`, like a [`LazyLock`], but since the value lives in
+/// the GC heap, it can point to other GCed objects. Since this type has interior mutability,
+/// it always ends up in writable static memory, which the collector scans for roots, so the
+/// value never gets collected.
+/// 
+/// If multiple threads try to initialize it at the same time, only one of them runs its
+/// initializer, and the rest wait for it to finish.
+/// 
+/// [`LazyLock`]: std::sync::LazyLock
+pub struct GcOnce<T: 'static> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<Gc<T>>>,
+}
+
+// SAFETY: the value is only ever written once (by whoever moves `state` to `RUNNING`), before
+//         `state` gets set to `DONE`, and only read after that. Also, `Gc<T>` is `Send + Sync`
+//         when `T: Sync`, and `T` has to be `Send` since it can get initialized on any thread.
+unsafe impl<T: Send + Sync> Sync for GcOnce<T> {}
+unsafe impl<T: Send + Sync> Send for GcOnce<T> {}
+
+impl<T> GcOnce<T> {
+    pub const fn new() -> Self {
+        Self { state: AtomicU8::new(UNINIT), value: UnsafeCell::new(MaybeUninit::uninit()) }
+    }
+    
+    /// Returns the value, if it has been initialized.
+    pub fn get(&self) -> Option<Gc<T>> {
+        if self.state.load(Ordering::Acquire) != DONE {
+            return None
+        }
+        // SAFETY: the value was written before `state` was set to `DONE`, and never changes after
+        Some(unsafe { (*self.value.get()).assume_init() })
+    }
+    
+    /// Returns the value, moving the result of `f` into the GC heap first if it hasn't been initialized yet.
+    /// 
+    /// If another thread is already initializing it, this blocks until it's done. If `f` panics,
+    /// the `GcOnce` stays uninitialized, and the next access tries again.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> Gc<T> where T: Send {
+        /// Puts the state back if the initializer panics, so that waiting threads don't spin forever.
+        struct ResetOnPanic<'a>(&'a AtomicU8);
+        impl Drop for ResetOnPanic<'_> {
+            fn drop(&mut self) {
+                self.0.store(UNINIT, Ordering::Release);
+            }
+        }
+        
+        loop {
+            if let Some(value) = self.get() {
+                return value
+            }
+            
+            match self.state.compare_exchange(UNINIT, RUNNING, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    let guard = ResetOnPanic(&self.state);
+                    let value = Gc::new(f());
+                    std::mem::forget(guard);
+                    
+                    // SAFETY: we're the only ones who could have moved `state` to `RUNNING`,
+                    //         and nobody reads the value until it's `DONE`
+                    unsafe { (*self.value.get()).write(value) };
+                    self.state.store(DONE, Ordering::Release);
+                    return value
+                }
+                // somebody else is initializing it, so just wait for them
+                Err(RUNNING) => std::thread::yield_now(),
+                // either it just got finished, or the initializer panicked, so try again
+                Err(_) => (),
+            }
+        }
+    }
+}
+
+impl<T> Default for GcOnce<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Debug> Debug for GcOnce<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("GcOnce").field(&*value).finish(),
+            None => f.write_str("GcOnce(<uninit>)"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Barrier;
+    
+    static X: GcOnce<Vec<i32>> = GcOnce::new();
+    static NUM_INITS: AtomicUsize = AtomicUsize::new(0);
+    
+    #[test]
+    fn test_concurrent_init() {
+        const THREADS: usize = 8;
+        
+        assert!(X.get().is_none());
+        
+        let barrier = Barrier::new(THREADS);
+        let values: Vec<Gc<Vec<i32>>> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..THREADS).map(|_| s.spawn(|| {
+                barrier.wait();
+                X.get_or_init(|| {
+                    NUM_INITS.fetch_add(1, Ordering::Relaxed);
+                    // make it more likely that the other threads have to wait
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    vec![1, 2, 3]
+                })
+            })).collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        
+        assert_eq!(NUM_INITS.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|v| Gc::ptr_eq(v, &values[0])));
+        drop(values);
+        
+        // the static should keep it alive on its own
+        crate::gc::allocator::GC_ALLOCATOR.wait_for_gc();
+        crate::gc::allocator::GC_ALLOCATOR.wait_for_gc();
+        assert_eq!(*X.get().unwrap(), [1, 2, 3]);
+    }
+}
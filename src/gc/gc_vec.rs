@@ -0,0 +1,152 @@
+//! A growable array whose buffer lives in the GC heap.
+
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+
+use super::allocator::{GCAllocator, GC_ALLOCATOR};
+
+
+/// A growable, contiguous array, allocated in the GC heap.
+/// 
+/// This is basically just a `Vec<T, &GCAllocator>`. The difference from a regular [`Vec`] is that
+/// the buffer lives in the GC heap, so the collector frees it once nothing points to it anymore,
+/// instead of it needing an owner to drop it. Like anything else in the GC heap, the collector also
+/// traces through it, so any [`Gc`]s in it stay alive.
+/// 
+/// Unlike [`GcMut<[T]>`](super::GcMut), this can grow after it has been allocated.
+/// 
+/// [`Gc`]: super::Gc
+pub struct GcVec<T>(Vec<T, &'static GCAllocator>);
+
+impl<T> GcVec<T> {
+    /// Constructs a new, empty `GcVec<T>`. This doesn't allocate until elements are pushed onto it.
+    pub fn new() -> Self {
+        Self(Vec::new_in(&*GC_ALLOCATOR))
+    }
+    
+    /// Constructs a new, empty `GcVec<T>` with at least the specified capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity_in(capacity, &*GC_ALLOCATOR))
+    }
+    
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+    
+    /// Appends an element to the end of the vector, reallocating (in the GC heap) if it is full.
+    pub fn push(&mut self, value: T) {
+        self.0.push(value)
+    }
+    
+    pub fn pop(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+    
+    /// Inserts an element at `index`, shifting everything after it to the right.
+    /// 
+    /// # Panics
+    /// If `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        self.0.insert(index, value)
+    }
+    
+    /// Removes and returns the element at `index`, shifting everything after it to the left.
+    /// 
+    /// # Panics
+    /// If `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        self.0.remove(index)
+    }
+    
+    /// Splits the vector in two at `at`, returning everything from `at` onwards in a new `GcVec`.
+    /// 
+    /// # Panics
+    /// If `at > len`.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        Self(self.0.split_off(at))
+    }
+    
+    /// Moves every element of `other` onto the end of this vector, leaving `other` empty.
+    pub fn append(&mut self, other: &mut Self) {
+        self.0.append(&mut other.0)
+    }
+}
+
+impl<T> Default for GcVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for GcVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for GcVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> FromIterator<T> for GcVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = Self::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T> Extend<T> for GcVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+impl<T: Debug> Debug for GcVec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <[T] as Debug>::fmt(self, f)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::Gc;
+    
+    #[test]
+    fn test_collect_and_push() {
+        const N: usize = 100;
+        
+        let mut vec: GcVec<usize> = (0..N).collect();
+        assert_eq!(vec.len(), N);
+        let capacity = vec.capacity();
+        
+        // push past the initial capacity, so it has to reallocate
+        vec.extend(N..capacity + 1);
+        vec.push(capacity + 1);
+        assert!(vec.capacity() > capacity);
+        assert!(GC_ALLOCATOR.contains(vec.as_ptr()));
+        assert!(vec.iter().copied().eq(0..capacity + 2));
+    }
+    
+    /// Makes sure that `Gc`s that are only reachable through the buffer aren't collected
+    #[test]
+    fn test_buffer_is_traced() {
+        let vec: GcVec<Gc<String>> = (0..20).map(|i| Gc::new(i.to_string())).collect();
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        assert!(vec.iter().enumerate().all(|(i, s)| **s == i.to_string()));
+    }
+}
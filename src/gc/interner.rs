@@ -0,0 +1,87 @@
+//! String interning, on top of [`Gc<str>`].
+
+use crate::concurrent_hashmap::ConcurrentHashMap;
+
+use super::Gc;
+
+
+/// Maps strings to a canonical [`Gc<str>`], so that equal strings always get the same allocation.
+/// 
+/// This means interned strings can be compared with [`Gc::ptr_eq`] instead of comparing every byte.
+/// 
+/// The map itself lives in the process heap, which the collector scans as a root, so interned
+/// strings are never collected while the interner is alive.
+// TODO: a weak-value version of this (i.e: a `WeakMap<K, V>` whose entries go away once nothing
+//       else points to the value) needs weak `Gc`s first. the collector would have to skip them
+//       while scanning (e.g: by storing the address inverted), blocks would need a generation
+//       count so a reused block isn't mistaken for the old object, and upgrading would have to
+//       wait out any sweep that's in progress, since the object might already be marked as dead.
+#[derive(Default)]
+pub struct Interner {
+    strings: ConcurrentHashMap<Box<str>, Gc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self { strings: ConcurrentHashMap::new() }
+    }
+    
+    /// Returns the canonical `Gc<str>` for `s`, copying it into the GC heap if it hasn't been interned yet.
+    /// 
+    /// If multiple threads intern the same string at the same time, they all get the same handle.
+    pub fn intern(&self, s: &str) -> Gc<str> {
+        // fast path, so we don't have to allocate a key every time
+        if let Some(interned) = self.strings.get(s) {
+            return interned
+        }
+        self.strings.get_or_insert_with(s.into(), || Gc::from_str(s))
+    }
+    
+    /// Returns the canonical `Gc<str>` for `s`, if it has already been interned.
+    pub fn get(&self, s: &str) -> Option<Gc<str>> {
+        self.strings.get(s)
+    }
+    
+    /// The number of distinct strings that have been interned.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::allocator::GC_ALLOCATOR;
+    
+    #[test]
+    fn test_intern_concurrent() {
+        const WORDS: [&str; 4] = ["hello", "world", "foo", "bar"];
+        
+        let interner = Interner::new();
+        let handles: Vec<Vec<Gc<str>>> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8).map(|_| s.spawn(|| {
+                WORDS.iter().map(|w| interner.intern(&w.to_string())).collect::<Vec<_>>()
+            })).collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        
+        assert_eq!(interner.len(), WORDS.len());
+        for (i, word) in WORDS.iter().enumerate() {
+            let canonical = interner.get(word).unwrap();
+            assert_eq!(&*canonical, *word);
+            assert!(handles.iter().all(|h| Gc::ptr_eq(&h[i], &canonical)));
+        }
+        
+        // the interner should keep the strings alive on its own
+        drop(handles);
+        GC_ALLOCATOR.wait_for_gc();
+        GC_ALLOCATOR.wait_for_gc();
+        assert!(WORDS.iter().all(|w| *interner.intern(w) == **w));
+        assert_eq!(interner.len(), WORDS.len());
+    }
+}
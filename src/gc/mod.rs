@@ -2,7 +2,21 @@
 pub mod allocator;
 
 mod smart_pointers;
+mod gc_cell;
+mod gc_vec;
+mod gc_btree_map;
+mod gc_once;
+mod gc_debug;
+mod rooted;
+mod interner;
 
 // re-export the `Gc` and `GcMut` smart pointers, they are the main API to use
 pub use smart_pointers::{Gc, GcMut};
+pub use gc_cell::GcCell;
+pub use gc_vec::GcVec;
+pub use gc_btree_map::GcBTreeMap;
+pub use gc_once::GcOnce;
+pub use gc_debug::GcDebug;
+pub use rooted::Rooted;
+pub use interner::Interner;
 
@@ -0,0 +1,174 @@
+//! A guard that explicitly roots a [`Gc`] for as long as it's alive.
+
+use std::alloc::{AllocError, Allocator, Layout};
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::ptr::NonNull;
+use std::sync::OnceLock;
+
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::Memory::{HeapAlloc, HeapCreate, HeapFree};
+
+use super::allocator::{RootHandle, GC_ALLOCATOR};
+use super::Gc;
+
+
+/// A [`Gc<T>`] that is registered as a root with the collector until this is dropped.
+/// 
+/// Normally, a `Gc` only stays alive because the collector conservatively finds it somewhere,
+/// like on the stack or in a register. That's usually fine, but the optimizer is free to keep
+/// the only copy of a pointer somewhere the collector doesn't look (or to get rid of it entirely
+/// once it thinks it's dead, even if something derived from it is still in use). This puts the
+/// `Gc` in its own slot, and registers that slot with [`GCAllocator::register_root`], so it gets
+/// treated as a root no matter what happens to it in registers.
+/// 
+/// NOTE: the slot lives in a private heap, since the collector already scans the process heap.
+/// if it were just a `Box`, registering it wouldn't do anything.
+/// 
+/// Usually made with the [`root!`](crate::root) macro.
+/// 
+/// [`GCAllocator::register_root`]: super::allocator::GCAllocator::register_root
+pub struct Rooted<T: ?Sized + 'static> {
+    // NOTE: this has to be dropped before `slot`, so the memory is never freed while it's still registered
+    _handle: RootHandle,
+    slot: Box<Gc<T>, SlotHeap>,
+}
+
+impl<T: ?Sized> Rooted<T> {
+    /// Roots `gc` until the returned guard is dropped.
+    pub fn new(gc: Gc<T>) -> Self {
+        let slot = Box::new_in(gc, SlotHeap::get());
+        // SAFETY: the box is pointer aligned, and doesn't get freed until after the handle is dropped
+        let handle = unsafe { GC_ALLOCATOR.register_root(std::ptr::from_ref(&*slot).cast(), size_of::<Gc<T>>()) };
+        Self { _handle: handle, slot }
+    }
+    
+    /// The rooted `Gc`.
+    /// 
+    /// NOTE: the returned copy isn't rooted by itself, but the guard still keeps the value alive.
+    pub fn get(&self) -> Gc<T> {
+        *self.slot
+    }
+}
+
+impl<T: ?Sized> Deref for Rooted<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.slot
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for Rooted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Rooted").field(&*self.slot).finish()
+    }
+}
+
+/// A private Win32 heap that the [`Rooted`] slots are allocated in.
+/// 
+/// The collector only scans the default process heap, so anything in here only gets found
+/// through the roots that are registered for it.
+#[derive(Clone, Copy)]
+struct SlotHeap(HANDLE);
+
+// SAFETY: Win32 heaps are internally synchronized (unless they're made with `HEAP_NO_SERIALIZE`)
+unsafe impl Send for SlotHeap {}
+unsafe impl Sync for SlotHeap {}
+
+impl SlotHeap {
+    fn get() -> Self {
+        static HEAP: OnceLock<SlotHeap> = OnceLock::new();
+        *HEAP.get_or_init(|| {
+            let handle = unsafe { HeapCreate(0, 0, 0) };
+            assert!(!handle.is_null(), "couldn't create the heap for `Rooted` slots");
+            SlotHeap(handle)
+        })
+    }
+}
+
+unsafe impl Allocator for SlotHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // NOTE: `HeapAlloc` is always aligned to `MEMORY_ALLOCATION_ALIGNMENT` (i.e: two words), which is plenty for a `Gc`
+        if layout.align() > 2 * size_of::<usize>() {
+            return Err(AllocError)
+        }
+        let ptr = NonNull::new(unsafe { HeapAlloc(self.0, 0, layout.size()) }.cast::<u8>()).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+    
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        unsafe { HeapFree(self.0, 0, ptr.as_ptr().cast()) };
+    }
+}
+
+/// Explicitly roots a [`Gc`] for the rest of the scope, returning a [`Rooted`](crate::gc::Rooted) guard.
+/// 
+/// ```ignore
+/// let value = root!(Gc::new(vec![1, 2, 3]));
+/// GC_ALLOCATOR.wait_for_gc();
+/// assert_eq!(*value, [1, 2, 3]);
+/// ```
+#[macro_export]
+macro_rules! root {
+    ($gc:expr) => {
+        $crate::gc::Rooted::new($gc)
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+    
+    struct DropCounter(usize);
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    /// Makes a `Gc` whose only copy ends up in the returned guard.
+    #[inline(never)]
+    fn make_rooted(value: usize) -> Rooted<DropCounter> {
+        root!(Gc::new(DropCounter(value)))
+    }
+    
+    #[test]
+    fn test_rooted_survives_collection() {
+        let rooted = make_rooted(1234);
+        
+        // keep a bunch of unrelated values live across the collection, so the registers are busy
+        let mut noise = std::hint::black_box([0usize; 32]);
+        for round in 0..4 {
+            for (i, n) in noise.iter_mut().enumerate() {
+                *n = std::hint::black_box(n.wrapping_mul(31).wrapping_add(i ^ round));
+            }
+            GC_ALLOCATOR.wait_for_gc();
+        }
+        std::hint::black_box(noise);
+        
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
+        assert_eq!(rooted.0, 1234);
+        assert_eq!(rooted.get().0, 1234);
+        
+        drop(rooted);
+    }
+    
+    #[test]
+    fn test_rooted_slot_is_registered() {
+        use crate::gc::allocator::RootLocation;
+        
+        let rooted = make_rooted(5678);
+        let slot = std::ptr::from_ref(&*rooted.slot).cast::<()>();
+        let roots = GC_ALLOCATOR.find_roots_to(rooted.get().as_ptr().cast());
+        
+        // the slot isn't anywhere the collector would look on its own, so the registration is what keeps this alive
+        assert!(roots.contains(&RootLocation::RegisteredRoot { address: slot }), "the slot wasn't registered (found {roots:016x?})");
+        assert!(!roots.contains(&RootLocation::ProcessHeap { address: slot }), "the slot is in the process heap (found {roots:016x?})");
+        
+        drop(rooted);
+    }
+}
@@ -7,11 +7,13 @@
 use std::alloc::{Allocator, Layout};
 use std::fmt::{Debug, Display};
 use std::marker::{PhantomData, Unsize};
-use std::mem::MaybeUninit;
+use std::mem::{ManuallyDrop, MaybeUninit};
 use std::ops::{CoerceUnsized, Deref, DerefPure, DispatchFromDyn};
 use std::ptr::{NonNull, Unique};
+use std::sync::Arc;
 
-use super::allocator::{GCAllocatorError, GC_ALLOCATOR};
+use super::allocator::{GCAllocatorError, RootLocation, GC_ALLOCATOR};
+use super::gc_debug::{debug_tracked, GcDebug};
 
 
 /// Shared access to Garbage Collected (GCed) memory.
@@ -67,6 +69,7 @@ impl<T: ?Sized> Gc<T> {
     /// Moves a value into GCed memory.
     /// 
     /// Requires `T: Send` since the GC thread will gain ownership of the value in order to drop it.
+    #[track_caller]
     pub fn new(value: T) -> Self where T: Sized + Send {
         let inner = super::allocator::GC_ALLOCATOR.allocate_for_value(value).map_err(|(e, _)| e).unwrap();
         // Casting is okay here because we just initialized the data
@@ -86,6 +89,52 @@ impl<T: ?Sized> Gc<T> {
         Self(ptr, PhantomData)
     }
     
+    /// Constructs a new `Gc<T>` from a pointer to `T`, if it actually points to a GC allocation.
+    /// 
+    /// This is the checked version of [`from_ptr`](Self::from_ptr), for when it isn't known for
+    /// sure where a pointer came from (like one that went through FFI, or got deserialized).
+    /// It returns `Some` only if `value` is the start of a block that is currently allocated in
+    /// the GC heap, and that block is big enough to hold a `T`. Pointers into the middle of an
+    /// allocation aren't accepted, since every `Gc` has to point to the start of one. Zero-sized
+    /// values aren't in the heap at all, so for those, `value` just has to be non-null and aligned.
+    /// 
+    /// NOTE: this can't check that the allocation actually holds a `T`, only that the memory is
+    /// live and big enough, which is why it's still `unsafe`. Also, the memory is only kept alive
+    /// if the collector can see `value` somewhere, like with any other pointer into the heap.
+    /// 
+    /// # Safety
+    /// If `value` is the start of a live allocation in the GC heap that's big enough for a `T`:
+    ///  - the allocation has to hold a valid `T` (usually because it was allocated as one)
+    ///  - there can't be a [`GcMut`] (or any other mutable reference) to it
+    pub unsafe fn try_from_raw(value: *const T) -> Option<Self> where T: Sized {
+        if !value.is_aligned() {
+            return None
+        }
+        if size_of::<T>() == 0 {
+            return NonNull::new(value.cast_mut()).map(|ptr| Self(ptr, PhantomData))
+        }
+        if !GC_ALLOCATOR.contains(value) || !GC_ALLOCATOR.is_allocation_start(value.cast(), size_of::<T>()) {
+            return None
+        }
+        // SAFETY: `value` is the start of a live GC allocation with a `T` in it, and nothing can mutate it (gauranteed by caller)
+        Some(unsafe { Self::from_ptr(value) })
+    }
+    
+    /// Makes a `Gc` that doesn't point to anything, for use as a placeholder.
+    /// 
+    /// This is the `Gc` version of [`NonNull::dangling`] (which is also what the allocator hands
+    /// out for zero-sized values). It's meant for data structures that need *some* `Gc` in a slot
+    /// before the real one is known, like a node that hasn't been linked in yet, without having
+    /// to deal with an `Option<Gc<T>>` everywhere. It doesn't point into the GC heap, so it
+    /// never keeps anything alive.
+    /// 
+    /// # Safety
+    /// Unless `T` is zero-sized, the returned `Gc` must be overwritten with a real one before it
+    /// gets dereferenced in any way. (including through things like [`Debug`] or [`PartialEq`])
+    pub const unsafe fn dangling() -> Self where T: Sized {
+        Self(NonNull::dangling(), PhantomData)
+    }
+    
     /// Promotes the shared pointer into an exclusive pointer.
     /// 
     /// # SAFETY
@@ -94,6 +143,71 @@ impl<T: ?Sized> Gc<T> {
         unsafe { GcMut::from_nonnull_ptr(self.0) }
     }
     
+    /// Promotes the shared pointer into an exclusive pointer, if there aren't any other pointers to the value.
+    /// 
+    /// This stops the world and scans everything the collector would (every thread's stack and
+    /// registers, the heaps, and static memory) for pointers to the value. If nothing else points
+    /// to it, nothing else can ever use it again, so this is safe. Otherwise, `self` is returned
+    /// back unchanged. This is basically the GC version of [`Arc::try_unwrap`].
+    /// 
+    /// NOTE: since the scan is conservative, any leftover copy of the pointer (even one that isn't
+    /// used anymore, like a variable that `self` was moved out of) makes this fail. In particular,
+    /// unoptimized builds tend to leave those around on the caller's stack.
+    pub fn try_into_unique(self) -> Result<GcMut<T>, Gc<T>> {
+        if !GC_ALLOCATOR.contains(self.as_ptr()) {
+            // zero-sized values aren't in the heap, so there's nothing to check
+            return Err(self)
+        }
+        
+        let metadata = std::ptr::metadata(self.as_ptr());
+        // SAFETY: `self` points to the start of an allocated block, since it keeps it alive
+        let header = std::hint::black_box(unsafe { GC_ALLOCATOR.block_header(self.0.cast()) });
+        // NOTE: while scanning, the only pointer to the data is kept in a box, so that it can be
+        //       told apart from every other one. the collector still scans the box, so the block
+        //       can't get freed in the meantime. pointers directly to the header don't count.
+        let pin = Box::new(self.0.cast::<()>());
+        let pin_address = std::ptr::from_ref(&*pin).cast::<()>();
+        
+        // SAFETY: the block is still allocated, since `pin` keeps it alive
+        let roots = unsafe { GC_ALLOCATOR.find_roots_to_header(header) };
+        let is_unique = roots.iter().all(|root| matches!(root, RootLocation::ProcessHeap { address } if *address == pin_address));
+        let ptr = NonNull::from_raw_parts(*pin, metadata);
+        drop(pin);
+        
+        if is_unique {
+            // SAFETY: nothing else points to the value, so this is the only `Gc` to it
+            Ok(unsafe { GcMut::from_nonnull_ptr(ptr) })
+        } else {
+            Err(Self(ptr, PhantomData))
+        }
+    }
+    
+    /// Gets a mutable reference to the value, without checking that nothing else can see it.
+    /// 
+    /// This is the `Gc` version of [`Arc::get_mut_unchecked`]. Usually, [`GcCell`](super::GcCell)
+    /// or [`try_into_unique`](Self::try_into_unique) are what you want instead, but this is for
+    /// when uniqueness has already been established some other way (like the value having just
+    /// been allocated, and the `Gc` never having been copied anywhere).
+    /// 
+    /// In debug builds, this checks that the pointer is actually to a live allocation in the GC
+    /// heap (or to a zero-sized value). Nothing checks for other pointers to it, though.
+    /// 
+    /// # Safety
+    ///  - no other `Gc`s (or any other pointers) to the value can be dereferenced while the
+    ///    returned reference is alive, *including* ones reachable from other threads, or from
+    ///    inside the value itself.
+    ///  - the returned reference can't outlive `self`. (the borrow checker only enforces this
+    ///    for this particular `Gc`, not for copies of it)
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut_unchecked(&self) -> &mut T {
+        debug_assert!(
+            size_of_val(&**self) == 0 || GC_ALLOCATOR.contains(self.as_ptr()),
+            "`Gc` at {:016x?} doesn't point into the GC heap", self.as_ptr()
+        );
+        // SAFETY: gauranteed by caller
+        unsafe { &mut *self.0.as_ptr() }
+    }
+    
     /// Runs the destructor of the referenced value, and frees the memory.
     /// 
     /// # SAFETY
@@ -114,13 +228,163 @@ impl<T: ?Sized> Gc<T> {
         self.0
     }
     
+    /// Moves the value out of an [`Arc`] and into GCed memory.
+    /// 
+    /// If `arc` is the only strong reference to the value, it gets moved without being cloned.
+    /// Otherwise, the value is cloned, and the other `Arc`s keep the original.
+    pub fn from_arc(arc: Arc<T>) -> Self where T: Sized + Send + Clone {
+        Self::new(Arc::try_unwrap(arc).unwrap_or_else(|arc| T::clone(&arc)))
+    }
+    
+    /// Clones the value into a new [`Arc`].
+    /// 
+    /// NOTE: this always has to clone, since other `Gc`s might still be pointing to the value.
+    /// To move a value out of the GC heap instead, use [`GcMut::into_arc`].
+    pub fn to_arc(self) -> Arc<T> where T: Sized + Clone {
+        Arc::new(T::clone(&self))
+    }
+    
+    /// Whether two `Gc`s point to the same allocation, like [`std::ptr::addr_eq`].
+    /// 
+    /// Any metadata (like slice lengths or vtables) is ignored.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        std::ptr::addr_eq(this.as_ptr(), other.as_ptr())
+    }
+    
+    /// The number of bytes in the GC heap that back this value.
+    /// 
+    /// This can be more than `size_of_val(&*self)` because of alignment padding and blocks
+    /// that weren't worth splitting. Zero sized values don't take up any space in the heap.
+    pub fn allocated_size(&self) -> usize {
+        if !GC_ALLOCATOR.contains(self.as_ptr()) {
+            return 0
+        }
+        // SAFETY: `Gc`s always point to the start of a live allocation
+        unsafe { GC_ALLOCATOR.block_size(self.0.cast()) }
+    }
+    
+    /// Formats the value with [`Debug`], printing a marker instead of recursing forever if it points back to itself.
+    /// 
+    /// See [`GcDebug`].
+    pub fn debug_cyclic(&self) -> GcDebug<'_, Self> where T: Debug {
+        GcDebug::new(self)
+    }
+}
+
+impl Gc<str> {
+    /// Copies a string into GCed memory, in a single allocation.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        // SAFETY: nothing else has a pointer to the new allocation
+        unsafe { Self::from_ptr(allocate_str(s).as_ptr()) }
+    }
+}
+
+impl<T, const N: usize> Gc<[T; N]> {
+    /// Makes an array in GCed memory, where each element is `f` of its index.
+    /// 
+    /// Unlike `Gc::new(std::array::from_fn(f))`, this writes the elements straight into the GC
+    /// heap, so the whole array never has to fit on the stack. If `f` panics, the elements that
+    /// were already made get dropped, and the allocation gets freed.
+    /// 
+    /// See [`std::array::from_fn`].
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::gc::Gc;
+    /// 
+    /// let x: Gc<[usize; 5]> = Gc::from_fn(|i| i * i);
+    /// assert_eq!(*x, [0, 1, 4, 9, 16]);
+    /// ```
+    #[track_caller]
+    pub fn from_fn(f: impl FnMut(usize) -> T) -> Self where T: Send {
+        Self(allocate_array(f), PhantomData)
+    }
+}
+
+impl<T> Gc<[T]> {
+    /// Converts a GCed slice back into a GCed array, if it has exactly `N` elements.
+    /// 
+    /// Going the other way is just an unsizing coercion. See `TryFrom<Box<[T]>>` for `Box<[T; N]>`.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::gc::Gc;
+    /// 
+    /// let x: Gc<[i32]> = Gc::new([1, 2, 3]);
+    /// assert_eq!(*x.try_into_array::<3>().unwrap(), [1, 2, 3]);
+    /// assert!(x.try_into_array::<2>().is_err());
+    /// ```
+    pub fn try_into_array<const N: usize>(self) -> Result<Gc<[T; N]>, Gc<[T]>> {
+        if self.len() != N { return Err(self) }
+        // SAFETY: it's the same allocation, and it holds exactly `N` `T`s
+        Ok(unsafe { Gc::from_ptr(self.as_ptr().cast()) })
+    }
+}
+
+/// Allocates an array in the GC heap, and fills it in place with `f` of each index.
+#[track_caller]
+fn allocate_array<T, const N: usize>(mut f: impl FnMut(usize) -> T) -> NonNull<[T; N]> {
+    /// Drops whatever has been written so far, and frees the allocation, if `f` panics.
+    struct Guard<T> {
+        data: NonNull<T>,
+        initialized: usize,
+        layout: Layout,
+    }
+    
+    impl<T> Drop for Guard<T> {
+        fn drop(&mut self) {
+            // SAFETY: the first `initialized` elements have been written, and nothing else has a pointer to them
+            unsafe { NonNull::slice_from_raw_parts(self.data, self.initialized).drop_in_place() };
+            if self.layout.size() != 0 {
+                // SAFETY: the allocation came from `GC_ALLOCATOR` with this layout, and never got shared
+                unsafe { GC_ALLOCATOR.deallocate(self.data.cast(), self.layout) };
+            }
+        }
+    }
+    
+    let layout = Layout::new::<[T; N]>();
+    // NOTE: the allocator doesn't do zero sized allocations, but `f` still has to be called for each element
+    let data = if layout.size() == 0 { NonNull::dangling() } else {
+        GC_ALLOCATOR.allocate(layout).unwrap_or_else(|_| std::alloc::handle_alloc_error(layout)).cast::<[T; N]>()
+    };
+    
+    // NOTE: the block doesn't have a destructor yet, so if this gets interrupted, the collector won't drop anything
+    let mut guard = Guard { data: data.cast::<T>(), initialized: 0, layout };
+    for i in 0..N {
+        // SAFETY: `i` is in bounds, and nothing has been written there yet
+        unsafe { guard.data.add(i).write(f(i)) };
+        guard.initialized += 1;
+    }
+    std::mem::forget(guard);
+    
+    if layout.size() != 0 {
+        // SAFETY: nothing else has a pointer to the new allocation, and every element was just written
+        unsafe { GC_ALLOCATOR.set_destructor(data) };
+    }
+    data
+}
+
+/// Allocates a copy of `s` in the GC heap.
+fn allocate_str(s: &str) -> NonNull<str> {
+    if s.is_empty() {
+        return NonNull::from_raw_parts(NonNull::<u8>::dangling(), 0)
+    }
+    
+    let layout = Layout::for_value(s);
+    let data = GC_ALLOCATOR.allocate(layout).unwrap_or_else(|_| std::alloc::handle_alloc_error(layout));
+    // SAFETY: we just allocated enough room for `s`
+    unsafe { data.cast::<u8>().copy_from_nonoverlapping(NonNull::from(s).cast(), s.len()) };
+    NonNull::from_raw_parts(data.cast::<u8>(), s.len())
 }
 
 // std trait impls
 
+/// This just formats the value, so it recurses forever if the value has a cycle back to itself.
+/// Use [`Gc::debug_cyclic`] for those.
 impl<T: ?Sized + Debug> Debug for Gc<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        <T as Debug>::fmt(self, f)
+        debug_tracked(self.as_ptr().cast(), f, |f| <T as Debug>::fmt(self, f))
     }
 }
 
@@ -138,7 +402,7 @@ impl<T: ?Sized + std::fmt::Pointer> std::fmt::Pointer for Gc<T> {
 
 impl<T: ?Sized + PartialEq> PartialEq for Gc<T> {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        **self == **other
     }
 }
 
@@ -162,6 +426,13 @@ impl<T: ?Sized + std::hash::Hash> std::hash::Hash for Gc<T> {
     }
 }
 
+// NOTE: `Eq`, `Ord`, and `Hash` all go through to the value, so this is fine for map keys
+impl<T: ?Sized> std::borrow::Borrow<T> for Gc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
 
 /// Exclusive access to Garbage-collected memory.
 /// 
@@ -192,6 +463,9 @@ unsafe impl<T: ?Sized + Sync> Sync for GcMut<T> {}
 // SAFETY: the implementation of `Deref for GcMut<T>` is "well-behaved" by any/all definitions
 unsafe impl<T: ?Sized> DerefPure for GcMut<T> {}
 
+// NOTE: the value never moves, even if the `GcMut` does (just like `Box`)
+impl<T: ?Sized> Unpin for GcMut<T> {}
+
 impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<GcMut<U>> for GcMut<T> {}
 impl<T: ?Sized + Unsize<U>, U: ?Sized> DispatchFromDyn<GcMut<U>> for GcMut<T> {}
 
@@ -212,6 +486,7 @@ impl<T: ?Sized> std::ops::DerefMut for GcMut<T> {
 
 impl<T: ?Sized> GcMut<T> {
     /// Moves a value into GCed memory.
+    #[track_caller]
     pub fn new(value: T) -> Self where T: Sized {
         match Self::try_new(value) {
             Err((e, _value)) => panic!("{:?}", e),
@@ -251,6 +526,17 @@ impl<T: ?Sized> GcMut<T> {
         self.0.as_non_null_ptr()
     }
     
+    /// The number of bytes in the GC heap that back this value.
+    /// 
+    /// See [`Gc::allocated_size`].
+    pub fn allocated_size(&self) -> usize {
+        if !GC_ALLOCATOR.contains(self.as_ptr()) {
+            return 0
+        }
+        // SAFETY: we own the allocation, so it is definitely still alive
+        unsafe { GC_ALLOCATOR.block_size(self.as_non_null_ptr().cast()) }
+    }
+    
     /// Constructs a new `GcMut<T>` from a pointer to `T`.
     /// 
     /// # Safety
@@ -267,6 +553,25 @@ impl<T: ?Sized> GcMut<T> {
         Self(value.into())
     }
     
+    /// Moves the value out of an [`Arc`] and into GCed memory.
+    /// 
+    /// See [`Gc::from_arc`].
+    pub fn from_arc(arc: Arc<T>) -> Self where T: Sized + Clone {
+        Self::new(Arc::try_unwrap(arc).unwrap_or_else(|arc| T::clone(&arc)))
+    }
+    
+    /// Moves the value out of GCed memory and into a new [`Arc`], freeing the allocation.
+    pub fn into_arc(self) -> Arc<T> where T: Sized {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: we own the value, and the allocation gets freed without dropping it
+        let value = unsafe { this.0.as_ptr().read() };
+        if size_of::<T>() != 0 {
+            // SAFETY: nothing else can be pointing to the allocation, since we owned it
+            unsafe { GC_ALLOCATOR.deallocate(this.0.as_non_null_ptr().cast(), Layout::new::<T>()) }
+        }
+        Arc::new(value)
+    }
+    
     /// Converts exclusive access into shared access.
     /// 
     /// `T` has to be `Send` since unlike a `GcMut`, the data's destructor will be run on the GC thread, and not this one.
@@ -278,6 +583,101 @@ impl<T: ?Sized> GcMut<T> {
         std::mem::forget(self);
         val
     }
+    
+    /// Converts exclusive access into shared access, without requiring `T: Send`.
+    /// 
+    /// The resulting `Gc<T>` is only [`Send`] if `T: Sync`, so for a type like
+    /// `Rc<U>`, it can't leave the current thread. However, since the collector can't
+    /// run the destructor on this thread (and running it on the GC thread would be
+    /// unsound), **the value's destructor is never run**, and it gets leaked once the
+    /// memory is collected. If `T: Send`, use [`demote`](Self::demote) instead.
+    pub fn demote_local(self) -> Gc<T> where T: 'static {
+        if GC_ALLOCATOR.contains(self.as_ptr()) {
+            // SAFETY: we own the allocation, so nobody else is touching its header
+            unsafe { GC_ALLOCATOR.forget_destructor(self.as_non_null_ptr().cast()) };
+        }
+        // SAFETY: `self.inner` is already GC-ed memory, and does not have any
+        //          other references to it (since we moved `self`)
+        let val = unsafe { Gc::from_ptr(self.0.as_ptr()) };
+        // prevent destructor from running
+        std::mem::forget(self);
+        val
+    }
+    
+    /// Runs the destructor of the value, but keeps the allocation around so it can be reused.
+    /// 
+    /// The returned `GcMut` points to the exact same block (so it has the same
+    /// [`allocated_size`](Self::allocated_size)), which makes this useful for reusing
+    /// allocations in hot loops without going through the allocator every time.
+    pub fn as_uninit_mut(self) -> GcMut<MaybeUninit<T>> where T: Sized {
+        let this = ManuallyDrop::new(self);
+        
+        // NOTE: this has to happen first, so that if the destructor panics, the collector
+        //       won't run it again once it collects the leaked allocation
+        if GC_ALLOCATOR.contains(this.as_ptr()) {
+            // SAFETY: we own the allocation, so nobody else is touching its header
+            unsafe { GC_ALLOCATOR.forget_destructor(this.as_non_null_ptr().cast()) };
+        }
+        
+        // SAFETY: we own the value, and it never gets used again
+        unsafe { std::ptr::drop_in_place(this.0.as_ptr()) };
+        GcMut(this.0.cast())
+    }
+}
+
+impl<T: ?Sized> GcMut<T> {
+    /// Whether dropping a `GcMut<T>` has to run any destructor before deallocating.
+    /// 
+    /// For types without drop glue (e.g: anything `Copy`), this lets `Drop` skip straight to
+    /// deallocation.
+    const NEEDS_DROP: bool = std::mem::needs_drop::<T>();
+}
+
+impl GcMut<str> {
+    /// Copies a string into GCed memory, in a single allocation.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        Self(allocate_str(s).into())
+    }
+}
+
+impl<T, const N: usize> GcMut<[T; N]> {
+    /// Makes an array in GCed memory, where each element is `f` of its index.
+    /// 
+    /// See [`Gc::from_fn`].
+    #[track_caller]
+    pub fn from_fn(f: impl FnMut(usize) -> T) -> Self {
+        Self(allocate_array(f).into())
+    }
+}
+
+impl<T> GcMut<[T]> {
+    /// Allocates an uninitialized slice of `len` elements in GCed memory.
+    /// 
+    /// See [`Box::new_uninit_slice`], and [`write_slice`](GcMut::write_slice) for filling it in.
+    /// 
+    /// NOTE: the collector's destructors don't know how long a slice is, so unlike a coerced
+    ///       array, the elements only get dropped if the `GcMut` itself gets dropped. (i.e: not
+    ///       if it gets [`demote`](GcMut::demote)d first)
+    #[track_caller]
+    pub fn new_uninit_slice(len: usize) -> GcMut<[MaybeUninit<T>]> {
+        let layout = Layout::array::<T>(len).expect("slice should fit in an `isize`");
+        // NOTE: the allocator doesn't do zero sized allocations
+        let data = if layout.size() == 0 { NonNull::dangling() } else {
+            GC_ALLOCATOR.allocate(layout).unwrap_or_else(|_| std::alloc::handle_alloc_error(layout)).cast::<MaybeUninit<T>>()
+        };
+        GcMut(NonNull::slice_from_raw_parts(data, len).into())
+    }
+    
+    /// Converts a GCed slice back into a GCed array, if it has exactly `N` elements.
+    /// 
+    /// See [`Gc::try_into_array`].
+    pub fn try_into_array<const N: usize>(self) -> Result<GcMut<[T; N]>, GcMut<[T]>> {
+        if self.len() != N { return Err(self) }
+        let this = ManuallyDrop::new(self);
+        // NOTE: it's the same allocation, so the destructor that the collector would run doesn't change
+        Ok(GcMut(this.0.cast()))
+    }
 }
 
 impl<T> GcMut<MaybeUninit<T>> {
@@ -287,7 +687,12 @@ impl<T> GcMut<MaybeUninit<T>> {
     /// 
     /// Same as [`Box::assume_init`]
     pub unsafe fn assume_init(self) -> GcMut<T> {
-        GcMut(self.0.cast())
+        let this = ManuallyDrop::new(self);
+        if GC_ALLOCATOR.contains(this.as_ptr()) {
+            // SAFETY: we own the allocation, and the caller promises it holds a valid `T` now
+            unsafe { GC_ALLOCATOR.set_destructor(this.0.as_non_null_ptr().cast::<T>()) };
+        }
+        GcMut(this.0.cast())
     }
     
     /// Writes a value into the [`MaybeUninit`], initializing it
@@ -299,13 +704,84 @@ impl<T> GcMut<MaybeUninit<T>> {
     }
 }
 
+impl<T> GcMut<[MaybeUninit<T>]> {
+    /// See [`Box::assume_init`]
+    /// 
+    /// # Safety
+    /// 
+    /// Every element has to be initialized.
+    pub unsafe fn assume_init(self) -> GcMut<[T]> {
+        let this = ManuallyDrop::new(self);
+        // NOTE: there's no destructor to set here (see `GcMut::new_uninit_slice`)
+        GcMut(NonNull::slice_from_raw_parts(this.0.as_non_null_ptr().cast::<T>(), this.len()).into())
+    }
+    
+    /// Copies every element of `src` into the slice, initializing it.
+    /// 
+    /// # Panics
+    /// If the two slices have different lengths.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use lockfree::gc::GcMut;
+    /// 
+    /// let x = GcMut::<[u8]>::new_uninit_slice(3).write_slice(b"abc");
+    /// assert_eq!(*x, *b"abc");
+    /// ```
+    #[track_caller]
+    pub fn write_slice(self, src: &[T]) -> GcMut<[T]> where T: Copy {
+        assert_eq!(self.len(), src.len(), "source slice length should match the destination");
+        // SAFETY: the lengths match, and `src` can't overlap with memory that we own
+        unsafe { self.as_non_null_ptr().cast::<T>().copy_from_nonoverlapping(NonNull::from(src).cast(), src.len()) };
+        // SAFETY: every element was just written
+        unsafe { self.assume_init() }
+    }
+    
+    /// Clones every element of `src` into the slice, initializing it.
+    /// 
+    /// If cloning some element panics, the ones that were already cloned get dropped, and the allocation gets freed.
+    /// 
+    /// # Panics
+    /// If the two slices have different lengths.
+    #[track_caller]
+    pub fn write_clone_of_slice(self, src: &[T]) -> GcMut<[T]> where T: Clone {
+        /// Drops whatever has been cloned so far, if a `clone` panics.
+        struct Guard<T> {
+            data: NonNull<T>,
+            initialized: usize,
+        }
+        
+        impl<T> Drop for Guard<T> {
+            fn drop(&mut self) {
+                // SAFETY: the first `initialized` elements have been written, and nothing else has a pointer to them
+                unsafe { NonNull::slice_from_raw_parts(self.data, self.initialized).drop_in_place() };
+            }
+        }
+        
+        assert_eq!(self.len(), src.len(), "source slice length should match the destination");
+        // NOTE: `self` still frees the allocation if this panics, since it's still a `GcMut`
+        let mut guard = Guard { data: self.as_non_null_ptr().cast::<T>(), initialized: 0 };
+        for (i, value) in src.iter().enumerate() {
+            // SAFETY: `i` is in bounds, and nothing has been written there yet
+            unsafe { guard.data.add(i).write(value.clone()) };
+            guard.initialized += 1;
+        }
+        std::mem::forget(guard);
+        
+        // SAFETY: every element was just written
+        unsafe { self.assume_init() }
+    }
+}
+
 unsafe impl<#[may_dangle] T: ?Sized> Drop for GcMut<T> {
     fn drop(&mut self) {
         // SAFETY: T must be sized on construction, so even if we have been coerced to unsized, its still valid
         let inner_layout = unsafe { Layout::for_value_raw(self.0.as_ptr()) };
         
-        // Drop the inner `T`
-        unsafe { std::ptr::drop_in_place(self.0.as_ptr()) };
+        // Drop the inner `T` (if it even has any drop glue)
+        if Self::NEEDS_DROP {
+            unsafe { std::ptr::drop_in_place(self.0.as_ptr()) };
+        }
         
         if inner_layout.size() != 0 {
             // SAFETY: if we get here, the GC can definitely free this allocation
@@ -337,7 +813,7 @@ impl<T: ?Sized + std::fmt::Pointer> std::fmt::Pointer for GcMut<T> {
 
 impl<T: ?Sized + PartialEq> PartialEq for GcMut<T> {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        **self == **other
     }
 }
 
@@ -361,6 +837,48 @@ impl<T: ?Sized + std::hash::Hash> std::hash::Hash for GcMut<T> {
     }
 }
 
+impl<T: ?Sized> std::borrow::Borrow<T> for GcMut<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> std::borrow::BorrowMut<T> for GcMut<T> {
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+/// Promotes a `Gc<T>` if nothing else points to its value, handing it back otherwise.
+/// 
+/// This is just [`Gc::try_into_unique`], so the same caveats apply: the check is conservative,
+/// so any leftover copy of the pointer (like one that a value was moved out of) makes it fail.
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// use lockfree::gc::{Gc, GcMut};
+/// 
+/// // `y` can still be used, so `x` can't be promoted
+/// let x = Gc::new(vec![1, 2, 3]);
+/// let y = x;
+/// let x: Gc<Vec<i32>> = GcMut::try_from(x).unwrap_err();
+/// assert!(Gc::ptr_eq(&x, &y));
+/// 
+/// // nothing else points to this one, so it can be (unless a copy got left behind somewhere)
+/// match GcMut::try_from(Gc::new(vec![4, 5, 6])) {
+///     Ok(mut v) => v.push(7),
+///     Err(v) => assert_eq!(*v, [4, 5, 6]),
+/// }
+/// ```
+impl<T: ?Sized> TryFrom<Gc<T>> for GcMut<T> {
+    type Error = Gc<T>;
+    
+    fn try_from(value: Gc<T>) -> Result<Self, Self::Error> {
+        value.try_into_unique()
+    }
+}
+
 
 // tests
 
@@ -371,6 +889,44 @@ mod tests {
     
     use super::*;
     
+    #[test]
+    fn test_try_from_raw() {
+        // a live allocation, but only from its start, and only as something that fits in it
+        // SAFETY: nothing here is ever mutated, and the start of the array is a valid `u64`
+        let x = Gc::new([1u64, 2, 3, 4]);
+        let gc = unsafe { Gc::try_from_raw(x.as_ptr()) }.expect("should be a live allocation");
+        assert!(Gc::ptr_eq(&gc, &x));
+        assert_eq!(unsafe { Gc::try_from_raw(x.as_ptr().cast::<u64>()) }.map(|gc| *gc), Some(1));
+        assert!(unsafe { Gc::try_from_raw(x.as_ptr().cast::<u64>().add(1)) }.is_none());
+        assert!(unsafe { Gc::try_from_raw(x.as_ptr().cast::<[u64; 0x100]>()) }.is_none());
+        
+        // memory that isn't in the GC heap at all
+        // SAFETY: none of these are GC allocations
+        let local = [1u64, 2, 3, 4];
+        assert!(unsafe { Gc::try_from_raw(&raw const local) }.is_none());
+        let boxed = Box::new(5u64);
+        assert!(unsafe { Gc::try_from_raw(&raw const *boxed) }.is_none());
+        assert!(unsafe { Gc::try_from_raw(std::ptr::null::<u64>()) }.is_none());
+        
+        // zero-sized values don't need to be in the heap
+        // SAFETY: same as above
+        let unit = ();
+        assert!(unsafe { Gc::try_from_raw(&raw const unit) }.is_some());
+        assert!(unsafe { Gc::try_from_raw(std::ptr::null::<()>()) }.is_none());
+    }
+    
+    #[test]
+    fn test_try_from_raw_freed() {
+        let x = GcMut::new(0x1234u64);
+        let ptr = x.as_ptr();
+        drop(x);
+        
+        // the block might have been handed to the collector to free, instead of being freed right away
+        GC_ALLOCATOR.wait_for_gc();
+        // SAFETY: the block was freed, and nothing else on this thread has allocated since
+        assert!(unsafe { Gc::try_from_raw(ptr) }.is_none());
+    }
+    
     /// Tests multiple allocations through the GcMut interface
     #[test]
     fn test_multiple_gc_muts() {
@@ -383,6 +939,66 @@ mod tests {
         assert!(x.as_ptr().cast() < y.as_ptr() && y.as_ptr().cast() < z.as_ptr());
     }
     
+    #[test]
+    fn test_arc_round_trip() {
+        // the only reference, so nothing should get cloned
+        let arc = Arc::new(vec![1, 2, 3]);
+        let buffer = arc.as_ptr();
+        let x = Gc::from_arc(arc);
+        assert_eq!((*x).as_ptr(), buffer);
+        
+        let arc = x.to_arc();
+        assert_eq!(*arc, [1, 2, 3]);
+        assert_ne!(arc.as_ptr(), buffer);
+        
+        // the original `Arc` has to keep its value
+        let y = Gc::from_arc(Arc::clone(&arc));
+        assert_eq!(*y, *arc);
+        assert_ne!((*y).as_ptr(), arc.as_ptr());
+        
+        let mut z = GcMut::from_arc(arc);
+        z.push(4);
+        let buffer = (*z).as_ptr();
+        let arc = z.into_arc();
+        assert_eq!(*arc, [1, 2, 3, 4]);
+        assert_eq!(arc.as_ptr(), buffer);
+    }
+    
+    #[test]
+    fn test_try_into_unique_aliased() {
+        let x = Gc::new(vec![1, 2, 3]);
+        let y = std::hint::black_box(x);
+        let x = x.try_into_unique().expect_err("`y` still points to the value");
+        assert!(Gc::ptr_eq(&x, &y));
+        assert_eq!(*x, [1, 2, 3]);
+    }
+    
+    /// Makes a `Gc`, and only gives back a reference to an odd byte in the middle of it.
+    #[inline(never)]
+    fn make_interior_borrow() -> &'static u8 {
+        let x = Gc::new([1u8, 2, 3, 4]);
+        // SAFETY: the value is never mutated, and the returned reference keeps it alive
+        unsafe { &*x.as_ptr().cast::<u8>().add(1) }
+    }
+    
+    #[test]
+    fn test_try_into_unique_interior_borrow() {
+        let interior = std::hint::black_box(make_interior_borrow());
+        // SAFETY: `interior` is the second byte of the array, and the array is never mutated
+        let x = unsafe { Gc::from_ptr(std::ptr::from_ref(interior).sub(1).cast::<[u8; 4]>()) };
+        x.try_into_unique().expect_err("`interior` still points into the value");
+        assert_eq!(*interior, 2);
+    }
+    
+    #[test]
+    #[cfg_attr(debug_assertions, ignore = "unoptimized builds leave moved-from copies of the pointer on the stack")]
+    fn test_try_into_unique_sole() {
+        let x = Gc::new(vec![1, 2, 3]);
+        let mut x = x.try_into_unique().expect("nothing else points to the value");
+        x.push(4);
+        assert_eq!(*x, [1, 2, 3, 4]);
+    }
+    
     /// Tests to make sure that `Drop` is synchronously run for `GcMut`
     #[test]
     fn test_gc_mut_drop() {
@@ -406,6 +1022,53 @@ mod tests {
         assert_eq!(*DATA.lock().unwrap(), 69);
     }
     
+    #[test]
+    fn test_get_mut_unchecked() {
+        let gc = Gc::new(vec![1, 2, 3]);
+        // SAFETY: `gc` was just allocated, and never gets copied
+        unsafe { gc.get_mut_unchecked() }.push(4);
+        assert_eq!(*gc, [1, 2, 3, 4]);
+        
+        // zero-sized values aren't in the heap, but this still works for them
+        let unit = Gc::new(());
+        // SAFETY: same as above
+        let () = *unsafe { unit.get_mut_unchecked() };
+    }
+    
+    /// Tests that types without drop glue skip `drop_in_place`, but still get deallocated
+    #[test]
+    fn test_gc_mut_drop_no_glue() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        
+        struct CountsDrops;
+        impl Drop for CountsDrops {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        assert!(!GcMut::<[u64; 16]>::NEEDS_DROP);
+        assert!(!GcMut::<[u8]>::NEEDS_DROP);
+        assert!(GcMut::<CountsDrops>::NEEDS_DROP);
+        assert!(GcMut::<dyn Send>::NEEDS_DROP);
+        
+        // the fast path should still give the memory back to the GC
+        let mut last_ptr = std::ptr::null();
+        for i in 0..100u64 {
+            let x = GcMut::new([i; 16]);
+            last_ptr = x.as_ptr();
+        }
+        super::GC_ALLOCATOR.wait_for_gc();
+        let reused = GcMut::new([0u64; 16]);
+        assert!(reused.as_ptr() < last_ptr);
+        
+        // and types with drop glue still get dropped
+        drop(GcMut::new(CountsDrops));
+        let unsized_drop: GcMut<dyn Send> = GcMut::new(CountsDrops);
+        drop(unsized_drop);
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 2);
+    }
+    
     #[test]
     #[allow(unused_assignments, unused_variables)]
     fn test_covariance() {
@@ -419,6 +1082,42 @@ mod tests {
         gc1 = gc2;
     }
     
+    #[test]
+    fn test_borrow_as_key() {
+        use std::collections::{BTreeSet, HashMap};
+        
+        let mut map = HashMap::new();
+        map.insert(Gc::from_str("one"), 1);
+        map.insert(Gc::from_str("two"), 2);
+        assert_eq!(map.get("two"), Some(&2));
+        assert_eq!(map.get("three"), None);
+        
+        let set = BTreeSet::from([GcMut::new(3), GcMut::new(1), GcMut::new(2)]);
+        assert!(set.contains(&2));
+        assert!(!set.contains(&4));
+    }
+    
+    #[test]
+    fn test_gc_mut_unpin() {
+        use std::marker::PhantomPinned;
+        use std::pin::Pin;
+        
+        fn assert_unpin<T: Unpin>(_: &T) {}
+        
+        let mut x = GcMut::new((PhantomPinned, 5));
+        assert_unpin(&x);
+        
+        // moving the `GcMut` around doesn't move the value, so this doesn't need any unsafe
+        let pinned: Pin<&mut GcMut<_>> = Pin::new(&mut x);
+        Pin::into_inner(pinned).1 += 1;
+        let y = x;
+        assert_eq!(y.1, 6);
+        
+        let mut z: GcMut<i32> = GcMut::new(1);
+        *std::borrow::BorrowMut::<i32>::borrow_mut(&mut z) += 1;
+        assert_eq!(*z, 2);
+    }
+    
     /// Sends a GCed atomic counter to a bunch of threads, and has them all update it
     #[test]
     fn test_gc_send_atomic() {
@@ -432,6 +1131,173 @@ mod tests {
         assert_eq!(counter.load(Ordering::Relaxed), (1 << N) - 1);
     }
     
+    #[test]
+    fn test_demote_local() {
+        use std::rc::Rc;
+        
+        struct Local(Rc<i32>);
+        
+        let rc = Rc::new(5);
+        let x: Gc<Local> = GcMut::new(Local(rc.clone())).demote_local();
+        let y = x;
+        assert_eq!(*x.0 + *y.0, 10);
+        assert_eq!(Rc::strong_count(&rc), 2);
+        
+        // the GC thread should never touch the `Rc`, even once `x` and `y` are garbage
+        super::GC_ALLOCATOR.wait_for_gc();
+        super::GC_ALLOCATOR.wait_for_gc();
+        assert_eq!(Rc::strong_count(&rc), 2);
+    }
+    
+    #[test]
+    fn test_as_uninit_mut() {
+        use std::rc::Rc;
+        
+        let rc = Rc::new(());
+        let mut x = GcMut::new((0, rc.clone()));
+        let (ptr, size) = (x.as_ptr(), x.allocated_size());
+        
+        for i in 1..10 {
+            x = x.as_uninit_mut().write((i, rc.clone()));
+            assert_eq!((x.as_ptr(), x.allocated_size()), (ptr, size));
+            assert_eq!((*x).0, i);
+            
+            // the old value should have been dropped
+            assert_eq!(Rc::strong_count(&rc), 2);
+        }
+        
+        drop(x);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+    
+    #[test]
+    fn test_gc_str() {
+        let a = Gc::from_str("hello");
+        let b = Gc::from_str("hello");
+        let c = Gc::from_str("world");
+        assert_eq!(&*a, "hello");
+        assert!(a == b && a != c);
+        assert_ne!(a.as_ptr(), b.as_ptr());
+        assert_eq!(&*Gc::from_str(""), "");
+        
+        let mut d = GcMut::from_str("hello world");
+        d.make_ascii_uppercase();
+        assert_eq!(&*d, "HELLO WORLD");
+        drop(d);
+        
+        // make a bunch of garbage strings, and make sure the live ones don't get collected
+        for i in 0..100 {
+            let _ = Gc::from_str(&i.to_string());
+        }
+        super::GC_ALLOCATOR.wait_for_gc();
+        super::GC_ALLOCATOR.wait_for_gc();
+        assert_eq!((&*a, &*b, &*c), ("hello", "hello", "world"));
+    }
+    
+    #[test]
+    fn test_from_fn() {
+        // NOTE: this would be 8MiB on the stack with `Gc::new`
+        const N: usize = 1 << 20;
+        let x: Gc<[u64; N]> = Gc::from_fn(|i| 3 * i as u64);
+        assert!(x.iter().enumerate().all(|(i, &value)| value == 3 * i as u64));
+        assert!(x.allocated_size() >= size_of::<[u64; N]>());
+        
+        let mut y: GcMut<[String; 3]> = GcMut::from_fn(|i| i.to_string());
+        y[2].push('!');
+        assert_eq!(*y, ["0", "1", "2!"]);
+        
+        let z: Gc<[(); 5]> = Gc::from_fn(|_| ());
+        assert_eq!(z.len(), 5);
+    }
+    
+    /// If the closure panics partway through, everything it already made should get dropped (exactly once)
+    #[test]
+    fn test_from_fn_panic() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        
+        struct Droppy;
+        impl Drop for Droppy {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        let result = std::panic::catch_unwind(|| {
+            GcMut::<[Droppy; 10]>::from_fn(|i| if i == 6 { panic!("oh no") } else { Droppy })
+        });
+        assert!(result.is_err());
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 6);
+        
+        // a successful one should only get dropped once it's done with
+        NUM_DROPS.store(0, Ordering::Relaxed);
+        let x = GcMut::<[Droppy; 10]>::from_fn(|_| Droppy);
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 10);
+        
+        // and the collector shouldn't drop the half-made array again later
+        super::GC_ALLOCATOR.wait_for_gc();
+        super::GC_ALLOCATOR.wait_for_gc();
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 10);
+    }
+    
+    #[test]
+    fn test_write_slice() {
+        let src: Vec<u8> = (0..1 << 16).map(|i: usize| (i * 7 + i / 256) as u8).collect();
+        let x = GcMut::<[u8]>::new_uninit_slice(src.len()).write_slice(&src);
+        assert_eq!(x.len(), src.len());
+        assert_eq!(*x, *src);
+        assert!(x.allocated_size() >= src.len());
+        
+        let empty = GcMut::<[u64]>::new_uninit_slice(0).write_slice(&[]);
+        assert!(empty.is_empty());
+        
+        // the clones should get dropped along with the slice
+        let rc = std::rc::Rc::new(5);
+        let src = vec![rc.clone(); 100];
+        let x = GcMut::<[std::rc::Rc<i32>]>::new_uninit_slice(src.len()).write_clone_of_slice(&src);
+        assert_eq!(std::rc::Rc::strong_count(&rc), 201);
+        assert!(x.iter().all(|x| **x == 5));
+        drop(x);
+        assert_eq!(std::rc::Rc::strong_count(&rc), 101);
+        
+        let result = std::panic::catch_unwind(|| GcMut::<[u8]>::new_uninit_slice(3).write_slice(&[1, 2]));
+        assert!(result.is_err(), "lengths don't match");
+    }
+    
+    #[test]
+    fn test_try_into_array() {
+        let x: Gc<[i32]> = Gc::new([1, 2, 3, 4]);
+        let array: Gc<[i32; 4]> = x.try_into_array().unwrap();
+        assert_eq!(*array, [1, 2, 3, 4]);
+        assert_eq!(array.as_ptr().cast::<i32>(), x.as_ptr().cast::<i32>());
+        
+        let x = x.try_into_array::<3>().unwrap_err();
+        assert_eq!(x.len(), 4);
+        assert!(x.try_into_array::<5>().is_err());
+        
+        let y: GcMut<[String]> = GcMut::new([String::from("a"), String::from("b")]);
+        let mut y = y.try_into_array::<1>().unwrap_err().try_into_array::<2>().unwrap();
+        y[1].push('c');
+        assert_eq!(*y, ["a", "bc"]);
+        
+        let empty: Gc<[i32]> = Gc::new([]);
+        assert_eq!(*empty.try_into_array::<0>().unwrap(), []);
+    }
+    
+    #[test]
+    fn test_allocated_size() {
+        // 17 bytes, which has to get padded out to the header alignment
+        let x = Gc::new([1u8; 17]);
+        assert!(x.allocated_size() > size_of::<[u8; 17]>());
+        assert_eq!(x.allocated_size() % 16, 0);
+        
+        let y = GcMut::new(0u128);
+        assert!(y.allocated_size() >= size_of::<u128>());
+        
+        assert_eq!(Gc::new(()).allocated_size(), 0);
+    }
+    
     #[test]
     fn test_garbage_leak() {
         const NUM_BLOCKS: i32 = 500;
@@ -453,6 +1319,42 @@ mod tests {
         assert!(new.as_ptr() < expected);
     }
     
+    /// Same as `test_garbage_leak`, but without depending on the timing of the collector thread.
+    #[test]
+    #[ignore = "has to run on its own, with `cargo test -- --ignored --test-threads=1`"]
+    fn test_garbage_leak_single_threaded() {
+        const NUM_BLOCKS: i32 = 500;
+        const HEADER_SIZE: usize = 0x20;
+        
+        let first = Gc::new(0);
+        for i in 1..NUM_BLOCKS {
+            let _ = Gc::new([i; 8]);
+        }
+        
+        let size_per_block = HEADER_SIZE + size_of::<[i32; 8]>();
+        let expected = first.as_ptr().wrapping_byte_add(size_per_block * (NUM_BLOCKS - 1) as usize);
+        
+        // SAFETY: this test is only ever run by itself
+        unsafe { super::GC_ALLOCATOR.collect_now_single_threaded() };
+        let new = Gc::new(123);
+        
+        // the new data should reuse old memory
+        assert!(new.as_ptr() < expected);
+        assert_eq!(*first, 0);
+    }
+    
+    #[test]
+    fn test_dangling_placeholder() {
+        // SAFETY: every slot gets overwritten before anything reads it
+        let mut slots = [unsafe { Gc::<usize>::dangling() }; 8];
+        assert!(slots.iter().all(|slot| !GC_ALLOCATOR.contains(slot.as_ptr())));
+        
+        for (i, slot) in slots.iter_mut().enumerate() {
+            *slot = Gc::new(i * 10);
+        }
+        assert!(slots.iter().enumerate().all(|(i, slot)| **slot == i * 10));
+    }
+    
     #[test]
     fn test_vec_gc() {
         let vec: Vec<Gc<i32>> = (0..20).map(Gc::new).collect();
@@ -481,6 +1383,9 @@ mod tests {
     ///     * This could *probably* be made much easier to implement if you can get dropchk info
     ///       for any given type, but I don't think thats possible.
     ///     * Overall this MASSIVELY slows down the 
+    ///     * A (batched) version of this is behind the `checked-drops` feature, which rescans the
+    ///       heap once after all of a cycle's destructors have run, and aborts if any of them
+    ///       resurrected something.
     /// 3. Only run destructors for non-cyclically referenced types
     ///     * This is *better*, but it reduces a lot of the advantage of having a GC in the first
     ///       place.
@@ -492,9 +1397,12 @@ mod tests {
     ///       conjure up a `Gc<T>` that points to `self` somehow, and then stash it somewhere.
     ///       Definitely need to think about this one more, and justify to myself why it works,
     ///       but in the meantime ill just implement it I think.
+    /// 
+    /// NOTE: catching this aborts the whole process, so it runs in its own process (see `test_evil_drop`).
     #[test]
     #[deny(unsafe_code)]
-    fn test_evil_drop() {
+    #[ignore = "aborts the process, it gets run by `test_evil_drop`"]
+    fn evil_drop_child() {
         use crate::cell::AtomicRefCell;
         use std::marker::PhantomPinned;
         
@@ -566,12 +1474,21 @@ mod tests {
             cycles += 1;
         }
         
-        // Dangling reference!
-        let x = long.dangle.try_borrow_mut().unwrap();
-        let dangle = x.as_deref().unwrap();
+        // the destructor ran, so the collector should have aborted before handing control back
+        panic!("`CantKillMe` was resurrected, but the collector didn't abort");
+    }
+    
+    #[test]
+    #[cfg_attr(not(feature = "checked-drops"), ignore = "needs the `checked-drops` feature to pass")]
+    fn test_evil_drop() {
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["gc::smart_pointers::tests::evil_drop_child", "--exact", "--ignored"])
+            .status()
+            .unwrap();
         
-        warn!("Dangling reference: {:016x?}", dangle as *const _);
-        panic!("Got a dangling reference: {:016x?}", dangle as *const _)
+        // it either aborted, or the destructor never ran at all (which is fine too).
+        // a failed test exits with 101, which means the resurrection got missed
+        assert_ne!(status.code(), Some(101), "the collector didn't abort after `CantKillMe` was resurrected");
     }
     
     /// just some unoptimizable busywork for test threads to do
@@ -0,0 +1,405 @@
+//! Hazard pointers, for safely freeing nodes of lock-free data structures without the GC.
+//! 
+//! Before a thread reads through a pointer that another thread might free, it publishes the
+//! pointer in a hazard slot ([`HazardRegistry::protect`]). Instead of freeing a node right away,
+//! whoever unlinks it [`retire`](HazardRegistry::retire)s it, and it only actually gets freed
+//! once no hazard slot has it anymore.
+//! 
+//! See Maged Michael's "Hazard Pointers: Safe Memory Reclamation for Lock-Free Objects" (2004).
+
+use std::collections::HashSet;
+use std::ptr::NonNull;
+use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use thread_local::ThreadLocal;
+
+use crate::spinlock_mutex::Mutex;
+
+/// A single published hazard pointer.
+struct HazardSlot {
+    /// The pointer that is being protected, or null.
+    ptr: AtomicPtr<()>,
+    /// Whether a [`HazardGuard`] is currently using this slot.
+    in_use: AtomicBool,
+    /// The next slot in [`HazardRegistry::slots`]. This never changes after the slot is pushed.
+    next: *const HazardSlot,
+}
+
+/// Something that has been retired, but not freed yet.
+struct Retired {
+    ptr: NonNull<()>,
+    /// Frees `ptr` (which really points to some `T`).
+    drop: unsafe fn(NonNull<()>),
+}
+
+// SAFETY: `retire` requires `T: Send`, so it can be freed from any thread
+unsafe impl Send for Retired {}
+
+/// Frees something that came from [`Box::into_raw`].
+unsafe fn drop_box<T>(ptr: NonNull<()>) {
+    drop(unsafe { Box::from_raw(ptr.cast::<T>().as_ptr()) })
+}
+
+/// Keeps track of every hazard pointer (and every retired pointer) for some set of data structures.
+/// 
+/// Usually there would only be one of these per data structure (or one `static` for everything),
+/// since anything retired in a registry is only protected by that same registry's hazard pointers.
+pub struct HazardRegistry {
+    /// A lock-free stack of every slot ever made. Slots are reused, but never removed until the registry is dropped.
+    slots: AtomicPtr<HazardSlot>,
+    /// The number of slots in `slots`.
+    num_slots: AtomicUsize,
+    /// The things that each thread has retired, which haven't been freed yet.
+    /// 
+    /// NOTE: these are only ever locked with `try_with_lock` by anyone but the thread that owns
+    /// them, so `reclaim` never has to wait for anybody.
+    retired: ThreadLocal<Mutex<Vec<Retired>>>,
+}
+
+// SAFETY: the raw pointers in the slots are only ever used for comparing addresses, and everything
+//         else is atomic (or behind a lock)
+unsafe impl Send for HazardRegistry {}
+unsafe impl Sync for HazardRegistry {}
+
+impl HazardRegistry {
+    /// The least number of retired pointers a thread holds on to before trying to free them.
+    const MIN_RECLAIM_THRESHOLD: usize = 64;
+    
+    pub const fn new() -> Self {
+        Self {
+            slots: AtomicPtr::new(std::ptr::null_mut()),
+            num_slots: AtomicUsize::new(0),
+            retired: ThreadLocal::new(),
+        }
+    }
+    
+    /// Gets a slot that isn't being used, making a new one if all of them are.
+    fn acquire_slot(&self) -> &HazardSlot {
+        let mut slot = self.slots.load(Ordering::Acquire);
+        // SAFETY: slots are only freed when the registry gets dropped
+        while let Some(s) = unsafe { slot.as_ref() } {
+            if !s.in_use.load(Ordering::Relaxed) && s.in_use.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return s
+            }
+            slot = s.next.cast_mut();
+        }
+        
+        // every slot is taken, so push a new one
+        let new = Box::into_raw(Box::new(HazardSlot {
+            ptr: AtomicPtr::new(std::ptr::null_mut()),
+            in_use: AtomicBool::new(true),
+            next: std::ptr::null(),
+        }));
+        let mut head = self.slots.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: nobody else can see the new slot until the CAS succeeds
+            unsafe { (*new).next = head };
+            match self.slots.compare_exchange_weak(head, new, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        self.num_slots.fetch_add(1, Ordering::Relaxed);
+        
+        // SAFETY: the slot will live until the registry is dropped
+        unsafe { &*new }
+    }
+    
+    /// Loads the pointer in `src`, and makes sure it won't be freed (if it gets retired through
+    /// this registry) until the returned guard is dropped.
+    /// 
+    /// # Examples
+    /// ```rust
+    /// use std::sync::atomic::AtomicPtr;
+    /// use lockfree::hazard_pointer::HazardRegistry;
+    /// 
+    /// let registry = HazardRegistry::new();
+    /// let shared = AtomicPtr::new(Box::into_raw(Box::new(5)));
+    /// 
+    /// let guard = registry.protect(&shared);
+    /// let old = shared.swap(Box::into_raw(Box::new(6)), std::sync::atomic::Ordering::AcqRel);
+    /// unsafe { registry.retire(old) };
+    /// 
+    /// // `old` can't have been freed yet, since `guard` is still protecting it
+    /// assert_eq!(unsafe { guard.as_ref() }, Some(&5));
+    /// # drop(guard);
+    /// # unsafe { registry.retire(shared.into_inner()) };
+    /// ```
+    pub fn protect<T>(&self, src: &AtomicPtr<T>) -> HazardGuard<'_, T> {
+        let slot = self.acquire_slot();
+        let mut ptr = src.load(Ordering::Relaxed);
+        loop {
+            slot.ptr.store(ptr.cast(), Ordering::SeqCst);
+            // NOTE: this has to be checked again after the hazard pointer is published, since
+            //       it could have been retired (and scanned for) in between
+            let current = src.load(Ordering::SeqCst);
+            if current == ptr { break }
+            ptr = current;
+        }
+        HazardGuard { slot, ptr }
+    }
+    
+    /// Frees `ptr` (as a `Box<T>`) once no hazard pointer is protecting it.
+    /// 
+    /// This might not happen right away, so the memory can stick around for a while. It might
+    /// also free some other things that were retired earlier.
+    /// 
+    /// # Safety
+    /// * `ptr` must have come from [`Box::into_raw`], and can't be retired (or freed) more than once
+    /// * `ptr` must not be reachable anymore, so that no new hazard pointers to it can be made
+    ///   (anything that's already protecting it is fine though)
+    pub unsafe fn retire<T: Send>(&self, ptr: *mut T) {
+        let Some(ptr) = NonNull::new(ptr) else { return };
+        let retired = self.retired.get_or(|| Mutex::new(Vec::new()));
+        let num_retired = retired.with_lock(|retired| {
+            retired.push(Retired { ptr: ptr.cast(), drop: drop_box::<T> });
+            retired.len()
+        });
+        
+        // NOTE: this makes the cost of each scan spread out over (at least) as many retires as there are hazard pointers
+        if num_retired >= Self::MIN_RECLAIM_THRESHOLD.max(2 * self.num_slots.load(Ordering::Relaxed)) {
+            self.reclaim();
+        }
+    }
+    
+    /// Frees everything that has been retired, which isn't protected by a hazard pointer.
+    /// 
+    /// This normally happens automatically every so often when things get retired, but this
+    /// can be used to clean up (e.g: after a thread that retired a bunch of stuff exits).
+    pub fn reclaim(&self) {
+        // NOTE: everything has to be taken out of the lists *before* looking at the hazard
+        //       pointers. otherwise, something that was protected after the scan (but before it got
+        //       unlinked) could be retired in the meantime, and then freed here anyway.
+        let mut candidates = Vec::new();
+        for retired in self.retired.iter() {
+            // somebody else is already reclaiming (or retiring) these, so just let them
+            retired.try_with_lock(|retired| candidates.append(retired));
+        }
+        if candidates.is_empty() { return }
+        
+        // syncs with the `SeqCst` in `protect`, so any hazard pointer published before the
+        // pointer got unlinked is seen here
+        atomic::fence(Ordering::SeqCst);
+        
+        let mut hazards = HashSet::new();
+        let mut slot = self.slots.load(Ordering::Acquire);
+        while let Some(s) = unsafe { slot.as_ref() } {
+            let ptr = s.ptr.load(Ordering::SeqCst);
+            if !ptr.is_null() {
+                hazards.insert(ptr.addr());
+            }
+            slot = s.next.cast_mut();
+        }
+        
+        candidates.retain(|r| {
+            if hazards.contains(&r.ptr.addr().get()) { return true }
+            // SAFETY: it was retired, so nobody can make new hazard pointers to it, and none of the old ones are left
+            unsafe { (r.drop)(r.ptr) };
+            false
+        });
+        
+        // whatever is still protected gets tried again later, by this thread
+        if !candidates.is_empty() {
+            self.retired.get_or(|| Mutex::new(Vec::new())).with_lock(|retired| retired.append(&mut candidates));
+        }
+    }
+}
+
+impl Default for HazardRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for HazardRegistry {
+    fn drop(&mut self) {
+        // nothing can be protected anymore, since every guard borrows the registry
+        for retired in self.retired.iter_mut() {
+            retired.with_lock(|retired| retired.drain(..).for_each(|r| unsafe { (r.drop)(r.ptr) }));
+        }
+        
+        let mut slot = *self.slots.get_mut();
+        while !slot.is_null() {
+            // SAFETY: every slot came from `Box::into_raw`, and nobody else can be using them now
+            let s = unsafe { Box::from_raw(slot) };
+            slot = s.next.cast_mut();
+        }
+    }
+}
+
+/// A pointer that won't be freed by its [`HazardRegistry`] until this is dropped. See [`HazardRegistry::protect`].
+pub struct HazardGuard<'r, T> {
+    slot: &'r HazardSlot,
+    ptr: *mut T,
+}
+
+impl<T> HazardGuard<'_, T> {
+    /// The protected pointer (which might be null).
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr
+    }
+    
+    /// A reference to the protected value, if the pointer isn't null.
+    /// 
+    /// # Safety
+    /// Every (non-null) pointer that is ever stored in the `AtomicPtr` that was protected has to
+    /// point to a valid `T` until it gets unlinked from it, and can then only be freed by retiring
+    /// it through the same registry. (i.e: it can't have been freed, or retired while it was still
+    /// reachable, before it got protected)
+    pub unsafe fn as_ref(&self) -> Option<&T> {
+        // SAFETY: gauranteed by caller, and retiring it can't free it until this guard is dropped
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> Drop for HazardGuard<'_, T> {
+    fn drop(&mut self) {
+        self.slot.ptr.store(std::ptr::null_mut(), Ordering::Release);
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    static NUM_NODES_FREED: AtomicUsize = AtomicUsize::new(0);
+    
+    /// A Treiber stack, whose nodes are freed with hazard pointers.
+    struct Stack<T> {
+        head: AtomicPtr<Node<T>>,
+        registry: HazardRegistry,
+    }
+    
+    struct Node<T> {
+        value: std::mem::ManuallyDrop<T>,
+        next: *mut Node<T>,
+    }
+    
+    // SAFETY: the values only ever get moved out by one thread
+    unsafe impl<T: Send> Send for Node<T> {}
+    
+    impl<T> Drop for Node<T> {
+        fn drop(&mut self) {
+            NUM_NODES_FREED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    impl<T: Send> Stack<T> {
+        fn new() -> Self {
+            Self { head: AtomicPtr::new(std::ptr::null_mut()), registry: HazardRegistry::new() }
+        }
+        
+        fn push(&self, value: T) {
+            let node = Box::into_raw(Box::new(Node { value: std::mem::ManuallyDrop::new(value), next: std::ptr::null_mut() }));
+            let mut head = self.head.load(Ordering::Relaxed);
+            loop {
+                unsafe { (*node).next = head };
+                match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                    Ok(_) => return,
+                    Err(current) => head = current,
+                }
+            }
+        }
+        
+        fn pop(&self) -> Option<T> {
+            loop {
+                let guard = self.registry.protect(&self.head);
+                // SAFETY: nodes are only ever freed by retiring them, after they're unlinked
+                let node = unsafe { guard.as_ref() }?;
+                // NOTE: this is only okay because the node can't be freed (and reused) while it's protected
+                if self.head.compare_exchange(guard.as_ptr(), node.next, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                    // SAFETY: only the thread that unlinked the node can take its value
+                    let value = unsafe { std::ptr::read(&*node.value) };
+                    let node = guard.as_ptr();
+                    drop(guard);
+                    unsafe { self.registry.retire(node) };
+                    return Some(value)
+                }
+            }
+        }
+    }
+    
+    impl<T> Drop for Stack<T> {
+        fn drop(&mut self) {
+            let mut node = *self.head.get_mut();
+            while !node.is_null() {
+                let mut boxed = unsafe { Box::from_raw(node) };
+                unsafe { std::mem::ManuallyDrop::drop(&mut boxed.value) };
+                node = boxed.next;
+            }
+        }
+    }
+    
+    #[test]
+    fn test_protected_not_freed() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        let registry = HazardRegistry::new();
+        let shared = AtomicPtr::new(Box::into_raw(Box::new(Counted)));
+        
+        let guard = registry.protect(&shared);
+        let old = shared.swap(std::ptr::null_mut(), Ordering::AcqRel);
+        unsafe { registry.retire(old) };
+        
+        registry.reclaim();
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
+        
+        drop(guard);
+        registry.reclaim();
+        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 1);
+    }
+    
+    /// Pushes and pops from a bunch of threads at once, and makes sure every node gets freed exactly once
+    #[test]
+    fn test_stack_stress() {
+        const THREADS: usize = 8;
+        const N: usize = 10_000;
+        static NUM_NODE_DROPS: AtomicUsize = AtomicUsize::new(0);
+        
+        struct Tracked(usize);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                NUM_NODE_DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        
+        let stack = Stack::new();
+        let popped: usize = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..THREADS).map(|t| {
+                let stack = &stack;
+                s.spawn(move || {
+                    let mut sum = 0;
+                    for i in 0..N {
+                        stack.push(Tracked(t * N + i));
+                        if i % 2 == 0 && let Some(value) = stack.pop() {
+                            sum += value.0;
+                        }
+                    }
+                    sum
+                })
+            }).collect();
+            threads.into_iter().map(|t| t.join().unwrap()).sum()
+        });
+        
+        // everything that didn't get popped is still on the stack
+        let mut remaining = 0;
+        while let Some(value) = stack.pop() {
+            remaining += value.0;
+        }
+        assert_eq!(popped + remaining, (0..THREADS * N).sum());
+        assert_eq!(NUM_NODE_DROPS.load(Ordering::Relaxed), THREADS * N);
+        
+        // every retired node should get freed along with the registry (and some of them before that)
+        assert!(NUM_NODES_FREED.load(Ordering::Relaxed) > 0);
+        drop(stack);
+        assert_eq!(NUM_NODES_FREED.load(Ordering::Relaxed), THREADS * N);
+    }
+}
@@ -24,6 +24,7 @@
 #![feature(never_type)]
 #![feature(sync_unsafe_cell)]
 #![feature(allocator_api)]
+#![feature(integer_atomics)]
 #![feature(deref_pure_trait)]
 #![feature(ptr_internals)] // for Unique<T>
 #![feature(ptr_metadata)]
@@ -53,6 +54,7 @@ pub mod non_concurrent;
 pub mod cell;
 pub mod atomic_refcount;
 pub mod spinlock_mutex;
+pub mod hazard_pointer;
 
 // garbage collection
 pub mod gc;
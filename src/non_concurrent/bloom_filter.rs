@@ -23,6 +23,15 @@ impl BloomFilter<5, RandomState> {
             hashes
         }
     }
+    
+    /// Creates a BloomFilter with at least `bits` bits, containing every item from `items`.
+    /// 
+    /// NOTE: this isn't a `FromIterator` impl, since there's no good way to pick the size.
+    pub fn from_iter_sized<T: Hash>(bits: usize, items: impl IntoIterator<Item=T>) -> Self {
+        let mut bf = Self::new(bits);
+        bf.extend(items);
+        bf
+    }
 }
 
 impl<S: BuildHasher, const NUM_HASHES: usize> BloomFilter<NUM_HASHES, S> {
@@ -48,6 +57,93 @@ impl<S: BuildHasher, const NUM_HASHES: usize> BloomFilter<NUM_HASHES, S> {
         (popcnt as f64 / self.bit_len() as f64).powi(NUM_HASHES as i32)
     }
     
+    /// The most that [`approx_false_positive_rate`](Self::approx_false_positive_rate) could be
+    /// after adding one more value (i.e: if every hash sets a new bit).
+    fn false_positive_rate_after_add(&self) -> f64 {
+        let popcnt = std::cmp::min(self.num_set_bits + NUM_HASHES, self.bit_len());
+        (popcnt as f64 / self.bit_len() as f64).powi(NUM_HASHES as i32)
+    }
+    
+    /// Creates an empty bloom filter with the same size and hash functions as this one.
+    /// 
+    /// Filters have to be made this way to be compared with
+    /// [`estimate_intersection`](Self::estimate_intersection) and
+    /// [`estimate_union_cardinality`](Self::estimate_union_cardinality), since otherwise the same
+    /// value would set different bits in each of them.
+    pub fn new_like(&self) -> Self where S: Clone {
+        Self {
+            bit_array: [0].repeat(self.num_u64s).into_boxed_slice(),
+            num_u64s: self.num_u64s,
+            num_elements: 0,
+            num_set_bits: 0,
+            hashes: self.hashes.clone(),
+        }
+    }
+    
+    /// The approximate amount of *distinct* elements in a filter of this size with `set_bits` bits set.
+    /// 
+    /// See https://doi.org/10.1021/ci600526a
+    fn cardinality_from_set_bits(&self, set_bits: usize) -> f64 {
+        let m = self.bit_len() as f64;
+        -(m / NUM_HASHES as f64) * (1.0 - set_bits as f64 / m).ln()
+    }
+    
+    /// Panics if `self` and `other` can't be compared (i.e: weren't made with [`new_like`](Self::new_like)).
+    /// 
+    /// NOTE: there's no way to compare hashers directly, so this just checks that they agree on one value.
+    fn assert_compatible(&self, other: &Self) {
+        assert_eq!(self.num_u64s, other.num_u64s, "bloom filters should be the same size");
+        assert!(
+            self.hashes.iter().zip(&other.hashes).all(|(a, b)| a.hash_one(0u64) == b.hash_one(0u64)),
+            "bloom filters should use the same hash functions"
+        );
+    }
+    
+    /// The amount of bits set in either `self` or `other`.
+    fn union_set_bits(&self, other: &Self) -> usize {
+        self.bit_array.iter().zip(&other.bit_array).map(|(a, b)| (a | b).count_ones() as usize).sum()
+    }
+    
+    /// Estimates the amount of distinct elements in the bloom filter, from how many bits are set.
+    /// 
+    /// Unlike [`len`](Self::len), adding the same value twice only counts it once. This is only
+    /// an estimate, and gets much less accurate as the filter fills up. Once every bit is set,
+    /// there's no way to tell how many elements there are, so this returns `usize::MAX`.
+    pub fn estimate_cardinality(&self) -> usize {
+        self.cardinality_from_set_bits(self.num_set_bits).round() as usize
+    }
+    
+    /// Estimates the amount of distinct elements in either `self` or `other`.
+    /// 
+    /// This is the estimated cardinality of the bitwise OR of the two filters, so it has the
+    /// same caveats as [`estimate_cardinality`](Self::estimate_cardinality).
+    /// 
+    /// # Panics
+    /// If `other` wasn't made with [`new_like`](Self::new_like) (or vice versa).
+    pub fn estimate_union_cardinality(&self, other: &Self) -> usize {
+        self.assert_compatible(other);
+        let union_bits = self.union_set_bits(other);
+        self.cardinality_from_set_bits(union_bits).round() as usize
+    }
+    
+    /// Estimates the amount of distinct elements in both `self` and `other`.
+    /// 
+    /// This uses inclusion-exclusion (`|A ∩ B| = |A| + |B| - |A ∪ B|`) on the estimates, so the
+    /// errors from all three of them add up. It works best when the overlap is a decent chunk
+    /// of both filters, and both filters are well under half full. Small overlaps can easily
+    /// get lost in the noise, and the result is clamped to 0 if it would come out negative.
+    /// 
+    /// # Panics
+    /// If `other` wasn't made with [`new_like`](Self::new_like) (or vice versa).
+    pub fn estimate_intersection(&self, other: &Self) -> usize {
+        self.assert_compatible(other);
+        let union_bits = self.union_set_bits(other);
+        let intersection = self.cardinality_from_set_bits(self.num_set_bits)
+            + other.cardinality_from_set_bits(other.num_set_bits)
+            - self.cardinality_from_set_bits(union_bits);
+        intersection.max(0.0).round() as usize
+    }
+    
     /// Inserts a value into the bloom filter.
     pub fn add<T: ?Sized + Hash>(&mut self, value: &T) {
         for h in &self.hashes {
@@ -55,7 +151,7 @@ impl<S: BuildHasher, const NUM_HASHES: usize> BloomFilter<NUM_HASHES, S> {
             let (word, bit) = (hash / 64, hash % 64);
             let index = word as usize % self.num_u64s;
             
-            self.num_set_bits += ((self.bit_array[index] >> bit) & 1) as usize;
+            self.num_set_bits += ((!self.bit_array[index] >> bit) & 1) as usize;
             self.bit_array[index] |= 1 << bit;
         }
         self.num_elements += 1;
@@ -76,6 +172,132 @@ impl<S: BuildHasher, const NUM_HASHES: usize> BloomFilter<NUM_HASHES, S> {
         }
         true
     }
+    
+    /// Inserts every item from `items` into the bloom filter.
+    pub fn add_all<T: Hash>(&mut self, items: impl IntoIterator<Item=T>) {
+        // TODO: batch the hashing
+        for item in items {
+            self.add(&item);
+        }
+    }
+    
+    /// Whether the bloom filter might contain every item in `items`.
+    /// 
+    /// This stops at the first item that definitely isn't in the bloom filter,
+    /// so the rest of `items` won't be consumed.
+    pub fn contains_all<T: Hash>(&self, items: impl IntoIterator<Item=T>) -> bool {
+        items.into_iter().all(|item| self.contains(&item))
+    }
+}
+
+impl<T: Hash, S: BuildHasher, const NUM_HASHES: usize> Extend<T> for BloomFilter<NUM_HASHES, S> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+        self.add_all(iter)
+    }
+}
+
+
+/// A bloom filter that grows as more things get added to it, so it doesn't need to be sized up front.
+/// 
+/// This is a chain of [`BloomFilter`]s, where each one is twice as big as the one before it. Once
+/// the newest one fills up enough to go over its share of the false positive rate, a new one gets
+/// added, and new items go in that one instead. Each filter gets half the false positive rate of
+/// the one before it, so the overall rate stays under the one given to [`new`](Self::new) no matter
+/// how many filters there end up being.
+/// 
+/// See https://doi.org/10.1016/j.ipl.2006.10.007
+pub struct ScalableBloomFilter {
+    filters: Vec<BloomFilter>,
+    false_positive_rate: f64,
+}
+
+impl ScalableBloomFilter {
+    /// How much bigger each filter is than the one before it.
+    const GROWTH_FACTOR: usize = 2;
+    /// How much lower each filter's false positive rate is than the one before it.
+    const TIGHTENING_RATIO: f64 = 0.5;
+    
+    /// Creates a ScalableBloomFilter whose first filter has at least `initial_bits` bits, and
+    /// which keeps its false positive rate under `false_positive_rate`.
+    /// 
+    /// # Panics
+    /// If `false_positive_rate` isn't between 0 and 1.
+    pub fn new(initial_bits: usize, false_positive_rate: f64) -> Self {
+        assert!(0.0 < false_positive_rate && false_positive_rate < 1.0, "false positive rate should be between 0 and 1");
+        Self {
+            filters: vec![BloomFilter::new(initial_bits)],
+            false_positive_rate,
+        }
+    }
+    
+    /// The false positive rate that the `i`th filter is allowed to go up to.
+    /// 
+    /// NOTE: these add up to (at most) the overall false positive rate.
+    fn target_rate(&self, i: usize) -> f64 {
+        self.false_positive_rate * (1.0 - Self::TIGHTENING_RATIO) * Self::TIGHTENING_RATIO.powi(i as i32)
+    }
+    
+    /// The amount of elements put into the bloom filter
+    pub fn len(&self) -> usize {
+        self.filters.iter().map(BloomFilter::len).sum()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// The total amount of bits in all of the filters.
+    pub fn bit_len(&self) -> usize {
+        self.filters.iter().map(BloomFilter::bit_len).sum()
+    }
+    
+    /// The (approximate) false positive rate for the bloom filter.
+    /// 
+    /// This has the same assumptions as [`BloomFilter::approx_false_positive_rate`].
+    pub fn approx_false_positive_rate(&self) -> f64 {
+        // a false positive from any of the filters is a false positive for the whole thing
+        1.0 - self.filters.iter().map(|bf| 1.0 - bf.approx_false_positive_rate()).product::<f64>()
+    }
+    
+    /// Inserts a value into the bloom filter, adding a new filter first if the newest one is full.
+    pub fn add<T: ?Sized + Hash>(&mut self, value: &T) {
+        let i = self.filters.len() - 1;
+        let newest = &self.filters[i];
+        // NOTE: this checks before adding, so that the newest filter never goes over its target
+        if newest.false_positive_rate_after_add() > self.target_rate(i) {
+            let bits = newest.bit_len() * Self::GROWTH_FACTOR;
+            self.filters.push(BloomFilter::new(bits));
+        }
+        self.filters.last_mut().unwrap().add(value);
+    }
+    
+    /// Whether the bloom filter might contain `value`.
+    /// 
+    /// This function may return false positives, but will never return false negatives.
+    pub fn contains<T: ?Sized + Hash>(&self, value: &T) -> bool {
+        self.filters.iter().any(|bf| bf.contains(value))
+    }
+    
+    /// Inserts every item from `items` into the bloom filter.
+    pub fn add_all<T: Hash>(&mut self, items: impl IntoIterator<Item=T>) {
+        for item in items {
+            self.add(&item);
+        }
+    }
+    
+    /// Whether the bloom filter might contain every item in `items`.
+    /// 
+    /// This stops at the first item that definitely isn't in the bloom filter,
+    /// so the rest of `items` won't be consumed.
+    pub fn contains_all<T: Hash>(&self, items: impl IntoIterator<Item=T>) -> bool {
+        items.into_iter().all(|item| self.contains(&item))
+    }
+}
+
+impl<T: Hash> Extend<T> for ScalableBloomFilter {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+        self.add_all(iter)
+    }
 }
 
 #[test]
@@ -96,3 +318,85 @@ fn basic_test() {
     }
 }
 
+
+#[test]
+fn bulk_test() {
+    let mut bf = BloomFilter::new(1024);
+    
+    bf.add_all(["hello", "world"]);
+    bf.add_all(0..10);
+    assert_eq!(bf.len(), 12);
+    assert!(bf.contains_all(["hello", "world"]));
+    assert!(bf.contains_all(0..10));
+    assert!(bf.contains_all(std::iter::empty::<u8>()));
+    
+    // should stop consuming the iterator after the first miss
+    let mut num_checked = 0;
+    let items = ["hello", "definitely not in there", "world"].into_iter().inspect(|_| num_checked += 1);
+    assert!(!bf.contains_all(items));
+    assert_eq!(num_checked, 2);
+}
+
+
+#[test]
+fn extend_test() {
+    let mut bf = BloomFilter::from_iter_sized(1024, 0..50);
+    assert_eq!(bf.len(), 50);
+    assert!(bf.contains_all(0..50));
+    
+    bf.extend(50..100);
+    assert_eq!(bf.len(), 100);
+    assert!(bf.contains_all(0..100));
+    assert!(!bf.contains_all(1000..1100));
+}
+
+
+#[test]
+fn cardinality_test() {
+    const BITS: usize = 1 << 16;
+    
+    let mut a = BloomFilter::new(BITS);
+    let mut b = a.new_like();
+    a.add_all(0..2000);
+    b.add_all(1000..3000);
+    // adding things again doesn't change the estimate
+    a.add_all(0..100);
+    
+    let within = |estimate: usize, actual: usize| estimate.abs_diff(actual) <= actual / 10;
+    assert!(within(a.estimate_cardinality(), 2000), "cardinality estimate was {}", a.estimate_cardinality());
+    assert!(within(a.estimate_union_cardinality(&b), 3000), "union estimate was {}", a.estimate_union_cardinality(&b));
+    assert!(within(a.estimate_intersection(&b), 1000), "intersection estimate was {}", a.estimate_intersection(&b));
+    assert_eq!(a.estimate_intersection(&b), b.estimate_intersection(&a));
+    
+    // filters with nothing in common should have (close to) no overlap
+    let mut c = a.new_like();
+    c.add_all(10_000..12_000);
+    assert!(a.estimate_intersection(&c) <= 100, "intersection estimate was {}", a.estimate_intersection(&c));
+    
+    // filters that don't hash the same way can't be compared
+    let d = BloomFilter::new(BITS);
+    let result = std::panic::catch_unwind(|| a.estimate_intersection(&d));
+    assert!(result.is_err());
+}
+
+
+#[test]
+fn scalable_test() {
+    const NUM_ITEMS: usize = 100_000;
+    const FALSE_POSITIVE_RATE: f64 = 0.01;
+    
+    let mut bf = ScalableBloomFilter::new(1024, FALSE_POSITIVE_RATE);
+    bf.add_all(0..NUM_ITEMS);
+    assert_eq!(bf.len(), NUM_ITEMS);
+    assert!(bf.contains_all(0..NUM_ITEMS));
+    
+    // it should have had to grow a bunch of times to fit everything
+    assert!(bf.filters.len() > 5, "only has {} filters", bf.filters.len());
+    assert!(bf.filters.iter().all(|f| f.len() < NUM_ITEMS / 2));
+    assert!(bf.approx_false_positive_rate() <= FALSE_POSITIVE_RATE);
+    
+    let false_positives = (NUM_ITEMS..2 * NUM_ITEMS).filter(|i| bf.contains(i)).count();
+    let rate = false_positives as f64 / NUM_ITEMS as f64;
+    // NOTE: leave some room, since the hashes are random
+    assert!(rate <= 1.5 * FALSE_POSITIVE_RATE, "false positive rate was {rate}");
+}
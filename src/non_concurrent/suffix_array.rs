@@ -1,3 +1,145 @@
+/// The length of the longest common prefix of two (different) strings.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    // TODO: this is not idiomatic
+    let mut i = 0;
+    let mut x = a.bytes();
+    let mut y = b.bytes();
+    while x.next() == y.next() { i += 1 }
+    i
+}
+
+/// Sorts the suffixes of `text` (whose values are all at most `upper`), using SA-IS.
+/// 
+/// Returns where each suffix starts, in sorted order.
+/// 
+/// The idea is to split the suffixes into S-type ones (smaller than the suffix after them) and
+/// L-type ones (bigger than the suffix after them). Once the leftmost S-type suffixes (LMS) of
+/// each run are sorted, every other suffix can be sorted from them with two linear passes (the
+/// "induced sort"). The LMS suffixes themselves get sorted by induced sorting just their LMS
+/// substrings, giving each distinct one a name, and then recursing on the (at most half as long)
+/// string of names if any of them weren't unique.
+/// 
+/// Complexity: O(n + upper)
+fn sa_is(text: &[usize], upper: usize) -> Vec<usize> {
+    /// Marks empty slots in the suffix array while inducing.
+    const EMPTY: usize = usize::MAX;
+    
+    let n = text.len();
+    match n {
+        0 => return vec![],
+        1 => return vec![0],
+        2 => return if text[0] < text[1] { vec![0, 1] } else { vec![1, 0] },
+        _ => {}
+    }
+    
+    // whether each suffix is S-type (NOTE: the last one is L-type, since it's bigger than the empty suffix)
+    let mut is_s = vec![false; n];
+    for i in (0..n-1).rev() {
+        is_s[i] = if text[i] == text[i+1] { is_s[i+1] } else { text[i] < text[i+1] };
+    }
+    
+    // where the S-type and L-type suffixes starting with each value go
+    // (within each value's bucket, the L-type suffixes come before the S-type ones)
+    let mut start_s = vec![0; upper + 1];
+    let mut start_l = vec![0; upper + 1];
+    for i in 0..n {
+        if is_s[i] { start_l[text[i] + 1] += 1 } else { start_s[text[i]] += 1 }
+    }
+    for c in 0..=upper {
+        start_s[c] += start_l[c];
+        if c < upper { start_l[c + 1] += start_s[c] }
+    }
+    
+    let is_lms = |i: usize| i > 0 && !is_s[i-1] && is_s[i];
+    
+    // sorts every suffix, given the LMS suffixes in sorted order
+    let induce = |sa: &mut [usize], lms: &[usize]| {
+        sa.fill(EMPTY);
+        
+        let mut bucket = start_s.clone();
+        for &i in lms {
+            sa[bucket[text[i]]] = i;
+            bucket[text[i]] += 1;
+        }
+        
+        // L-type suffixes, from left to right
+        bucket.copy_from_slice(&start_l);
+        sa[bucket[text[n-1]]] = n-1;
+        bucket[text[n-1]] += 1;
+        for j in 0..n {
+            let i = sa[j];
+            if i != EMPTY && i > 0 && !is_s[i-1] {
+                sa[bucket[text[i-1]]] = i-1;
+                bucket[text[i-1]] += 1;
+            }
+        }
+        
+        // S-type suffixes, from right to left (from the end of each bucket)
+        bucket.copy_from_slice(&start_l);
+        for j in (0..n).rev() {
+            let i = sa[j];
+            if i != EMPTY && i > 0 && is_s[i-1] {
+                bucket[text[i-1] + 1] -= 1;
+                sa[bucket[text[i-1] + 1]] = i-1;
+            }
+        }
+    };
+    
+    let lms = Vec::from_iter((1..n).filter(|&i| is_lms(i)));
+    let mut lms_index = vec![EMPTY; n];
+    for (j, &i) in lms.iter().enumerate() { lms_index[i] = j }
+    
+    // this sorts the LMS substrings (but not necessarily the LMS suffixes)
+    let mut sa = vec![EMPTY; n];
+    induce(&mut sa, &lms);
+    
+    if !lms.is_empty() {
+        let sorted_lms = Vec::from_iter(sa.iter().copied().filter(|&i| lms_index[i] != EMPTY));
+        
+        // name each LMS substring by its rank among the distinct ones
+        let mut names = vec![0; lms.len()];
+        let mut name = 0;
+        for w in sorted_lms.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let end_a = lms.get(lms_index[a] + 1).copied().unwrap_or(n);
+            let end_b = lms.get(lms_index[b] + 1).copied().unwrap_or(n);
+            // NOTE: the last LMS substring ends with the (imaginary) empty suffix, so it can't be the same as any other
+            let same = end_a < n && end_b < n && end_a - a == end_b - b && text[a..=end_a] == text[b..=end_b];
+            if !same { name += 1 }
+            names[lms_index[b]] = name;
+        }
+        
+        // if any of the names weren't unique, sort the LMS suffixes by sorting the string of names
+        let sorted_lms = if name + 1 == lms.len() { sorted_lms } else {
+            Vec::from_iter(sa_is(&names, name).into_iter().map(|j| lms[j]))
+        };
+        induce(&mut sa, &sorted_lms);
+    }
+    
+    sa
+}
+
+/// The length of the longest common prefix of each pair of adjacent suffixes in `sa`, using Kasai's algorithm.
+/// 
+/// Complexity: O(n)
+fn lcp_array(text: &[usize], sa: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    let mut rank = vec![0; n];
+    for (r, &i) in sa.iter().enumerate() { rank[i] = r }
+    
+    let mut lcp = vec![0; n.saturating_sub(1)];
+    // NOTE: going from one suffix to the next one in the string only ever shortens the LCP by one
+    let mut h: usize = 0;
+    for i in 0..n {
+        h = h.saturating_sub(1);
+        if rank[i] == 0 { continue }
+        let j = sa[rank[i] - 1];
+        while i + h < n && j + h < n && text[i + h] == text[j + h] { h += 1 }
+        lcp[rank[i] - 1] = h;
+    }
+    lcp
+}
+
 /// Suffix Array Data Structure
 pub struct SuffixArray<'a> {
     // NOTE: these are both O(n) space!
@@ -6,21 +148,14 @@ pub struct SuffixArray<'a> {
 }
 
 impl<'a> SuffixArray<'a> {
-    /// Complexity: O(n log(n))
+    /// Complexity: O(n log(n)) comparisons, but each of those can take O(n) on repetitive strings.
     /// 
-    /// TODO: O(n) complexity at https://arxiv.org/abs/1610.08305
+    /// See [`new_linear`](Self::new_linear) for an O(n) version.
     pub fn new(string: &'a str) -> Self {
         let mut suffixes = Vec::from_iter((0..string.len()).map(|i| &string[i..]));
         suffixes.sort();
         
-        // TODO: this is not idiomatic
-        let lcp_array = suffixes.array_chunks::<2>().map(|&[a, b]| {
-            let mut i = 0;
-            let mut x = a.bytes();
-            let mut y = b.bytes();
-            while x.next() == y.next() { i += 1 }
-            i
-        }).collect();
+        let lcp_array = suffixes.windows(2).map(|w| common_prefix_len(w[0], w[1])).collect();
         
         Self {
             suffixes: suffixes.into(),
@@ -28,6 +163,40 @@ impl<'a> SuffixArray<'a> {
         }
     }
     
+    /// Same as [`new`](Self::new), but builds the suffix array with SA-IS instead of sorting.
+    /// 
+    /// Complexity: O(n)
+    /// 
+    /// The suffixes are sorted as bytes (which is the same order as for `&str`s), and then the
+    /// ones that don't start on a `char` boundary are left out, so unlike `new`, this works for
+    /// strings that aren't ASCII.
+    /// 
+    /// See https://arxiv.org/abs/1610.08305 and https://doi.org/10.1109/DCC.2009.42
+    pub fn new_linear(string: &'a str) -> Self {
+        let bytes = string.as_bytes();
+        let text = Vec::from_iter(bytes.iter().map(|&b| b as usize));
+        let order = sa_is(&text, u8::MAX as usize);
+        let lcps = lcp_array(&text, &order);
+        
+        let mut suffixes = Vec::with_capacity(order.len());
+        let mut lcp_array = Vec::with_capacity(order.len().saturating_sub(1));
+        // the LCP of two suffixes is the smallest LCP of the adjacent ones between them
+        let mut min_lcp = usize::MAX;
+        for (i, &start) in order.iter().enumerate() {
+            if i > 0 { min_lcp = std::cmp::min(min_lcp, lcps[i-1]) }
+            if !string.is_char_boundary(start) { continue }
+            
+            if !suffixes.is_empty() { lcp_array.push(min_lcp) }
+            suffixes.push(&string[start..]);
+            min_lcp = usize::MAX;
+        }
+        
+        Self {
+            suffixes: suffixes.into(),
+            lcp_array: lcp_array.into()
+        }
+    }
+    
     /// Complexity: O(log(n))
     pub fn is_suffix(&self, value: &str) -> bool {
         self.suffixes.binary_search(&value).is_ok()
@@ -39,7 +208,7 @@ impl<'a> SuffixArray<'a> {
             Ok(_) => true, // not just any substring, but a suffix
             Err(idx) => {
                 // `suffix_idxes[idx]` is the suffix where `value` would be a prefix, if any
-                self.suffixes[idx].strip_prefix(value).is_some()
+                self.suffixes.get(idx).is_some_and(|s| s.starts_with(value))
             }
         }
     }
@@ -64,6 +233,156 @@ impl<'a> SuffixArray<'a> {
     }
 }
 
+/// Suffix Array Data Structure, storing suffixes as offsets into the string.
+/// 
+/// This is the same as a [`SuffixArray`], but only stores a `u32` per suffix
+/// instead of a `&str` (which is two `usize`s), so it takes a quarter of the
+/// memory on 64-bit platforms. The tradeoff is that strings can't be longer
+/// than 4GiB, and every suffix has to be re-sliced out of the original string.
+pub struct CompactSuffixArray<'a> {
+    string: &'a str,
+    // NOTE: these are both O(n) space!
+    suffixes: Box<[u32]>,
+    lcp_array: Box<[usize]>,
+}
+
+impl<'a> CompactSuffixArray<'a> {
+    /// Complexity: O(n log(n))
+    /// 
+    /// # Panics
+    /// If `string` is longer than [`u32::MAX`] bytes.
+    pub fn new(string: &'a str) -> Self {
+        assert!(u32::try_from(string.len()).is_ok(), "string is too long for a `CompactSuffixArray`");
+        
+        let mut suffixes = Vec::from_iter(string.char_indices().map(|(i, _)| i as u32));
+        suffixes.sort_by_key(|&i| &string[i as usize..]);
+        
+        let lcp_array = suffixes.windows(2).map(|w| {
+            common_prefix_len(&string[w[0] as usize..], &string[w[1] as usize..])
+        }).collect();
+        
+        Self {
+            string,
+            suffixes: suffixes.into(),
+            lcp_array
+        }
+    }
+    
+    /// The `i`th suffix, in sorted order.
+    fn suffix(&self, i: usize) -> &'a str {
+        &self.string[self.suffixes[i] as usize..]
+    }
+    
+    fn binary_search(&self, value: &str) -> Result<usize, usize> {
+        self.suffixes.binary_search_by(|&i| self.string[i as usize..].cmp(value))
+    }
+    
+    /// Complexity: O(log(n))
+    pub fn is_suffix(&self, value: &str) -> bool {
+        self.binary_search(value).is_ok()
+    }
+    
+    /// Complexity: O(log(n))
+    pub fn has_substring(&self, value: &str) -> bool {
+        match self.binary_search(value) {
+            Ok(_) => true, // not just any substring, but a suffix
+            Err(idx) => {
+                // `suffixes[idx]` is the suffix where `value` would be a prefix, if any
+                idx < self.suffixes.len() && self.suffix(idx).starts_with(value)
+            }
+        }
+    }
+    
+    /// Complexity: O(n)
+    pub fn longest_repeated_substring(&self) -> Option<&'a str> {
+        let (idx, &len) = self.lcp_array.iter().enumerate().max_by_key(|&(_, a)| a)?;
+        if len == 0 { return None }
+        Some(&self.suffix(idx)[..len])
+    }
+    
+    pub fn shortest_non_repeated_substring(&self) -> Option<&'a str> {
+        // min of pairwise maxes of lcp array values
+        let (len, idx) = (1..self.suffixes.len()).map(|i| {
+            let x = self.lcp_array[i-1];
+            let y = *self.lcp_array.get(i).unwrap_or(&0);
+            let l = std::cmp::max(x, y);
+            if l == self.suffix(i).len() { return (usize::MAX, i) }
+            (l, i)
+        }).min_by_key(|&(l, _)| l)?;
+        Some(&self.suffix(idx)[..=len])
+    }
+}
+
+/// Some strings to check the different constructors against each other with
+#[cfg(test)]
+const TEST_STRINGS: [&str; 10] = [
+    "", "a", "ab", "ba", "aaaaaaaa", "abababab", "mississippi", "abracadabra", "yabbadabbado",
+    "CGTATGCGGCATGCTAGCTAGGCGTGTAGTGCTGGAGGTTTTTCGGATCGTAGCTAGTGCGTGTATTCAGTTTATTAATTATAATATCGAGTCGTGCAGTCGTACATGCATGCTGCA",
+];
+
+/// A pseudo-random string of `len` bytes, made of the letters in `alphabet`
+#[cfg(test)]
+fn random_string(len: usize, alphabet: &[u8], mut seed: u64) -> String {
+    let bytes = (0..len).map(|_| {
+        // xorshift
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        alphabet[(seed % alphabet.len() as u64) as usize]
+    }).collect();
+    String::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn linear_matches() {
+    let random = [random_string(1000, b"ab", 1), random_string(1000, b"ACGT", 2), random_string(1000, b"abcdefghijklmnopqrstuvwxyz", 3)];
+    for string in TEST_STRINGS.into_iter().chain(random.iter().map(String::as_str)) {
+        let x = SuffixArray::new(string);
+        let y = SuffixArray::new_linear(string);
+        assert_eq!(x.suffixes, y.suffixes, "suffixes of {string:?}");
+        assert_eq!(x.lcp_array, y.lcp_array, "LCP array of {string:?}");
+    }
+}
+
+#[test]
+fn linear_non_ascii() {
+    for string in ["héllo wörld", "ééééé", "日本語の日本", "a€b€a€b€", "🦀🦀x🦀"] {
+        let x = CompactSuffixArray::new(string);
+        let y = SuffixArray::new_linear(string);
+        assert_eq!(x.suffixes.len(), y.suffixes.len(), "number of suffixes of {string:?}");
+        for i in 0..y.suffixes.len() {
+            assert_eq!(x.suffix(i), y.suffixes[i], "suffix {i} of {string:?}");
+        }
+        assert_eq!(x.lcp_array, y.lcp_array, "LCP array of {string:?}");
+    }
+}
+
+/// Run with `cargo test --release -- --ignored linear_benchmark --nocapture`
+#[test]
+#[ignore = "benchmark"]
+fn linear_benchmark() {
+    fn time<T>(f: impl FnOnce() -> T) -> (T, std::time::Duration) {
+        let start = std::time::Instant::now();
+        let result = f();
+        (result, start.elapsed())
+    }
+    
+    let random = random_string(1 << 20, b"ACGT", 4);
+    let (linear, linear_time) = time(|| SuffixArray::new_linear(&random));
+    let (sorted, sorted_time) = time(|| SuffixArray::new(&random));
+    println!("1MiB random: new_linear took {linear_time:?}, new took {sorted_time:?}");
+    assert_eq!(linear.suffixes, sorted.suffixes);
+    
+    // NOTE: this is the worst case for sorting (every comparison goes through most of the string),
+    //       so `new` only gets a small piece of it, or else it would take hours
+    let repetitive = "abcdefgh".repeat(1 << 17);
+    let (_, linear_time) = time(|| SuffixArray::new_linear(&repetitive));
+    let (_, small_linear_time) = time(|| SuffixArray::new_linear(&repetitive[..1 << 14]));
+    let (_, small_sorted_time) = time(|| SuffixArray::new(&repetitive[..1 << 14]));
+    println!("1MiB repetitive: new_linear took {linear_time:?}");
+    println!("16KiB repetitive: new_linear took {small_linear_time:?}, new took {small_sorted_time:?}");
+}
+
 #[test]
 fn doesitwork() {
     let x = SuffixArray::new("CGTATGCGGCATGCTAGCTAGGCGTGTAGTGCTGGAGGTTTTTCGGATCGTAGCTAGTGCGTGTATTCAGTTTATTAATTATAATATCGAGTCGTGCAGTCGTACATGCATGCTGCA");
@@ -72,3 +391,17 @@ fn doesitwork() {
     println!("{:?}", x.has_substring("TGCTGA"));
 }
 
+
+#[test]
+fn compact_matches() {
+    const STRING: &str = "CGTATGCGGCATGCTAGCTAGGCGTGTAGTGCTGGAGGTTTTTCGGATCGTAGCTAGTGCGTGTATTCAGTTTATTAATTATAATATCGAGTCGTGCAGTCGTACATGCATGCTGCA";
+    let x = SuffixArray::new(STRING);
+    let y = CompactSuffixArray::new(STRING);
+    
+    assert_eq!(x.longest_repeated_substring(), y.longest_repeated_substring());
+    assert_eq!(x.shortest_non_repeated_substring(), y.shortest_non_repeated_substring());
+    for query in ["TGCTGA", "GCTGCA", "A", "CGTAT", "GGG", "TTTTT", "TTTTTT", "ATGCTGCA", "ACGT"] {
+        assert_eq!(x.is_suffix(query), y.is_suffix(query), "is_suffix({query:?})");
+        assert_eq!(x.has_substring(query), y.has_substring(query), "has_substring({query:?})");
+    }
+}
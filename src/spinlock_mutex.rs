@@ -1,5 +1,14 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+/// Lets the OS run something else while we wait for a lock, if there is an OS.
+#[inline]
+fn relax() {
+    core::hint::spin_loop();
+    #[cfg(feature = "std")]
+    std::thread::yield_now();
+}
 
 // following along with https://www.youtube.com/watch?v=rMGWeSjctlY
 pub struct Mutex<T> {
@@ -8,26 +17,38 @@ pub struct Mutex<T> {
 }
 
 impl<T> Mutex<T> {
-    pub fn new(t : T) -> Self {
+    /// This is `const`, so a `Mutex` can be put directly in a `static`.
+    pub const fn new(t : T) -> Self {
         Self {
             locked: AtomicBool::new(false),
             v: UnsafeCell::new(t)
         }
     }
     
+    /// Since this takes `&mut self`, nobody else can be holding the lock, so there's no need to take it.
+    pub const fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+    
+    pub const fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+    
+    /// Calls `f` with the value once the lock is aquired, spinning until it is.
+    /// 
+    /// If `f` panics, the lock still gets released. There's no poisoning, so whatever `f` was in
+    /// the middle of doing to the value is just left that way.
     // https://matklad.github.io/2020/01/02/spinlocks-considered-harmful.html
     pub fn with_lock<F, R>(&self, f: F) -> R where F: FnOnce(&mut T) -> R {
         while self.locked
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
-            std::hint::spin_loop();
-            std::thread::yield_now();
+            relax();
             
             // this is here because of the [MESI protocol](https://en.wikipedia.org/wiki/MESI_protocol) ... or something ?
             while self.locked.load(Ordering::Relaxed) {
-                std::hint::spin_loop();
-                std::thread::yield_now();
+                relax();
             }
             
             // compare_exchange vs compare_exchange_weak:
@@ -35,19 +56,262 @@ impl<T> Mutex<T> {
             //   - x.compare_exchange_weak(a, ...) can fail even when x = a
         }
         
-        // SAFETY: cast into &mut is safe because no other thread has access to the `T`, since only this thread holds the lock.
-        //         This also must happen AFTER we aquire the lock, and BEFORE we release the lock, because of the mem orderings.
-        let ret = f(unsafe { &mut *self.v.get() } );
+        // SAFETY: we just aquired the lock
+        unsafe { self.run_and_unlock(f) }
+    }
+    
+    /// Like [`with_lock`](Self::with_lock), but gives up and returns `None` if the lock
+    /// couldn't be aquired before `timeout` runs out.
+    /// 
+    /// This is useful when a hung holder of the lock shouldn't wedge everything else.
+    #[cfg(feature = "std")]
+    pub fn with_lock_timeout<F, R>(&self, timeout: std::time::Duration, f: F) -> Option<R> where F: FnOnce(&mut T) -> R {
+        let start = std::time::Instant::now();
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            relax();
+            
+            while self.locked.load(Ordering::Relaxed) {
+                if start.elapsed() >= timeout { return None }
+                relax();
+            }
+        }
+        
+        // SAFETY: we just aquired the lock
+        Some(unsafe { self.run_and_unlock(f) })
+    }
+    
+    /// Like [`with_lock`](Self::with_lock), but returns `None` right away instead of waiting if
+    /// the lock is already held (including by the current thread).
+    pub fn try_with_lock<F, R>(&self, f: F) -> Option<R> where F: FnOnce(&mut T) -> R {
+        // NOTE: this has to be the strong version, since the weak one can fail even if nobody holds the lock
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).ok()?;
         
-        // store(Release) → everything that happens earlier on this thread is seen by any load(Aquire+)
-        self.locked.store(false, Ordering::Release);
+        // SAFETY: we just aquired the lock
+        Some(unsafe { self.run_and_unlock(f) })
+    }
+    
+    /// # Safety
+    /// The current thread has to be holding the lock.
+    unsafe fn run_and_unlock<F, R>(&self, f: F) -> R where F: FnOnce(&mut T) -> R {
+        /// Releases the lock when dropped, so that a panic in `f` doesn't leave it locked forever.
+        struct Unlock<'a>(&'a AtomicBool);
+        impl Drop for Unlock<'_> {
+            fn drop(&mut self) {
+                // store(Release) → everything that happens earlier on this thread is seen by any load(Aquire+)
+                self.0.store(false, Ordering::Release);
+            }
+        }
+        let _unlock = Unlock(&self.locked);
         
-        ret
+        // SAFETY: cast into &mut is safe because no other thread has access to the `T`, since only this thread holds the lock.
+        //         This also must happen AFTER we aquire the lock, and BEFORE we release the lock, because of the mem orderings.
+        f(unsafe { &mut *self.v.get() } )
     }
 }
 
 unsafe impl<T> Sync for Mutex<T> where T: Send {}
 
+/// Like [`std::sync::Mutex`], this never waits for the lock, so it can't deadlock (e.g: when
+/// printing a mutex that the current thread is holding). It just prints `<locked>` instead.
+impl<T: core::fmt::Debug> core::fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_with_lock(|v| { d.field("data", v); }) {
+            Some(()) => (),
+            None => { d.field("data", &format_args!("<locked>")); },
+        }
+        d.finish_non_exhaustive()
+    }
+}
+
+/// Waits for longer each time a lock turns out to still be held, so that everybody waiting on
+/// it isn't hammering the same cache line the whole time.
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    /// After this many steps, it stops spinning longer and just lets the OS run something else.
+    const MAX_SPIN_STEP: u32 = 6;
+    
+    fn new() -> Self {
+        Self { step: 0 }
+    }
+    
+    fn snooze(&mut self) {
+        if self.step <= Self::MAX_SPIN_STEP {
+            for _ in 0..1 << self.step {
+                core::hint::spin_loop();
+            }
+            self.step += 1;
+        } else {
+            relax();
+        }
+    }
+}
+
+/// A reader-writer lock that spins (with backoff) instead of blocking in the OS.
+/// 
+/// Any number of readers can hold the lock at once, or one writer. Unlike [`AtomicRefCell`],
+/// which gives up right away if the value is already borrowed the wrong way, this waits for the
+/// lock to be released. It's mostly meant for short critical sections in read-heavy code, where
+/// going through [`std::sync::RwLock`] would cost more than the actual work.
+/// 
+/// NOTE: readers don't wait for writers that haven't gotten the lock yet, so if there are always
+/// readers holding it, a writer can end up waiting forever.
+/// 
+/// [`AtomicRefCell`]: crate::cell::AtomicRefCell
+pub struct SpinRwLock<T: ?Sized> {
+    /// The number of readers holding the lock, with [`WRITER`](Self::WRITER) set while a writer does.
+    state: AtomicUsize,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for SpinRwLock<T> {}
+
+impl<T> SpinRwLock<T> {
+    /// This is `const`, so a `SpinRwLock` can be put directly in a `static`.
+    pub const fn new(t: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            v: UnsafeCell::new(t),
+        }
+    }
+    
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinRwLock<T> {
+    const WRITER: usize = 1 << (usize::BITS - 1);
+    
+    /// Since this takes `&mut self`, nobody else can be holding the lock, so there's no need to take it.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+    
+    /// Waits until there isn't a writer, and then gets shared access to the value.
+    pub fn read(&self) -> SpinReadGuard<'_, T> {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard
+            }
+            // wait until the writer is gone without writing to the state (see `Mutex::with_lock`)
+            while self.state.load(Ordering::Relaxed) & Self::WRITER != 0 {
+                backoff.snooze();
+            }
+        }
+    }
+    
+    /// Gets shared access to the value, or returns `None` right away if there's a writer.
+    pub fn try_read(&self) -> Option<SpinReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        // NOTE: just adding 1 and taking it back if there's a writer would be simpler, but then
+        //       a writer waiting for the count to hit 0 could keep missing it
+        while state & Self::WRITER == 0 {
+            assert!(state + 1 < Self::WRITER, "too many readers");
+            match self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Some(SpinReadGuard { lock: self }),
+                Err(actual) => state = actual,
+            }
+        }
+        None
+    }
+    
+    /// Waits until nobody else is holding the lock, and then gets exclusive access to the value.
+    pub fn write(&self) -> SpinWriteGuard<'_, T> {
+        let mut backoff = Backoff::new();
+        while self.state
+            .compare_exchange_weak(0, Self::WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.state.load(Ordering::Relaxed) != 0 {
+                backoff.snooze();
+            }
+        }
+        SpinWriteGuard { lock: self }
+    }
+    
+    /// Gets exclusive access to the value, or returns `None` right away if anybody (including the
+    /// current thread) is holding the lock.
+    pub fn try_write(&self) -> Option<SpinWriteGuard<'_, T>> {
+        // NOTE: this has to be the strong version, since the weak one can fail even if nobody holds the lock
+        self.state.compare_exchange(0, Self::WRITER, Ordering::Acquire, Ordering::Relaxed).ok()?;
+        Some(SpinWriteGuard { lock: self })
+    }
+}
+
+impl<T: Default> Default for SpinRwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Like the [`Mutex`] one, this never waits for the lock, and just prints `<locked>` if there's a writer.
+impl<T: ?Sized + core::fmt::Debug> core::fmt::Debug for SpinRwLock<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut d = f.debug_struct("SpinRwLock");
+        match self.try_read() {
+            Some(guard) => { d.field("data", &&*guard); },
+            None => { d.field("data", &format_args!("<locked>")); },
+        }
+        d.finish_non_exhaustive()
+    }
+}
+
+/// Shared access to the value in a [`SpinRwLock`], until this is dropped.
+pub struct SpinReadGuard<'a, T: ?Sized> {
+    lock: &'a SpinRwLock<T>,
+}
+
+impl<T: ?Sized> Deref for SpinReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: there aren't any writers while this guard holds the lock
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release, so the next writer sees everything that happened while reading
+        self.lock.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Exclusive access to the value in a [`SpinRwLock`], until this is dropped.
+pub struct SpinWriteGuard<'a, T: ?Sized> {
+    lock: &'a SpinRwLock<T>,
+}
+
+impl<T: ?Sized> Deref for SpinWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: nobody else has access to the value while this guard holds the lock
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: nobody else has access to the value while this guard holds the lock
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // NOTE: readers can't even bump the count while there's a writer, so it has to be exactly `WRITER`
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -80,6 +344,25 @@ mod tests {
         assert_eq!(m.with_lock(|v| *v), T*R);
     }
     
+    #[test]
+    fn mutex_static() {
+        static COUNTER: Mutex<usize> = Mutex::new(0);
+        const T: usize = 16;
+        const R: usize = 1000;
+        
+        std::thread::scope(|s| for _ in 0..T {
+            s.spawn(|| for _ in 0..R {
+                COUNTER.with_lock(|v| *v += 1)
+            });
+        });
+        
+        assert_eq!(COUNTER.with_lock(|v| *v), T*R);
+        
+        let mut m = Mutex::new(vec![1]);
+        m.get_mut().push(2);
+        assert_eq!(m.into_inner(), [1, 2]);
+    }
+    
     #[test]
     fn mutex_vec_push() {
         use std::thread;
@@ -102,4 +385,92 @@ mod tests {
         
         assert_eq!(m.with_lock(|v| v.len()), T*R);
     }
+    
+    #[test]
+    #[cfg(feature = "std")]
+    fn mutex_lock_timeout() {
+        use std::sync::Barrier;
+        use std::time::Duration;
+        
+        let m = Mutex::new(0);
+        let barrier = Barrier::new(2);
+        
+        std::thread::scope(|s| {
+            s.spawn(|| m.with_lock(|v| {
+                barrier.wait();
+                std::thread::sleep(Duration::from_millis(200));
+                *v += 1;
+            }));
+            
+            // the holder keeps the lock for way longer than this
+            barrier.wait();
+            assert_eq!(m.with_lock_timeout(Duration::from_millis(20), |v| *v), None);
+        });
+        
+        assert_eq!(m.with_lock_timeout(Duration::from_millis(20), |v| *v), Some(1));
+    }
+    
+    #[test]
+    fn mutex_debug() {
+        let m = Mutex::new(vec![1, 2]);
+        assert_eq!(format!("{m:?}"), "Mutex { data: [1, 2], .. }");
+        
+        // printing it while it's held (even by this thread) shouldn't deadlock
+        m.with_lock(|_| assert_eq!(format!("{m:?}"), "Mutex { data: <locked>, .. }"));
+        assert_eq!(m.try_with_lock(|v| v.len()), Some(2));
+    }
+    
+    #[test]
+    fn rwlock_try() {
+        let lock = SpinRwLock::new(vec![1, 2]);
+        
+        // any number of readers, but no writers while there are any
+        let a = lock.try_read().unwrap();
+        let b = lock.read();
+        assert_eq!(*a, *b);
+        assert!(lock.try_write().is_none());
+        assert_eq!(format!("{lock:?}"), "SpinRwLock { data: [1, 2], .. }");
+        drop((a, b));
+        
+        // one writer, and nobody else
+        let mut w = lock.try_write().unwrap();
+        w.push(3);
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        assert_eq!(format!("{lock:?}"), "SpinRwLock { data: <locked>, .. }");
+        drop(w);
+        
+        assert_eq!(*lock.read(), [1, 2, 3]);
+        assert_eq!(lock.into_inner(), [1, 2, 3]);
+    }
+    
+    #[test]
+    fn rwlock_stress() {
+        const READERS: usize = 16;
+        const WRITERS: usize = 2;
+        const R: usize = 10000;
+        const W: usize = 100;
+        
+        // the writers always keep both halves the same, so a reader should never see them different
+        let lock = SpinRwLock::new((0usize, 0usize));
+        
+        std::thread::scope(|s| {
+            for _ in 0..READERS {
+                s.spawn(|| for _ in 0..R {
+                    let guard = lock.read();
+                    assert_eq!(guard.0, guard.1);
+                });
+            }
+            for _ in 0..WRITERS {
+                s.spawn(|| for _ in 0..W {
+                    let mut guard = lock.write();
+                    guard.0 += 1;
+                    std::hint::black_box(&mut *guard);
+                    guard.1 += 1;
+                });
+            }
+        });
+        
+        assert_eq!(lock.into_inner(), (WRITERS * W, WRITERS * W));
+    }
 }